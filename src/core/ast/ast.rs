@@ -1,14 +1,38 @@
 use crate::core::ast::stmt::Stmt;
+use crate::core::dodo::modules::Module;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 
+// First and last source line of a statement
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub line: usize,
+    pub end_line: usize,
+}
+
 pub struct Program {
-    pub statements: Vec<Stmt>
+    pub statements: Vec<Stmt>,
+    pub spans: Vec<Span>,
+    // Files the program imports, by the name its import statements give; filled in by `modules::Loader`
+    pub imports: HashMap<String, Rc<Module>>,
 }
 
 impl Program {
     pub fn new(program: Vec<Stmt>) -> Self {
+        let spans = vec![Span::default(); program.len()];
+        Program {
+            statements: program,
+            spans,
+            imports: HashMap::new(),
+        }
+    }
+
+    pub fn with_spans(statements: Vec<Stmt>, spans: Vec<Span>) -> Self {
         Program {
-            statements: program
+            statements,
+            spans,
+            imports: HashMap::new(),
         }
     }
 }
@@ -16,9 +40,28 @@ impl Program {
 
 #[cfg(test)]
 mod tests {
+    use super::Program;
+    use crate::core::ast::expr::Expr;
+    use crate::core::ast::parser::Parser;
+    use crate::core::ast::stmt::{DeclaredType, Stmt};
+    use crate::core::lexer::lexer::Lexer;
+    use crate::core::token::token::{Token, TokenType};
+
     #[test]
     fn basic_statements() {
         let input = "scalar x
                            vector y[4]";
+
+        let mut lexer = Lexer::new(input.to_string());
+        let mut tokens = vec![lexer.next_token()];
+        while tokens.last().unwrap().token_type != TokenType::EOF {
+            tokens.push(lexer.next_token());
+        }
+        let program = Program::new(Parser::new(tokens).parse());
+
+        assert_eq!(program.statements, vec![
+            Stmt::Definition(Token::new(TokenType::IDENT, "x".to_string()), DeclaredType::Scalar, Vec::new(), None),
+            Stmt::Definition(Token::new(TokenType::IDENT, "y".to_string()), DeclaredType::Vector, vec![Expr::Literal(4)], None),
+        ]);
     }
 }
\ No newline at end of file
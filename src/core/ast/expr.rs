@@ -1,4 +1,4 @@
-use crate::core::token::token::{Token, TokenType};
+use crate::core::token::token::Token;
 use std::fmt;
 
 
@@ -7,13 +7,20 @@ pub enum Expr {
     Assign(Token, Box<Expr>, String),
     Grouping(Box<Expr>),
     Literal(i128),
+    // Integer literal too large for i128, as written
+    Big(String),
+    Float(f64),
+    // Imaginary literal such as `2i`, holding the imaginary part
+    Imaginary(f64),
+    Str(String),
     Set(Box<Expr>, Token, Box<Expr>),
-    Vector(Token, Vec<i128>),
-    Matrix(Vec<i128>),
+    Vector(Token, Vec<Expr>),
+    Matrix(Token, Vec<Vec<Expr>>),
     FunctionCall(Box<Expr>, Vec<Expr>),
     Binary(Box<Expr>, Token, Box<Expr>),
     Variable(Token, String),
-    Get(String, Token),
+    // Member of an imported module, such as `lu.solve`: the namespace and the member
+    Get(Token, Token),
     Unary(Token, Box<Expr>),
     Err,
 }
@@ -0,0 +1,246 @@
+use crate::core::ast::ast::Program;
+use crate::core::ast::expr::Expr;
+use crate::core::ast::stmt::{DeclaredType, Stmt};
+use crate::core::dodo::builtins;
+use crate::core::dodo::value::{Arithmetic, Scalar};
+use crate::core::token::token::TokenType;
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use std::collections::{HashMap, HashSet};
+
+// Literal holding a scalar; rationals and complex numbers with a real part have none
+fn literal(val: &Scalar) -> Option<Expr> {
+    match val {
+        Scalar::Int(val) => Some(Expr::Literal(*val)),
+        Scalar::Big(val) => Some(Expr::Big(val.to_string())),
+        Scalar::Float(val) => Some(Expr::Float(*val)),
+        Scalar::Complex(val) if val.re == 0.0 && val.re.is_sign_positive() => Some(Expr::Imaginary(val.im)),
+        _ => None,
+    }
+}
+
+fn scalar(expr: &Expr) -> Option<Scalar> {
+    match expr {
+        Expr::Literal(val) => Some(Scalar::Int(*val)),
+        Expr::Big(val) => val.parse::<BigInt>().ok().map(Scalar::big),
+        Expr::Float(val) => Some(Scalar::Float(*val)),
+        Expr::Imaginary(val) => Some(Scalar::Complex(Complex64::new(0.0, *val))),
+        _ => None,
+    }
+}
+
+// Whether a folded expression is a value written out in full, which can stand in for a constant
+fn is_value(expr: &Expr) -> bool {
+    match expr {
+        Expr::Str(_) => true,
+        Expr::Vector(_, elements) => elements.iter().all(|element| scalar(element).is_some()),
+        Expr::Matrix(_, rows) => rows.iter().flatten().all(|element| scalar(element).is_some()),
+        other => scalar(other).is_some(),
+    }
+}
+
+// Every name a program declares at any depth; these hide the built-in constants
+fn collect(statements: &[Stmt], names: &mut HashSet<String>) {
+    for stmt in statements {
+        match stmt {
+            Stmt::Definition(name, _, _, _) => {
+                names.insert(name.val.clone());
+            }
+            Stmt::Function(name, params, body) => {
+                names.insert(name.val.clone());
+                names.extend(params.iter().map(|param| param.val.clone()));
+                collect(&body.statements, names);
+            }
+            Stmt::While(_, _, body) | Stmt::Loop(_, body) | Stmt::Test(_, body) => collect(&body.statements, names),
+            Stmt::FromImport(_, imported) => names.extend(imported.iter().map(|name| name.val.clone())),
+            _ => (),
+        }
+    }
+}
+
+/// Replaces constants with their values and evaluates arithmetic on literals before
+/// the program runs. Anything that could fail, or whose result cannot be written as a
+/// literal, is left to the interpreter, so folding never changes what a program does.
+/// State carries over between calls to `fold`, so the REPL can fold line by line.
+#[derive(Default)]
+pub struct Folder {
+    // Names declared among the globals, with the value of those that are constants
+    globals: HashMap<String, Option<Expr>>,
+    // The same for the function body being folded; bodies only see their own locals and the globals
+    locals: Option<HashMap<String, Option<Expr>>>,
+    declared: HashSet<String>,
+}
+
+impl Folder {
+    pub fn new() -> Self {
+        Folder::default()
+    }
+
+    pub fn fold(&mut self, program: &mut Program) {
+        collect(&program.statements, &mut self.declared);
+        self.statements(&mut program.statements);
+    }
+
+    fn statements(&mut self, statements: &mut [Stmt]) {
+        for stmt in statements.iter_mut() {
+            self.statement(stmt);
+        }
+    }
+
+    fn bind(&mut self, name: &str, value: Option<Expr>) {
+        let scope = match self.locals.as_mut() {
+            Some(locals) => locals,
+            None => &mut self.globals,
+        };
+        scope.insert(name.to_string(), value);
+    }
+
+    fn constant(&self, name: &str) -> Option<Expr> {
+        if let Some(value) = self.locals.as_ref().and_then(|locals| locals.get(name)) {
+            return value.clone();
+        }
+        if let Some(value) = self.globals.get(name) {
+            return value.clone();
+        }
+        match self.declared.contains(name) {
+            true => None,
+            false => builtins::constant(name).as_ref().and_then(literal),
+        }
+    }
+
+    fn statement(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Definition(name, declared, dims, initial) => {
+                for dim in dims.iter_mut() {
+                    self.expr(dim);
+                }
+                if let Some(value) = initial.as_mut() {
+                    self.expr(value);
+                }
+                let value = match (declared, initial) {
+                    (DeclaredType::Constant, Some(value)) if is_value(value) => Some(value.clone()),
+                    _ => None,
+                };
+                self.bind(&name.val, value);
+            }
+            Stmt::Expression(expr) | Stmt::Print(expr) | Stmt::Return(_, Some(expr)) => self.expr(expr),
+            Stmt::Function(name, params, body) => {
+                self.bind(&name.val, None);
+                let frame = params.iter().map(|param| (param.val.clone(), None)).collect();
+                let outer = self.locals.replace(frame);
+                self.statements(&mut body.statements);
+                self.locals = outer;
+            }
+            Stmt::While(_, condition, body) => {
+                self.expr(condition);
+                self.statements(&mut body.statements);
+            }
+            Stmt::Loop(_, body) => self.statements(&mut body.statements),
+            Stmt::Test(_, body) => {
+                let outer = self.locals.replace(HashMap::new());
+                self.statements(&mut body.statements);
+                self.locals = outer;
+            }
+            Stmt::FromImport(_, names) => {
+                for name in names.iter() {
+                    self.bind(&name.val, None);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        let folded = match expr {
+            Expr::Variable(token, _) => self.constant(&token.val),
+            Expr::Grouping(inner) => {
+                self.expr(inner);
+                scalar(inner).and_then(|val| literal(&val))
+            }
+            Expr::Unary(operator, right) => {
+                self.expr(right);
+                match operator.token_type {
+                    TokenType::MINUS => scalar(right).and_then(|val| val.negate_in(Arithmetic::Checked).ok()).as_ref().and_then(literal),
+                    TokenType::BANG => scalar(right).map(|val| Scalar::Int(val.is_zero() as i128)).as_ref().and_then(literal),
+                    _ => None,
+                }
+            }
+            Expr::Binary(left, operator, right) => {
+                self.expr(left);
+                self.expr(right);
+                match (scalar(left), scalar(right)) {
+                    (Some(left), Some(right)) => left.arithmetic_in(Arithmetic::Checked, operator.token_type, &right).ok().as_ref().and_then(literal),
+                    _ => None,
+                }
+            }
+            Expr::Assign(_, value, _) => {
+                self.expr(value);
+                None
+            }
+            Expr::Vector(_, elements) | Expr::FunctionCall(_, elements) => {
+                for element in elements.iter_mut() {
+                    self.expr(element);
+                }
+                None
+            }
+            Expr::Matrix(_, rows) => {
+                for element in rows.iter_mut().flatten() {
+                    self.expr(element);
+                }
+                None
+            }
+            _ => None,
+        };
+        if let Some(folded) = folded {
+            *expr = folded;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Folder;
+    use crate::core::ast::expr::Expr;
+    use crate::core::ast::parser::Parser;
+    use crate::core::ast::stmt::Stmt;
+    use crate::core::lexer::lexer::Lexer;
+
+    fn fold(source: &str) -> Vec<Stmt> {
+        let mut program = Parser::new(Lexer::new(source.to_string()).scan_tokens()).parse_program();
+        Folder::new().fold(&mut program);
+        program.statements
+    }
+
+    fn printed(stmts: &[Stmt]) -> Vec<Expr> {
+        stmts.iter().filter_map(|stmt| match stmt {
+            Stmt::Print(expr) => Some(expr.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn folds_constants_and_literal_arithmetic() {
+        let stmts = fold("const N = 10\nconst M = N * 2 + 1\nscalar x\nprint M - (N // 3)\nprint x + N\nprint 2 * pi\nprint 1 / 0\nprint 170141183460469231731687303715884105727 + 1\n");
+        let printed = printed(&stmts);
+
+        assert_eq!(printed[0], Expr::Literal(18));
+        assert!(matches!(&printed[1], Expr::Binary(_, _, right) if **right == Expr::Literal(10)));
+        assert_eq!(printed[2], Expr::Float(2.0 * std::f64::consts::PI));
+        // Failures and overflow are left for the interpreter, which reports them in its own arithmetic mode
+        assert!(matches!(printed[3], Expr::Binary(_, _, _)));
+        assert!(matches!(printed[4], Expr::Binary(_, _, _)));
+    }
+
+    #[test]
+    fn declarations_hide_constants() {
+        let stmts = fold("const N = 3\nfn f(N) {\n    print N\n}\nfn g() {\n    print N\n    scalar e = 1\n    print e\n}\n");
+        let bodies: Vec<Vec<Expr>> = stmts.iter().filter_map(|stmt| match stmt {
+            Stmt::Function(_, _, body) => Some(printed(&body.statements)),
+            _ => None,
+        }).collect();
+
+        assert!(matches!(bodies[0][0], Expr::Variable(_, _)));
+        assert_eq!(bodies[1][0], Expr::Literal(3));
+        assert!(matches!(bodies[1][1], Expr::Variable(_, _)));
+    }
+}
@@ -1,4 +1,6 @@
+#[allow(clippy::module_inception)]
 pub mod ast;
 pub mod parser;
 pub mod expr;
-pub mod stmt;
\ No newline at end of file
+pub mod stmt;
+pub mod fold;
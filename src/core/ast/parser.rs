@@ -1,14 +1,20 @@
-use crate::core::ast::stmt::Stmt;
+use crate::core::ast::ast::{Program, Span};
+use crate::core::ast::stmt::{Block, DeclaredType, Stmt};
 use crate::core::ast::expr::Expr;
 use crate::core::token::token::Token;
 use crate::core::token::token::TokenType;
 use crate::core::dodo::error_types::DodoParseError;
-use crate::core::dodo::error_types::throw_error;
-use crate::core::token::token::TokenType::VECTOR;
+
+// How deep expressions and blocks may nest. The passes after parsing walk the tree
+// recursively, so a deeper program would overflow the stack instead of failing.
+const MAX_NESTING: usize = 200;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: i32,
+    errors: Vec<DodoParseError>,
+    spans: Vec<Span>,
+    nesting: usize,
 }
 
 impl Parser {
@@ -16,99 +22,325 @@ impl Parser {
         Parser {
             tokens,
             current: 0,
+            errors: Vec::new(),
+            spans: Vec::new(),
+            nesting: 0,
         }
     }
 
     pub fn parse(&mut self) -> Vec<Stmt> {
         let mut statements = Vec::new();
-        while !self.at_end() {
-            statements.push(self.declaration())
+        loop {
+            self.skip_newlines();
+            if self.at_end() {
+                break;
+            }
+            let line = self.peek().line;
+            match self.declaration() {
+                Ok(stmt) => {
+                    statements.push(stmt);
+                    let end_line = self.previous().line;
+                    self.spans.push(Span { line, end_line });
+                }
+                Err(error) => {
+                    self.errors.push(error);
+                    self.sync();
+                }
+            }
         }
-        return statements;
+        statements
     }
-    fn declaration(&mut self) -> Stmt {
+
+    pub fn parse_program(&mut self) -> Program {
+        let statements = self.parse();
+        Program::with_spans(statements, self.spans.clone())
+    }
+
+    pub fn errors(&self) -> &[DodoParseError] {
+        &self.errors
+    }
+
+    fn declaration(&mut self) -> Result<Stmt, DodoParseError> {
+        if self.match_token(vec![TokenType::COMMENT]) {
+            let comment = self.previous();
+            self.end_statement()?;
+            return Ok(Stmt::Comment(comment.val));
+        }
         if self.match_token(vec![TokenType::SCALAR]) {
-            let mut var_type = self.peek().clone();
-            return self.scalar_declaration(var_type);
+            return self.scalar_declaration();
         }
         if self.match_token(vec![TokenType::VECTOR]) {
-            let mut token = self.peek().clone();
-            return self.vector_declaration(token);
+            return self.vector_declaration();
         }
         if self.match_token(vec![TokenType::MATRIX]) {
-            let mut token = self.peek().clone();
-            return self.matrix_declaration(token);
+            return self.matrix_declaration();
+        }
+        if self.match_token(vec![TokenType::LET, TokenType::CONST]) {
+            return self.let_declaration();
+        }
+        if self.match_token(vec![TokenType::FUNCTION]) {
+            return self.function();
+        }
+        if self.match_token(vec![TokenType::IMPORT, TokenType::FROM]) {
+            return self.import();
+        }
+        if self.match_token(vec![TokenType::TEST]) {
+            let name = self.consume(TokenType::STRING, "Expect test name after 'test'".to_string())?;
+            let body = self.block()?;
+            self.end_statement()?;
+            return Ok(Stmt::Test(name, body));
+        }
+        self.statement()
+    }
+
+    // `import "file" as name` or `from "file" import a, b`
+    fn import(&mut self) -> Result<Stmt, DodoParseError> {
+        let keyword = self.previous();
+        let file = self.consume(TokenType::STRING, format!("Expect file name after '{}'", keyword.val))?;
+        let stmt = match keyword.token_type {
+            TokenType::IMPORT => {
+                self.consume(TokenType::AS, "Expect 'as' after file name".to_string())?;
+                let namespace = self.consume(TokenType::IDENT, "Expect module name after 'as'".to_string())?;
+                Stmt::Import(file, namespace)
+            }
+            _ => {
+                self.consume(TokenType::IMPORT, "Expect 'import' after file name".to_string())?;
+                let mut names = Vec::new();
+                loop {
+                    names.push(self.consume(TokenType::IDENT, "Expect name to import".to_string())?);
+                    if !self.match_token(vec![TokenType::COMMA]) {
+                        break;
+                    }
+                }
+                Stmt::FromImport(file, names)
+            }
+        };
+        self.end_statement()?;
+        Ok(stmt)
+    }
+
+    fn function(&mut self) -> Result<Stmt, DodoParseError> {
+        let name = self.consume(TokenType::IDENT, "Expect function name".to_string())?;
+        self.consume(TokenType::LeftParenthesis, "Expect '(' after function name".to_string())?;
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParenthesis) {
+            loop {
+                let param = self.consume(TokenType::IDENT, "Expect parameter name".to_string())?;
+                if params.contains(&param) {
+                    return Err(self.error(param, "Duplicate parameter name".to_string()));
+                }
+                params.push(param);
+                if !self.match_token(vec![TokenType::COMMA]) {
+                    break;
+                }
+            }
         }
-        return self.statement();
-        // todo error
+        self.consume(TokenType::RightParenthesis, "Expect ')' after parameters".to_string())?;
+        let body = self.block()?;
+        self.end_statement()?;
+        Ok(Stmt::Function(name, params, body))
     }
 
-    fn scalar_declaration(&mut self, var_type: Token) -> Stmt {
-        let mut name = self.consume(TokenType::IDENT, "expect".to_string());
-        let mut token = name.unwrap().clone();
-        self.consume(TokenType::NewLine, "Expect".to_string());
-        return Stmt::Definition(Token::new(TokenType::SCALAR, var_type.val.to_string()), "0".to_string(), "0".to_string());
+    fn block(&mut self) -> Result<Block, DodoParseError> {
+        self.nested("Blocks", Self::block_statements)
     }
 
-    fn vector_declaration(&mut self, var_type: Token) -> Stmt {
-        self.consume(TokenType::IDENT, "expect".to_string());
-        self.consume(TokenType::LeftBracket, "expect".to_string());
-        let size = self.consume(TokenType::INT, "expect".to_string());
-        self.consume(TokenType::RightBracket, "expect".to_string());
-        self.consume(TokenType::NewLine, "Expect".to_string());
-        return Stmt::Definition(Token::new(TokenType::VECTOR, var_type.val.to_string()), size.unwrap().val, "1".to_string());
+    // Statements up to the closing '}'; errors inside are recorded and parsing resumes on the next line
+    fn block_statements(&mut self) -> Result<Block, DodoParseError> {
+        self.consume(TokenType::LeftBrace, "Expect '{' before block".to_string())?;
+        let mut block = Block::default();
+        loop {
+            self.skip_newlines();
+            if self.check(TokenType::RightBrace) || self.at_end() {
+                break;
+            }
+            let line = self.peek().line;
+            match self.declaration() {
+                Ok(stmt) => {
+                    block.statements.push(stmt);
+                    let end_line = self.previous().line;
+                    block.spans.push(Span { line, end_line });
+                }
+                Err(error) => {
+                    self.errors.push(error);
+                    self.sync();
+                }
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block".to_string())?;
+        Ok(block)
     }
 
-    fn matrix_declaration(&mut self, var_type: Token) -> Stmt {
-        self.consume(TokenType::IDENT, "expect".to_string());
-        self.consume(TokenType::LeftBracket, "expect".to_string());
-        let rows = self.consume(TokenType::INT, "expect".to_string());
-        self.consume(TokenType::COMMA, "expect".to_string());
-        let columns = self.consume(TokenType::INT, "expect".to_string());
-        self.consume(TokenType::RightBracket, "expect".to_string());
-        self.consume(TokenType::NewLine, "Expect".to_string());
-        return Stmt::Definition(Token::new(TokenType::MATRIX, var_type.val.to_string()), rows.unwrap().val, columns.unwrap().val);
+    fn scalar_declaration(&mut self) -> Result<Stmt, DodoParseError> {
+        let name = self.consume(TokenType::IDENT, "Expect variable name".to_string())?;
+        let value = self.initialiser()?;
+        self.end_statement()?;
+        Ok(Stmt::Definition(name, DeclaredType::Scalar, Vec::new(), value))
     }
 
-    fn statement(&mut self) -> Stmt {
-        let for_stmt = vec![TokenType::FOR];
-        let print_stmt = vec![TokenType::PRINT];
+    fn vector_declaration(&mut self) -> Result<Stmt, DodoParseError> {
+        let name = self.consume(TokenType::IDENT, "Expect variable name".to_string())?;
+        self.consume(TokenType::LeftBracket, "Expect '[' after vector name".to_string())?;
+        let size = self.size("Expect vector size")?;
+        self.consume(TokenType::RightBracket, "Expect ']' after vector size".to_string())?;
+        let value = self.initialiser()?;
+        self.end_statement()?;
+        Ok(Stmt::Definition(name, DeclaredType::Vector, vec![size], value))
+    }
+
+    fn matrix_declaration(&mut self) -> Result<Stmt, DodoParseError> {
+        let name = self.consume(TokenType::IDENT, "Expect variable name".to_string())?;
+        self.consume(TokenType::LeftBracket, "Expect '[' after matrix name".to_string())?;
+        let rows = self.size("Expect number of rows")?;
+        self.consume(TokenType::COMMA, "Expect ',' between matrix dimensions".to_string())?;
+        let columns = self.size("Expect number of columns")?;
+        self.consume(TokenType::RightBracket, "Expect ']' after matrix size".to_string())?;
+        let value = self.initialiser()?;
+        self.end_statement()?;
+        Ok(Stmt::Definition(name, DeclaredType::Matrix, vec![rows, columns], value))
+    }
+
+    // `let x = value` or `const N = value`, whose type and shape come from the value
+    fn let_declaration(&mut self) -> Result<Stmt, DodoParseError> {
+        let declared = match self.previous().token_type {
+            TokenType::CONST => DeclaredType::Constant,
+            _ => DeclaredType::Inferred,
+        };
+        let name = self.consume(TokenType::IDENT, "Expect variable name".to_string())?;
+        self.consume(TokenType::ASSIGN, format!("Expect '=' after name in '{}'", declared.keyword()))?;
+        let value = self.expression()?;
+        self.end_statement()?;
+        Ok(Stmt::Definition(name, declared, Vec::new(), Some(value)))
+    }
+
+    // A dimension between the brackets of a declaration, any expression short of an assignment
+    fn size(&mut self, message: &str) -> Result<Expr, DodoParseError> {
+        self.skip_trivia();
+        if self.check(TokenType::RightBracket) || self.check(TokenType::COMMA) {
+            let token = self.peek();
+            return Err(self.error(token, message.to_string()));
+        }
+        let size = self.nested("Expression", Self::addition)?;
+        self.skip_trivia();
+        Ok(size)
+    }
+
+    fn initialiser(&mut self) -> Result<Option<Expr>, DodoParseError> {
+        match self.match_token(vec![TokenType::ASSIGN]) {
+            true => Ok(Some(self.expression()?)),
+            false => Ok(None),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt, DodoParseError> {
         //todo
-        //if self.match_token(for_stmt) {
+        //if self.match_token(vec![TokenType::FOR]) {
         //    return self.for_statement;
         //}
-        if self.match_token(print_stmt) {
+        if self.check(TokenType::IDENT) && self.peek_next().token_type == TokenType::COLON {
+            let label = self.advance();
+            self.advance();
+            return self.loop_statement(Some(label));
+        }
+        if self.check(TokenType::WHILE) || self.check(TokenType::LOOP) {
+            return self.loop_statement(None);
+        }
+        if self.match_token(vec![TokenType::BREAK, TokenType::CONTINUE]) {
+            return self.jump_statement();
+        }
+        if self.match_token(vec![TokenType::PRINT]) {
             return self.print_statement();
         }
+        if self.match_token(vec![TokenType::RETURN]) {
+            return self.return_statement();
+        }
 
-        return self.expression_statement();
+        self.expression_statement()
     }
 
-    fn print_statement(&mut self) -> Stmt {
-        let value = self.expression();
-        self.consume(TokenType::NewLine, "Expect ".to_string());
-        return Stmt::Print(value);
+    // `while condition { ... }` or `loop { ... }`, after the label if there is one
+    fn loop_statement(&mut self, label: Option<Token>) -> Result<Stmt, DodoParseError> {
+        let stmt = if self.match_token(vec![TokenType::WHILE]) {
+            let condition = self.expression()?;
+            Stmt::While(label, condition, self.block()?)
+        } else if self.match_token(vec![TokenType::LOOP]) {
+            Stmt::Loop(label, self.block()?)
+        } else {
+            let token = self.peek();
+            return Err(self.error(token, "Expect 'while' or 'loop' after label".to_string()));
+        };
+        self.end_statement()?;
+        Ok(stmt)
     }
-    fn expression_statement(&mut self) -> Stmt {
-        let expr = self.expression();
-        self.consume(TokenType::NewLine, "Expect ".to_string());
-        return Stmt::Expression(expr);
+
+    fn jump_statement(&mut self) -> Result<Stmt, DodoParseError> {
+        let keyword = self.previous();
+        let label = match self.check(TokenType::IDENT) {
+            true => Some(self.advance()),
+            false => None,
+        };
+        self.end_statement()?;
+        match keyword.token_type {
+            TokenType::BREAK => Ok(Stmt::Break(keyword, label)),
+            _ => Ok(Stmt::Continue(keyword, label)),
+        }
     }
-    fn expression(&mut self) -> Expr {
-        return self.assignment();
+
+    fn print_statement(&mut self) -> Result<Stmt, DodoParseError> {
+        let value = self.expression()?;
+        self.end_statement()?;
+        Ok(Stmt::Print(value))
+    }
+    fn return_statement(&mut self) -> Result<Stmt, DodoParseError> {
+        let keyword = self.previous();
+        let value = match self.at_end() || self.check(TokenType::NewLine) || self.check(TokenType::COMMENT) || self.check(TokenType::RightBrace) {
+            true => None,
+            false => Some(self.expression()?),
+        };
+        self.end_statement()?;
+        Ok(Stmt::Return(keyword, value))
+    }
+    fn expression_statement(&mut self) -> Result<Stmt, DodoParseError> {
+        let expr = self.expression()?;
+        self.end_statement()?;
+        Ok(Stmt::Expression(expr))
+    }
+    fn end_statement(&mut self) -> Result<(), DodoParseError> {
+        if self.at_end() || self.check(TokenType::COMMENT) || self.check(TokenType::RightBrace) {
+            return Ok(());
+        }
+        self.consume(TokenType::NewLine, "Expect end of line after statement".to_string())?;
+        Ok(())
+    }
+    fn expression(&mut self) -> Result<Expr, DodoParseError> {
+        self.nested("Expression", Self::assignment)
+    }
+    // One level deeper; the level is only given back when the enclosing `nested` returns
+    fn deeper(&mut self, what: &str) -> Result<(), DodoParseError> {
+        if self.nesting == MAX_NESTING {
+            let token = self.peek();
+            return Err(self.error(token, format!("{} nested too deeply", what)));
+        }
+        self.nesting += 1;
+        Ok(())
+    }
+    fn nested<T>(&mut self, what: &str, parse: fn(&mut Self) -> Result<T, DodoParseError>) -> Result<T, DodoParseError> {
+        let outer = self.nesting;
+        let result = self.deeper(what).and_then(|_| parse(self));
+        self.nesting = outer;
+        result
     }
-    fn assignment(&mut self) -> Expr {
-        let expr = self.addition();
+    fn assignment(&mut self) -> Result<Expr, DodoParseError> {
+        let expr = self.equality()?;
         if self.match_token(vec![TokenType::ASSIGN]) {
             let equals = self.previous();
-            let value = self.assignment();
+            let value = self.nested("Expression", Self::assignment)?;
             if let Expr::Variable(token, variable_type) = expr {
-                println!("{}", token.val);
-                return Expr::Assign(token, Box::new(value), variable_type);
+                return Ok(Expr::Assign(token, Box::new(value), variable_type));
             }
-            // todo error(equals, "Invalid");
+            return Err(self.error(equals, "Invalid assignment target".to_string()));
         }
-        return expr;
+        Ok(expr)
     }
     fn match_token(&mut self, token_types: Vec<TokenType>) -> bool {
         for token in token_types {
@@ -117,121 +349,228 @@ impl Parser {
                 return true;
             }
         }
-        return false;
+        false
     }
     fn check(&mut self, token_type: TokenType) -> bool {
-        return match self.at_end() {
+        match self.at_end() {
             true => false,
             _ => self.peek().token_type == token_type
-        };
+        }
     }
     fn advance(&mut self) -> Token {
         if !self.at_end() {
             self.current += 1;
         }
-        return self.previous();
+        self.previous()
     }
     fn at_end(&mut self) -> bool {
-        match self.peek().token_type {
-            TokenType::EOF => true,
-            _ => false
-        }
+        self.peek().token_type == TokenType::EOF
     }
     fn peek(&mut self) -> Token {
-        let index = self.current.clone();
-        return self.tokens[index as usize].clone();
+        let index = self.current as usize;
+        match self.tokens.get(index) {
+            Some(token) => token.clone(),
+            None => Token::new(TokenType::EOF, "\0".to_string())
+        }
+    }
+
+    fn peek_next(&mut self) -> Token {
+        match self.tokens.get(self.current as usize + 1) {
+            Some(token) => token.clone(),
+            None => Token::new(TokenType::EOF, "\0".to_string())
+        }
     }
 
     fn previous(&mut self) -> Token {
-        let index = self.current.clone();
-        return self.tokens[(index - 1) as usize].clone();
+        let index = self.current;
+        self.tokens[(index - 1) as usize].clone()
+    }
+    fn skip_newlines(&mut self) {
+        while self.match_token(vec![TokenType::NewLine]) {}
+    }
+    // Line breaks and comments inside brackets do not end the statement
+    fn skip_trivia(&mut self) {
+        while self.match_token(vec![TokenType::NewLine, TokenType::COMMENT]) {}
+    }
+    fn equality(&mut self) -> Result<Expr, DodoParseError> {
+        let mut expr = self.comparison()?;
+        while self.match_token(vec![TokenType::EqualEqual, TokenType::BangEqual]) {
+            let operator = self.previous();
+            self.deeper("Expression")?;
+            let right = self.comparison()?;
+            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+        }
+        Ok(expr)
     }
-    fn addition(&mut self) -> Expr {
-        let mut expr = self.multiplication();
+    fn comparison(&mut self) -> Result<Expr, DodoParseError> {
+        let mut expr = self.addition()?;
+        while self.match_token(vec![TokenType::LESS, TokenType::LessEqual, TokenType::GREATER, TokenType::GreaterEqual]) {
+            let operator = self.previous();
+            self.deeper("Expression")?;
+            let right = self.addition()?;
+            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+        }
+        Ok(expr)
+    }
+    fn addition(&mut self) -> Result<Expr, DodoParseError> {
+        let mut expr = self.multiplication()?;
         while self.match_token(vec![TokenType::MINUS, TokenType::PLUS]) {
             let operator = self.previous();
-            let right = self.multiplication();
+            self.deeper("Expression")?;
+            let right = self.multiplication()?;
             expr = Expr::Binary(Box::new(expr), operator, Box::new(right))
         }
-        return expr;
+        Ok(expr)
     }
-    fn multiplication(&mut self) -> Expr {
-        let mut expr = self.unary();
-        while self.match_token(vec![TokenType::ASTERISK, TokenType::SLASH]) {
+    fn multiplication(&mut self) -> Result<Expr, DodoParseError> {
+        let mut expr = self.unary()?;
+        while self.match_token(vec![TokenType::ASTERISK, TokenType::SLASH, TokenType::SlashSlash, TokenType::PERCENT]) {
             let operator = self.previous();
-            let right = self.unary();
+            self.deeper("Expression")?;
+            let right = self.unary()?;
             expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
         }
-        return expr;
+        Ok(expr)
     }
-    fn unary(&mut self) -> Expr {
+    fn unary(&mut self) -> Result<Expr, DodoParseError> {
         if self.match_token(vec![TokenType::MINUS, TokenType::BANG]) {
             let operator = self.previous();
-            let right = self.unary();
-            return Expr::Unary(operator, Box::new(right));
+            let right = self.nested("Expression", Self::unary)?;
+            return Ok(Expr::Unary(operator, Box::new(right)));
         }
-        return self.primary().unwrap();
+        self.power()
+    }
+    // Right-associative and tighter than unary minus, so `-2 ^ 2` is -4 and `2 ^ -1` is 0.5
+    fn power(&mut self) -> Result<Expr, DodoParseError> {
+        let expr = self.call()?;
+        if self.match_token(vec![TokenType::CARET, TokenType::DotCaret]) {
+            let operator = self.previous();
+            let right = self.nested("Expression", Self::unary)?;
+            return Ok(Expr::Binary(Box::new(expr), operator, Box::new(right)));
+        }
+        Ok(expr)
+    }
+    fn call(&mut self) -> Result<Expr, DodoParseError> {
+        let mut expr = self.primary()?;
+        while self.match_token(vec![TokenType::LeftParenthesis, TokenType::DOT]) {
+            self.deeper("Expression")?;
+            if self.previous().token_type == TokenType::DOT {
+                let dot = self.previous();
+                expr = match expr {
+                    Expr::Variable(namespace, _) => {
+                        let member = self.consume(TokenType::IDENT, "Expect name after '.'".to_string())?;
+                        Expr::Get(namespace, member)
+                    }
+                    _ => return Err(self.error(dot, "Only modules have members".to_string())),
+                };
+                continue;
+            }
+            let mut arguments = Vec::new();
+            self.skip_trivia();
+            if !self.check(TokenType::RightParenthesis) {
+                loop {
+                    arguments.push(self.expression()?);
+                    self.skip_trivia();
+                    if !self.match_token(vec![TokenType::COMMA]) {
+                        break;
+                    }
+                    self.skip_trivia();
+                }
+            }
+            self.consume(TokenType::RightParenthesis, "Expect ')' after arguments".to_string())?;
+            expr = Expr::FunctionCall(Box::new(expr), arguments);
+        }
+        Ok(expr)
     }
     fn primary(&mut self) -> Result<Expr, DodoParseError> {
         if self.match_token(vec![TokenType::IDENT]) {
             return Ok(Expr::Variable(self.previous(), "literal".to_string()));
         }
         if self.match_token(vec![TokenType::INT]) {
-            return Ok(Expr::Literal(self.previous().val.parse::<i128>().unwrap()));
+            let token = self.previous();
+            return match token.val.parse::<i128>() {
+                Ok(val) => Ok(Expr::Literal(val)),
+                Err(_) => Ok(Expr::Big(token.val)),
+            };
+        }
+        if self.match_token(vec![TokenType::FLOAT]) {
+            let token = self.previous();
+            return match token.val.parse::<f64>() {
+                Ok(val) => Ok(Expr::Float(val)),
+                Err(_) => Err(self.error(token, "Invalid float literal".to_string()))
+            };
+        }
+        if self.match_token(vec![TokenType::IMAGINARY]) {
+            let token = self.previous();
+            return match token.val.parse::<f64>() {
+                Ok(val) => Ok(Expr::Imaginary(val)),
+                Err(_) => Err(self.error(token, "Invalid imaginary literal".to_string()))
+            };
+        }
+        if self.match_token(vec![TokenType::STRING]) {
+            return Ok(Expr::Str(self.previous().val));
         }
         if self.match_token(vec![TokenType::LeftParenthesis]) {
-            let expr = self.expression();
-            self.consume(TokenType::RightParenthesis, "Expect )".to_string());
+            let expr = self.expression()?;
+            self.consume(TokenType::RightParenthesis, "Expect ')' after expression".to_string())?;
             return Ok(Expr::Grouping(Box::new(expr)));
         }
         if self.match_token(vec![TokenType::LeftBrace]) {
-            if self.peek().token_type == TokenType::LeftBrace {
-                let mut vector = Vec::new();
-                self.consume(TokenType::INT, "Expect )".to_string());
-                vector.push(self.previous().val.parse::<i128>().unwrap());
-                loop {
-                    if self.match_token(vec![TokenType::COMMA]) {
-                        continue;
-                    } else if self.match_token(vec![TokenType::INT]) {
-                        vector.push(self.previous().val.parse::<i128>().unwrap());
-                    } else if self.match_token(vec![TokenType::RightBrace]) {
-                        break;
-                    } else {
-                        return Err(DodoParseError);
-                    }
-                }
-            } else {
-                let token = self.previous();
-                println!("{}", token);
-                let mut vector = Vec::new();
-                self.consume(TokenType::INT, "Expect )".to_string());
-                vector.push(self.previous().val.parse::<i128>().unwrap());
-                loop {
-                    if self.match_token(vec![TokenType::COMMA]) {
-                        continue;
-                    } else if self.match_token(vec![TokenType::INT]) {
-                        vector.push(self.previous().val.parse::<i128>().unwrap());
-                    } else if self.match_token(vec![TokenType::RightBrace]) {
-                        break;
-                    } else {
-                        return Err(DodoParseError);
+            let token = self.previous();
+            self.skip_trivia();
+            if !self.check(TokenType::LeftBrace) {
+                let vector = self.literal_elements()?;
+                return Ok(Expr::Vector(token, vector));
+            }
+            let mut rows: Vec<Vec<Expr>> = Vec::new();
+            while self.match_token(vec![TokenType::LeftBrace]) {
+                let row = self.literal_elements()?;
+                if let Some(first) = rows.first() {
+                    if first.len() != row.len() {
+                        let brace = self.previous();
+                        return Err(self.error(brace, "Matrix rows must have the same length".to_string()));
                     }
                 }
-                return Ok(Expr::Vector(token, vector));
+                rows.push(row);
+                self.skip_trivia();
+                self.match_token(vec![TokenType::COMMA]);
+                self.skip_trivia();
             }
+            self.consume(TokenType::RightBrace, "Expect '}' after matrix rows".to_string())?;
+            return Ok(Expr::Matrix(token, rows));
         }
 
-        return Err(DodoParseError);
+        let token = self.peek();
+        Err(self.error(token, "Expect expression".to_string()))
+    }
+    // Elements of a brace literal up to and including the closing '}'; commas are optional
+    fn literal_elements(&mut self) -> Result<Vec<Expr>, DodoParseError> {
+        let mut elements = Vec::new();
+        loop {
+            self.skip_trivia();
+            if self.match_token(vec![TokenType::RightBrace]) {
+                return Ok(elements);
+            }
+            if self.at_end() {
+                let token = self.peek();
+                return Err(self.error(token, "Expect '}' after elements".to_string()));
+            }
+            elements.push(self.expression()?);
+            self.skip_trivia();
+            self.match_token(vec![TokenType::COMMA]);
+        }
     }
     fn consume(&mut self, token_type: TokenType, message: String) -> Result<Token, DodoParseError> {
         match self.check(token_type) {
-            true => return Ok(self.advance()),
-            _ => Err(DodoParseError)
+            true => Ok(self.advance()),
+            _ => {
+                let token = self.peek();
+                Err(self.error(token, message))
+            }
         }
     }
     fn error(&mut self, token: Token, message: String) -> DodoParseError {
-        throw_error(token, message);
-        return DodoParseError;
+        DodoParseError::new(token, message)
     }
     fn sync(&mut self) {
         self.advance();
@@ -241,9 +580,22 @@ impl Parser {
             }
             match self.peek().token_type {
                 TokenType::SCALAR => break,
+                TokenType::VECTOR => break,
                 TokenType::MATRIX => break,
+                TokenType::LET => break,
+                TokenType::CONST => break,
                 TokenType::FOR => break,
+                TokenType::WHILE => break,
+                TokenType::LOOP => break,
+                TokenType::BREAK => break,
+                TokenType::CONTINUE => break,
                 TokenType::PRINT => break,
+                TokenType::FUNCTION => break,
+                TokenType::RETURN => break,
+                TokenType::IMPORT => break,
+                TokenType::FROM => break,
+                TokenType::TEST => break,
+                TokenType::RightBrace => break,
                 _ => ()
             }
             self.advance();
@@ -258,7 +610,7 @@ mod tests {
     use crate::core::token::token::TokenType;
     use crate::core::lexer::lexer::Lexer;
     use crate::core::ast::expr::Expr;
-    use crate::core::ast::stmt::Stmt;
+    use crate::core::ast::stmt::{DeclaredType, Stmt};
 
     // #[test]
     // fn basic_operations() {
@@ -306,7 +658,143 @@ mod tests {
             }
         }
         let mut parser = Parser::new(tokens.clone());
-        let mut stmts = parser.parse();
+        let stmts = parser.parse();
         assert_eq!(stmts, vec![Stmt::Print(Expr::Literal(1))]);
     }
+
+    fn parse(input: &str) -> (Vec<Stmt>, usize) {
+        let mut tokens = Vec::new();
+        let mut lexer = Lexer::new(input.to_string());
+        loop {
+            let lexed_token = lexer.next_token();
+            let done = lexed_token.token_type == TokenType::EOF;
+            tokens.push(lexed_token);
+            if done {
+                break;
+            }
+        }
+        let mut parser = Parser::new(tokens);
+        let stmts = parser.parse();
+        (stmts, parser.errors().len())
+    }
+
+    #[test]
+    fn function_call_and_matrix_literal() {
+        let (stmts, errors) = parse("savenpy(\"a.npy\", {{1, 2},\n {3, 4}}, \"F\")\n");
+
+        let call = Expr::FunctionCall(
+            Box::new(Expr::Variable(Token::new(TokenType::IDENT, "savenpy".to_string()), "literal".to_string())),
+            vec![
+                Expr::Str("a.npy".to_string()),
+                Expr::Matrix(Token::new(TokenType::LeftBrace, "{".to_string()), vec![
+                    vec![Expr::Literal(1), Expr::Literal(2)],
+                    vec![Expr::Literal(3), Expr::Literal(4)],
+                ]),
+                Expr::Str("F".to_string()),
+            ],
+        );
+        assert_eq!(errors, 0);
+        assert_eq!(stmts, vec![Stmt::Expression(call)]);
+    }
+
+    #[test]
+    fn integer_literals_past_i128() {
+        let (stmts, errors) = parse("print 170141183460469231731687303715884105728 // 7\n");
+
+        assert_eq!(errors, 0);
+        let big = Expr::Big("170141183460469231731687303715884105728".to_string());
+        assert!(matches!(&stmts[0], Stmt::Print(Expr::Binary(left, _, _)) if **left == big));
+    }
+
+    #[test]
+    fn power_binds_tighter_than_minus_and_to_the_right() {
+        let (stmts, errors) = parse("print -2 ^ 3 ^ -1 .^ 2\n");
+
+        let token = |token_type, val: &str| Token::new(token_type, val.to_string());
+        let inner = Expr::Binary(Box::new(Expr::Literal(1)), token(TokenType::DotCaret, ".^"), Box::new(Expr::Literal(2)));
+        let exponent = Expr::Binary(Box::new(Expr::Literal(3)), token(TokenType::CARET, "^"), Box::new(Expr::Unary(token(TokenType::MINUS, "-"), Box::new(inner))));
+        let power = Expr::Binary(Box::new(Expr::Literal(2)), token(TokenType::CARET, "^"), Box::new(exponent));
+        assert_eq!(errors, 0);
+        assert_eq!(stmts, vec![Stmt::Print(Expr::Unary(token(TokenType::MINUS, "-"), Box::new(power)))]);
+    }
+
+    #[test]
+    fn comparisons_bind_looser_than_arithmetic() {
+        let (stmts, errors) = parse("print a + 1 < b == !c\n");
+
+        let token = |token_type, val: &str| Token::new(token_type, val.to_string());
+        let variable = |name: &str| Box::new(Expr::Variable(token(TokenType::IDENT, name), "literal".to_string()));
+        let sum = Expr::Binary(variable("a"), token(TokenType::PLUS, "+"), Box::new(Expr::Literal(1)));
+        let less = Expr::Binary(Box::new(sum), token(TokenType::LESS, "<"), variable("b"));
+        let not = Expr::Unary(token(TokenType::BANG, "!"), variable("c"));
+        assert_eq!(errors, 0);
+        assert_eq!(stmts, vec![Stmt::Print(Expr::Binary(Box::new(less), token(TokenType::EqualEqual, "=="), Box::new(not)))]);
+    }
+
+    #[test]
+    fn declaration_sizes_and_initialisers() {
+        let (stmts, errors) = parse("vector y[n * 2]\nscalar x = 5\nlet A = {{1, 2}}\nmatrix B[]\n");
+
+        let token = |token_type, val: &str| Token::new(token_type, val.to_string());
+        let n = Expr::Variable(token(TokenType::IDENT, "n"), "literal".to_string());
+        let size = Expr::Binary(Box::new(n), token(TokenType::ASTERISK, "*"), Box::new(Expr::Literal(2)));
+        let row = Expr::Matrix(token(TokenType::LeftBrace, "{"), vec![vec![Expr::Literal(1), Expr::Literal(2)]]);
+        assert_eq!(errors, 1);
+        assert_eq!(stmts, vec![
+            Stmt::Definition(token(TokenType::IDENT, "y"), DeclaredType::Vector, vec![size], None),
+            Stmt::Definition(token(TokenType::IDENT, "x"), DeclaredType::Scalar, Vec::new(), Some(Expr::Literal(5))),
+            Stmt::Definition(token(TokenType::IDENT, "A"), DeclaredType::Inferred, Vec::new(), Some(row)),
+        ]);
+    }
+
+    #[test]
+    fn labelled_loops_and_jumps() {
+        let (stmts, errors) = parse("outer: loop {\n    while n {\n        continue outer\n    }\n    break\n}\nlabel: print 1\n");
+
+        let token = |token_type, val: &str| Token::new(token_type, val.to_string());
+        let n = Expr::Variable(token(TokenType::IDENT, "n"), "literal".to_string());
+        let jump = Stmt::Continue(token(TokenType::CONTINUE, "continue"), Some(token(TokenType::IDENT, "outer")));
+        let inner = match &stmts[0] {
+            Stmt::Loop(Some(label), body) if label.val == "outer" => body.statements.clone(),
+            other => panic!("expected a labelled loop, got {}", other),
+        };
+        assert_eq!(errors, 1);
+        assert_eq!(stmts.len(), 1);
+        assert!(matches!(&inner[0], Stmt::While(None, condition, body) if *condition == n && body.statements == vec![jump.clone()]));
+        assert_eq!(inner[1], Stmt::Break(token(TokenType::BREAK, "break"), None));
+    }
+
+    #[test]
+    fn imports_and_members() {
+        let (stmts, errors) = parse("import \"linalg_utils.dodo\" as lu\nfrom \"utils.dodo\" import normalize, EPS\nprint lu.solve(A).x\nimport \"x.dodo\"\nprint (1).x\n");
+
+        let token = |token_type, val: &str| Token::new(token_type, val.to_string());
+        let a = Expr::Variable(token(TokenType::IDENT, "A"), "literal".to_string());
+        let solve = Expr::Get(token(TokenType::IDENT, "lu"), token(TokenType::IDENT, "solve"));
+        assert_eq!(errors, 3);
+        assert_eq!(stmts, vec![
+            Stmt::Import(token(TokenType::STRING, "linalg_utils.dodo"), token(TokenType::IDENT, "lu")),
+            Stmt::FromImport(token(TokenType::STRING, "utils.dodo"), vec![token(TokenType::IDENT, "normalize"), token(TokenType::IDENT, "EPS")]),
+        ]);
+        assert_eq!(parse("print lu.solve(A)\n").0, vec![Stmt::Print(Expr::FunctionCall(Box::new(solve), vec![a]))]);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let (stmts, errors) = parse("test \"adds\" {\n    assert_eq(1 + 1, 2)\n}\ntest adds {\n}\n");
+
+        let token = |token_type, val: &str| Token::new(token_type, val.to_string());
+        // The missing name, then the stray block after it
+        assert_eq!(errors, 2);
+        assert!(matches!(&stmts[0], Stmt::Test(name, body) if *name == token(TokenType::STRING, "adds") && body.statements.len() == 1));
+        assert_eq!(stmts.len(), 1);
+    }
+
+    #[test]
+    fn recovers_after_error() {
+        let (stmts, errors) = parse("print (1\n\nprint 2.5\n{{1, 2}, {3}}\n");
+
+        assert_eq!(errors, 2);
+        assert_eq!(stmts, vec![Stmt::Print(Expr::Float(2.5))]);
+    }
 }
\ No newline at end of file
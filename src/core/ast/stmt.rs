@@ -1,20 +1,79 @@
 use crate::core::token::token::Token;
+use crate::core::ast::ast::Span;
 use crate::core::ast::expr::Expr;
 use std::fmt;
 
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Stmt {
-    Definition(Token, String, String),
+    // Name, declared type, size expressions and the initial value, if any
+    Definition(Token, DeclaredType, Vec<Expr>, Option<Expr>),
     Expression(Expr),
     Print(Expr),
     FOR(Expr, Box<Stmt>),
-    Function(Token, Vec<Token>, Vec<Stmt>),
+    // Optional label, condition and body
+    While(Option<Token>, Expr, Block),
+    Loop(Option<Token>, Block),
+    // The keyword and the label of the loop it leaves or continues, if given
+    Break(Token, Option<Token>),
+    Continue(Token, Option<Token>),
+    Function(Token, Vec<Token>, Block),
+    Return(Token, Option<Expr>),
+    // `import "file" as name`: the file as written and the namespace its globals are bound to
+    Import(Token, Token),
+    // `from "file" import a, b`: the file as written and the names bound from it
+    FromImport(Token, Vec<Token>),
+    // `test "name" { ... }`: skipped when the program runs, and run on its own by `dodolang test`
+    Test(Token, Block),
     Comment(String),
 }
 
+/// What a declaration says about its variable. `let` and `const` take the type of
+/// their value and cannot be assigned to; a constant's value is known before the program runs.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DeclaredType {
+    Scalar,
+    Vector,
+    Matrix,
+    Inferred,
+    Constant,
+}
+
+impl DeclaredType {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            DeclaredType::Scalar => "scalar",
+            DeclaredType::Vector => "vector",
+            DeclaredType::Matrix => "matrix",
+            DeclaredType::Inferred => "let",
+            DeclaredType::Constant => "const",
+        }
+    }
+
+    pub fn is_mutable(&self) -> bool {
+        !matches!(self, DeclaredType::Inferred | DeclaredType::Constant)
+    }
+}
+
+// Statements between braces, with the source lines of each one
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Block {
+    pub statements: Vec<Stmt>,
+    pub spans: Vec<Span>,
+}
+
+impl Stmt {
+    /// The statements nested inside a function, loop or test.
+    pub fn body(&self) -> Option<&Block> {
+        match self {
+            Stmt::Function(_, _, body) | Stmt::While(_, _, body) | Stmt::Loop(_, body) | Stmt::Test(_, body) => Some(body),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for Stmt {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self)
     }
-}
\ No newline at end of file
+}
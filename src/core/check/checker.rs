@@ -0,0 +1,1074 @@
+use crate::core::ast::ast::{Program, Span};
+use crate::core::ast::expr::Expr;
+use crate::core::ast::stmt::{Block, DeclaredType, Stmt};
+use crate::core::dodo::builtins;
+use crate::core::dodo::error_types::DodoTypeError;
+use crate::core::dodo::modules::Module;
+use crate::core::token::token::{Token, TokenType};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// A dimension is either known or a symbol standing for a size only known at runtime.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Dim {
+    Known(usize),
+    Symbol(usize),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Type {
+    Scalar,
+    Vector(Dim),
+    Matrix(Dim, Dim),
+    Str,
+    Nil,
+    // User-defined function taking this many arguments
+    Function(usize),
+    Unknown,
+}
+
+/// Infers types and shapes for a program before it runs. Dense and sparse
+/// matrices share the matrix type since every operation treats them alike.
+/// State carries over between calls to `check`, so the REPL can check line by line.
+#[derive(Default)]
+pub struct Checker {
+    scope: HashMap<String, Type>,
+    // Globals declared by statements checked so far, as opposed to functions hoisted by `check`
+    declared: HashSet<String>,
+    // Parameters and locals of the function bodies being checked
+    locals: Vec<HashMap<String, Type>>,
+    // Names bound by `let` or `const`, which cannot be assigned, among the globals and the locals
+    immutable: HashMap<String, DeclaredType>,
+    local_immutable: Vec<HashMap<String, DeclaredType>>,
+    // Labels of the loops around the statement being checked, innermost last
+    loops: Vec<Option<String>>,
+    // Files loaded for the import statements checked, by the name they are imported with
+    imports: HashMap<String, Rc<Module>>,
+    // Modules bound by `import ... as`, or none when the file was not loaded and its members are unknown
+    namespaces: HashMap<String, Option<Rc<Module>>>,
+    // Names of the test blocks checked so far
+    tests: HashSet<String>,
+    // Symbol id to the dimension it has been unified with
+    bindings: HashMap<usize, Dim>,
+    symbol_names: Vec<String>,
+    named_symbols: HashMap<String, usize>,
+    errors: Vec<DodoTypeError>,
+    line: usize,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Checker::default()
+    }
+
+    pub fn check(&mut self, program: &Program) -> Vec<DodoTypeError> {
+        // Functions are visible before their definition so they can call each other
+        for stmt in program.statements.iter() {
+            if let Stmt::Function(name, params, _) = stmt {
+                self.scope.insert(name.val.clone(), Type::Function(params.len()));
+            }
+        }
+        self.imports.extend(program.imports.iter().map(|(name, module)| (name.clone(), module.clone())));
+        self.statements(&program.statements, &program.spans);
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Type of a variable as currently known to the checker.
+    pub fn variable_type(&self, name: &str) -> Option<&Type> {
+        self.locals.last()
+            .and_then(|locals| locals.get(name))
+            .or_else(|| self.scope.get(name))
+    }
+
+    /// Types of the globals, which a file importing this program can use; sizes known
+    /// only at runtime are left as symbols for the importing checker to replace.
+    pub fn exports(&self) -> HashMap<String, Type> {
+        self.scope.iter()
+            .map(|(name, ty)| {
+                let ty = match ty {
+                    Type::Vector(len) => Type::Vector(self.resolve(*len)),
+                    Type::Matrix(rows, cols) => Type::Matrix(self.resolve(*rows), self.resolve(*cols)),
+                    other => other.clone(),
+                };
+                (name.clone(), ty)
+            })
+            .collect()
+    }
+
+    // Type of a global exported by another program, with its symbols replaced by fresh ones
+    fn imported(&mut self, ty: &Type) -> Type {
+        let mut dim = |dim: Dim| match dim {
+            Dim::Known(_) => dim,
+            Dim::Symbol(_) => self.fresh_symbol(),
+        };
+        match ty {
+            Type::Vector(len) => Type::Vector(dim(*len)),
+            Type::Matrix(rows, cols) => Type::Matrix(dim(*rows), dim(*cols)),
+            other => other.clone(),
+        }
+    }
+
+    // Adds a name to the innermost scope unless it is already declared there
+    fn declare(&mut self, name: &Token, ty: Type) -> bool {
+        let redeclared = match self.locals.last() {
+            Some(locals) => locals.contains_key(&name.val),
+            None => !self.declared.insert(name.val.clone()),
+        };
+        if redeclared {
+            self.error(Some(name.clone()), format!("'{}' is already declared in this scope", name.val));
+            return false;
+        }
+        match self.locals.last_mut() {
+            Some(locals) => locals.insert(name.val.clone(), ty),
+            None => self.scope.insert(name.val.clone(), ty),
+        };
+        true
+    }
+
+    // How a name that cannot be assigned was bound, for the declaration a use of it refers to
+    fn binding(&self, name: &str) -> Option<DeclaredType> {
+        match self.locals.last() {
+            Some(locals) if locals.contains_key(name) => self.local_immutable.last().and_then(|immutable| immutable.get(name)).copied(),
+            _ if self.scope.contains_key(name) => self.immutable.get(name).copied(),
+            _ => builtins::constant(name).map(|_| DeclaredType::Constant),
+        }
+    }
+
+    // Whether an expression only combines literals and constants
+    fn is_constant(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Literal(_) | Expr::Big(_) | Expr::Float(_) | Expr::Imaginary(_) | Expr::Str(_) => true,
+            Expr::Grouping(inner) | Expr::Unary(_, inner) => self.is_constant(inner),
+            Expr::Binary(left, _, right) => self.is_constant(left) && self.is_constant(right),
+            Expr::Vector(_, elements) => elements.iter().all(|element| self.is_constant(element)),
+            Expr::Matrix(_, rows) => rows.iter().flatten().all(|element| self.is_constant(element)),
+            Expr::Variable(token, _) => self.binding(&token.val) == Some(DeclaredType::Constant),
+            _ => false,
+        }
+    }
+
+    fn kind(ty: &Type) -> &'static str {
+        match ty {
+            Type::Scalar => "scalar",
+            Type::Vector(_) => "vector",
+            Type::Matrix(_, _) => "matrix",
+            Type::Str => "string",
+            Type::Nil => "nil",
+            Type::Function(_) => "function",
+            Type::Unknown => "unknown",
+        }
+    }
+
+    pub fn describe(&self, ty: &Type) -> String {
+        match ty {
+            Type::Scalar => "scalar".to_string(),
+            Type::Vector(len) => format!("vector[{}]", self.dim_name(*len)),
+            Type::Matrix(rows, cols) => format!("matrix[{}, {}]", self.dim_name(*rows), self.dim_name(*cols)),
+            Type::Str => "string".to_string(),
+            Type::Nil => "nil".to_string(),
+            Type::Function(arity) => format!("function({})", arity),
+            Type::Unknown => "unknown".to_string(),
+        }
+    }
+
+    fn dim_name(&self, dim: Dim) -> String {
+        match self.resolve(dim) {
+            Dim::Known(size) => size.to_string(),
+            Dim::Symbol(id) => self.symbol_names[id].clone(),
+        }
+    }
+
+    fn resolve(&self, dim: Dim) -> Dim {
+        let mut dim = dim;
+        while let Dim::Symbol(id) = dim {
+            match self.bindings.get(&id) {
+                Some(bound) => dim = *bound,
+                None => break,
+            }
+        }
+        dim
+    }
+
+    fn unify(&mut self, left: Dim, right: Dim) -> bool {
+        match (self.resolve(left), self.resolve(right)) {
+            (Dim::Known(a), Dim::Known(b)) => a == b,
+            (Dim::Symbol(a), Dim::Symbol(b)) if a == b => true,
+            (Dim::Symbol(id), other) | (other, Dim::Symbol(id)) => {
+                self.bindings.insert(id, other);
+                true
+            }
+        }
+    }
+
+    fn fresh_symbol(&mut self) -> Dim {
+        self.symbol_names.push(format!("?{}", self.symbol_names.len() + 1));
+        Dim::Symbol(self.symbol_names.len() - 1)
+    }
+
+    fn named_symbol(&mut self, name: &str) -> Dim {
+        if let Some(id) = self.named_symbols.get(name) {
+            return Dim::Symbol(*id);
+        }
+        self.symbol_names.push(name.to_string());
+        self.named_symbols.insert(name.to_string(), self.symbol_names.len() - 1);
+        Dim::Symbol(self.symbol_names.len() - 1)
+    }
+
+    // Dimension denoted by a size argument such as the `n` in `sparse(n, n, ...)`
+    fn dim_of(&mut self, expr: &Expr) -> Dim {
+        match expr {
+            Expr::Literal(size) if *size >= 0 => Dim::Known(*size as usize),
+            Expr::Grouping(inner) => self.dim_of(inner),
+            Expr::Variable(token, _) if self.variable_type(&token.val) == Some(&Type::Scalar) => self.named_symbol(&token.val),
+            _ => self.fresh_symbol(),
+        }
+    }
+
+    fn error(&mut self, token: Option<Token>, message: String) {
+        let token = token.unwrap_or_else(|| Token::at(TokenType::ILLEGAL, String::new(), self.line, 0));
+        self.errors.push(DodoTypeError::new(token, message));
+    }
+
+    fn compatible(&mut self, target: &Type, value: &Type) -> bool {
+        match (target, value) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (Type::Vector(a), Type::Vector(b)) => self.unify(*a, *b),
+            (Type::Matrix(r1, c1), Type::Matrix(r2, c2)) => {
+                let rows = self.unify(*r1, *r2);
+                self.unify(*c1, *c2) && rows
+            }
+            _ => target == value,
+        }
+    }
+
+    fn statements(&mut self, statements: &[Stmt], spans: &[Span]) {
+        for (stmt, span) in statements.iter().zip(spans.iter()) {
+            self.line = span.line;
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Definition(name, declared, dims, initial) => {
+                for dim in dims {
+                    let ty = self.expr(dim);
+                    if !matches!(ty, Type::Scalar | Type::Unknown) {
+                        let message = format!("Size of '{}' must be a scalar, got {}", name.val, self.describe(&ty));
+                        self.error(Some(name.clone()), message);
+                    }
+                }
+                let sizes: Vec<Dim> = dims.iter().map(|dim| self.dim_of(dim)).collect();
+                let expected = match declared {
+                    DeclaredType::Scalar => Some(Type::Scalar),
+                    DeclaredType::Vector => Some(Type::Vector(sizes[0])),
+                    DeclaredType::Matrix => Some(Type::Matrix(sizes[0], sizes[1])),
+                    DeclaredType::Inferred | DeclaredType::Constant => None,
+                };
+                if let (DeclaredType::Constant, Some(value)) = (declared, initial) {
+                    if !self.is_constant(value) {
+                        self.error(Some(name.clone()), format!("Value of constant '{}' must be known before the program runs", name.val));
+                    }
+                }
+                let ty = match (expected, initial) {
+                    // `let` and `const` take the type of their value
+                    (None, Some(value)) => self.expr(value),
+                    (Some(expected), Some(value)) => {
+                        let value = self.expr(value);
+                        // Described before unifying, which may bind some of its sizes
+                        let description = self.describe(&expected);
+                        if !self.compatible(&expected, &value) {
+                            let message = format!("Cannot initialise {} '{}' with {}", description, name.val, self.describe(&value));
+                            self.error(Some(name.clone()), message);
+                        }
+                        expected
+                    }
+                    (expected, None) => expected.unwrap_or(Type::Unknown),
+                };
+                if self.declare(name, ty) && !declared.is_mutable() {
+                    let immutable = match self.local_immutable.last_mut() {
+                        Some(immutable) => immutable,
+                        None => &mut self.immutable,
+                    };
+                    immutable.insert(name.val.clone(), *declared);
+                }
+            }
+            Stmt::Expression(expr) | Stmt::Print(expr) => {
+                self.expr(expr);
+            }
+            Stmt::Function(name, params, body) => {
+                self.declare(name, Type::Function(params.len()));
+                self.locals.push(params.iter().map(|param| (param.val.clone(), Type::Unknown)).collect());
+                self.local_immutable.push(HashMap::new());
+                // A function body cannot break out of the loops around its definition
+                let loops = std::mem::take(&mut self.loops);
+                self.statements(&body.statements, &body.spans);
+                self.loops = loops;
+                self.local_immutable.pop();
+                self.locals.pop();
+            }
+            Stmt::Test(name, body) => {
+                if !self.locals.is_empty() || !self.loops.is_empty() {
+                    self.error(Some(name.clone()), "Tests are only allowed at the top level".to_string());
+                } else if !self.tests.insert(name.val.clone()) {
+                    self.error(Some(name.clone()), format!("Duplicate test \"{}\"", name.val));
+                }
+                // A test body has its own locals, like a function body without parameters
+                self.locals.push(HashMap::new());
+                self.local_immutable.push(HashMap::new());
+                let loops = std::mem::take(&mut self.loops);
+                self.statements(&body.statements, &body.spans);
+                self.loops = loops;
+                self.local_immutable.pop();
+                self.locals.pop();
+            }
+            Stmt::While(label, condition, body) => {
+                let ty = self.expr(condition);
+                if !matches!(ty, Type::Scalar | Type::Unknown) {
+                    let token = Token::at(TokenType::WHILE, "while".to_string(), self.line, 0);
+                    self.error(Some(token), format!("Loop condition must be a scalar, got {}", self.describe(&ty)));
+                }
+                self.loop_body(label, body);
+            }
+            Stmt::Loop(label, body) => self.loop_body(label, body),
+            Stmt::Break(keyword, label) | Stmt::Continue(keyword, label) => {
+                let message = match label {
+                    _ if self.loops.is_empty() => format!("'{}' outside of a loop", keyword.val),
+                    Some(label) if !self.loops.contains(&Some(label.val.clone())) => format!("No loop labelled '{}' around '{}'", label.val, keyword.val),
+                    _ => return,
+                };
+                self.error(Some(keyword.clone()), message);
+            }
+            Stmt::Import(file, namespace) => {
+                self.top_level_import(file);
+                let module = self.imports.get(&file.val).cloned();
+                if self.namespaces.contains_key(&namespace.val) || self.scope.contains_key(&namespace.val) {
+                    self.error(Some(namespace.clone()), format!("'{}' is already declared in this scope", namespace.val));
+                    return;
+                }
+                self.declared.insert(namespace.val.clone());
+                self.namespaces.insert(namespace.val.clone(), module);
+            }
+            Stmt::FromImport(file, names) => {
+                self.top_level_import(file);
+                let module = self.imports.get(&file.val).cloned();
+                for name in names {
+                    let ty = match module.as_ref().map(|module| module.exports.get(&name.val)) {
+                        Some(Some(ty)) => self.imported(ty),
+                        Some(None) => {
+                            self.error(Some(name.clone()), format!("'{}' does not define '{}'", file.val, name.val));
+                            Type::Unknown
+                        }
+                        None => Type::Unknown,
+                    };
+                    self.declare(name, ty);
+                }
+            }
+            Stmt::Return(keyword, value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+                if self.locals.is_empty() {
+                    self.error(Some(keyword.clone()), "Cannot return from top-level code".to_string());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn top_level_import(&mut self, file: &Token) {
+        if !self.locals.is_empty() || !self.loops.is_empty() {
+            self.error(Some(file.clone()), "Imports are only allowed at the top level".to_string());
+        }
+    }
+
+    fn loop_body(&mut self, label: &Option<Token>, body: &Block) {
+        self.loops.push(label.as_ref().map(|label| label.val.clone()));
+        self.statements(&body.statements, &body.spans);
+        self.loops.pop();
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal(_) | Expr::Big(_) | Expr::Float(_) | Expr::Imaginary(_) => Type::Scalar,
+            Expr::Str(_) => Type::Str,
+            Expr::Grouping(inner) => self.expr(inner),
+            Expr::Vector(token, elements) => {
+                self.scalar_elements(token, elements);
+                Type::Vector(Dim::Known(elements.len()))
+            }
+            Expr::Matrix(token, rows) => {
+                for row in rows.iter() {
+                    self.scalar_elements(token, row);
+                }
+                Type::Matrix(Dim::Known(rows.len()), Dim::Known(rows.first().map_or(0, Vec::len)))
+            }
+            Expr::Variable(token, _) => match self.variable_type(&token.val) {
+                Some(ty) => ty.clone(),
+                None if builtins::constant(&token.val).is_some() => Type::Scalar,
+                None => {
+                    self.error(Some(token.clone()), format!("Undeclared variable '{}'", token.val));
+                    Type::Unknown
+                }
+            },
+            Expr::Assign(token, value, _) => {
+                let value = self.expr(value);
+                if let Some(declared) = self.binding(&token.val) {
+                    let message = match declared {
+                        DeclaredType::Constant => format!("Cannot assign to constant '{}'", token.val),
+                        _ => format!("Cannot assign to '{}', which is bound with let", token.val),
+                    };
+                    self.error(Some(token.clone()), message);
+                    return self.variable_type(&token.val).cloned().unwrap_or(Type::Scalar);
+                }
+                match self.variable_type(&token.val).cloned() {
+                    Some(target) => {
+                        if !self.compatible(&target, &value) {
+                            let message = format!("Cannot assign {} to '{}' declared as {}", self.describe(&value), token.val, self.describe(&target));
+                            self.error(Some(token.clone()), message);
+                        }
+                        target
+                    }
+                    None => {
+                        self.error(Some(token.clone()), format!("Assignment to undeclared variable '{}'", token.val));
+                        value
+                    }
+                }
+            }
+            Expr::Unary(operator, right) => {
+                let right = self.expr(right);
+                if !matches!(operator.token_type, TokenType::MINUS | TokenType::BANG) || matches!(right, Type::Str | Type::Nil | Type::Function(_)) {
+                    let message = format!("Unsupported operand {} for unary '{}'", self.describe(&right), operator.val);
+                    self.error(Some(operator.clone()), message);
+                    return Type::Unknown;
+                }
+                right
+            }
+            Expr::Binary(left, operator, right) => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                self.binary(left, operator, right)
+            }
+            Expr::Get(namespace, member) => {
+                let module = match self.namespaces.get(&namespace.val) {
+                    Some(Some(module)) => module.clone(),
+                    Some(None) => return Type::Unknown,
+                    None => {
+                        self.error(Some(namespace.clone()), format!("Undeclared module '{}'", namespace.val));
+                        return Type::Unknown;
+                    }
+                };
+                match module.exports.get(&member.val) {
+                    Some(ty) => self.imported(ty),
+                    None => {
+                        self.error(Some(member.clone()), format!("Module '{}' does not define '{}'", namespace.val, member.val));
+                        Type::Unknown
+                    }
+                }
+            }
+            Expr::FunctionCall(callee, args) => self.call(callee, args),
+            _ => Type::Unknown,
+        }
+    }
+
+    fn scalar_elements(&mut self, token: &Token, elements: &[Expr]) {
+        for element in elements {
+            let ty = self.expr(element);
+            if !matches!(ty, Type::Scalar | Type::Unknown) {
+                let message = format!("Literal elements must be scalars, got {}", self.describe(&ty));
+                self.error(Some(token.clone()), message);
+            }
+        }
+    }
+
+    // Mirrors the shape rules of `Interpreter::evaluate_binary`
+    fn binary(&mut self, left: Type, operator: &Token, right: Type) -> Type {
+        let op = operator.token_type;
+        let mismatch = |checker: &mut Checker, left: &Type, right: &Type| {
+            let message = format!("Shape mismatch for '{}': {} and {}", operator.val, checker.describe(left), checker.describe(right));
+            checker.error(Some(operator.clone()), message);
+            Type::Unknown
+        };
+        match (&left, &right) {
+            (Type::Str, Type::Str) if op == TokenType::EqualEqual || op == TokenType::BangEqual => Type::Scalar,
+            (Type::Str, _) | (_, Type::Str) | (Type::Nil, _) | (_, Type::Nil) | (Type::Function(_), _) | (_, Type::Function(_)) => {
+                let message = format!("Unsupported operand types for '{}': {} and {}", operator.val, self.describe(&left), self.describe(&right));
+                self.error(Some(operator.clone()), message);
+                Type::Unknown
+            }
+            (Type::Unknown, _) | (_, Type::Unknown) => Type::Unknown,
+            (Type::Scalar, Type::Scalar) => Type::Scalar,
+            (Type::Matrix(rows, cols), Type::Scalar) if op == TokenType::CARET => match self.unify(*rows, *cols) {
+                true => left.clone(),
+                false => {
+                    self.error(Some(operator.clone()), format!("Matrix power expects a square matrix, got {}", self.describe(&left)));
+                    Type::Unknown
+                }
+            },
+            _ if op == TokenType::CARET => {
+                let message = format!("'^' expects a square matrix and an integer power, got {} and {}; use '.^' for element-wise powers", self.describe(&left), self.describe(&right));
+                self.error(Some(operator.clone()), message);
+                Type::Unknown
+            }
+            (Type::Vector(a), Type::Vector(b)) if op == TokenType::ASTERISK => match self.unify(*a, *b) {
+                true => Type::Scalar,
+                false => mismatch(self, &left, &right),
+            },
+            (Type::Matrix(r1, c1), Type::Matrix(r2, c2)) if op == TokenType::ASTERISK => match self.unify(*c1, *r2) {
+                true => Type::Matrix(*r1, *c2),
+                false => mismatch(self, &left, &right),
+            },
+            (Type::Matrix(rows, cols), Type::Vector(len)) if op == TokenType::ASTERISK => match self.unify(*cols, *len) {
+                true => Type::Vector(*rows),
+                false => mismatch(self, &left, &right),
+            },
+            (Type::Vector(len), Type::Matrix(rows, cols)) if op == TokenType::ASTERISK => match self.unify(*len, *rows) {
+                true => Type::Vector(*cols),
+                false => mismatch(self, &left, &right),
+            },
+            _ => match self.broadcast(&left, &right) {
+                Some(ty) => ty,
+                None => mismatch(self, &left, &right),
+            },
+        }
+    }
+
+    // Mirrors `value::broadcast`: a vector lines up with the columns of a
+    // matrix and a dimension of length 1 stretches to match the other one
+    fn broadcast(&mut self, left: &Type, right: &Type) -> Option<Type> {
+        match (left, right) {
+            (Type::Unknown, _) | (_, Type::Unknown) => Some(Type::Unknown),
+            (Type::Scalar, other) | (other, Type::Scalar) => Some(other.clone()),
+            (Type::Vector(a), Type::Vector(b)) => self.stretch(*a, *b).map(Type::Vector),
+            (Type::Matrix(rows, cols), Type::Vector(len)) | (Type::Vector(len), Type::Matrix(rows, cols)) => {
+                self.stretch(*cols, *len).map(|cols| Type::Matrix(*rows, cols))
+            }
+            (Type::Matrix(r1, c1), Type::Matrix(r2, c2)) => {
+                let rows = self.stretch(*r1, *r2);
+                let cols = self.stretch(*c1, *c2);
+                Some(Type::Matrix(rows?, cols?))
+            }
+            _ => None,
+        }
+    }
+
+    fn stretch(&mut self, a: Dim, b: Dim) -> Option<Dim> {
+        match (self.resolve(a), self.resolve(b)) {
+            (Dim::Known(1), _) => Some(b),
+            (_, Dim::Known(1)) => Some(a),
+            _ if self.unify(a, b) => Some(a),
+            _ => None,
+        }
+    }
+
+    fn call(&mut self, callee: &Expr, args: &[Expr]) -> Type {
+        let (token, callee) = match callee {
+            Expr::Variable(token, _) => (token.clone(), self.variable_type(&token.val).cloned()),
+            Expr::Get(_, member) => (member.clone(), Some(self.expr(callee))),
+            _ => {
+                self.expr(callee);
+                self.error(None, "Can only call functions".to_string());
+                return Type::Unknown;
+            }
+        };
+        let arg_types: Vec<Type> = args.iter().map(|arg| self.expr(arg)).collect();
+        match callee {
+            Some(Type::Function(arity)) => {
+                if args.len() != arity {
+                    self.error(Some(token.clone()), format!("{} expects {} arguments, got {}", token.val, arity, args.len()));
+                }
+                return Type::Unknown;
+            }
+            Some(Type::Unknown) => return Type::Unknown,
+            Some(other) => {
+                let message = format!("Cannot call a {}", Checker::kind(&other));
+                self.error(Some(token.clone()), message);
+                return Type::Unknown;
+            }
+            None => (),
+        }
+        let builtin = match builtins::lookup(&token.val) {
+            Some(builtin) => builtin,
+            None => {
+                self.error(Some(token.clone()), format!("Undefined function '{}'", token.val));
+                return Type::Unknown;
+            }
+        };
+        if args.len() < builtin.min_arity || args.len() > builtin.max_arity {
+            self.error(Some(token.clone()), format!("{} expects {} arguments, got {}", builtin.name, builtin.arity(), args.len()));
+            return Type::Unknown;
+        }
+        self.builtin_type(&token, args, &arg_types)
+    }
+
+    fn expect(&mut self, token: &Token, arg_types: &[Type], index: usize, expected: Type) {
+        let ty = &arg_types[index];
+        if *ty != Type::Unknown && Checker::kind(ty) != Checker::kind(&expected) {
+            let message = format!("{} expects a {} as argument {}, got {}", token.val, Checker::kind(&expected), index + 1, self.describe(ty));
+            self.error(Some(token.clone()), message);
+        }
+    }
+
+    // A reduction gives a scalar, or along the axis at `axis_index` one element per column (0) or row (1)
+    fn reduction_type(&mut self, token: &Token, args: &[Expr], arg_types: &[Type], axis_index: usize) -> Type {
+        if axis_index == 2 && !matches!(arg_types.get(1), None | Some(Type::Vector(_)) | Some(Type::Unknown)) {
+            self.error(Some(token.clone()), format!("{} expects {{}} as argument 2 when given an axis", token.val));
+        }
+        if !matches!(arg_types[0], Type::Scalar | Type::Vector(_) | Type::Matrix(_, _) | Type::Unknown) {
+            let message = format!("{} expects a number, vector or matrix, got {}", token.val, self.describe(&arg_types[0]));
+            self.error(Some(token.clone()), message);
+            return Type::Unknown;
+        }
+        if args.len() <= axis_index {
+            return Type::Scalar;
+        }
+        self.expect(token, arg_types, axis_index, Type::Scalar);
+        match (&arg_types[0], &args[axis_index]) {
+            (Type::Matrix(_, cols), Expr::Literal(0)) => Type::Vector(*cols),
+            (Type::Matrix(rows, _), Expr::Literal(1)) => Type::Vector(*rows),
+            (_, Expr::Literal(axis)) if *axis != 0 && *axis != 1 => {
+                self.error(Some(token.clone()), format!("{} expects axis 0 or 1, got {}", token.val, axis));
+                Type::Unknown
+            }
+            (Type::Matrix(_, _), _) => Type::Vector(self.fresh_symbol()),
+            (Type::Unknown, _) => Type::Unknown,
+            _ => Type::Scalar,
+        }
+    }
+
+    // A scalar, vector or matrix sized by the arguments from `first` on
+    fn sized_type(&mut self, token: &Token, args: &[Expr], arg_types: &[Type], first: usize) -> Type {
+        for index in first..args.len() {
+            self.expect(token, arg_types, index, Type::Scalar);
+        }
+        match &args[first.min(args.len())..] {
+            [] => Type::Scalar,
+            [n] => Type::Vector(self.dim_of(n)),
+            [rows, cols, ..] => Type::Matrix(self.dim_of(rows), self.dim_of(cols)),
+        }
+    }
+
+    fn builtin_type(&mut self, token: &Token, args: &[Expr], arg_types: &[Type]) -> Type {
+        let vector = Type::Vector(Dim::Known(0));
+        let matrix = Type::Matrix(Dim::Known(0), Dim::Known(0));
+        match token.val.as_str() {
+            "loadnpy" | "loadmtx" => {
+                self.expect(token, arg_types, 0, Type::Str);
+                Type::Unknown
+            }
+            "loadnpz" => {
+                self.expect(token, arg_types, 0, Type::Str);
+                self.expect(token, arg_types, 1, Type::Str);
+                Type::Unknown
+            }
+            "savenpy" | "savemtx" | "savenpz" => {
+                self.expect(token, arg_types, 0, Type::Str);
+                Type::Nil
+            }
+            "sparse" if args.len() == 5 => {
+                self.expect(token, arg_types, 0, Type::Scalar);
+                self.expect(token, arg_types, 1, Type::Scalar);
+                for index in 2..5 {
+                    self.expect(token, arg_types, index, vector.clone());
+                }
+                if let (Type::Vector(i), Type::Vector(j), Type::Vector(v)) = (&arg_types[2], &arg_types[3], &arg_types[4]) {
+                    if !self.unify(*i, *j) || !self.unify(*i, *v) {
+                        self.error(Some(token.clone()), "sparse expects i, j and v to have the same length".to_string());
+                    }
+                }
+                Type::Matrix(self.dim_of(&args[0]), self.dim_of(&args[1]))
+            }
+            "sparse" if args.len() == 1 => {
+                self.expect(token, arg_types, 0, matrix);
+                arg_types[0].clone()
+            }
+            "sparse" => {
+                self.error(Some(token.clone()), format!("sparse expects 1 or 5 arguments, got {}", args.len()));
+                Type::Unknown
+            }
+            "dense" => {
+                self.expect(token, arg_types, 0, matrix);
+                arg_types[0].clone()
+            }
+            "nnz" => {
+                self.expect(token, arg_types, 0, matrix);
+                Type::Scalar
+            }
+            "re" | "im" | "abs" | "arg" | "conj" | "sin" | "cos" | "tan" | "exp" | "log" | "sqrt" | "floor" | "ceil" | "round" | "sign" => match &arg_types[0] {
+                Type::Scalar | Type::Vector(_) | Type::Matrix(_, _) | Type::Unknown => arg_types[0].clone(),
+                other => {
+                    let message = format!("{} expects a number, vector or matrix, got {}", token.val, self.describe(other));
+                    self.error(Some(token.clone()), message);
+                    Type::Unknown
+                }
+            },
+            "min" | "max" if args.len() != 2 => self.reduction_type(token, args, arg_types, 2),
+            "sum" | "prod" | "mean" | "median" | "var" | "std" | "argmin" | "argmax" => self.reduction_type(token, args, arg_types, 1),
+            "cumsum" | "cumprod" => {
+                let axis = arg_types.len() > 1;
+                if axis {
+                    self.expect(token, arg_types, 1, Type::Scalar);
+                }
+                match &arg_types[0] {
+                    Type::Matrix(_, _) if axis => arg_types[0].clone(),
+                    Type::Vector(_) | Type::Unknown => arg_types[0].clone(),
+                    Type::Scalar => Type::Vector(Dim::Known(1)),
+                    Type::Matrix(_, _) => Type::Vector(self.fresh_symbol()),
+                    other => {
+                        let message = format!("{} expects a number, vector or matrix, got {}", token.val, self.describe(other));
+                        self.error(Some(token.clone()), message);
+                        Type::Unknown
+                    }
+                }
+            }
+            "cov" | "corrcoef" => {
+                self.expect(token, arg_types, 0, matrix);
+                match &arg_types[0] {
+                    Type::Matrix(_, cols) => Type::Matrix(*cols, *cols),
+                    _ => Type::Unknown,
+                }
+            }
+            "zeros" | "ones" | "rand" | "randn" => self.sized_type(token, args, arg_types, 0),
+            "randint" => {
+                self.expect(token, arg_types, 0, Type::Scalar);
+                self.expect(token, arg_types, 1, Type::Scalar);
+                self.sized_type(token, args, arg_types, 2)
+            }
+            "eye" => {
+                self.expect(token, arg_types, 0, Type::Scalar);
+                let n = self.dim_of(&args[0]);
+                Type::Matrix(n, n)
+            }
+            "diag" => match &arg_types[0] {
+                Type::Vector(n) => Type::Matrix(*n, *n),
+                Type::Matrix(_, _) => Type::Vector(self.fresh_symbol()),
+                Type::Unknown => Type::Unknown,
+                other => {
+                    let message = format!("diag expects a vector or matrix, got {}", self.describe(other));
+                    self.error(Some(token.clone()), message);
+                    Type::Unknown
+                }
+            },
+            "linspace" => {
+                self.expect(token, arg_types, 0, Type::Scalar);
+                self.expect(token, arg_types, 1, Type::Scalar);
+                self.sized_type(token, args, arg_types, 2)
+            }
+            "arange" => {
+                for index in 0..args.len() {
+                    self.expect(token, arg_types, index, Type::Scalar);
+                }
+                Type::Vector(self.fresh_symbol())
+            }
+            "repmat" | "reshape" | "hstack" | "vstack" => {
+                let limit = if token.val == "repmat" || token.val == "reshape" { 1 } else { args.len() };
+                for (index, ty) in arg_types.iter().enumerate().take(limit) {
+                    if !matches!(ty, Type::Scalar | Type::Vector(_) | Type::Matrix(_, _) | Type::Unknown) {
+                        let message = format!("{} expects a number, vector or matrix as argument {}, got {}", token.val, index + 1, self.describe(ty));
+                        self.error(Some(token.clone()), message);
+                        return Type::Unknown;
+                    }
+                }
+                match token.val.as_str() {
+                    "reshape" => self.sized_type(token, args, arg_types, 1),
+                    "repmat" => {
+                        for index in 1..args.len() {
+                            self.expect(token, arg_types, index, Type::Scalar);
+                        }
+                        match args.len() {
+                            2 => Type::Vector(self.fresh_symbol()),
+                            _ => Type::Matrix(self.fresh_symbol(), self.fresh_symbol()),
+                        }
+                    }
+                    "hstack" if arg_types.iter().all(|ty| matches!(ty, Type::Scalar | Type::Vector(_))) => Type::Vector(self.fresh_symbol()),
+                    _ => Type::Matrix(self.fresh_symbol(), self.fresh_symbol()),
+                }
+            }
+            "seed" => {
+                self.expect(token, arg_types, 0, Type::Scalar);
+                Type::Nil
+            }
+            "assert" => {
+                if !matches!(arg_types[0], Type::Scalar | Type::Vector(_) | Type::Matrix(_, _) | Type::Unknown) {
+                    let message = format!("assert expects a number, vector or matrix as argument 1, got {}", self.describe(&arg_types[0]));
+                    self.error(Some(token.clone()), message);
+                }
+                if args.len() == 2 {
+                    self.expect(token, arg_types, 1, Type::Str);
+                }
+                Type::Nil
+            }
+            "assert_eq" | "assert_close" => {
+                // Values of different shapes can only fail the assertion
+                let description = self.describe(&arg_types[0]);
+                if !self.compatible(&arg_types[0], &arg_types[1]) {
+                    let message = format!("Shape mismatch for '{}': {} and {}", token.val, description, self.describe(&arg_types[1]));
+                    self.error(Some(token.clone()), message);
+                }
+                if token.val == "assert_close" {
+                    self.expect(token, arg_types, 2, Type::Scalar);
+                }
+                Type::Nil
+            }
+            "min" | "max" | "clamp" => {
+                let mut result = Type::Scalar;
+                for (index, ty) in arg_types.iter().enumerate() {
+                    if !matches!(ty, Type::Scalar | Type::Vector(_) | Type::Matrix(_, _) | Type::Unknown) {
+                        let message = format!("{} expects a number, vector or matrix as argument {}, got {}", token.val, index + 1, self.describe(ty));
+                        self.error(Some(token.clone()), message);
+                        return Type::Unknown;
+                    }
+                    result = match self.broadcast(&result, ty) {
+                        Some(combined) => combined,
+                        None => {
+                            let message = format!("Shape mismatch for '{}': {} and {}", token.val, self.describe(&result), self.describe(ty));
+                            self.error(Some(token.clone()), message);
+                            return Type::Unknown;
+                        }
+                    };
+                }
+                result
+            }
+            "transpose" | "ctranspose" => match &arg_types[0] {
+                Type::Matrix(rows, cols) => Type::Matrix(*cols, *rows),
+                Type::Vector(_) | Type::Unknown => arg_types[0].clone(),
+                other => {
+                    let message = format!("{} expects a matrix or vector, got {}", token.val, self.describe(other));
+                    self.error(Some(token.clone()), message);
+                    Type::Unknown
+                }
+            },
+            "rational" => {
+                for index in 0..args.len() {
+                    self.expect(token, arg_types, index, Type::Scalar);
+                }
+                Type::Scalar
+            }
+            "det" | "rank" => {
+                self.expect(token, arg_types, 0, matrix);
+                if let Type::Matrix(rows, cols) = &arg_types[0] {
+                    if token.val == "det" && !self.unify(*rows, *cols) {
+                        let message = format!("det expects a square matrix, got {}", self.describe(&arg_types[0]));
+                        self.error(Some(token.clone()), message);
+                    }
+                }
+                Type::Scalar
+            }
+            "solve" => {
+                self.expect(token, arg_types, 0, matrix);
+                let rows = match &arg_types[0] {
+                    Type::Matrix(rows, cols) => {
+                        if !self.unify(*rows, *cols) {
+                            let message = format!("solve expects a square matrix, got {}", self.describe(&arg_types[0]));
+                            self.error(Some(token.clone()), message);
+                        }
+                        Some(*cols)
+                    }
+                    _ => None,
+                };
+                let (length, result) = match &arg_types[1] {
+                    Type::Vector(length) => (*length, Type::Vector(rows.unwrap_or(*length))),
+                    Type::Matrix(length, cols) => (*length, Type::Matrix(rows.unwrap_or(*length), *cols)),
+                    Type::Unknown => return Type::Unknown,
+                    other => {
+                        let message = format!("solve expects a vector or matrix as argument 2, got {}", self.describe(other));
+                        self.error(Some(token.clone()), message);
+                        return Type::Unknown;
+                    }
+                };
+                if let Some(rows) = rows {
+                    if !self.unify(rows, length) {
+                        let message = format!("Shape mismatch for 'solve': {} and {}", self.describe(&arg_types[0]), self.describe(&arg_types[1]));
+                        self.error(Some(token.clone()), message);
+                    }
+                }
+                result
+            }
+            _ => Type::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Checker, Type};
+    use crate::core::ast::parser::Parser;
+    use crate::core::lexer::lexer::Lexer;
+
+    fn check(source: &str) -> (Checker, Vec<String>) {
+        let tokens = Lexer::new(source.to_string()).scan_tokens();
+        let program = Parser::new(tokens).parse_program();
+        let mut checker = Checker::new();
+        let errors = checker.check(&program).iter().map(|error| error.to_string()).collect();
+        (checker, errors)
+    }
+
+    #[test]
+    fn shape_mismatch_and_undeclared() {
+        let (_, errors) = check("vector y[3]\nvector z[4]\nprint y * z\nprint w\nmatrix A[2,3]\nA = A * A\n");
+
+        assert_eq!(errors, vec![
+            "[line 3] Type error: Shape mismatch for '*': vector[3] and vector[4]",
+            "[line 4] Type error: Undeclared variable 'w'",
+            "[line 6] Type error: Shape mismatch for '*': matrix[2, 3] and matrix[2, 3]",
+        ]);
+    }
+
+    #[test]
+    fn broadcasting() {
+        let source = "matrix A[2,3]\nmatrix B[2,1]\nA = A - {1, 2, 3}\nA = B + {1, 2, 3}\nprint A / {1, 2}\nvector v[3]\nv = max(v, 0)\nA = clamp(A, 0, {{1}, {2}})\nprint min(v, \"x\")\n";
+        let (_, errors) = check(source);
+
+        assert_eq!(errors, vec![
+            "[line 5] Type error: Shape mismatch for '/': matrix[2, 3] and vector[2]",
+            "[line 9] Type error: min expects a number, vector or matrix as argument 2, got string",
+        ]);
+    }
+
+    #[test]
+    fn reductions() {
+        let source = "matrix A[2,3]\nvector c[3]\nvector r[2]\nc = sum(A, 0)\nr = mean(A, 1)\nc = max(A, {}, 1)\nscalar s\ns = median(A)\nA = cumsum(A, 1)\nc = cov(A)\nprint var(A, 2)\n";
+        let (_, errors) = check(source);
+
+        assert_eq!(errors, vec![
+            "[line 6] Type error: Cannot assign vector[2] to 'c' declared as vector[3]",
+            "[line 10] Type error: Cannot assign matrix[3, 3] to 'c' declared as vector[3]",
+            "[line 11] Type error: var expects axis 0 or 1, got 2",
+        ]);
+    }
+
+    #[test]
+    fn constructors() {
+        let source = "scalar n\nn = 4\nmatrix A[2,3]\nvector v[4]\nA = zeros(2, 3)\nv = linspace(0, 1, n)\nA = eye(3)\nA = reshape(v, 2, 3)\nv = diag(eye(n))\nprint seed(\"x\")\n";
+        let (_, errors) = check(source);
+
+        assert_eq!(errors, vec![
+            "[line 7] Type error: Cannot assign matrix[3, 3] to 'A' declared as matrix[2, 3]",
+            "[line 10] Type error: seed expects a scalar as argument 1, got string",
+        ]);
+    }
+
+    #[test]
+    fn declarations_with_sizes_and_values() {
+        let source = "scalar n = 3\nvector y[n] = {1, 2, 3}\nvector z[n] = y\nmatrix A[2, n * 2] = {{1, 2}}\nlet B = {{1, 2}, {3, 4}}\nB = {1, 2}\nvector w[\"x\"]\n";
+        let (_, errors) = check(source);
+
+        assert_eq!(errors, vec![
+            "[line 4] Type error: Cannot initialise matrix[2, ?2] 'A' with matrix[1, 2]",
+            "[line 6] Type error: Cannot assign to 'B', which is bound with let",
+            "[line 7] Type error: Size of 'w' must be a scalar, got string",
+        ]);
+    }
+
+    #[test]
+    fn redeclarations() {
+        let source = "scalar x\nx = 3\nvector x[2]\nfn f(a) {\n    scalar a = 1\n    scalar b\n    b = {1, 2}\n}\nfn f(c) {\n}\nscalar y\ny = \"s\"\n";
+        let (_, errors) = check(source);
+
+        assert_eq!(errors, vec![
+            "[line 3] Type error: 'x' is already declared in this scope",
+            "[line 5] Type error: 'a' is already declared in this scope",
+            "[line 7] Type error: Cannot assign vector[2] to 'b' declared as scalar",
+            "[line 9] Type error: 'f' is already declared in this scope",
+            "[line 12] Type error: Cannot assign string to 'y' declared as scalar",
+        ]);
+    }
+
+    #[test]
+    fn constants_and_immutable_bindings() {
+        let source = "const N = 4\nscalar x = 1\nconst M = N * x\nN = 5\npi = 3\nvector v[N] = linspace(0, pi, N)\nfn f(N) {\n    N = 2\n    let k = N\n    k = 3\n}\nscalar e = 2\ne = 3\n";
+        let (_, errors) = check(source);
+
+        assert_eq!(errors, vec![
+            "[line 3] Type error: Value of constant 'M' must be known before the program runs",
+            "[line 4] Type error: Cannot assign to constant 'N'",
+            "[line 5] Type error: Cannot assign to constant 'pi'",
+            "[line 10] Type error: Cannot assign to 'k', which is bound with let",
+        ]);
+    }
+
+    #[test]
+    fn loops() {
+        let source = "scalar n = 3\nouter: while n {\n    loop {\n        continue outer\n    }\n    break inner\n}\nbreak\nloop {\n    fn f() {\n        continue\n    }\n}\nwhile {1, 2} {\n}\n";
+        let (_, errors) = check(source);
+
+        assert_eq!(errors, vec![
+            "[line 6] Type error: No loop labelled 'inner' around 'break'",
+            "[line 8] Type error: 'break' outside of a loop",
+            "[line 11] Type error: 'continue' outside of a loop",
+            "[line 14] Type error: Loop condition must be a scalar, got vector[2]",
+        ]);
+    }
+
+    #[test]
+    fn test_blocks_and_assertions() {
+        let source = "vector v[2] = {1, 2}\ntest \"v\" {\n    scalar local = 1\n    assert(local, \"local\")\n    assert_eq(v, {1, 2, 3})\n    assert_close(v, v, \"tol\")\n}\ntest \"v\" {\n    assert(\"x\")\n}\nprint local\nfn f() {\n    test \"inner\" {\n    }\n}\n";
+        let (_, errors) = check(source);
+
+        assert_eq!(errors, vec![
+            "[line 5] Type error: Shape mismatch for 'assert_eq': vector[2] and vector[3]",
+            "[line 6] Type error: assert_close expects a scalar as argument 3, got string",
+            "[line 8] Type error: Duplicate test \"v\"",
+            "[line 9] Type error: assert expects a number, vector or matrix as argument 1, got string",
+            "[line 11] Type error: Undeclared variable 'local'",
+            "[line 13] Type error: Tests are only allowed at the top level",
+        ]);
+    }
+
+    #[test]
+    fn imports_without_loaded_files() {
+        let source = "import \"la.dodo\" as la\nfrom \"u.dodo\" import f\nprint la.solve(f(1), 2)\nprint q.x\nscalar la\nloop {\n    import \"v.dodo\" as v\n}\n";
+        let (_, errors) = check(source);
+
+        // Members of files the checker was not given are unknown rather than errors
+        assert_eq!(errors, vec![
+            "[line 4] Type error: Undeclared module 'q'",
+            "[line 5] Type error: 'la' is already declared in this scope",
+            "[line 7] Type error: Imports are only allowed at the top level",
+        ]);
+    }
+
+    #[test]
+    fn powers() {
+        let (_, errors) = check("matrix A[2,3]\nmatrix B[2,2]\nB = B ^ -1\nprint A ^ 2\nprint {1, 2} ^ 2\nA = A .^ 2\nprint {1, 2} .^ {3, 4, 5}\n");
+
+        assert_eq!(errors, vec![
+            "[line 4] Type error: Matrix power expects a square matrix, got matrix[2, 3]",
+            "[line 5] Type error: '^' expects a square matrix and an integer power, got vector[2] and scalar; use '.^' for element-wise powers",
+            "[line 7] Type error: Shape mismatch for '.^': vector[2] and vector[3]",
+        ]);
+    }
+
+    #[test]
+    fn infers_products_and_assignments() {
+        let (checker, errors) = check("matrix A[2,3]\nvector v[2]\nv = A * {1, 2, 3}\nv = {1, 2} * A\n");
+
+        assert_eq!(errors, vec!["[line 4] Type error: Cannot assign vector[3] to 'v' declared as vector[2]"]);
+        assert_eq!(checker.variable_type("A"), Some(&Type::Matrix(super::Dim::Known(2), super::Dim::Known(3))));
+    }
+
+    #[test]
+    fn symbolic_dimensions_and_arity() {
+        let source = "scalar n\nvector i[3]\nmatrix S[3,3]\nS = sparse(n, n, i, i, i)\nprint sparse(n, n, i, i, i) * {1, 2}\nsavenpy(\"a.npy\")\nprint nope(1)\n";
+        let (_, errors) = check(source);
+
+        assert_eq!(errors, vec![
+            "[line 5] Type error: Shape mismatch for '*': matrix[3, 3] and vector[2]",
+            "[line 6] Type error: savenpy expects 2 to 3 arguments, got 1",
+            "[line 7] Type error: Undefined function 'nope'",
+        ]);
+    }
+
+    #[test]
+    fn functions_and_locals() {
+        let source = "fn twice(v) {\n    vector w[2]\n    w = v * 2\n    return even(w)\n}\nfn even(u) {\n    return u\n}\nprint twice({1, 2}, 3)\nprint w\nreturn 1\n";
+        let (checker, errors) = check(source);
+
+        assert_eq!(errors, vec![
+            "[line 9] Type error: twice expects 1 arguments, got 2",
+            "[line 10] Type error: Undeclared variable 'w'",
+            "[line 11] Type error: Cannot return from top-level code",
+        ]);
+        assert_eq!(checker.variable_type("even"), Some(&Type::Function(1)));
+    }
+}
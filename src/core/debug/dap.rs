@@ -0,0 +1,528 @@
+use crate::core::ast::ast::{Program, Span};
+use crate::core::ast::stmt::Stmt;
+use crate::core::debug::debugger::{parse_expression, Mode, Stepping};
+use crate::core::dodo::environment::Environment;
+use crate::core::dodo::error_types::DodoRuntimeError;
+use crate::core::dodo::interpreter::{Hook, Interpreter};
+use crate::core::dodo::runner::{compile_errors, EX_SOFTWARE};
+use crate::core::dodo::value::Value;
+use crate::core::lsp::server::{read_message, write_message};
+use crate::core::token::token::{Token, TokenType};
+use serde_json::{json, Value as Json};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// dodo programs run on a single thread
+const THREAD_ID: i64 = 1;
+
+// Collects what the program prints so it can be sent as `output` events
+#[derive(Clone, Default)]
+struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// What a `variablesReference` handed to the client points at
+enum Handle {
+    Scope(usize),
+    Value(Value),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Action {
+    Wait,
+    Run,
+    Resume,
+    Disconnect,
+}
+
+fn summary(value: &Value) -> String {
+    match value {
+        Value::Vector(items) => format!("vector[{}] {}", items.len(), value),
+        Value::Matrix(matrix) => format!("matrix[{}, {}]", matrix.rows, matrix.cols),
+        Value::Sparse(sparse) => format!("sparse[{}, {}] ({} stored)", sparse.rows, sparse.cols, sparse.nnz()),
+        Value::Str(val) => format!("{:?}", val),
+        other => other.to_string(),
+    }
+}
+
+/// One debugging session. While the program is stopped the interpreter hook
+/// keeps reading requests from the client until one of them resumes execution.
+pub struct Session<R, W> {
+    input: R,
+    output: W,
+    seq: i64,
+    path: String,
+    // Folders searched for imports, as given with --path
+    search_path: Vec<PathBuf>,
+    program: Option<Program>,
+    stepping: Option<Stepping>,
+    // Lines asked for before the program was loaded
+    requested: Vec<usize>,
+    stop_on_entry: bool,
+    configured: bool,
+    started: bool,
+    disconnected: bool,
+    handles: Vec<Handle>,
+    printed: Captured,
+}
+
+impl<R: BufRead, W: Write> Session<R, W> {
+    pub fn new(input: R, output: W, search_path: &[PathBuf]) -> Self {
+        Session {
+            input,
+            output,
+            seq: 0,
+            path: String::new(),
+            search_path: search_path.to_vec(),
+            program: None,
+            stepping: None,
+            requested: Vec::new(),
+            stop_on_entry: false,
+            configured: false,
+            started: false,
+            disconnected: false,
+            handles: Vec::new(),
+            printed: Captured::default(),
+        }
+    }
+
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Json, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn flush_printed(&mut self) -> io::Result<()> {
+        let text = String::from_utf8_lossy(&self.printed.0.borrow()).to_string();
+        self.printed.0.borrow_mut().clear();
+        match text.is_empty() {
+            true => Ok(()),
+            false => self.event("output", json!({ "category": "stdout", "output": text })),
+        }
+    }
+
+    fn handle(&mut self, value: Value) -> i64 {
+        self.handles.push(Handle::Value(value));
+        self.handles.len() as i64
+    }
+
+    fn variable(&mut self, name: String, value: &Value) -> Json {
+        let reference = match value {
+            Value::Vector(_) | Value::Matrix(_) | Value::Sparse(_) => self.handle(value.clone()),
+            _ => 0,
+        };
+        json!({ "name": name, "value": summary(value), "type": value.type_name(), "variablesReference": reference })
+    }
+
+    // Children of an expandable value: elements of a vector, rows of a matrix, stored entries of a sparse matrix
+    fn variables(&mut self, reference: usize, interpreter: Option<&Interpreter>) -> Vec<Json> {
+        let children: Vec<(String, Value)> = match self.handles.get(reference.wrapping_sub(1)) {
+            Some(Handle::Scope(depth)) => {
+                let scope = interpreter.and_then(|interpreter| interpreter.environment().scope(*depth));
+                let mut values: Vec<(String, Value)> = scope.map_or(Vec::new(), |scope| {
+                    scope.iter().map(|(name, value)| (name.clone(), value.clone())).collect()
+                });
+                values.sort_by(|a, b| a.0.cmp(&b.0));
+                values
+            }
+            Some(Handle::Value(Value::Vector(items))) => items.iter().enumerate()
+                .map(|(index, item)| (format!("[{}]", index), Value::Scalar(item.clone())))
+                .collect(),
+            Some(Handle::Value(Value::Matrix(matrix))) => (0..matrix.rows)
+                .map(|row| (format!("[{}]", row), Value::Vector(matrix.row(row).to_vec())))
+                .collect(),
+            Some(Handle::Value(Value::Sparse(sparse))) => sparse.triplets().into_iter()
+                .map(|(row, col, val)| (format!("({}, {})", row, col), Value::Scalar(val)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        children.into_iter().map(|(name, value)| self.variable(name, &value)).collect()
+    }
+
+    fn set_breakpoints(&mut self, lines: Vec<usize>) -> Vec<Json> {
+        match self.stepping.as_mut() {
+            Some(stepping) => {
+                stepping.breakpoints.clear();
+                lines.into_iter()
+                    .map(|line| match stepping.add_breakpoint(line) {
+                        Some(actual) => json!({ "verified": true, "line": actual }),
+                        None => json!({ "verified": false, "line": line, "message": "No statement at or after this line" }),
+                    })
+                    .collect()
+            }
+            None => {
+                self.requested = lines.clone();
+                lines.into_iter().map(|line| json!({ "verified": true, "line": line })).collect()
+            }
+        }
+    }
+
+    fn launch(&mut self, request: &Json) -> io::Result<Action> {
+        let arguments = &request["arguments"];
+        self.path = arguments["program"].as_str().unwrap_or_default().to_string();
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        let program = fs::read_to_string(&self.path)
+            .map_err(|error| format!("{}: {}", self.path, error))
+            .and_then(|source| compile_errors(&source, &self.path, &self.search_path, false).map_err(|errors| errors.join("\n")));
+        match program {
+            Ok(program) => {
+                let mut stepping = Stepping::new(&program);
+                stepping.mode = if self.stop_on_entry { Mode::Step } else { Mode::Continue };
+                self.stepping = Some(stepping);
+                self.program = Some(program);
+                let requested = std::mem::take(&mut self.requested);
+                self.set_breakpoints(requested);
+                self.respond(request, Json::Null)?;
+                Ok(if self.configured { Action::Run } else { Action::Wait })
+            }
+            Err(message) => {
+                self.fail(request, &message)?;
+                Ok(Action::Wait)
+            }
+        }
+    }
+
+    // Answers one request; `interpreter` is only present while the program is stopped
+    fn request(&mut self, request: &Json, interpreter: Option<&mut Interpreter>) -> io::Result<Action> {
+        let arguments = &request["arguments"];
+        let depth = interpreter.as_ref().map_or(0, |interpreter| interpreter.call_stack().len());
+        let command = request["command"].as_str().unwrap_or_default();
+        match command {
+            "initialize" => {
+                self.respond(request, json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsEvaluateForHovers": true,
+                }))?;
+                self.event("initialized", json!({}))?;
+            }
+            "launch" => return self.launch(request),
+            "setBreakpoints" => {
+                let lines: Vec<usize> = arguments["breakpoints"].as_array().map_or(Vec::new(), |breakpoints| {
+                    breakpoints.iter().filter_map(|breakpoint| breakpoint["line"].as_u64()).map(|line| line as usize).collect()
+                });
+                let breakpoints = self.set_breakpoints(lines);
+                self.respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "configurationDone" => {
+                self.configured = true;
+                self.respond(request, Json::Null)?;
+                if self.program.is_some() && !self.started {
+                    return Ok(Action::Run);
+                }
+            }
+            "threads" => self.respond(request, json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }))?,
+            "stackTrace" => {
+                let name = self.path.rsplit('/').next().unwrap_or_default().to_string();
+                let frames: Vec<Json> = interpreter.as_ref().map_or(Vec::new(), |interpreter| {
+                    interpreter.call_stack().iter().enumerate().rev()
+                        .map(|(id, frame)| json!({
+                            "id": id,
+                            "name": frame.name,
+                            "line": frame.line,
+                            "column": 1,
+                            "source": { "name": name, "path": self.path },
+                        }))
+                        .collect()
+                });
+                let total = frames.len();
+                self.respond(request, json!({ "stackFrames": frames, "totalFrames": total }))?;
+            }
+            "scopes" => {
+                let frame = arguments["frameId"].as_u64().unwrap_or(0) as usize;
+                self.handles.push(Handle::Scope(frame));
+                let mut scopes = vec![json!({ "name": "Locals", "variablesReference": self.handles.len(), "expensive": false })];
+                if frame > 0 {
+                    self.handles.push(Handle::Scope(0));
+                    scopes.push(json!({ "name": "Globals", "variablesReference": self.handles.len(), "expensive": false }));
+                }
+                self.respond(request, json!({ "scopes": scopes }))?;
+            }
+            "variables" => {
+                let reference = arguments["variablesReference"].as_u64().unwrap_or(0) as usize;
+                let variables = self.variables(reference, interpreter.as_deref());
+                self.respond(request, json!({ "variables": variables }))?;
+            }
+            "evaluate" => {
+                let frame = arguments["frameId"].as_u64().map_or(depth.saturating_sub(1), |frame| frame as usize);
+                let result = match (interpreter, parse_expression(arguments["expression"].as_str().unwrap_or_default())) {
+                    (None, _) => Err("The program is not stopped".to_string()),
+                    (_, Err(message)) => Err(message),
+                    (Some(interpreter), Ok(expr)) => interpreter.evaluate_in_frame(&expr, frame).map_err(|error| error.message),
+                };
+                match result {
+                    Ok(value) => {
+                        let variable = self.variable(String::new(), &value);
+                        self.respond(request, json!({ "result": variable["value"], "variablesReference": variable["variablesReference"] }))?;
+                    }
+                    Err(message) => self.fail(request, &message)?,
+                }
+            }
+            "continue" | "next" | "stepIn" | "stepOut" if depth > 0 => {
+                let mode = match command {
+                    "continue" => Mode::Continue,
+                    "next" => Mode::Next(depth),
+                    "stepIn" => Mode::Step,
+                    _ => Mode::Finish(depth),
+                };
+                if let Some(stepping) = self.stepping.as_mut() {
+                    stepping.mode = mode;
+                }
+                self.respond(request, json!({ "allThreadsContinued": true }))?;
+                return Ok(Action::Resume);
+            }
+            "disconnect" | "terminate" => {
+                self.disconnected = true;
+                self.respond(request, Json::Null)?;
+                return Ok(Action::Disconnect);
+            }
+            _ => self.fail(request, &format!("Unsupported request '{}'", command))?,
+        }
+        Ok(Action::Wait)
+    }
+
+    fn stop(&mut self, interpreter: &mut Interpreter, line: usize) -> io::Result<Action> {
+        let reason = match self.stepping.as_ref() {
+            Some(stepping) if stepping.breakpoints.contains(&line) => "breakpoint",
+            _ if interpreter.call_stack().len() == 1 && self.stop_on_entry => "entry",
+            _ => "step",
+        };
+        self.stop_on_entry = false;
+        self.handles.clear();
+        self.event("stopped", json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }))?;
+        loop {
+            let request = match read_message(&mut self.input)? {
+                Some(Ok(request)) => request,
+                Some(Err(_)) => continue,
+                None => return Ok(Action::Disconnect),
+            };
+            match self.request(&request, Some(interpreter))? {
+                Action::Wait | Action::Run => (),
+                action => return Ok(action),
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Hook for Session<R, W> {
+    fn before_statement(&mut self, interpreter: &mut Interpreter, _stmt: &Stmt, span: Span) -> Result<(), DodoRuntimeError> {
+        let depth = interpreter.call_stack().len();
+        let stop = self.stepping.as_ref().is_some_and(|stepping| stepping.should_stop(span.line, depth));
+        let action = self.flush_printed().and_then(|_| match stop {
+            true => self.stop(interpreter, span.line),
+            false => Ok(Action::Resume),
+        });
+        match action {
+            Ok(Action::Resume) if !self.disconnected => Ok(()),
+            _ => {
+                self.disconnected = true;
+                let token = Token::at(TokenType::ILLEGAL, String::new(), span.line, 0);
+                Err(DodoRuntimeError::new(token, "Stopped by the debugger".to_string()))
+            }
+        }
+    }
+}
+
+fn run<R: BufRead + 'static, W: Write + 'static>(session: &Rc<RefCell<Session<R, W>>>) -> io::Result<()> {
+    let (program, printed) = {
+        let mut session = session.borrow_mut();
+        session.started = true;
+        (session.program.take(), session.printed.clone())
+    };
+    let program = match program {
+        Some(program) => program,
+        None => return Ok(()),
+    };
+    let mut interpreter = Interpreter::new(Environment::new());
+    interpreter.set_output(Box::new(printed));
+    interpreter.set_hook(Box::new(session.clone()));
+    let result = interpreter.interpret(&program);
+
+    let mut session = session.borrow_mut();
+    if session.disconnected {
+        return Ok(());
+    }
+    session.flush_printed()?;
+    let code = match result {
+        Ok(()) => 0,
+        Err(error) => {
+            session.event("output", json!({ "category": "stderr", "output": format!("{}\n", error) }))?;
+            EX_SOFTWARE
+        }
+    };
+    session.event("exited", json!({ "exitCode": code }))?;
+    session.event("terminated", json!({}))
+}
+
+/// Serves one debugging session until the client disconnects, looking for
+/// imports in `search_path` as well as beside the program.
+pub fn serve<R: BufRead + 'static, W: Write + 'static>(input: R, output: W, search_path: &[PathBuf]) -> io::Result<()> {
+    let session = Rc::new(RefCell::new(Session::new(input, output, search_path)));
+    loop {
+        let request = match read_message(&mut session.borrow_mut().input)? {
+            Some(Ok(request)) => request,
+            Some(Err(_)) => continue,
+            None => return Ok(()),
+        };
+        let action = session.borrow_mut().request(&request, None)?;
+        match action {
+            Action::Run => run(&session)?,
+            Action::Disconnect => return Ok(()),
+            _ => (),
+        }
+    }
+}
+
+/// `dodolang dap`: runs a Debug Adapter Protocol server over stdin and stdout.
+pub fn command(search_path: &[PathBuf]) -> i32 {
+    match serve(io::BufReader::new(io::stdin()), io::stdout(), search_path) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("dap: {}", error);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Captured;
+    use crate::core::lsp::server::{read_message, write_message};
+    use serde_json::{json, Value as Json};
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    fn session(requests: &[Json], search_path: &[PathBuf]) -> Vec<Json> {
+        let mut input = Vec::new();
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            write_message(&mut input, &request).unwrap();
+        }
+        let output = Rc::new(RefCell::new(Vec::new()));
+        super::serve(Cursor::new(input), Captured(output.clone()), search_path).unwrap();
+        let bytes = output.borrow().clone();
+        let mut reader = Cursor::new(bytes);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message.unwrap());
+        }
+        messages
+    }
+
+    fn find<'a>(messages: &'a [Json], command: &str) -> Vec<&'a Json> {
+        messages.iter().filter(|message| message["command"] == json!(command) || message["event"] == json!(command)).collect()
+    }
+
+    #[test]
+    fn breakpoints_variables_and_evaluate() {
+        let path = std::env::temp_dir().join("dodolang_dap_test.dodo");
+        let source = "matrix A[2, 2]\nA = {{1, 2}, {3, 4}}\nfn scale(x, k) {\n    return x * k\n}\nprint scale(A, 2)\nprint 5\n";
+        std::fs::write(&path, source).unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        let messages = session(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({ "command": "setBreakpoints", "arguments": { "source": { "path": path }, "breakpoints": [{ "line": 4 }, { "line": 99 }] } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "scopes", "arguments": { "frameId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 3 } }),
+            json!({ "command": "evaluate", "arguments": { "expression": "A * 0", "frameId": 0 } }),
+            json!({ "command": "evaluate", "arguments": { "expression": "k", "frameId": 0 } }),
+            json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ], &[]);
+
+        let breakpoints = &find(&messages, "setBreakpoints")[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints, &json!([{ "verified": true, "line": 4 }, { "verified": false, "line": 99, "message": "No statement at or after this line" }]));
+
+        let stopped = find(&messages, "stopped");
+        assert_eq!(stopped.len(), 2);
+        assert_eq!(stopped[0]["body"]["reason"], json!("breakpoint"));
+        assert_eq!(stopped[1]["body"]["reason"], json!("step"));
+
+        let frames = &find(&messages, "stackTrace")[0]["body"]["stackFrames"];
+        assert_eq!((frames[0]["name"].clone(), frames[0]["line"].clone()), (json!("scale"), json!(4)));
+        assert_eq!((frames[1]["name"].clone(), frames[1]["line"].clone()), (json!("<script>"), json!(6)));
+
+        let locals = &find(&messages, "variables")[0]["body"]["variables"];
+        assert_eq!(locals[0]["name"], json!("k"));
+        assert_eq!(locals[1]["value"], json!("matrix[2, 2]"));
+        let rows = &find(&messages, "variables")[1]["body"]["variables"];
+        assert_eq!(rows[1]["value"], json!("vector[2] {3, 4}"));
+
+        let evaluations = find(&messages, "evaluate");
+        assert_eq!(evaluations[0]["body"]["result"], json!("matrix[2, 2]"));
+        assert_eq!(evaluations[1]["success"], json!(false));
+
+        let output: Vec<&Json> = find(&messages, "output");
+        assert_eq!(output[0]["body"]["output"], json!("{{2, 4},\n {6, 8}}\n"));
+        assert_eq!(output[1]["body"]["output"], json!("5\n"));
+        assert_eq!(find(&messages, "exited")[0]["body"]["exitCode"], json!(0));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn imports_come_from_the_search_path() {
+        let dir = std::env::temp_dir().join("dodolang_dap_search_path");
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib").join("half.dodo"), "const HALF = 0.5\n").unwrap();
+        std::fs::write(dir.join("main.dodo"), "from \"half.dodo\" import HALF\nprint HALF * 4\n").unwrap();
+        let path = dir.join("main.dodo").to_string_lossy().to_string();
+        let requests = [
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "disconnect" }),
+        ];
+
+        let messages = session(&requests, &[]);
+        assert_eq!(find(&messages, "launch")[0]["success"], json!(false));
+
+        let messages = session(&requests, &[dir.join("lib")]);
+        assert_eq!(find(&messages, "launch")[0]["success"], json!(true));
+        assert_eq!(find(&messages, "output")[0]["body"]["output"], json!("2.0\n"));
+    }
+}
@@ -0,0 +1,388 @@
+use crate::core::ast::ast::{Program, Span};
+use crate::core::ast::expr::Expr;
+use crate::core::ast::parser::Parser;
+use crate::core::ast::stmt::Stmt;
+use crate::core::dodo::environment::Environment;
+use crate::core::dodo::error_types::DodoRuntimeError;
+use crate::core::dodo::interpreter::{Hook, Interpreter};
+use crate::core::dodo::runner::{compile, EX_NOINPUT, EX_DATAERR, EX_SOFTWARE};
+use crate::core::lexer::lexer::Lexer;
+use crate::core::token::token::{Token, TokenType};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const PROMPT: &str = "(dodo) ";
+const HELP: &str = "\
+break N      stop when line N is reached (b)
+delete N     remove the breakpoint on line N (d)
+breakpoints  list breakpoints
+step         run to the next statement, entering calls (s)
+next         run to the next statement in this function (n)
+finish       run until the current function returns (f)
+continue     run to the next breakpoint (c)
+print EXPR   evaluate an expression in the current frame (p)
+watch EXPR   evaluate an expression at every stop (w)
+unwatch N    remove watch N
+locals       show the variables of the current frame
+globals      show the global variables
+backtrace    show the active calls (bt)
+list         show the source around the current line (l)
+quit         stop the program (q)
+An empty line repeats the previous command.";
+
+/// Parses a single expression such as a watch or an `evaluate` request.
+pub fn parse_expression(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser::new(Lexer::new(text.to_string()).scan_tokens());
+    let mut statements = parser.parse();
+    if let Some(error) = parser.errors().first() {
+        return Err(error.message.clone());
+    }
+    match (statements.pop(), statements.is_empty()) {
+        (Some(Stmt::Expression(expr)), true) => Ok(expr),
+        _ => Err("Expect a single expression".to_string()),
+    }
+}
+
+/// Lines where a statement starts, including those inside function and loop bodies.
+pub fn statement_lines(program: &Program) -> BTreeSet<usize> {
+    fn collect(statements: &[Stmt], spans: &[Span], lines: &mut BTreeSet<usize>) {
+        for (stmt, span) in statements.iter().zip(spans.iter()) {
+            if let Stmt::Comment(_) = stmt {
+                continue;
+            }
+            if let Some(body) = stmt.body() {
+                collect(&body.statements, &body.spans, lines);
+            }
+            lines.insert(span.line);
+        }
+    }
+    let mut lines = BTreeSet::new();
+    collect(&program.statements, &program.spans, &mut lines);
+    lines
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    // Stop at the next statement
+    Step,
+    // Stop at the next statement at or above this call depth
+    Next(usize),
+    // Stop once the call at this depth has returned
+    Finish(usize),
+    Continue,
+}
+
+/// Breakpoints and the pending step request, shared by the terminal debugger and the DAP server.
+pub struct Stepping {
+    pub lines: BTreeSet<usize>,
+    pub breakpoints: BTreeSet<usize>,
+    pub mode: Mode,
+}
+
+impl Stepping {
+    pub fn new(program: &Program) -> Self {
+        Stepping {
+            lines: statement_lines(program),
+            breakpoints: BTreeSet::new(),
+            mode: Mode::Step,
+        }
+    }
+
+    /// Sets a breakpoint on the first statement at or after `line`, returning where it landed.
+    pub fn add_breakpoint(&mut self, line: usize) -> Option<usize> {
+        let line = *self.lines.range(line..).next()?;
+        self.breakpoints.insert(line);
+        Some(line)
+    }
+
+    pub fn should_stop(&self, line: usize, depth: usize) -> bool {
+        let stepped = match self.mode {
+            Mode::Step => true,
+            Mode::Next(at) => depth <= at,
+            Mode::Finish(at) => depth < at,
+            Mode::Continue => false,
+        };
+        stepped || self.breakpoints.contains(&line)
+    }
+}
+
+fn write_scope<W: Write>(output: &mut W, env: &Environment, depth: usize) -> io::Result<()> {
+    let scope = match env.scope(depth) {
+        Some(scope) => scope,
+        None => return Ok(()),
+    };
+    let mut names: Vec<&String> = scope.keys().collect();
+    names.sort();
+    if names.is_empty() {
+        writeln!(output, "No variables.")?;
+    }
+    for name in names {
+        writeln!(output, "{} = {}", name, scope[name])?;
+    }
+    Ok(())
+}
+
+/// Terminal debugger driven by commands read from `input`.
+pub struct Debugger<R, W> {
+    source: Vec<String>,
+    stepping: Stepping,
+    watches: Vec<(String, Expr)>,
+    last_command: String,
+    input: R,
+    output: W,
+    pub quit: bool,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    pub fn new(source: &str, program: &Program, input: R, output: W) -> Self {
+        Debugger {
+            source: source.lines().map(str::to_string).collect(),
+            stepping: Stepping::new(program),
+            watches: Vec::new(),
+            last_command: String::new(),
+            input,
+            output,
+            quit: false,
+        }
+    }
+
+    fn source_line(&self, line: usize) -> String {
+        self.source.get(line.wrapping_sub(1)).map_or(String::new(), |text| text.trim().to_string())
+    }
+
+    fn evaluate(interpreter: &mut Interpreter, expr: &Expr) -> String {
+        match interpreter.evaluate(expr) {
+            Ok(value) => value.to_string(),
+            Err(error) => format!("error: {}", error.message),
+        }
+    }
+
+    fn show_stop(&mut self, interpreter: &mut Interpreter, line: usize) -> io::Result<()> {
+        let frame = interpreter.call_stack().last().map_or(String::new(), |frame| frame.name.clone());
+        if self.stepping.breakpoints.contains(&line) {
+            writeln!(self.output, "Breakpoint at line {}", line)?;
+        }
+        let text = self.source_line(line);
+        writeln!(self.output, "{}:{}  {}", frame, line, text)?;
+        for (index, (text, expr)) in self.watches.iter().enumerate() {
+            writeln!(self.output, "watch {}: {} = {}", index + 1, text, Debugger::<R, W>::evaluate(interpreter, expr))?;
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, line: usize) -> io::Result<()> {
+        let first = line.saturating_sub(3).max(1);
+        let last = (line + 3).min(self.source.len());
+        for number in first..=last {
+            let marker = match (number == line, self.stepping.breakpoints.contains(&number)) {
+                (true, _) => "->",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            writeln!(self.output, "{} {:>3}  {}", marker, number, self.source[number - 1])?;
+        }
+        Ok(())
+    }
+
+    // Reads commands until one resumes execution; returns false when the user quits
+    fn prompt(&mut self, interpreter: &mut Interpreter, line: usize) -> io::Result<bool> {
+        loop {
+            write!(self.output, "{}", PROMPT)?;
+            self.output.flush()?;
+            let mut command = String::new();
+            if self.input.read_line(&mut command)? == 0 {
+                return Ok(false);
+            }
+            let mut command = command.trim().to_string();
+            if command.is_empty() {
+                command = self.last_command.clone();
+            }
+            self.last_command = command.clone();
+            let (name, argument) = match command.split_once(' ') {
+                Some((name, argument)) => (name, argument.trim()),
+                None => (command.as_str(), ""),
+            };
+            let depth = interpreter.call_stack().len();
+            match name {
+                "" => (),
+                "s" | "step" => {
+                    self.stepping.mode = Mode::Step;
+                    return Ok(true);
+                }
+                "n" | "next" => {
+                    self.stepping.mode = Mode::Next(depth);
+                    return Ok(true);
+                }
+                "f" | "finish" => {
+                    self.stepping.mode = Mode::Finish(depth);
+                    return Ok(true);
+                }
+                "c" | "continue" => {
+                    self.stepping.mode = Mode::Continue;
+                    return Ok(true);
+                }
+                "q" | "quit" => return Ok(false),
+                "b" | "break" => match argument.parse::<usize>().ok().and_then(|line| self.stepping.add_breakpoint(line)) {
+                    Some(line) => writeln!(self.output, "Breakpoint at line {}", line)?,
+                    None => writeln!(self.output, "No statement at or after line '{}'", argument)?,
+                },
+                "d" | "delete" => match argument.parse::<usize>() {
+                    Ok(line) if self.stepping.breakpoints.remove(&line) => writeln!(self.output, "Deleted breakpoint at line {}", line)?,
+                    _ => writeln!(self.output, "No breakpoint at line '{}'", argument)?,
+                },
+                "breakpoints" => {
+                    if self.stepping.breakpoints.is_empty() {
+                        writeln!(self.output, "No breakpoints.")?;
+                    }
+                    let lines: Vec<usize> = self.stepping.breakpoints.iter().copied().collect();
+                    for line in lines {
+                        let text = self.source_line(line);
+                        writeln!(self.output, "line {}  {}", line, text)?;
+                    }
+                }
+                "p" | "print" => match parse_expression(argument) {
+                    Ok(expr) => writeln!(self.output, "{}", Debugger::<R, W>::evaluate(interpreter, &expr))?,
+                    Err(message) => writeln!(self.output, "error: {}", message)?,
+                },
+                "w" | "watch" => match parse_expression(argument) {
+                    Ok(expr) => {
+                        let value = Debugger::<R, W>::evaluate(interpreter, &expr);
+                        self.watches.push((argument.to_string(), expr));
+                        writeln!(self.output, "watch {}: {} = {}", self.watches.len(), argument, value)?;
+                    }
+                    Err(message) => writeln!(self.output, "error: {}", message)?,
+                },
+                "unwatch" => match argument.parse::<usize>() {
+                    Ok(index) if index >= 1 && index <= self.watches.len() => {
+                        self.watches.remove(index - 1);
+                    }
+                    _ => writeln!(self.output, "No watch '{}'", argument)?,
+                },
+                "locals" => write_scope(&mut self.output, interpreter.environment(), depth - 1)?,
+                "globals" => write_scope(&mut self.output, interpreter.environment(), 0)?,
+                "bt" | "backtrace" => {
+                    for (index, frame) in interpreter.call_stack().iter().rev().enumerate() {
+                        writeln!(self.output, "#{} {} at line {}", index, frame.name, frame.line)?;
+                    }
+                }
+                "l" | "list" => self.list(line)?,
+                "h" | "help" => writeln!(self.output, "{}", HELP)?,
+                _ => writeln!(self.output, "Unknown command '{}'. Type 'help' for a list.", name)?,
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Hook for Debugger<R, W> {
+    fn before_statement(&mut self, interpreter: &mut Interpreter, _stmt: &Stmt, span: Span) -> Result<(), DodoRuntimeError> {
+        if self.quit || !self.stepping.should_stop(span.line, interpreter.call_stack().len()) {
+            return Ok(());
+        }
+        let resumed = self.show_stop(interpreter, span.line).and_then(|_| self.prompt(interpreter, span.line));
+        match resumed {
+            Ok(true) => Ok(()),
+            _ => {
+                self.quit = true;
+                let token = Token::at(TokenType::ILLEGAL, String::new(), span.line, 0);
+                Err(DodoRuntimeError::new(token, "Stopped by the debugger".to_string()))
+            }
+        }
+    }
+}
+
+/// `dodolang debug <file>`: runs a script under the terminal debugger, stopped at its first statement.
+pub fn command(path: &str, search_path: &[PathBuf]) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            return EX_NOINPUT;
+        }
+    };
+    let program = match compile(&source, path, search_path) {
+        Some(program) => program,
+        None => return EX_DATAERR,
+    };
+    println!("Debugging {}. Type 'help' for commands.", path);
+    let debugger = Rc::new(RefCell::new(Debugger::new(&source, &program, BufReader::new(io::stdin()), io::stdout())));
+    let mut interpreter = Interpreter::new(Environment::new());
+    interpreter.set_hook(Box::new(debugger.clone()));
+    let result = interpreter.interpret(&program);
+    if debugger.borrow().quit {
+        return 0;
+    }
+    match result {
+        Ok(()) => {
+            println!("Program finished.");
+            0
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            EX_SOFTWARE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_expression, Debugger};
+    use crate::core::ast::parser::Parser;
+    use crate::core::dodo::environment::Environment;
+    use crate::core::dodo::interpreter::Interpreter;
+    use crate::core::lexer::lexer::Lexer;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    const SOURCE: &str = "vector v[2]\nfn scale(x, k) {\n    # double it\n    return x * k\n}\nv = scale({1, 2}, 2)\nv = v + {1, 1}\nprint v\n";
+
+    fn debug(commands: &str) -> (String, bool) {
+        let program = Parser::new(Lexer::new(SOURCE.to_string()).scan_tokens()).parse_program();
+        let debugger = Rc::new(RefCell::new(Debugger::new(SOURCE, &program, Cursor::new(commands.to_string()), Vec::new())));
+        let mut interpreter = Interpreter::new(Environment::new());
+        interpreter.set_hook(Box::new(debugger.clone()));
+        let finished = interpreter.interpret(&program).is_ok();
+        let output = String::from_utf8(debugger.borrow().output.clone()).unwrap();
+        (output, finished)
+    }
+
+    #[test]
+    fn breakpoints_stepping_and_inspection() {
+        let (output, finished) = debug("b 3\nc\nw v\np x * 10\nbt\n\nlocals\nf\nn\nq\n");
+        let expected = "\
+<script>:1  vector v[2]
+(dodo) Breakpoint at line 4
+(dodo) Breakpoint at line 4
+scale:4  return x * k
+(dodo) watch 1: v = {0, 0}
+(dodo) {10, 20}
+(dodo) #0 scale at line 4
+#1 <script> at line 6
+(dodo) #0 scale at line 4
+#1 <script> at line 6
+(dodo) k = 2
+x = {1, 2}
+(dodo) <script>:7  v = v + {1, 1}
+watch 1: v = {2, 4}
+(dodo) <script>:8  print v
+watch 1: v = {3, 5}
+(dodo) ";
+        assert_eq!(output, expected);
+        assert!(!finished);
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let (output, finished) = debug("n\nn\nn\nglobals\nc\n");
+
+        assert!(output.contains("<script>:6  v = scale({1, 2}, 2)\n(dodo) <script>:7"));
+        assert!(output.contains("scale = <fn scale>\nv = {2, 4}\n"));
+        assert!(finished);
+        assert_eq!(parse_expression("v +"), Err("Expect expression".to_string()));
+        assert!(parse_expression("print v").is_err());
+    }
+}
@@ -0,0 +1,991 @@
+use crate::core::dodo::interpreter::Interpreter;
+use crate::core::dodo::linalg;
+use crate::core::dodo::random::Rng;
+use crate::core::dodo::sparse::SparseMatrix;
+use crate::core::dodo::stats;
+use crate::core::dodo::value::{self, Arithmetic, Matrix, Scalar, Value};
+use crate::core::io::{mtx, npy};
+use crate::core::token::token::TokenType;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+pub type BuiltinFn = fn(&mut Interpreter, Vec<Value>) -> Result<Value, String>;
+
+/// A native function callable from dodo code.
+pub struct Builtin {
+    pub name: &'static str,
+    pub min_arity: usize,
+    pub max_arity: usize,
+    // Reads or writes files, so sandboxed programs may not call it
+    pub files: bool,
+    pub function: BuiltinFn,
+}
+
+impl Builtin {
+    /// Accepted argument counts as used in messages, e.g. "2 to 3".
+    pub fn arity(&self) -> String {
+        match self.max_arity {
+            max if max == self.min_arity => max.to_string(),
+            usize::MAX => format!("at least {}", self.min_arity),
+            max => format!("{} to {}", self.min_arity, max),
+        }
+    }
+}
+
+pub static BUILTINS: &[Builtin] = &[
+    Builtin { name: "loadnpy", min_arity: 1, max_arity: 1, files: true, function: loadnpy },
+    Builtin { name: "savenpy", min_arity: 2, max_arity: 3, files: true, function: savenpy },
+    Builtin { name: "loadnpz", min_arity: 2, max_arity: 2, files: true, function: loadnpz },
+    Builtin { name: "savenpz", min_arity: 3, max_arity: usize::MAX, files: true, function: savenpz },
+    Builtin { name: "sparse", min_arity: 1, max_arity: 5, files: false, function: sparse },
+    Builtin { name: "dense", min_arity: 1, max_arity: 1, files: false, function: dense },
+    Builtin { name: "nnz", min_arity: 1, max_arity: 1, files: false, function: nnz },
+    Builtin { name: "loadmtx", min_arity: 1, max_arity: 1, files: true, function: loadmtx },
+    Builtin { name: "savemtx", min_arity: 2, max_arity: 2, files: true, function: savemtx },
+    Builtin { name: "rational", min_arity: 1, max_arity: 2, files: false, function: rational },
+    Builtin { name: "det", min_arity: 1, max_arity: 1, files: false, function: det },
+    Builtin { name: "rank", min_arity: 1, max_arity: 1, files: false, function: rank },
+    Builtin { name: "solve", min_arity: 2, max_arity: 2, files: false, function: solve },
+    Builtin { name: "re", min_arity: 1, max_arity: 1, files: false, function: re },
+    Builtin { name: "im", min_arity: 1, max_arity: 1, files: false, function: im },
+    Builtin { name: "abs", min_arity: 1, max_arity: 1, files: false, function: abs },
+    Builtin { name: "arg", min_arity: 1, max_arity: 1, files: false, function: arg },
+    Builtin { name: "conj", min_arity: 1, max_arity: 1, files: false, function: conj },
+    Builtin { name: "transpose", min_arity: 1, max_arity: 1, files: false, function: transpose },
+    Builtin { name: "ctranspose", min_arity: 1, max_arity: 1, files: false, function: ctranspose },
+    Builtin { name: "sin", min_arity: 1, max_arity: 1, files: false, function: sin },
+    Builtin { name: "cos", min_arity: 1, max_arity: 1, files: false, function: cos },
+    Builtin { name: "tan", min_arity: 1, max_arity: 1, files: false, function: tan },
+    Builtin { name: "exp", min_arity: 1, max_arity: 1, files: false, function: exp },
+    Builtin { name: "log", min_arity: 1, max_arity: 1, files: false, function: log },
+    Builtin { name: "sqrt", min_arity: 1, max_arity: 1, files: false, function: sqrt },
+    Builtin { name: "floor", min_arity: 1, max_arity: 1, files: false, function: floor },
+    Builtin { name: "ceil", min_arity: 1, max_arity: 1, files: false, function: ceil },
+    Builtin { name: "round", min_arity: 1, max_arity: 1, files: false, function: round },
+    Builtin { name: "sign", min_arity: 1, max_arity: 1, files: false, function: sign },
+    Builtin { name: "min", min_arity: 1, max_arity: 3, files: false, function: min },
+    Builtin { name: "max", min_arity: 1, max_arity: 3, files: false, function: max },
+    Builtin { name: "clamp", min_arity: 3, max_arity: 3, files: false, function: clamp },
+    Builtin { name: "sum", min_arity: 1, max_arity: 2, files: false, function: sum },
+    Builtin { name: "prod", min_arity: 1, max_arity: 2, files: false, function: prod },
+    Builtin { name: "mean", min_arity: 1, max_arity: 2, files: false, function: mean },
+    Builtin { name: "median", min_arity: 1, max_arity: 2, files: false, function: median },
+    Builtin { name: "var", min_arity: 1, max_arity: 2, files: false, function: var },
+    Builtin { name: "std", min_arity: 1, max_arity: 2, files: false, function: std },
+    Builtin { name: "argmin", min_arity: 1, max_arity: 2, files: false, function: argmin },
+    Builtin { name: "argmax", min_arity: 1, max_arity: 2, files: false, function: argmax },
+    Builtin { name: "cumsum", min_arity: 1, max_arity: 2, files: false, function: cumsum },
+    Builtin { name: "cumprod", min_arity: 1, max_arity: 2, files: false, function: cumprod },
+    Builtin { name: "cov", min_arity: 1, max_arity: 1, files: false, function: cov },
+    Builtin { name: "corrcoef", min_arity: 1, max_arity: 1, files: false, function: corrcoef },
+    Builtin { name: "zeros", min_arity: 1, max_arity: 2, files: false, function: zeros },
+    Builtin { name: "ones", min_arity: 1, max_arity: 2, files: false, function: ones },
+    Builtin { name: "eye", min_arity: 1, max_arity: 1, files: false, function: eye },
+    Builtin { name: "diag", min_arity: 1, max_arity: 1, files: false, function: diag },
+    Builtin { name: "linspace", min_arity: 3, max_arity: 3, files: false, function: linspace },
+    Builtin { name: "arange", min_arity: 1, max_arity: 3, files: false, function: arange },
+    Builtin { name: "repmat", min_arity: 2, max_arity: 3, files: false, function: repmat },
+    Builtin { name: "reshape", min_arity: 2, max_arity: 3, files: false, function: reshape },
+    Builtin { name: "hstack", min_arity: 1, max_arity: usize::MAX, files: false, function: hstack },
+    Builtin { name: "vstack", min_arity: 1, max_arity: usize::MAX, files: false, function: vstack },
+    Builtin { name: "seed", min_arity: 1, max_arity: 1, files: false, function: seed },
+    Builtin { name: "rand", min_arity: 0, max_arity: 2, files: false, function: rand },
+    Builtin { name: "randn", min_arity: 0, max_arity: 2, files: false, function: randn },
+    Builtin { name: "randint", min_arity: 2, max_arity: 4, files: false, function: randint },
+    Builtin { name: "assert", min_arity: 1, max_arity: 2, files: false, function: assert },
+    Builtin { name: "assert_eq", min_arity: 2, max_arity: 2, files: false, function: assert_eq },
+    Builtin { name: "assert_close", min_arity: 3, max_arity: 3, files: false, function: assert_close },
+];
+
+/// Start of the message of every failed assertion, which test reports count as failures rather than errors.
+pub const ASSERTION_FAILED: &str = "Assertion failed";
+
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// Constants every script can use; a declaration of the same name hides them.
+pub const CONSTANTS: &[(&str, f64)] = &[
+    ("pi", std::f64::consts::PI),
+    ("e", std::f64::consts::E),
+    ("inf", f64::INFINITY),
+    ("nan", f64::NAN),
+];
+
+pub fn constant(name: &str) -> Option<Scalar> {
+    CONSTANTS.iter().find(|(constant, _)| *constant == name).map(|(_, val)| Scalar::Float(*val))
+}
+
+fn string_arg<'a>(args: &'a [Value], index: usize, function: &str) -> Result<&'a str, String> {
+    match &args[index] {
+        Value::Str(val) => Ok(val),
+        other => Err(format!("{} expects a string as argument {}, got {}", function, index + 1, other.type_name())),
+    }
+}
+
+fn index_arg(value: &Value, function: &str) -> Result<usize, String> {
+    match value {
+        Value::Scalar(Scalar::Int(val)) if *val >= 0 => usize::try_from(*val).map_err(|_| format!("{} argument {} is too large", function, val)),
+        other => Err(format!("{} expects a non-negative integer, got {}", function, other)),
+    }
+}
+
+// Dense or sparse matrix argument as a dense matrix
+fn matrix_arg(interpreter: &mut Interpreter, args: &[Value], index: usize, function: &str) -> Result<Matrix, String> {
+    match &args[index] {
+        Value::Matrix(matrix) => Ok(matrix.clone()),
+        Value::Sparse(sparse) => densify(interpreter, sparse),
+        other => Err(format!("{} expects a matrix as argument {}, got {}", function, index + 1, other.type_name())),
+    }
+}
+
+// Dense copy of a sparse matrix, charged against the memory limit before it is built
+fn densify(interpreter: &mut Interpreter, sparse: &SparseMatrix) -> Result<Matrix, String> {
+    interpreter.reserve_elements(Matrix::size(sparse.rows, sparse.cols)?)?;
+    sparse.to_dense()
+}
+
+// Applies `f` to every element of a scalar, vector or matrix; sparse matrices
+// keep their pattern unless `f` maps zero to something else
+fn map_scalars<F>(interpreter: &mut Interpreter, value: &Value, function: &str, f: F) -> Result<Value, String> where F: Fn(&Scalar) -> Scalar {
+    match value {
+        Value::Sparse(sparse) if !f(&Scalar::Int(0)).is_zero() => {
+            let dense = densify(interpreter, sparse)?;
+            map_scalars(interpreter, &Value::Matrix(dense), function, f)
+        },
+        Value::Scalar(val) => Ok(Value::Scalar(f(val))),
+        Value::Vector(items) => Ok(Value::Vector(items.iter().map(&f).collect())),
+        Value::Matrix(matrix) => Ok(Value::Matrix(Matrix::new(matrix.rows, matrix.cols, matrix.data.iter().map(&f).collect()))),
+        Value::Sparse(sparse) => Ok(Value::Sparse(sparse.map(|val| Ok(f(val)))?)),
+        other => Err(format!("{} expects a number, vector or matrix, got {}", function, other.type_name())),
+    }
+}
+
+// Scalar, vector or matrix argument of an element-wise function, with sparse matrices made dense
+fn numeric_arg(interpreter: &mut Interpreter, args: &[Value], index: usize, function: &str) -> Result<Value, String> {
+    match &args[index] {
+        Value::Scalar(_) | Value::Vector(_) | Value::Matrix(_) => Ok(args[index].clone()),
+        Value::Sparse(sparse) => Ok(Value::Matrix(densify(interpreter, sparse)?)),
+        other => Err(format!("{} expects a number, vector or matrix as argument {}, got {}", function, index + 1, other.type_name())),
+    }
+}
+
+fn vector_arg<'a>(args: &'a [Value], index: usize, function: &str) -> Result<&'a [Scalar], String> {
+    match &args[index] {
+        Value::Vector(val) => Ok(val),
+        other => Err(format!("{} expects a vector as argument {}, got {}", function, index + 1, other.type_name())),
+    }
+}
+
+// loadnpy(path)
+fn loadnpy(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    npy::read_npy(string_arg(&args, 0, "loadnpy")?)
+}
+
+// savenpy(path, array[, order]) where order is "C" (default) or "F"
+fn savenpy(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let order = match args.len() {
+        3 => npy::Order::parse(string_arg(&args, 2, "savenpy")?)?,
+        _ => npy::Order::C,
+    };
+    npy::write_npy(string_arg(&args, 0, "savenpy")?, &args[1], order)?;
+    Ok(Value::Nil)
+}
+
+// loadnpz(path, name)
+fn loadnpz(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    npy::read_npz(string_arg(&args, 0, "loadnpz")?, string_arg(&args, 1, "loadnpz")?)
+}
+
+// savenpz(path, name1, array1, name2, array2, ...)
+fn savenpz(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    if args.len().is_multiple_of(2) {
+        return Err("savenpz expects a path followed by name/array pairs".to_string());
+    }
+    let mut arrays = Vec::new();
+    for index in (1..args.len()).step_by(2) {
+        let name = string_arg(&args, index, "savenpz")?;
+        arrays.push((name.to_string(), args[index + 1].clone()));
+    }
+    npy::write_npz(string_arg(&args, 0, "savenpz")?, &arrays)?;
+    Ok(Value::Nil)
+}
+
+// sparse(rows, cols, i, j, v) with 0-based row and column index vectors, or sparse(A) from a dense matrix
+fn sparse(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    match args.len() {
+        1 => match &args[0] {
+            Value::Matrix(matrix) => Ok(Value::Sparse(SparseMatrix::from_dense(matrix))),
+            Value::Sparse(_) => Ok(args[0].clone()),
+            other => Err(format!("sparse expects a matrix, got {}", other.type_name())),
+        },
+        5 => {
+            let rows = index_arg(&args[0], "sparse")?;
+            let cols = index_arg(&args[1], "sparse")?;
+            let (i, j, v) = (vector_arg(&args, 2, "sparse")?, vector_arg(&args, 3, "sparse")?, vector_arg(&args, 4, "sparse")?);
+            if i.len() != j.len() || i.len() != v.len() {
+                return Err("sparse expects i, j and v to have the same length".to_string());
+            }
+            let mut triplets = Vec::with_capacity(v.len());
+            for k in 0..v.len() {
+                let row = index_arg(&Value::Scalar(i[k].clone()), "sparse")?;
+                let col = index_arg(&Value::Scalar(j[k].clone()), "sparse")?;
+                triplets.push((row, col, v[k].clone()));
+            }
+            Ok(Value::Sparse(SparseMatrix::from_triplets(rows, cols, triplets)?))
+        }
+        count => Err(format!("sparse expects 1 or 5 arguments, got {}", count)),
+    }
+}
+
+// dense(S)
+fn dense(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    match &args[0] {
+        Value::Sparse(sparse) => Ok(Value::Matrix(densify(interpreter, sparse)?)),
+        Value::Matrix(_) => Ok(args[0].clone()),
+        other => Err(format!("dense expects a sparse matrix, got {}", other.type_name())),
+    }
+}
+
+// nnz(S), the number of stored entries
+fn nnz(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    match &args[0] {
+        Value::Sparse(sparse) => Ok(Value::int(sparse.nnz() as i128)),
+        other => Err(format!("nnz expects a sparse matrix, got {}", other.type_name())),
+    }
+}
+
+// loadmtx(path)
+fn loadmtx(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    mtx::read_mtx(string_arg(&args, 0, "loadmtx")?)
+}
+
+// savemtx(path, matrix)
+fn savemtx(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    mtx::write_mtx(string_arg(&args, 0, "savemtx")?, &args[1])?;
+    Ok(Value::Nil)
+}
+
+// rational(p, q), the exact fraction p/q of two integers, or rational(x) for the exact value of x
+fn rational(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let exact = |index: usize| match &args[index] {
+        Value::Scalar(Scalar::Float(val)) if args.len() == 1 => {
+            BigRational::from_float(*val).ok_or_else(|| format!("rational expects a finite number, got {}", val))
+        }
+        Value::Scalar(val) if val.is_integer() => Ok(val.as_rational().unwrap_or_default()),
+        other => Err(format!("rational expects integers, got {}", other)),
+    };
+    match args.len() {
+        2 => {
+            let denominator = exact(1)?;
+            if denominator.is_zero() {
+                return Err("Division by zero".to_string());
+            }
+            Ok(Value::Scalar(Scalar::rational(exact(0)? / denominator)))
+        }
+        _ => Ok(Value::Scalar(Scalar::rational(exact(0)?))),
+    }
+}
+
+// det(A), exact for integer and rational matrices
+fn det(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    Ok(Value::Scalar(linalg::det(&matrix_arg(interpreter, &args, 0, "det")?)?))
+}
+
+// rank(A)
+fn rank(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    Ok(Value::int(linalg::rank(&matrix_arg(interpreter, &args, 0, "rank")?) as i128))
+}
+
+// solve(A, b) for a vector b, or solve(A, B) with one column per right-hand side
+fn solve(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let a = matrix_arg(interpreter, &args, 0, "solve")?;
+    match &args[1] {
+        Value::Vector(b) => Ok(Value::Vector(linalg::solve(&a, &Matrix::new(b.len(), 1, b.clone()))?.data)),
+        _ => Ok(Value::Matrix(linalg::solve(&a, &matrix_arg(interpreter, &args, 1, "solve")?)?)),
+    }
+}
+
+// re(z), the real part, element-wise
+fn re(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "re", |val| match val {
+        Scalar::Complex(val) => Scalar::Float(val.re),
+        other => other.clone(),
+    })
+}
+
+// im(z), the imaginary part, element-wise
+fn im(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "im", |val| match val {
+        Scalar::Complex(val) => Scalar::Float(val.im),
+        _ => Scalar::Int(0),
+    })
+}
+
+// abs(x), element-wise; the modulus for complex numbers
+fn abs(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "abs", Scalar::abs)
+}
+
+// arg(z), the phase angle in radians, element-wise
+fn arg(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "arg", |val| Scalar::Float(val.as_complex().arg()))
+}
+
+// conj(z), the complex conjugate, element-wise
+fn conj(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "conj", Scalar::conj)
+}
+
+// transpose(A); vectors have no orientation and come back unchanged
+fn transpose(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    match &args[0] {
+        Value::Matrix(matrix) => {
+            let data = (0..matrix.cols * matrix.rows).map(|k| matrix.get(k % matrix.rows, k / matrix.rows).clone()).collect();
+            Ok(Value::Matrix(Matrix::new(matrix.cols, matrix.rows, data)))
+        }
+        Value::Sparse(sparse) => {
+            let triplets = sparse.triplets().into_iter().map(|(row, col, val)| (col, row, val)).collect();
+            Ok(Value::Sparse(SparseMatrix::from_triplets(sparse.cols, sparse.rows, triplets)?))
+        }
+        Value::Vector(_) => Ok(args[0].clone()),
+        other => Err(format!("transpose expects a matrix or vector, got {}", other.type_name())),
+    }
+}
+
+// ctranspose(A), the conjugate (Hermitian) transpose
+fn ctranspose(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let transposed = transpose(interpreter, args)?;
+    map_scalars(interpreter, &transposed, "ctranspose", Scalar::conj)
+}
+
+// Real functions give floats; complex arguments use the complex version
+fn transcendental(val: &Scalar, real: fn(f64) -> f64, complex: fn(Complex64) -> Complex64) -> Scalar {
+    match val {
+        Scalar::Complex(val) => Scalar::Complex(complex(*val)),
+        other => Scalar::Float(real(other.as_f64())),
+    }
+}
+
+// sin(x), element-wise, in radians
+fn sin(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "sin", |val| transcendental(val, f64::sin, Complex64::sin))
+}
+
+// cos(x), element-wise, in radians
+fn cos(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "cos", |val| transcendental(val, f64::cos, Complex64::cos))
+}
+
+// tan(x), element-wise, in radians
+fn tan(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "tan", |val| transcendental(val, f64::tan, Complex64::tan))
+}
+
+// exp(x), element-wise
+fn exp(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "exp", |val| transcendental(val, f64::exp, Complex64::exp))
+}
+
+// log(x), the natural logarithm, element-wise; negative numbers have complex logarithms
+fn log(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "log", |val| match val.as_f64() < 0.0 {
+        true => Scalar::Complex(val.as_complex().ln()),
+        false => transcendental(val, f64::ln, Complex64::ln),
+    })
+}
+
+// sqrt(x), element-wise; negative numbers have imaginary roots
+fn sqrt(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "sqrt", |val| match val.as_f64() < 0.0 {
+        true => Scalar::Complex(val.as_complex().sqrt()),
+        false => transcendental(val, f64::sqrt, Complex64::sqrt),
+    })
+}
+
+// Integers stay as they are, rationals round exactly and floats stay floats; complex numbers round both parts
+fn rounding(val: &Scalar, exact: fn(&BigRational) -> BigRational, float: fn(f64) -> f64) -> Scalar {
+    match val {
+        Scalar::Rational(val) => Scalar::rational(exact(val)),
+        Scalar::Float(val) => Scalar::Float(float(*val)),
+        Scalar::Complex(val) => Scalar::Complex(Complex64::new(float(val.re), float(val.im))),
+        other => other.clone(),
+    }
+}
+
+// floor(x), element-wise
+fn floor(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "floor", |val| rounding(val, BigRational::floor, f64::floor))
+}
+
+// ceil(x), element-wise
+fn ceil(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "ceil", |val| rounding(val, BigRational::ceil, f64::ceil))
+}
+
+// round(x), element-wise, with halves rounded away from zero
+fn round(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "round", |val| rounding(val, BigRational::round, f64::round))
+}
+
+// sign(x), element-wise: -1, 0 or 1, and z / abs(z) for complex numbers
+fn sign(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    map_scalars(interpreter, &args[0], "sign", |val| match val {
+        Scalar::Float(val) if *val == 0.0 || val.is_nan() => Scalar::Float(*val),
+        Scalar::Float(val) => Scalar::Float(val.signum()),
+        Scalar::Complex(val) if val.norm() == 0.0 => Scalar::Complex(*val),
+        Scalar::Complex(val) => Scalar::Complex(val / val.norm()),
+        other => Scalar::Int(other.compare(&Scalar::Int(0)).map_or(0, |ordering| ordering as i128)),
+    })
+}
+
+// The smaller or larger of two real numbers, keeping its type; NaN wins, as in NumPy
+fn pick(function: &str, val1: &Scalar, val2: &Scalar, keep: Ordering) -> Result<Scalar, String> {
+    if val1.is_complex() || val2.is_complex() {
+        return Err(format!("{} cannot order complex numbers", function));
+    }
+    match val1.compare(val2) {
+        Some(ordering) if ordering == keep.reverse() => Ok(val2.clone()),
+        Some(_) => Ok(val1.clone()),
+        None if val1.as_f64().is_nan() => Ok(val1.clone()),
+        None => Ok(val2.clone()),
+    }
+}
+
+// `value::broadcast`, with the memory limit checked before the result is built
+fn broadcast<F>(interpreter: &mut Interpreter, left: &Value, right: &Value, f: F) -> Result<Value, String>
+    where F: FnMut(&Scalar, &Scalar) -> Result<Scalar, String> {
+    let (rows, cols) = value::broadcast_shape(left, right)?;
+    interpreter.reserve_elements(Matrix::size(rows, cols)?)?;
+    value::broadcast(left, right, f)
+}
+
+// min(x) or min(A, {}, axis) reduce like `sum`; min(a, b) is element-wise with broadcasting
+fn min(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() == 2 {
+        let (a, b) = (numeric_arg(interpreter, &args, 0, "min")?, numeric_arg(interpreter, &args, 1, "min")?);
+        return broadcast(interpreter, &a, &b, |val1, val2| pick("min", val1, val2, Ordering::Less));
+    }
+    let axis = extreme_axis(&args, "min")?;
+    reduce(interpreter, &args[0], axis, "min", |items, _| Ok(items[stats::argmin(items, "min")?].clone()))
+}
+
+// max(x) or max(A, {}, axis) reduce like `sum`; max(a, b) is element-wise with broadcasting
+fn max(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    if args.len() == 2 {
+        let (a, b) = (numeric_arg(interpreter, &args, 0, "max")?, numeric_arg(interpreter, &args, 1, "max")?);
+        return broadcast(interpreter, &a, &b, |val1, val2| pick("max", val1, val2, Ordering::Greater));
+    }
+    let axis = extreme_axis(&args, "max")?;
+    reduce(interpreter, &args[0], axis, "max", |items, _| Ok(items[stats::argmax(items, "max")?].clone()))
+}
+
+// clamp(x, lo, hi), element-wise with broadcasting
+fn clamp(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let (x, lo, hi) = (numeric_arg(interpreter, &args, 0, "clamp")?, numeric_arg(interpreter, &args, 1, "clamp")?, numeric_arg(interpreter, &args, 2, "clamp")?);
+    broadcast(interpreter, &lo, &hi, |lo, hi| match lo.compare(hi) {
+        Some(Ordering::Greater) => Err(format!("clamp expects lo <= hi, got {} and {}", lo, hi)),
+        _ => Ok(Scalar::Int(0)),
+    })?;
+    let above = broadcast(interpreter, &x, &lo, |val, lo| pick("clamp", val, lo, Ordering::Greater))?;
+    broadcast(interpreter, &above, &hi, |val, hi| pick("clamp", val, hi, Ordering::Less))
+}
+
+// Optional axis argument: 0 works down each column, 1 along each row
+fn axis_arg(args: &[Value], index: usize, function: &str) -> Result<Option<usize>, String> {
+    match args.get(index) {
+        None => Ok(None),
+        Some(Value::Scalar(Scalar::Int(axis))) if *axis == 0 || *axis == 1 => Ok(Some(*axis as usize)),
+        Some(other) => Err(format!("{} expects axis 0 or 1, got {}", function, other)),
+    }
+}
+
+// The axis of min(A, {}, axis), where the empty vector tells it apart from min(a, b)
+fn extreme_axis(args: &[Value], function: &str) -> Result<Option<usize>, String> {
+    match args.get(1) {
+        None => Ok(None),
+        Some(Value::Vector(items)) if items.is_empty() => axis_arg(args, 2, function),
+        Some(_) => Err(format!("{} expects {{}} as argument 2 when given an axis", function)),
+    }
+}
+
+// Elements of a reduction's argument; vectors only have axis 0
+fn elements_arg(interpreter: &mut Interpreter, value: &Value, axis: Option<usize>, function: &str) -> Result<Matrix, String> {
+    match (value, axis) {
+        (Value::Scalar(_) | Value::Vector(_), Some(1)) => Err(format!("{} expects axis 0 for a {}", function, value.type_name())),
+        (Value::Scalar(val), _) => Ok(Matrix::new(1, 1, vec![val.clone()])),
+        (Value::Vector(items), _) => Ok(Matrix::new(items.len(), 1, items.clone())),
+        (Value::Matrix(matrix), Some(_)) => Ok(matrix.clone()),
+        (Value::Sparse(sparse), Some(_)) => densify(interpreter, sparse),
+        // Without an axis, a matrix reduces as one long column
+        (Value::Matrix(_) | Value::Sparse(_), None) => {
+            let items = matrix_arg(interpreter, std::slice::from_ref(value), 0, function)?.data;
+            elements_arg(interpreter, &Value::Vector(items), None, function)
+        }
+        (other, _) => Err(format!("{} expects a number, vector or matrix, got {}", function, other.type_name())),
+    }
+}
+
+// Reduces all elements to a scalar, or each column (axis 0) or row (axis 1) of a matrix to a vector element
+fn reduce<F>(interpreter: &mut Interpreter, value: &Value, axis: Option<usize>, function: &str, f: F) -> Result<Value, String>
+    where F: Fn(&[Scalar], Arithmetic) -> Result<Scalar, String> {
+    let mode = interpreter.arithmetic();
+    let matrix = elements_arg(interpreter, value, axis, function)?;
+    let lanes = stats::lanes(&matrix, axis.unwrap_or(0));
+    match (axis, value) {
+        (Some(_), Value::Matrix(_) | Value::Sparse(_)) => Ok(Value::Vector(lanes.iter().map(|lane| f(lane, mode)).collect::<Result<Vec<Scalar>, String>>()?)),
+        _ => Ok(Value::Scalar(f(&lanes[0], mode)?)),
+    }
+}
+
+// Like `reduce`, but keeps every running result: a vector for vectors and whole
+// matrices (in row-major order), a matrix of the same shape along an axis
+fn accumulate<F>(interpreter: &mut Interpreter, value: &Value, axis: Option<usize>, function: &str, f: F) -> Result<Value, String>
+    where F: Fn(&[Scalar], Arithmetic) -> Result<Vec<Scalar>, String> {
+    let mode = interpreter.arithmetic();
+    let matrix = elements_arg(interpreter, value, axis, function)?;
+    match (axis, value) {
+        (Some(axis), Value::Matrix(_) | Value::Sparse(_)) => {
+            let lanes = stats::lanes(&matrix, axis).iter().map(|lane| f(lane, mode)).collect::<Result<Vec<Vec<Scalar>>, String>>()?;
+            let data = (0..matrix.rows * matrix.cols).map(|k| {
+                let (row, col) = (k / matrix.cols, k % matrix.cols);
+                match axis {
+                    0 => lanes[col][row].clone(),
+                    _ => lanes[row][col].clone(),
+                }
+            }).collect();
+            Ok(Value::Matrix(Matrix::new(matrix.rows, matrix.cols, data)))
+        }
+        _ => Ok(Value::Vector(f(&matrix.data, mode)?)),
+    }
+}
+
+// sum(x[, axis])
+fn sum(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    reduce(interpreter, &args[0], axis_arg(&args, 1, "sum")?, "sum", stats::sum)
+}
+
+// prod(x[, axis])
+fn prod(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    reduce(interpreter, &args[0], axis_arg(&args, 1, "prod")?, "prod", stats::prod)
+}
+
+// mean(x[, axis])
+fn mean(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    reduce(interpreter, &args[0], axis_arg(&args, 1, "mean")?, "mean", stats::mean)
+}
+
+// median(x[, axis])
+fn median(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    reduce(interpreter, &args[0], axis_arg(&args, 1, "median")?, "median", stats::median)
+}
+
+// var(x[, axis]), the sample variance
+fn var(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    reduce(interpreter, &args[0], axis_arg(&args, 1, "var")?, "var", stats::var)
+}
+
+// std(x[, axis]), the sample standard deviation
+fn std(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    reduce(interpreter, &args[0], axis_arg(&args, 1, "std")?, "std", stats::std)
+}
+
+// argmin(x[, axis]), the 0-based index of the first smallest element; row-major for a whole matrix
+fn argmin(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    reduce(interpreter, &args[0], axis_arg(&args, 1, "argmin")?, "argmin", |items, _| Ok(Scalar::Int(stats::argmin(items, "argmin")? as i128)))
+}
+
+// argmax(x[, axis]), the 0-based index of the first largest element; row-major for a whole matrix
+fn argmax(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    reduce(interpreter, &args[0], axis_arg(&args, 1, "argmax")?, "argmax", |items, _| Ok(Scalar::Int(stats::argmax(items, "argmax")? as i128)))
+}
+
+// cumsum(x[, axis])
+fn cumsum(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    accumulate(interpreter, &args[0], axis_arg(&args, 1, "cumsum")?, "cumsum", stats::cumsum)
+}
+
+// cumprod(x[, axis])
+fn cumprod(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    accumulate(interpreter, &args[0], axis_arg(&args, 1, "cumprod")?, "cumprod", stats::cumprod)
+}
+
+// cov(A), the covariance of the columns of A, whose rows are observations
+fn cov(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    Ok(Value::Matrix(stats::cov(&matrix_arg(interpreter, &args, 0, "cov")?, interpreter.arithmetic())?))
+}
+
+// corrcoef(A), the correlation coefficients of the columns of A
+fn corrcoef(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    Ok(Value::Matrix(stats::corrcoef(&matrix_arg(interpreter, &args, 0, "corrcoef")?, interpreter.arithmetic())?))
+}
+
+// Builds a scalar, a vector of n or an r x c matrix from zero, one or two size
+// arguments, checking the memory limit before anything is allocated
+fn generate<F>(interpreter: &mut Interpreter, sizes: &[Value], function: &str, mut f: F) -> Result<Value, String>
+    where F: FnMut(&mut Interpreter) -> Result<Scalar, String> {
+    let dims = sizes.iter().map(|size| index_arg(size, function)).collect::<Result<Vec<usize>, String>>()?;
+    let too_large = || format!("{} cannot allocate {} elements", function, dims.iter().map(usize::to_string).collect::<Vec<String>>().join("x"));
+    let elements = dims.iter().try_fold(1usize, |product, dim| product.checked_mul(*dim)).ok_or_else(too_large)?;
+    interpreter.reserve_elements(elements)?;
+    let mut data = Vec::new();
+    data.try_reserve_exact(elements).map_err(|_| too_large())?;
+    for _ in 0..elements {
+        data.push(f(interpreter)?);
+    }
+    match dims.len() {
+        0 => Ok(Value::Scalar(data.remove(0))),
+        1 => Ok(Value::Vector(data)),
+        _ => Ok(Value::Matrix(Matrix::new(dims[0], dims[1], data))),
+    }
+}
+
+// zeros(n) or zeros(r, c)
+fn zeros(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    generate(interpreter, &args, "zeros", |_| Ok(Scalar::Int(0)))
+}
+
+// ones(n) or ones(r, c)
+fn ones(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    generate(interpreter, &args, "ones", |_| Ok(Scalar::Int(1)))
+}
+
+// eye(n), the n x n identity matrix
+fn eye(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let n = index_arg(&args[0], "eye")?;
+    let mut k = 0;
+    generate(interpreter, &[args[0].clone(), args[0].clone()], "eye", |_| {
+        k += 1;
+        Ok(Scalar::Int(((k - 1) % (n + 1) == 0) as i128))
+    })
+}
+
+// diag(v), a square matrix with v on its diagonal, or diag(A), the diagonal of A
+fn diag(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    match &args[0] {
+        Value::Vector(items) => {
+            let n = Value::Scalar(Scalar::Int(items.len() as i128));
+            let mut k = 0;
+            generate(interpreter, &[n.clone(), n], "diag", |_| {
+                let (row, col) = (k / items.len(), k % items.len());
+                k += 1;
+                Ok(if row == col { items[row].clone() } else { Scalar::Int(0) })
+            })
+        }
+        Value::Matrix(_) | Value::Sparse(_) => {
+            let matrix = matrix_arg(interpreter, &args, 0, "diag")?;
+            Ok(Value::Vector((0..matrix.rows.min(matrix.cols)).map(|i| matrix.get(i, i).clone()).collect()))
+        }
+        other => Err(format!("diag expects a vector or matrix, got {}", other.type_name())),
+    }
+}
+
+fn number_arg(args: &[Value], index: usize, function: &str) -> Result<Scalar, String> {
+    match &args[index] {
+        Value::Scalar(val) if !val.is_complex() => Ok(val.clone()),
+        other => Err(format!("{} expects a real number as argument {}, got {}", function, index + 1, other)),
+    }
+}
+
+// linspace(a, b, n), n evenly spaced floats from a to b inclusive
+fn linspace(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let (start, stop) = (number_arg(&args, 0, "linspace")?.as_f64(), number_arg(&args, 1, "linspace")?.as_f64());
+    let n = index_arg(&args[2], "linspace")?;
+    let mut k = 0;
+    generate(interpreter, &args[2..], "linspace", |_| {
+        // The first and last points are exactly `start` and `stop` rather than computed approximations
+        let val = match k {
+            0 => start,
+            k if k + 1 == n => stop,
+            k => start + (stop - start) * k as f64 / (n - 1) as f64,
+        };
+        k += 1;
+        Ok(Scalar::Float(val))
+    })
+}
+
+// arange(stop), arange(start, stop) or arange(start, stop, step): from start up
+// to but excluding stop; exact unless an argument is a float
+fn arange(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let mode = interpreter.arithmetic();
+    let numbers = (0..args.len()).map(|index| number_arg(&args, index, "arange")).collect::<Result<Vec<Scalar>, String>>()?;
+    let (start, stop, step) = match numbers.as_slice() {
+        [stop] => (Scalar::Int(0), stop.clone(), Scalar::Int(1)),
+        [start, stop] => (start.clone(), stop.clone(), Scalar::Int(1)),
+        [start, stop, step] => (start.clone(), stop.clone(), step.clone()),
+        _ => unreachable!(),
+    };
+    if step.is_zero() {
+        return Err("arange expects a non-zero step".to_string());
+    }
+    // ceil((stop - start) / step), or 0 when stop lies behind start
+    let count = match (start.as_rational(), stop.as_rational(), step.as_rational()) {
+        (Some(start), Some(stop), Some(step)) => {
+            let count = ((stop - start) / step).ceil().to_integer();
+            // Counts past i128 are far too large when positive and empty when negative
+            count.to_i128().unwrap_or(if count.is_negative() { 0 } else { i128::MAX })
+        }
+        _ => ((stop.as_f64() - start.as_f64()) / step.as_f64()).ceil() as i128,
+    };
+    let count = Value::Scalar(Scalar::Int(count.max(0)));
+    let mut next = match numbers.iter().any(|number| matches!(number, Scalar::Float(_))) {
+        true => Scalar::Float(start.as_f64()),
+        false => start,
+    };
+    generate(interpreter, &[count], "arange", |_| {
+        let val = next.clone();
+        next = next.arithmetic_in(mode, TokenType::PLUS, &step)?;
+        Ok(val)
+    })
+}
+
+// Rows, columns and row-major elements, with a vector as a single row
+fn rows_of(interpreter: &mut Interpreter, value: &Value, function: &str) -> Result<(usize, usize, Vec<Scalar>), String> {
+    match value {
+        Value::Scalar(val) => Ok((1, 1, vec![val.clone()])),
+        Value::Vector(items) => Ok((1, items.len(), items.clone())),
+        Value::Matrix(matrix) => Ok((matrix.rows, matrix.cols, matrix.data.clone())),
+        Value::Sparse(sparse) => {
+            let dense = densify(interpreter, sparse)?;
+            rows_of(interpreter, &Value::Matrix(dense), function)
+        }
+        other => Err(format!("{} expects a number, vector or matrix, got {}", function, other.type_name())),
+    }
+}
+
+// repmat(x, n) repeats x into a vector; repmat(x, m, n) tiles it m times down and n times across
+fn repmat(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let (rows, cols, data) = rows_of(interpreter, &args[0], "repmat")?;
+    let (down, across) = match args.len() {
+        2 => (1, index_arg(&args[1], "repmat")?),
+        _ => (index_arg(&args[1], "repmat")?, index_arg(&args[2], "repmat")?),
+    };
+    let product = |a: usize, b: usize| a.checked_mul(b).map(|n| Value::Scalar(Scalar::Int(n as i128)))
+        .ok_or_else(|| format!("repmat cannot allocate {}x{} copies of {} elements", down, across, data.len()));
+    let sizes = match args.len() {
+        2 => vec![product(data.len(), across)?],
+        _ => vec![product(rows, down)?, product(cols, across)?],
+    };
+    let width = cols * across;
+    let mut k = 0;
+    generate(interpreter, &sizes, "repmat", |_| {
+        let (row, col) = match args.len() {
+            2 => (0, k % data.len()),
+            _ => ((k / width) % rows, (k % width) % cols),
+        };
+        k += 1;
+        Ok(data[row * cols + col].clone())
+    })
+}
+
+// reshape(x, n) into a vector or reshape(x, r, c) into a matrix, keeping the row-major order of the elements
+fn reshape(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let (_, _, data) = rows_of(interpreter, &args[0], "reshape")?;
+    let dims = args[1..].iter().map(|size| index_arg(size, "reshape")).collect::<Result<Vec<usize>, String>>()?;
+    if dims.iter().try_fold(1usize, |product, dim| product.checked_mul(*dim)) != Some(data.len()) {
+        let shape = dims.iter().map(usize::to_string).collect::<Vec<String>>().join("x");
+        return Err(format!("reshape cannot fit {} elements into {}", data.len(), shape));
+    }
+    let mut items = data.into_iter();
+    generate(interpreter, &args[1..], "reshape", |_| Ok(items.next().unwrap_or(Scalar::Int(0))))
+}
+
+// hstack(a, b, ...) joins vectors end to end; with a matrix, vectors join as columns
+fn hstack(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    if args.iter().all(|arg| matches!(arg, Value::Scalar(_) | Value::Vector(_))) {
+        let mut items = Vec::new();
+        for (index, arg) in args.iter().enumerate() {
+            items.extend(rows_of(interpreter, arg, "hstack").map_err(|_| format!("hstack expects numbers, vectors or matrices, got {} as argument {}", arg.type_name(), index + 1))?.2);
+        }
+        interpreter.reserve_elements(items.len())?;
+        return Ok(Value::Vector(items));
+    }
+    let columns = args.iter().map(|arg| match arg {
+        Value::Vector(items) => Ok((items.len(), 1, items.clone())),
+        other => rows_of(interpreter, other, "hstack"),
+    }).collect::<Result<Vec<(usize, usize, Vec<Scalar>)>, String>>()?;
+    let rows = columns[0].0;
+    if let Some((other, _, _)) = columns.iter().find(|(other, _, _)| *other != rows) {
+        return Err(format!("hstack expects the same number of rows, got {} and {}", rows, other));
+    }
+    let cols = columns.iter().map(|(_, cols, _)| cols).sum();
+    interpreter.reserve_elements(Matrix::size(rows, cols)?)?;
+    let mut data = Matrix::storage(rows, cols)?;
+    for row in 0..rows {
+        for (_, width, items) in &columns {
+            data.extend_from_slice(&items[row * width..(row + 1) * width]);
+        }
+    }
+    Ok(Value::Matrix(Matrix::new(rows, cols, data)))
+}
+
+// vstack(a, b, ...) stacks matrices and vectors, which become rows, on top of each other
+fn vstack(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let blocks = args.iter().map(|arg| rows_of(interpreter, arg, "vstack")).collect::<Result<Vec<(usize, usize, Vec<Scalar>)>, String>>()?;
+    let cols = blocks[0].1;
+    if let Some((_, other, _)) = blocks.iter().find(|(_, other, _)| *other != cols) {
+        return Err(format!("vstack expects the same number of columns, got {} and {}", cols, other));
+    }
+    let rows = blocks.iter().map(|(rows, _, _)| rows).sum();
+    interpreter.reserve_elements(Matrix::size(rows, cols)?)?;
+    let mut data = Matrix::storage(rows, cols)?;
+    data.extend(blocks.into_iter().flat_map(|(_, _, data)| data));
+    Ok(Value::Matrix(Matrix::new(rows, cols, data)))
+}
+
+// seed(n) restarts the random number generator, so the numbers that follow repeat from run to run
+fn seed(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let seed = match &args[0] {
+        Value::Scalar(Scalar::Int(val)) => *val as u64,
+        other => return Err(format!("seed expects an integer, got {}", other)),
+    };
+    *interpreter.rng() = Rng::new(seed);
+    Ok(Value::Nil)
+}
+
+// rand(), rand(n) or rand(r, c), uniform in [0, 1)
+fn rand(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    generate(interpreter, &args, "rand", |interpreter| Ok(Scalar::Float(interpreter.rng().uniform())))
+}
+
+// randn(), randn(n) or randn(r, c), standard normal
+fn randn(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    generate(interpreter, &args, "randn", |interpreter| Ok(Scalar::Float(interpreter.rng().normal())))
+}
+
+// randint(lo, hi[, n[, c]]), integers from lo up to but excluding hi
+fn randint(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let (lo, hi) = match (&args[0], &args[1]) {
+        (Value::Scalar(Scalar::Int(lo)), Value::Scalar(Scalar::Int(hi))) => (*lo, *hi),
+        (lo, hi) => return Err(format!("randint expects integer bounds, got {} and {}", lo, hi)),
+    };
+    let span = match hi.checked_sub(lo).map(u64::try_from) {
+        Some(Ok(span)) if span > 0 => span,
+        Some(Ok(_)) => return Err(format!("randint expects lo < hi, got {} and {}", lo, hi)),
+        _ => return Err(format!("randint cannot draw from a range as wide as {} to {}", lo, hi)),
+    };
+    generate(interpreter, &args[2..], "randint", |interpreter| Ok(Scalar::Int(lo + interpreter.rng().below(span) as i128)))
+}
+
+// assert(condition[, message]) fails unless every element of the condition is nonzero
+fn assert(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let holds = match numeric_arg(interpreter, &args, 0, "assert")? {
+        Value::Scalar(val) => !val.is_zero(),
+        Value::Vector(items) => items.iter().all(|item| !item.is_zero()),
+        Value::Matrix(matrix) => matrix.data.iter().all(|item| !item.is_zero()),
+        _ => false,
+    };
+    match (holds, args.get(1)) {
+        (true, _) => Ok(Value::Nil),
+        (false, Some(_)) => Err(format!("{}: {}", ASSERTION_FAILED, string_arg(&args, 1, "assert")?)),
+        (false, None) => Err(ASSERTION_FAILED.to_string()),
+    }
+}
+
+// Shape and elements of a number, vector or matrix; values are only compared when their shapes agree
+fn shaped(interpreter: &mut Interpreter, args: &[Value], index: usize, function: &str) -> Result<(String, Vec<Scalar>), String> {
+    match numeric_arg(interpreter, args, index, function)? {
+        Value::Vector(items) => Ok((format!("vector[{}]", items.len()), items)),
+        Value::Matrix(matrix) => Ok((format!("matrix[{}, {}]", matrix.rows, matrix.cols), matrix.data)),
+        Value::Scalar(val) => Ok(("scalar".to_string(), vec![val])),
+        other => Err(format!("{} expects a number, vector or matrix as argument {}, got {}", function, index + 1, other.type_name())),
+    }
+}
+
+// Compared values of different shapes, as a failed assertion
+fn shapes_differ(left: &str, right: &str) -> Result<Value, String> {
+    Err(format!("{}: shapes differ, {} and {}", ASSERTION_FAILED, left, right))
+}
+
+// assert_eq(a, b) fails unless a and b are equal strings, or have the same shape and equal
+// elements; an integer equals the float or rational of the same value
+fn assert_eq(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let equal = match (&args[0], &args[1]) {
+        (Value::Str(left), Value::Str(right)) => left == right,
+        _ => {
+            let ((left_shape, left), (right_shape, right)) = (shaped(interpreter, &args, 0, "assert_eq")?, shaped(interpreter, &args, 1, "assert_eq")?);
+            if left_shape != right_shape {
+                return shapes_differ(&left_shape, &right_shape);
+            }
+            left.iter().zip(right.iter()).all(|(left, right)| match left.is_complex() || right.is_complex() {
+                true => left.as_complex() == right.as_complex(),
+                false => left.compare(right) == Some(Ordering::Equal),
+            })
+        }
+    };
+    match equal {
+        true => Ok(Value::Nil),
+        false => Err(format!("{}: {} != {}", ASSERTION_FAILED, args[0], args[1])),
+    }
+}
+
+// assert_close(a, b, tol) fails unless a and b have the same shape and no elements differ by more than tol
+fn assert_close(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, String> {
+    let ((left_shape, left), (right_shape, right)) = (shaped(interpreter, &args, 0, "assert_close")?, shaped(interpreter, &args, 1, "assert_close")?);
+    let tolerance = number_arg(&args, 2, "assert_close")?.as_f64();
+    if left_shape != right_shape {
+        return shapes_differ(&left_shape, &right_shape);
+    }
+    // NaN differences are never within the tolerance
+    let worst = left.iter().zip(right.iter())
+        .map(|(left, right)| (left.as_complex() - right.as_complex()).norm())
+        .fold(0.0, |worst: f64, difference| if difference.is_nan() || worst.is_nan() { f64::NAN } else { worst.max(difference) });
+    match worst <= tolerance {
+        true => Ok(Value::Nil),
+        false => Err(format!("{}: {} and {} differ by {}, more than {}", ASSERTION_FAILED, args[0], args[1], worst, tolerance)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lookup;
+    use crate::core::dodo::environment::Environment;
+    use crate::core::dodo::interpreter::Interpreter;
+    use crate::core::dodo::value::{Scalar, Value};
+    use num_bigint::BigInt;
+
+    fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+        (lookup(name).unwrap().function)(&mut Interpreter::new(Environment::new()), args)
+    }
+
+    #[test]
+    fn sizes_past_usize_are_errors() {
+        let size = Value::Scalar(Scalar::Int(u64::MAX as i128 + 3));
+        assert_eq!(call("zeros", vec![size]).unwrap_err(), "zeros argument 18446744073709551618 is too large");
+        assert!(call("zeros", vec![Value::int(-1)]).unwrap_err().contains("expects a non-negative integer"));
+    }
+
+    #[test]
+    fn shapes_whose_size_overflows_are_errors() {
+        let big = Value::int(1 << 33);
+        for name in ["zeros", "ones", "rand"].iter() {
+            let error = call(name, vec![big.clone(), big.clone()]).unwrap_err();
+            assert_eq!(error, format!("{} cannot allocate 8589934592x8589934592 elements", name));
+        }
+        assert_eq!(call("eye", vec![big.clone()]).unwrap_err(), "eye cannot allocate 8589934592x8589934592 elements");
+
+        let tile = Value::Vector(vec![Scalar::Int(1), Scalar::Int(2)]);
+        let error = call("repmat", vec![tile.clone(), Value::int(1), Value::int(usize::MAX as i128)]).unwrap_err();
+        assert_eq!(error, format!("repmat cannot allocate 1x{} copies of 2 elements", usize::MAX));
+        assert!(call("repmat", vec![tile, Value::int(usize::MAX as i128)]).is_err());
+    }
+
+    #[test]
+    fn linspace_with_zero_and_one_points() {
+        let linspace = |n| call("linspace", vec![Value::int(2), Value::int(5), Value::int(n)]).unwrap();
+        assert_eq!(linspace(0), Value::Vector(vec![]));
+        assert_eq!(linspace(1), Value::Vector(vec![Scalar::Float(2.0)]));
+        assert_eq!(linspace(2), Value::Vector(vec![Scalar::Float(2.0), Scalar::Float(5.0)]));
+    }
+
+    #[test]
+    fn arange_counts_past_i128() {
+        let huge = |sign: i32| Value::Scalar(Scalar::big(BigInt::from(10).pow(41) * sign));
+        assert_eq!(call("arange", vec![Value::int(0), huge(-1), Value::int(1)]).unwrap(), Value::Vector(vec![]));
+        assert_eq!(call("arange", vec![Value::int(0), huge(1), Value::int(-1)]).unwrap(), Value::Vector(vec![]));
+        assert!(call("arange", vec![Value::int(0), huge(1), Value::int(1)]).unwrap_err().contains("too large"));
+    }
+}
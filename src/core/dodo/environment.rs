@@ -1,36 +1,207 @@
-use crate::core::ast::stmt::Stmt;
-use crate::core::ast::expr::Expr;
 use crate::core::token::token::Token;
-use crate::core::token::token::TokenType;
-use crate::core::dodo::error_types::DodoParseError;
-use crate::core::dodo::error_types::throw_error;
+use crate::core::dodo::error_types::DodoRuntimeError;
+use crate::core::dodo::value::Value;
 use std::collections::HashMap;
+use std::mem;
 
+// Global values plus one frame of locals per active function call
 pub struct Environment {
-    pub values: HashMap<String, Vec<i128>>
+    pub values: HashMap<String, Value>,
+    frames: Vec<HashMap<String, Value>>,
+    // Declared variables for the globals and then each frame: the kind that assignments
+    // must keep and whether they may be assigned at all; parameters and functions have none
+    kinds: Vec<HashMap<String, Declared>>,
+    // Imported modules bound to a name among the globals
+    namespaces: HashMap<String, usize>,
+    // Globals of each module, the main program being 0; those of the running module are
+    // the ones above, and its slot here stays empty until another module runs
+    modules: Vec<Option<Globals>>,
+    module: usize,
+}
+
+#[derive(Default)]
+struct Globals {
+    values: HashMap<String, Value>,
+    kinds: HashMap<String, Declared>,
+    namespaces: HashMap<String, usize>,
+}
+
+struct Declared {
+    kind: &'static str,
+    mutable: bool,
+}
+
+/// Call frames taken out by `hide_frames`, to be put back by `restore_frames`.
+pub struct HiddenFrames {
+    values: Vec<HashMap<String, Value>>,
+    kinds: Vec<HashMap<String, Declared>>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::new()
+    }
 }
 
 impl Environment {
     pub fn new() -> Self {
         Environment {
-            values: HashMap::new()
+            values: HashMap::new(),
+            frames: Vec::new(),
+            kinds: vec![HashMap::new()],
+            namespaces: HashMap::new(),
+            modules: vec![None],
+            module: 0,
         }
     }
-    pub fn define(&mut self, name: String, value: Vec<i128>) {
-        println!("{}", &name);
-        self.values.insert(name, value);
+    pub fn define(&mut self, name: String, value: Value) {
+        if let Some(kinds) = self.kinds.last_mut() {
+            kinds.remove(&name);
+        }
+        match self.frames.last_mut() {
+            Some(frame) => frame.insert(name, value),
+            None => self.values.insert(name, value),
+        };
+    }
+    /// Defines a variable whose later values must be of the same `kind`, as given by
+    /// `Value::kind`; an immutable one cannot be assigned to at all.
+    pub fn declare(&mut self, name: String, kind: &'static str, mutable: bool, value: Value) {
+        self.define(name.clone(), value);
+        if let Some(kinds) = self.kinds.last_mut() {
+            kinds.insert(name, Declared { kind, mutable });
+        }
     }
 
-    pub fn assign(&mut self, name: Token, values: Vec<i128>) {
-        // todo handle different types
-        if self.values.contains_key(&name.val) {
-            self.values.insert(name.val, values);
+    pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), DodoRuntimeError> {
+        let (scope, kinds) = match self.frames.last_mut() {
+            Some(frame) if frame.contains_key(&name.val) => (frame, self.kinds.last()),
+            _ => (&mut self.values, self.kinds.first()),
+        };
+        if let Some(declared) = kinds.and_then(|kinds| kinds.get(&name.val)) {
+            if !declared.mutable {
+                return Err(DodoRuntimeError::new(name.clone(), format!("Cannot assign to immutable '{}'", name.val)));
+            }
+            if declared.kind != value.kind() {
+                let message = format!("Cannot assign {} to '{}' declared as {}", value.kind(), name.val, declared.kind);
+                return Err(DodoRuntimeError::new(name.clone(), message));
+            }
+        }
+        if let Some(slot) = scope.get_mut(&name.val) {
+            *slot = value;
+            return Ok(());
         }
+        let message = format!("Undefined variable '{}'", name.val);
+        Err(DodoRuntimeError::new(name.clone(), message))
     }
-    pub fn get(&mut self, name: Token) -> Option<&Vec<i128>> {
-        if self.values.contains_key(&name.val) {
-            return self.values.get(&name.val);
+    pub fn get(&self, name: &Token) -> Option<&Value> {
+        self.frames.last()
+            .and_then(|frame| frame.get(&name.val))
+            .or_else(|| self.values.get(&name.val))
+    }
+
+    /// Adds an imported module with no globals yet, returning its id.
+    pub fn add_module(&mut self) -> usize {
+        self.modules.push(Some(Globals::default()));
+        self.modules.len() - 1
+    }
+    /// The module whose globals lookups see.
+    pub fn module(&self) -> usize {
+        self.module
+    }
+    /// Makes the globals of module `id` the ones lookups see, returning the module that was running.
+    pub fn enter_module(&mut self, id: usize) -> usize {
+        let previous = self.module;
+        if id == previous {
+            return previous;
         }
-        return None;
+        let globals = self.modules[id].take().unwrap_or_default();
+        let running = Globals {
+            values: mem::replace(&mut self.values, globals.values),
+            kinds: mem::replace(&mut self.kinds[0], globals.kinds),
+            namespaces: mem::replace(&mut self.namespaces, globals.namespaces),
+        };
+        self.modules[previous] = Some(running);
+        self.module = id;
+        previous
+    }
+    pub fn bind_module(&mut self, namespace: String, id: usize) {
+        self.namespaces.insert(namespace, id);
+    }
+    /// The module bound to `namespace` among the running module's globals.
+    pub fn namespace(&self, namespace: &str) -> Option<usize> {
+        self.namespaces.get(namespace).copied()
     }
-}
\ No newline at end of file
+    /// A global of module `id`.
+    pub fn member(&self, id: usize, name: &str) -> Option<&Value> {
+        match id == self.module {
+            true => self.values.get(name),
+            false => self.modules.get(id)?.as_ref()?.values.get(name),
+        }
+    }
+
+    pub fn push_frame(&mut self) {
+        self.frames.push(HashMap::new());
+        self.kinds.push(HashMap::new());
+    }
+    pub fn pop_frame(&mut self) {
+        self.frames.pop();
+        self.kinds.pop();
+    }
+    /// Detaches the locals of calls deeper than `depth` so lookups see that frame as the innermost.
+    pub fn hide_frames(&mut self, depth: usize) -> HiddenFrames {
+        let keep = depth.min(self.frames.len());
+        HiddenFrames {
+            values: self.frames.split_off(keep),
+            kinds: self.kinds.split_off(keep + 1),
+        }
+    }
+    pub fn restore_frames(&mut self, hidden: HiddenFrames) {
+        self.frames.extend(hidden.values);
+        self.kinds.extend(hidden.kinds);
+    }
+    /// Values visible in call frame `depth`: the globals at 0, then the locals of each active call.
+    pub fn scope(&self, depth: usize) -> Option<&HashMap<String, Value>> {
+        match depth {
+            0 => Some(&self.values),
+            depth => self.frames.get(depth - 1),
+        }
+    }
+    /// Scalars held by vectors and matrices in every scope.
+    pub fn elements(&self) -> usize {
+        self.frames.iter()
+            .chain(std::iter::once(&self.values))
+            .chain(self.modules.iter().flatten().map(|globals| &globals.values))
+            .flat_map(|scope| scope.values())
+            .map(Value::elements)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Environment;
+    use crate::core::dodo::value::Value;
+    use crate::core::token::token::{Token, TokenType};
+
+    #[test]
+    fn assignments_keep_the_declared_kind() {
+        let name = |val: &str| Token::new(TokenType::IDENT, val.to_string());
+        let mut env = Environment::new();
+        env.declare("x".to_string(), "scalar", true, Value::int(0));
+        env.declare("n".to_string(), "scalar", false, Value::int(1));
+        env.define("f".to_string(), Value::Nil);
+
+        assert!(env.assign(&name("x"), Value::float(2.5)).is_ok());
+        let error = env.assign(&name("x"), Value::Vector(Vec::new())).unwrap_err();
+        assert_eq!(error.message, "Cannot assign vector to 'x' declared as scalar");
+        assert_eq!(env.assign(&name("n"), Value::int(2)).unwrap_err().message, "Cannot assign to immutable 'n'");
+
+        // Parameters take any value, and a local declaration shadows a global only inside its frame
+        env.push_frame();
+        env.define("x".to_string(), Value::int(1));
+        assert!(env.assign(&name("x"), Value::Str("a".to_string())).is_ok());
+        env.pop_frame();
+        assert!(env.assign(&name("f"), Value::int(3)).is_ok());
+        assert!(env.assign(&name("x"), Value::Str("a".to_string())).is_err());
+    }
+}
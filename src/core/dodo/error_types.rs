@@ -1,31 +1,151 @@
 use std::fmt;
+use std::time::Duration;
 use crate::core::token::token::Token;
 use crate::core::token::token::TokenType;
 
-// Custom error type; can be any type which defined in the current crate
-// 💡 In here, we use a simple "unit struct" to simplify the example
-pub struct DodoParseError;
+// Error raised while building the syntax tree; carries the offending token
+#[derive(Debug, Clone, PartialEq)]
+pub struct DodoParseError {
+    pub token: Token,
+    pub message: String,
+}
+
+impl DodoParseError {
+    pub fn new(token: Token, message: String) -> Self {
+        DodoParseError {
+            token,
+            message,
+        }
+    }
+}
 
 // Implement std::fmt::Display for ParseError
 impl fmt::Display for DodoParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "An Error Occurred, Please Try Again!") // user-facing output
+        if self.token.line > 0 {
+            write!(f, "[line {}] ", self.token.line)?;
+        }
+        match self.token.token_type {
+            TokenType::EOF => write!(f, "Error at end: {}", self.message),
+            TokenType::NewLine => write!(f, "Error at end of line: {}", self.message),
+            _ => write!(f, "Error at '{}': {}", self.token.val, self.message)
+        }
     }
 }
 
-// Implement std::fmt::Debug for ParseError
-impl fmt::Debug for DodoParseError {
+// Which of the interpreter's limits stopped a program, with the configured bound
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitExceeded {
+    Steps(u64),
+    Memory(usize),
+    Depth(usize),
+    Timeout(Duration),
+    Cancelled,
+}
+
+impl fmt::Display for LimitExceeded {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{{ file: {}, line: {} }}", file!(), line!()) // programmer-facing output
+        match self {
+            LimitExceeded::Steps(max) => write!(f, "more than {} steps", max),
+            LimitExceeded::Memory(max) => write!(f, "more than {} bytes of vectors and matrices", max),
+            LimitExceeded::Depth(max) => write!(f, "more than {} nested calls", max),
+            LimitExceeded::Timeout(max) => write!(f, "ran for more than {} ms", max.as_millis()),
+            LimitExceeded::Cancelled => write!(f, "cancelled"),
+        }
     }
 }
 
-pub fn throw_error(token: Token, message: String) {
-    match token.token_type {
-        TokenType::EOF => println!("At end"),
-        _ => println!("An Error Occurred, Please Try Again!")
+// Error raised while evaluating statements; `limit` is set when a resource limit stopped the program
+#[derive(Debug, Clone, PartialEq)]
+pub struct DodoRuntimeError {
+    pub token: Token,
+    pub message: String,
+    pub limit: Option<LimitExceeded>,
+}
+
+impl DodoRuntimeError {
+    pub fn new(token: Token, message: String) -> Self {
+        DodoRuntimeError {
+            token,
+            message,
+            limit: None,
+        }
+    }
+
+    pub fn limit_exceeded(token: Token, limit: LimitExceeded) -> Self {
+        DodoRuntimeError {
+            token,
+            message: limit.to_string(),
+            limit: Some(limit),
+        }
+    }
+}
+
+impl fmt::Display for DodoRuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.token.line > 0 {
+            write!(f, "[line {}] ", self.token.line)?;
+        }
+        match self.limit {
+            Some(_) => write!(f, "Limit exceeded: {}", self.message),
+            None => write!(f, "Runtime error: {}", self.message),
+        }
+    }
+}
+
+// Error found by the static checker before a program runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct DodoTypeError {
+    pub token: Token,
+    pub message: String,
+}
+
+impl DodoTypeError {
+    pub fn new(token: Token, message: String) -> Self {
+        DodoTypeError {
+            token,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for DodoTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.token.line > 0 {
+            write!(f, "[line {}] ", self.token.line)?;
+        }
+        write!(f, "Type error: {}", self.message)
+    }
+}
+
+// Error loading a file named by an import statement; carries the file name token
+#[derive(Debug, Clone, PartialEq)]
+pub struct DodoImportError {
+    pub token: Token,
+    pub message: String,
+}
+
+impl DodoImportError {
+    pub fn new(token: Token, message: String) -> Self {
+        DodoImportError {
+            token,
+            message,
+        }
     }
 }
 
+impl fmt::Display for DodoImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.token.line > 0 {
+            write!(f, "[line {}] ", self.token.line)?;
+        }
+        write!(f, "Import error: {}", self.message)
+    }
+}
+
+pub fn throw_error(token: Token, message: String) {
+    eprintln!("{}", DodoParseError::new(token, message));
+}
+
 #[cfg(test)]
-mod tests {}
\ No newline at end of file
+mod tests {}
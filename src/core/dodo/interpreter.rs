@@ -1,233 +1,851 @@
-use crate::core::ast::stmt::Stmt;
+use crate::core::ast::ast::{Program, Span};
+use crate::core::ast::stmt::{Block, DeclaredType, Stmt};
 use crate::core::ast::expr::Expr;
 use crate::core::token::token::Token;
 use crate::core::token::token::TokenType;
+use crate::core::dodo::builtins;
 use crate::core::dodo::environment::Environment;
-use crate::core::dodo::error_types::DodoParseError;
-use ndarray::{arr1, arr2, Array1};
+use crate::core::dodo::error_types::{DodoRuntimeError, LimitExceeded};
+use crate::core::dodo::limits::Limits;
+use crate::core::dodo::linalg;
+use crate::core::dodo::modules::Module;
+use crate::core::dodo::random::{self, Rng};
+use crate::core::dodo::sparse::SparseMatrix;
+use crate::core::dodo::value::{self, Arithmetic, Function, Matrix, Scalar, Value};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
+
+// Calls allowed at once when no depth limit is set, keeping runaway recursion off the native stack's end
+pub const DEFAULT_MAX_DEPTH: usize = 200;
+
+// Attaches the token being evaluated to an error from a value operation
+fn at<T>(token: &Token, result: Result<T, String>) -> Result<T, DodoRuntimeError> {
+    result.map_err(|message| DodoRuntimeError::new(token.clone(), message))
+}
+
+// How a statement finished; `Return` unwinds to the enclosing function call, `Break` and
+// `Continue` to the innermost loop or the one with the given label
+#[derive(Debug, PartialEq)]
+pub enum Flow {
+    Normal,
+    Return(Value),
+    Break(Option<String>),
+    Continue(Option<String>),
+}
+
+/// Observes execution, e.g. to stop at breakpoints. The hook is detached
+/// while it runs, so evaluating expressions from inside it does not re-enter it.
+pub trait Hook {
+    fn before_statement(&mut self, interpreter: &mut Interpreter, stmt: &Stmt, span: Span) -> Result<(), DodoRuntimeError>;
+
+    fn after_statement(&mut self, _interpreter: &Interpreter, _span: Span) {}
+
+    fn enter_call(&mut self, _name: &str) {}
+
+    fn exit_call(&mut self, _name: &str) {}
+
+    /// A vector or matrix value holding `elements` scalars was created.
+    fn allocated(&mut self, _elements: usize) {}
+}
+
+// Lets the owner of a hook keep a handle to it while the interpreter runs
+impl<T: Hook> Hook for Rc<RefCell<T>> {
+    fn before_statement(&mut self, interpreter: &mut Interpreter, stmt: &Stmt, span: Span) -> Result<(), DodoRuntimeError> {
+        self.borrow_mut().before_statement(interpreter, stmt, span)
+    }
+
+    fn after_statement(&mut self, interpreter: &Interpreter, span: Span) {
+        self.borrow_mut().after_statement(interpreter, span)
+    }
+
+    fn enter_call(&mut self, name: &str) {
+        self.borrow_mut().enter_call(name)
+    }
+
+    fn exit_call(&mut self, name: &str) {
+        self.borrow_mut().exit_call(name)
+    }
+
+    fn allocated(&mut self, elements: usize) {
+        self.borrow_mut().allocated(elements)
+    }
+}
+
+// An active call: the script itself at the bottom, then one frame per function call
+#[derive(Debug, PartialEq, Clone)]
+pub struct CallFrame {
+    pub name: String,
+    pub line: usize,
+}
 
 pub struct Interpreter {
-    env: Environment
+    env: Environment,
+    hook: Option<Box<dyn Hook>>,
+    frames: Vec<CallFrame>,
+    // Where `print` writes
+    output: Box<dyn Write>,
+    limits: Limits,
+    arithmetic: Arithmetic,
+    // Steps taken and the time to stop by in the current `interpret` call
+    steps: u64,
+    deadline: Option<Instant>,
+    // A limit a builtin ran into, reported once it returns
+    builtin_limit: Option<LimitExceeded>,
+    rng: Rng,
+    // Files the running program or module imports, and the environment's id for each module run so far
+    imports: HashMap<String, Rc<Module>>,
+    modules: HashMap<PathBuf, usize>,
 }
 
 impl Interpreter {
     pub fn new(env: Environment) -> Self {
         Interpreter {
-            env
+            env,
+            hook: None,
+            frames: vec![CallFrame { name: "<script>".to_string(), line: 0 }],
+            output: Box::new(io::stdout()),
+            limits: Limits::default(),
+            arithmetic: Arithmetic::default(),
+            steps: 0,
+            deadline: None,
+            builtin_limit: None,
+            rng: Rng::new(random::DEFAULT_SEED),
+            imports: HashMap::new(),
+            modules: HashMap::new(),
         }
     }
 
-    pub fn interpret(&mut self, stmts: Vec<Stmt>) {
-        for stmt in stmts {
-            self.statement(stmt);
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Sets how integer operations handle overflow.
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    /// The generator behind `rand`, `randn` and `randint`.
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    pub fn set_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hook = Some(hook);
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.env
+    }
+
+    /// Active calls, outermost first, each with the line it is executing.
+    pub fn call_stack(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn interpret(&mut self, program: &Program) -> Result<(), DodoRuntimeError> {
+        self.steps = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+        self.imports = program.imports.clone();
+        self.run_program(program)
+    }
+
+    fn run_program(&mut self, program: &Program) -> Result<(), DodoRuntimeError> {
+        for (stmt, span) in program.statements.iter().zip(program.spans.iter()) {
+            if let Flow::Return(_) = self.statement(stmt, *span)? {
+                break;
+            }
         }
+        Ok(())
     }
 
-    pub fn evaluate_literal(&mut self, val: i128) -> i128 {
-        return val;
+    /// Runs the body of a test block in its own frame, after `interpret` has run the program
+    /// around it. Test blocks are skipped when the program itself runs.
+    pub fn run_test(&mut self, name: &Token, body: &Block) -> Result<(), DodoRuntimeError> {
+        self.env.push_frame();
+        self.frames.push(CallFrame { name: format!("test \"{}\"", name.val), line: name.line });
+        let result = self.execute_block(body);
+        self.frames.pop();
+        self.env.pop_frame();
+        result.map(|_| ())
     }
 
-    pub fn evaluate_binary(&mut self, left: Expr, right: Expr, operator: Token) -> Expr {
-        println!("{} ---- {}", left, right);
-        if let Expr::Literal(val1) = left {
-            match right {
-                Expr::Literal(val2) => {
-                    match operator.token_type {
-                        TokenType::MINUS => return Expr::Literal(val1 - val2),
-                        TokenType::PLUS => return Expr::Literal(val1 + val2),
-                        TokenType::ASTERISK => return Expr::Literal(val1 * val2),
-                        TokenType::SLASH => return Expr::Literal(val1 / val2),
-                        _ => ()
-                    }
-                }
-                Expr::Vector(token, val2) => {
-                    let mut new_vector = vec![0; val2.len()];
-                    match operator.token_type {
-                        TokenType::ASTERISK => {
-                            for (i, item) in val2.iter().enumerate() {
-                                new_vector[i] = item * val1
-                            }
-                            return Expr::Vector(token, new_vector);
-                        }
-                        TokenType::SLASH => {
-                            for (i, item) in val2.iter().enumerate() {
-                                new_vector[i] = item / val1
-                            }
-                            return Expr::Vector(token, new_vector);
-                        }
-                        _ => ()
-                    }
+    // Runs an imported file the first time it is imported, in its own globals, returning its module id
+    fn import(&mut self, file: &Token) -> Result<usize, DodoRuntimeError> {
+        let module = match self.imports.get(&file.val) {
+            Some(module) => module.clone(),
+            None => return Err(DodoRuntimeError::new(file.clone(), format!("'{}' has not been loaded", file.val))),
+        };
+        if let Some(id) = self.modules.get(&module.path) {
+            return Ok(*id);
+        }
+        let id = self.env.add_module();
+        self.modules.insert(module.path.clone(), id);
+        let imports = mem::replace(&mut self.imports, module.program.imports.clone());
+        let hidden = self.env.hide_frames(0);
+        let previous = self.env.enter_module(id);
+        self.frames.push(CallFrame { name: file.val.clone(), line: 0 });
+        let result = self.run_program(&module.program);
+        self.frames.pop();
+        self.env.enter_module(previous);
+        self.env.restore_frames(hidden);
+        self.imports = imports;
+        result.map(|_| id)
+    }
+
+    fn member(&self, namespace: &Token, member: &Token) -> Result<Value, DodoRuntimeError> {
+        let id = match self.env.namespace(&namespace.val) {
+            Some(id) => id,
+            None => return Err(DodoRuntimeError::new(namespace.clone(), format!("Undefined module '{}'", namespace.val))),
+        };
+        match self.env.member(id, &member.val) {
+            Some(value) => Ok(value.clone()),
+            None => Err(DodoRuntimeError::new(member.clone(), format!("Module '{}' does not define '{}'", namespace.val, member.val))),
+        }
+    }
+
+    // Errors that are not about one token point at the line the innermost call is executing
+    fn limit_exceeded(&self, limit: LimitExceeded) -> DodoRuntimeError {
+        let line = self.frames.last().map_or(0, |frame| frame.line);
+        DodoRuntimeError::limit_exceeded(Token::at(TokenType::ILLEGAL, String::new(), line, 0), limit)
+    }
+
+    // Counts one step and checks the step, time and cancellation limits
+    fn step(&mut self) -> Result<(), DodoRuntimeError> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(self.limit_exceeded(LimitExceeded::Steps(max)));
+            }
+        }
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout) {
+            if Instant::now() >= deadline {
+                return Err(self.limit_exceeded(LimitExceeded::Timeout(timeout)));
+            }
+        }
+        if self.limits.cancelled() {
+            return Err(self.limit_exceeded(LimitExceeded::Cancelled));
+        }
+        Ok(())
+    }
+
+    // Checks that `elements` more scalars fit next to what the variables already hold
+    fn reserve(&self, elements: usize) -> Result<(), DodoRuntimeError> {
+        if let Some(max) = self.limits.max_memory {
+            let bytes = self.env.elements().saturating_add(elements).saturating_mul(mem::size_of::<Scalar>());
+            if bytes > max {
+                return Err(self.limit_exceeded(LimitExceeded::Memory(max)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Lets a builtin check the memory limit before it builds a value of
+    /// `elements` scalars; the call then fails with the limit error.
+    pub fn reserve_elements(&mut self, elements: usize) -> Result<(), String> {
+        self.reserve(elements).map_err(|error| {
+            self.builtin_limit = error.limit;
+            error.message
+        })
+    }
+
+    // A new vector or matrix value: enforce the memory limit and tell the hook
+    fn allocated(&mut self, elements: usize) -> Result<(), DodoRuntimeError> {
+        if elements == 0 {
+            return Ok(());
+        }
+        self.reserve(elements)?;
+        if let Some(hook) = self.hook.as_mut() {
+            hook.allocated(elements);
+        }
+        Ok(())
+    }
+
+    // Zero-filled storage for a declaration, failing cleanly when it is too large to hold
+    fn zeros(&self, name: &Token, declared: DeclaredType, rows: usize, columns: usize) -> Result<Vec<Scalar>, DodoRuntimeError> {
+        let shape = match declared {
+            DeclaredType::Vector => format!("vector[{}]", rows),
+            _ => format!("matrix[{}, {}]", rows, columns),
+        };
+        let too_large = || DodoRuntimeError::new(name.clone(), format!("Cannot allocate {} for '{}'", shape, name.val));
+        let elements = rows.checked_mul(columns).ok_or_else(too_large)?;
+        self.reserve(elements)?;
+        let mut data = Vec::new();
+        data.try_reserve_exact(elements).map_err(|_| too_large())?;
+        data.resize(elements, Scalar::Int(0));
+        Ok(data)
+    }
+
+    // A dimension of a declaration, evaluated when the declaration runs
+    fn size(&mut self, name: &Token, dim: &Expr) -> Result<usize, DodoRuntimeError> {
+        match self.evaluate(dim)? {
+            Value::Scalar(Scalar::Int(size)) if size >= 0 => usize::try_from(size).map_err(|_| {
+                DodoRuntimeError::new(name.clone(), format!("Size {} of '{}' is too large", size, name.val))
+            }),
+            other => Err(DodoRuntimeError::new(name.clone(), format!("Size of '{}' must be a non-negative integer, got {}", name.val, other))),
+        }
+    }
+
+    // The initial value of a typed declaration must have the declared kind and sizes
+    fn check_initial(&self, name: &Token, declared: DeclaredType, sizes: &[usize], value: &Value) -> Result<(), DodoRuntimeError> {
+        let shape = |sizes: &[usize], kind: &str| match sizes {
+            [] => kind.to_string(),
+            sizes => format!("{}[{}]", kind, sizes.iter().map(usize::to_string).collect::<Vec<String>>().join(", ")),
+        };
+        let dims = match value {
+            Value::Matrix(matrix) => Some((matrix.rows, matrix.cols)),
+            Value::Sparse(sparse) => Some((sparse.rows, sparse.cols)),
+            _ => None,
+        };
+        let fits = match declared {
+            DeclaredType::Scalar => matches!(value, Value::Scalar(_)),
+            DeclaredType::Vector => matches!(value, Value::Vector(items) if items.len() == sizes[0]),
+            DeclaredType::Matrix => dims == Some((sizes[0], sizes[1])),
+            DeclaredType::Inferred | DeclaredType::Constant => return Ok(()),
+        };
+        if fits {
+            return Ok(());
+        }
+        let actual = match value {
+            Value::Vector(items) => shape(&[items.len()], "vector"),
+            _ if dims.is_some() => dims.map_or(String::new(), |(rows, cols)| shape(&[rows, cols], "matrix")),
+            other => other.type_name().to_string(),
+        };
+        let message = format!("Cannot initialise {} '{}' with {}", shape(sizes, declared.keyword()), name.val, actual);
+        Err(DodoRuntimeError::new(name.clone(), message))
+    }
+
+    fn scalar_op(&self, left: &Scalar, right: &Scalar, operator: &Token) -> Result<Scalar, DodoRuntimeError> {
+        at(operator, left.arithmetic_in(self.arithmetic, operator.token_type, right))
+    }
+
+    fn elementwise(&self, left: &[Scalar], right: &[Scalar], operator: &Token) -> Result<Vec<Scalar>, DodoRuntimeError> {
+        if left.len() != right.len() {
+            let message = format!("Length mismatch: {} and {} elements", left.len(), right.len());
+            return Err(DodoRuntimeError::new(operator.clone(), message));
+        }
+        left.iter().zip(right.iter()).map(|(a, b)| self.scalar_op(a, b, operator)).collect()
+    }
+
+    fn dot(&self, left: &[Scalar], right: &[Scalar], operator: &Token) -> Result<Scalar, DodoRuntimeError> {
+        let sum = Token::new(TokenType::PLUS, "+".to_string());
+        let mut new_value = Scalar::Int(0);
+        for item in self.elementwise(left, right, operator)?.iter() {
+            new_value = self.scalar_op(&new_value, item, &sum)?;
+        }
+        Ok(new_value)
+    }
+
+    fn matrix_product(&self, left: &Matrix, right: &Matrix, operator: &Token) -> Result<Matrix, DodoRuntimeError> {
+        if left.cols != right.rows {
+            let message = format!("Cannot multiply {}x{} matrix by {}x{} matrix", left.rows, left.cols, right.rows, right.cols);
+            return Err(DodoRuntimeError::new(operator.clone(), message));
+        }
+        self.reserve_matrix(left.rows, right.cols, operator)?;
+        let mut data = at(operator, Matrix::storage(left.rows, right.cols))?;
+        for row in 0..left.rows {
+            for col in 0..right.cols {
+                let column: Vec<Scalar> = (0..right.rows).map(|k| right.get(k, col).clone()).collect();
+                data.push(self.dot(left.row(row), &column, operator)?);
+            }
+        }
+        Ok(Matrix::new(left.rows, right.cols, data))
+    }
+
+    // Checks the memory limit for a rows x cols result before any of it is built
+    fn reserve_matrix(&self, rows: usize, cols: usize, operator: &Token) -> Result<(), DodoRuntimeError> {
+        self.reserve(at(operator, Matrix::size(rows, cols))?)
+    }
+
+    // A sparse operand made dense, charged against the memory limit first
+    fn densify(&self, sparse: &SparseMatrix, operator: &Token) -> Result<Matrix, DodoRuntimeError> {
+        self.reserve_matrix(sparse.rows, sparse.cols, operator)?;
+        at(operator, sparse.to_dense())
+    }
+
+    // Exponentiation by squaring; negative powers raise the inverse
+    fn matrix_power(&self, matrix: &Matrix, power: &Scalar, operator: &Token) -> Result<Matrix, DodoRuntimeError> {
+        if matrix.rows != matrix.cols {
+            let message = format!("Matrix power expects a square matrix, got {}x{}", matrix.rows, matrix.cols);
+            return Err(DodoRuntimeError::new(operator.clone(), message));
+        }
+        let power = match power {
+            Scalar::Int(power) => *power,
+            Scalar::Big(_) => return Err(DodoRuntimeError::new(operator.clone(), format!("Exponent {} is too large", power))),
+            other => return Err(DodoRuntimeError::new(operator.clone(), format!("Matrix power expects an integer exponent, got {}", other))),
+        };
+        let mut base = match power < 0 {
+            true => at(operator, linalg::inverse(matrix))?,
+            false => matrix.clone(),
+        };
+        // The products multiply entries with the operator they are given
+        let times = Token::at(TokenType::ASTERISK, "*".to_string(), operator.line, operator.column);
+        let mut remaining = power.unsigned_abs();
+        let mut result = linalg::identity(matrix.rows);
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                result = self.matrix_product(&result, &base, &times)?;
+            }
+            remaining >>= 1;
+            if remaining > 0 {
+                base = self.matrix_product(&base, &base, &times)?;
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn evaluate_binary(&mut self, left: Value, right: Value, operator: Token) -> Result<Value, DodoRuntimeError> {
+        let op = operator.token_type;
+        let mode = self.arithmetic;
+        match (left, right) {
+            (Value::Scalar(val1), Value::Scalar(val2)) => {
+                Ok(Value::Scalar(self.scalar_op(&val1, &val2, &operator)?))
+            }
+            (Value::Str(val1), Value::Str(val2)) if op == TokenType::EqualEqual || op == TokenType::BangEqual => {
+                Ok(Value::int(((val1 == val2) == (op == TokenType::EqualEqual)) as i128))
+            }
+            (Value::Matrix(matrix), Value::Scalar(power)) if op == TokenType::CARET => {
+                Ok(Value::Matrix(self.matrix_power(&matrix, &power, &operator)?))
+            }
+            (Value::Sparse(sparse), Value::Scalar(power)) if op == TokenType::CARET => {
+                Ok(Value::Matrix(self.matrix_power(&self.densify(&sparse, &operator)?, &power, &operator)?))
+            }
+            (left, right) if op == TokenType::CARET => {
+                let message = format!("'^' expects a square matrix and an integer power, got {} and {}; use '.^' for element-wise powers", left.type_name(), right.type_name());
+                Err(DodoRuntimeError::new(operator, message))
+            }
+            (Value::Vector(vector1), Value::Vector(vector2)) if op == TokenType::ASTERISK => {
+                Ok(Value::Scalar(self.dot(&vector1, &vector2, &operator)?))
+            }
+            (Value::Matrix(matrix1), Value::Matrix(matrix2)) if op == TokenType::ASTERISK => {
+                Ok(Value::Matrix(self.matrix_product(&matrix1, &matrix2, &operator)?))
+            }
+            // Other operators work element by element, broadcasting as in NumPy
+            (left @ (Value::Scalar(_) | Value::Vector(_) | Value::Matrix(_)), right @ (Value::Scalar(_) | Value::Vector(_) | Value::Matrix(_)))
+                if op != TokenType::ASTERISK || matches!(left, Value::Scalar(_)) || matches!(right, Value::Scalar(_)) => {
+                let (rows, cols) = at(&operator, value::broadcast_shape(&left, &right))?;
+                self.reserve_matrix(rows, cols, &operator)?;
+                at(&operator, value::broadcast(&left, &right, |val1, val2| val1.arithmetic_in(mode, op, val2)))
+            }
+            (Value::Matrix(matrix), Value::Vector(vector)) if op == TokenType::ASTERISK => {
+                let column = Matrix::new(vector.len(), 1, vector);
+                Ok(Value::Vector(self.matrix_product(&matrix, &column, &operator)?.data))
+            }
+            (Value::Vector(vector), Value::Matrix(matrix)) if op == TokenType::ASTERISK => {
+                let row = Matrix::new(1, vector.len(), vector);
+                Ok(Value::Vector(self.matrix_product(&row, &matrix, &operator)?.data))
+            }
+            (Value::Sparse(sparse1), Value::Sparse(sparse2)) => match op {
+                TokenType::ASTERISK => Ok(Value::Sparse(at(&operator, sparse1.mul_sparse(&sparse2, mode))?)),
+                TokenType::PLUS | TokenType::MINUS => Ok(Value::Sparse(at(&operator, sparse1.combine(op, &sparse2, mode))?)),
+                _ => Err(DodoRuntimeError::new(operator, "Unsupported operation on sparse matrices".to_string())),
+            },
+            (Value::Sparse(sparse), Value::Matrix(matrix)) => match op {
+                TokenType::ASTERISK => {
+                    self.reserve_matrix(sparse.rows, matrix.cols, &operator)?;
+                    Ok(Value::Matrix(at(&operator, sparse.mul_dense(&matrix, mode))?))
                 }
-                _ => {}
-            }
-        } else if let Expr::Literal(val1) = right {
-            if let Expr::Literal(val2) = left {
-                match operator.token_type {
-                    TokenType::MINUS => return Expr::Literal(val1 - val2),
-                    TokenType::PLUS => return Expr::Literal(val1 + val2),
-                    TokenType::ASTERISK => return Expr::Literal(val1 * val2),
-                    TokenType::SLASH => return Expr::Literal(val1 / val2),
-                    _ => ()
+                _ => self.evaluate_binary(Value::Matrix(self.densify(&sparse, &operator)?), Value::Matrix(matrix), operator),
+            },
+            (Value::Matrix(matrix), Value::Sparse(sparse)) => match op {
+                TokenType::ASTERISK => {
+                    self.reserve_matrix(matrix.rows, sparse.cols, &operator)?;
+                    Ok(Value::Matrix(at(&operator, SparseMatrix::dense_mul(&matrix, &sparse, mode))?))
                 }
+                _ => self.evaluate_binary(Value::Matrix(matrix), Value::Matrix(self.densify(&sparse, &operator)?), operator),
+            },
+            (Value::Sparse(sparse), Value::Vector(vector)) if op == TokenType::ASTERISK => {
+                Ok(Value::Vector(at(&operator, sparse.mul_vector(&vector, mode))?))
             }
-            if let Expr::Vector(token, val2) = left {
-                let mut new_vector = vec![0; val2.len()];
-                match operator.token_type {
-                    TokenType::ASTERISK => {
-                        for (i, item) in val2.iter().enumerate() {
-                            new_vector[i] = item * val1
-                        }
-                        return Expr::Vector(token, new_vector);
-                    }
-                    TokenType::SLASH => {
-                        for (i, item) in val2.iter().enumerate() {
-                            new_vector[i] = item / val1
-                        }
-                        return Expr::Vector(token, new_vector);
-                    }
-                    _ => ()
-                }
+            (Value::Vector(vector), Value::Sparse(sparse)) if op == TokenType::ASTERISK => {
+                Ok(Value::Vector(at(&operator, SparseMatrix::vector_mul(&vector, &sparse, mode))?))
             }
-        } else if let Expr::Vector(token1, vector1) = right {
-            if let Expr::Vector(token2, vector2) = left {
-                let mut new_value = 0;
-                match operator.token_type {
-                    TokenType::ASTERISK => {
-                        for (i, item) in vector2.iter().enumerate() {
-                            new_value += item * vector1[i]
-                        }
-                        return Expr::Literal(new_value);
-                    }
-                    TokenType::SLASH => {
-                        for (i, item) in vector2.iter().enumerate() {
-                            new_value += item / vector1[i]
-                        }
-                        return Expr::Literal(new_value);
-                    }
-                    _ => ()
-                }
+            (Value::Sparse(sparse), Value::Vector(vector)) => self.evaluate_binary(Value::Matrix(self.densify(&sparse, &operator)?), Value::Vector(vector), operator),
+            (Value::Vector(vector), Value::Sparse(sparse)) => self.evaluate_binary(Value::Vector(vector), Value::Matrix(self.densify(&sparse, &operator)?), operator),
+            (Value::Sparse(sparse), Value::Scalar(val)) if op == TokenType::ASTERISK || op == TokenType::SLASH => {
+                Ok(Value::Sparse(at(&operator, sparse.map(|item| item.arithmetic_in(mode, op, &val)))?))
+            }
+            (Value::Scalar(val), Value::Sparse(sparse)) if op == TokenType::ASTERISK => {
+                Ok(Value::Sparse(at(&operator, sparse.map(|item| val.arithmetic_in(mode, op, item)))?))
+            }
+            (left, right) => {
+                let message = format!("Unsupported operand types for '{}': {} and {}", operator.val, left.type_name(), right.type_name());
+                Err(DodoRuntimeError::new(operator, message))
             }
         }
-        else if let Expr::Vector(token1, vector1) = left {
-            if let Expr::Vector(token2, vector2) = right {
-                let mut new_value = 0;
-                match operator.token_type {
-                    TokenType::ASTERISK => {
-                        for (i, item) in vector2.iter().enumerate() {
-                            new_value += item * vector1[i]
-                        }
-                        return Expr::Literal(new_value);
-                    }
-                    TokenType::SLASH => {
-                        for (i, item) in vector2.iter().enumerate() {
-                            new_value += item / vector1[i]
-                        }
-                        return Expr::Literal(new_value);
-                    }
-                    _ => ()
-                }
+    }
+
+    pub fn evaluate_unary(&mut self, operator: Token, value: Value) -> Result<Value, DodoRuntimeError> {
+        if operator.token_type == TokenType::BANG {
+            return self.evaluate_not(operator, value);
+        }
+        if operator.token_type != TokenType::MINUS {
+            return Err(DodoRuntimeError::new(operator, "Unsupported unary operator".to_string()));
+        }
+        let negate = |items: &[Scalar]| -> Result<Vec<Scalar>, DodoRuntimeError> {
+            items.iter().map(|item| at(&operator, item.negate_in(self.arithmetic))).collect()
+        };
+        match value {
+            Value::Scalar(val) => Ok(Value::Scalar(at(&operator, val.negate_in(self.arithmetic))?)),
+            Value::Vector(val) => Ok(Value::Vector(negate(&val)?)),
+            Value::Matrix(val) => {
+                let data = negate(&val.data)?;
+                Ok(Value::Matrix(Matrix::new(val.rows, val.cols, data)))
+            }
+            Value::Sparse(val) => {
+                let data = negate(&val.data)?;
+                Ok(Value::Sparse(SparseMatrix { data, ..val }))
+            }
+            other => {
+                let message = format!("Cannot negate a {}", other.type_name());
+                Err(DodoRuntimeError::new(operator, message))
             }
         }
-        return Expr::Literal(0);
     }
 
-    pub fn evaluate_vector_assignment(&mut self, token: Token, expr: Expr) -> Expr {
-        if let Expr::Vector(token1, val) = expr {
-            println!("{}", token);
-            &self.env.assign(token.clone(), val.clone());
-            return Expr::Vector(token.clone(), val.clone());
+    // `!x` is 1 where x is zero and 0 elsewhere, so a sparse matrix becomes dense
+    fn evaluate_not(&mut self, operator: Token, value: Value) -> Result<Value, DodoRuntimeError> {
+        let not = |items: &[Scalar]| -> Vec<Scalar> { items.iter().map(|item| Scalar::Int(item.is_zero() as i128)).collect() };
+        match value {
+            Value::Scalar(val) => Ok(Value::Scalar(Scalar::Int(val.is_zero() as i128))),
+            Value::Vector(val) => Ok(Value::Vector(not(&val))),
+            Value::Matrix(val) => Ok(Value::Matrix(Matrix::new(val.rows, val.cols, not(&val.data)))),
+            Value::Sparse(val) => {
+                let dense = self.densify(&val, &operator)?;
+                Ok(Value::Matrix(Matrix::new(dense.rows, dense.cols, not(&dense.data))))
+            }
+            other => {
+                let message = format!("Cannot apply '!' to a {}", other.type_name());
+                Err(DodoRuntimeError::new(operator, message))
+            }
         }
-        return Expr::Vector(token, vec![0]);
     }
 
-    pub fn evaluate_literal_assignment(&mut self, token: Token, expr: Expr) -> Expr {
-        println!("wtf");
-        if let Expr::Literal(val) = expr {
-            &self.env.assign(token, vec![val as i128]);
-            return Expr::Literal(val);
+    pub fn evaluate_variable(&mut self, token: &Token) -> Result<Value, DodoRuntimeError> {
+        if let Some(value) = self.env.get(token) {
+            return Ok(value.clone());
         }
-        if let Expr::Vector(token1, val) = expr {
-            println!("{}", token);
-            &self.env.assign(token.clone(), val.clone());
-            return Expr::Vector(token.clone(), val.clone());
+        match builtins::constant(&token.val) {
+            Some(val) => Ok(Value::Scalar(val)),
+            None => {
+                let message = format!("Undefined variable '{}'", token.val);
+                Err(DodoRuntimeError::new(token.clone(), message))
+            }
         }
-        return Expr::Literal(0);
     }
 
-    pub fn evaluate_unary(&mut self, expr: Expr) -> Expr {
-        if let Expr::Literal(val) = expr {
-            return Expr::Literal(-val);
+    fn scalar_elements<'a, I>(&mut self, token: &Token, exprs: I) -> Result<Vec<Scalar>, DodoRuntimeError> where I: Iterator<Item = &'a Expr> {
+        let mut elements = Vec::new();
+        for expr in exprs {
+            match self.evaluate(expr)? {
+                Value::Scalar(val) => elements.push(val),
+                other => {
+                    let message = format!("Literal elements must be scalars, got {}", other.type_name());
+                    return Err(DodoRuntimeError::new(token.clone(), message));
+                }
+            }
         }
-        return Expr::Literal(0);
+        Ok(elements)
     }
 
-    pub fn evaluate_variable(&mut self, token: Token) -> Expr {
-        let mut final_val = self.env.get(token.clone());
-        return if final_val.unwrap().clone().len() > 1 {
-            Expr::Vector(token.clone(), final_val.unwrap().clone())
-        } else {
-            Expr::Literal(final_val.unwrap().clone()[0])
+    pub fn evaluate_call(&mut self, callee: &Expr, args: &[Expr]) -> Result<Value, DodoRuntimeError> {
+        let token = match callee {
+            Expr::Variable(token, _) => token,
+            Expr::Get(namespace, member) => {
+                return match self.member(namespace, member)? {
+                    Value::Function(function) => self.call_function(member, function, args),
+                    other => Err(DodoRuntimeError::new(member.clone(), format!("Cannot call a {}", other.type_name()))),
+                };
+            }
+            _ => return Err(DodoRuntimeError::new(Token::new(TokenType::LeftParenthesis, "(".to_string()), "Can only call functions".to_string())),
         };
+        // User functions shadow builtins of the same name
+        if let Some(value) = self.env.get(token) {
+            return match value.clone() {
+                Value::Function(function) => self.call_function(token, function, args),
+                other => {
+                    let message = format!("Cannot call a {}", other.type_name());
+                    Err(DodoRuntimeError::new(token.clone(), message))
+                }
+            };
+        }
+        let builtin = match builtins::lookup(&token.val) {
+            Some(builtin) => builtin,
+            None => {
+                let message = format!("Undefined function '{}'", token.val);
+                return Err(DodoRuntimeError::new(token.clone(), message));
+            }
+        };
+        if args.len() < builtin.min_arity || args.len() > builtin.max_arity {
+            let message = format!("{} expects {} arguments, got {}", builtin.name, builtin.arity(), args.len());
+            return Err(DodoRuntimeError::new(token.clone(), message));
+        }
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(self.evaluate(arg)?);
+        }
+        if builtin.files && self.limits.sandbox {
+            let message = format!("{} cannot access files in a sandbox", builtin.name);
+            return Err(DodoRuntimeError::new(token.clone(), message));
+        }
+        let result = (builtin.function)(self, values);
+        if let Some(limit) = self.builtin_limit.take() {
+            return Err(self.limit_exceeded(limit));
+        }
+        let result = at(token, result)?;
+        self.allocated(result.elements())?;
+        Ok(result)
     }
 
-    pub fn evaluate(&mut self, expr: Expr) -> Expr {
-        if let Expr::Literal(val) = expr.clone() {
-            return expr.clone();
+    fn call_function(&mut self, token: &Token, function: Rc<Function>, args: &[Expr]) -> Result<Value, DodoRuntimeError> {
+        if args.len() != function.params.len() {
+            let message = format!("{} expects {} arguments, got {}", function.name.val, function.params.len(), args.len());
+            return Err(DodoRuntimeError::new(token.clone(), message));
         }
-        if let Expr::Vector(token, val) = expr.clone() {
-            return expr.clone();
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(self.evaluate(arg)?);
         }
-        if let Expr::Matrix(val) = expr.clone() {
-            return expr.clone();
+        // The script itself is the first frame
+        let max_depth = self.limits.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+        if self.frames.len() > max_depth {
+            return Err(DodoRuntimeError::limit_exceeded(token.clone(), LimitExceeded::Depth(max_depth)));
         }
-        if let Expr::Grouping(val) = expr.clone() {
-            return self.evaluate(*val);
+        self.step()?;
+        self.env.push_frame();
+        for (param, value) in function.params.iter().zip(values) {
+            self.env.define(param.val.clone(), value);
         }
-        if let Expr::Binary(left, operator, right) = expr.clone() {
-            let mut left = self.evaluate(*left);
-            let mut right = self.evaluate(*right);
-            return self.evaluate_binary(left, right, operator);
+        self.frames.push(CallFrame { name: function.name.val.clone(), line: function.name.line });
+        if let Some(hook) = self.hook.as_mut() {
+            hook.enter_call(&function.name.val);
         }
-        if let Expr::Unary(token, val) = expr.clone() {
-            let mut primary = self.evaluate(*val);
-            return self.evaluate_unary(primary);
+        let caller = self.env.enter_module(function.module);
+        let result = self.execute_block(&function.body);
+        self.env.enter_module(caller);
+        if let Some(hook) = self.hook.as_mut() {
+            hook.exit_call(&function.name.val);
         }
-        if let Expr::Assign(token, val, variable_type) = expr.clone() {
-            return if let Expr::Vector(token1, vector) = *val.clone() {
-                let mut final_expr = self.evaluate(*val);
-                println!("asd2");
-                self.evaluate_vector_assignment(token, final_expr)
-            } else {
-                let mut final_expr = self.evaluate(*val);
-                self.evaluate_literal_assignment(token, final_expr)
-            };
+        self.frames.pop();
+        self.env.pop_frame();
+        match result? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(Value::Nil),
+        }
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value, DodoRuntimeError> {
+        let value = self.evaluate_expr(expr)?;
+        // Variables, assignments and calls hand back values made elsewhere; everything else is a new temporary
+        if !matches!(expr, Expr::Variable(_, _) | Expr::Get(_, _) | Expr::Assign(_, _, _) | Expr::Grouping(_) | Expr::FunctionCall(_, _)) {
+            self.allocated(value.elements())?;
+        }
+        Ok(value)
+    }
+
+    fn evaluate_expr(&mut self, expr: &Expr) -> Result<Value, DodoRuntimeError> {
+        match expr {
+            Expr::Literal(val) => Ok(Value::int(*val)),
+            Expr::Float(val) => Ok(Value::float(*val)),
+            Expr::Imaginary(val) => Ok(Value::Scalar(Scalar::Complex(Complex64::new(0.0, *val)))),
+            Expr::Big(val) => match val.parse::<BigInt>() {
+                Ok(val) => Ok(Value::Scalar(Scalar::big(val))),
+                Err(error) => Err(DodoRuntimeError::new(Token::new(TokenType::INT, val.clone()), format!("Invalid integer literal: {}", error))),
+            },
+            Expr::Str(val) => Ok(Value::Str(val.clone())),
+            Expr::Vector(token, elements) => Ok(Value::Vector(self.scalar_elements(token, elements.iter())?)),
+            Expr::Matrix(token, rows) => {
+                let cols = rows.first().map_or(0, Vec::len);
+                let data = self.scalar_elements(token, rows.iter().flatten())?;
+                Ok(Value::Matrix(Matrix::new(rows.len(), cols, data)))
+            }
+            Expr::Grouping(val) => self.evaluate(val),
+            Expr::Binary(left, operator, right) => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                self.evaluate_binary(left, right, operator.clone())
+            }
+            Expr::Unary(token, val) => {
+                let primary = self.evaluate(val)?;
+                self.evaluate_unary(token.clone(), primary)
+            }
+            Expr::Assign(token, val, _) => {
+                let value = self.evaluate(val)?;
+                self.env.assign(token, value.clone())?;
+                Ok(value)
+            }
+            Expr::Variable(token, _) => self.evaluate_variable(token),
+            Expr::Get(namespace, member) => self.member(namespace, member),
+            Expr::FunctionCall(callee, args) => self.evaluate_call(callee, args),
+            other => {
+                let message = format!("Unsupported expression {}", other);
+                Err(DodoRuntimeError::new(Token::new(TokenType::ILLEGAL, String::new()), message))
+            }
+        }
+    }
+
+    /// Evaluates `expr` as if call frame `depth` were the innermost one.
+    pub fn evaluate_in_frame(&mut self, expr: &Expr, depth: usize) -> Result<Value, DodoRuntimeError> {
+        let hidden = self.env.hide_frames(depth);
+        let result = self.evaluate(expr);
+        self.env.restore_frames(hidden);
+        result
+    }
+
+    // Runs a body until the condition, if any, is zero or a break reaches this loop; each
+    // iteration counts as a step, so even an empty loop runs into the step limit
+    fn run_loop(&mut self, label: &Option<Token>, condition: Option<&Expr>, body: &Block, span: Span) -> Result<Flow, DodoRuntimeError> {
+        let targets = |target: &Option<String>| match target {
+            Some(target) => label.as_ref().is_some_and(|label| label.val == *target),
+            None => true,
+        };
+        loop {
+            self.step()?;
+            if let Some(condition) = condition {
+                match self.evaluate(condition)? {
+                    Value::Scalar(val) if val.is_zero() => break,
+                    Value::Scalar(_) => (),
+                    other => {
+                        let token = Token::at(TokenType::WHILE, "while".to_string(), span.line, 0);
+                        return Err(DodoRuntimeError::new(token, format!("Loop condition must be a scalar, got {}", other.type_name())));
+                    }
+                }
+            }
+            match self.execute_block(body)? {
+                Flow::Break(target) if targets(&target) => break,
+                Flow::Continue(target) if targets(&target) => (),
+                Flow::Normal => (),
+                flow => return Ok(flow),
+            }
         }
-        if let Expr::Variable(token, variable_type) = expr.clone() {
-            return self.evaluate_variable(token);
+        Ok(Flow::Normal)
+    }
+
+    pub fn execute_block(&mut self, block: &Block) -> Result<Flow, DodoRuntimeError> {
+        for (stmt, span) in block.statements.iter().zip(block.spans.iter()) {
+            match self.statement(stmt, *span)? {
+                Flow::Normal => (),
+                flow => return Ok(flow),
+            }
         }
-        return Expr::Err;
+        Ok(Flow::Normal)
     }
 
-    pub fn statement(&mut self, stmt: Stmt) {
-        if let Stmt::Expression(expr) = stmt.clone() {
-            self.evaluate(expr);
+    pub fn statement(&mut self, stmt: &Stmt, span: Span) -> Result<Flow, DodoRuntimeError> {
+        if let Stmt::Comment(_) = stmt {
+            return Ok(Flow::Normal);
+        }
+        if let Some(frame) = self.frames.last_mut() {
+            frame.line = span.line;
+        }
+        self.step()?;
+        if let Some(mut hook) = self.hook.take() {
+            let result = hook.before_statement(self, stmt, span);
+            self.hook = Some(hook);
+            result?;
         }
-        if let Stmt::Print(expr) = stmt.clone() {
-            let printable = self.evaluate(expr);
-            println!("{}", printable);
+        let flow = self.execute(stmt, span);
+        if let Some(mut hook) = self.hook.take() {
+            hook.after_statement(self, span);
+            self.hook = Some(hook);
         }
-        if let Stmt::Definition(token, rows, columns) = stmt.clone() {
-            match token.token_type {
-                TokenType::SCALAR => self.env.define(token.val, vec![0]),
-                TokenType::VECTOR => {
-                    self.env.define(token.val, vec![0; rows.parse::<usize>().unwrap()])
+        flow
+    }
+
+    fn execute(&mut self, stmt: &Stmt, span: Span) -> Result<Flow, DodoRuntimeError> {
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.evaluate(expr)?;
+            }
+            Stmt::Print(expr) => {
+                let printable = self.evaluate(expr)?;
+                if let Err(error) = writeln!(self.output, "{}", printable) {
+                    let token = Token::at(TokenType::PRINT, "print".to_string(), span.line, 0);
+                    return Err(DodoRuntimeError::new(token, format!("Cannot print: {}", error)));
                 }
-                TokenType::MATRIX => {
-                    self.env.define(token.val, vec![0; columns.parse::<usize>().unwrap()])
+            }
+            Stmt::Definition(name, declared, dims, initial) => {
+                let mut sizes = Vec::with_capacity(dims.len());
+                for dim in dims {
+                    sizes.push(self.size(name, dim)?);
+                }
+                let value = match initial {
+                    Some(expr) => {
+                        let value = self.evaluate(expr)?;
+                        self.check_initial(name, *declared, &sizes, &value)?;
+                        value
+                    }
+                    None => {
+                        let value = match sizes.as_slice() {
+                            [size] => Value::Vector(self.zeros(name, *declared, *size, 1)?),
+                            [rows, columns] => Value::Matrix(Matrix::new(*rows, *columns, self.zeros(name, *declared, *rows, *columns)?)),
+                            _ => Value::int(0),
+                        };
+                        if let Some(hook) = self.hook.as_mut() {
+                            if value.elements() > 0 {
+                                hook.allocated(value.elements());
+                            }
+                        }
+                        value
+                    }
+                };
+                let kind = match declared {
+                    DeclaredType::Inferred | DeclaredType::Constant => value.kind(),
+                    declared => declared.keyword(),
+                };
+                self.env.declare(name.val.clone(), kind, declared.is_mutable(), value);
+            }
+            Stmt::Function(name, params, body) => {
+                let function = Function { name: name.clone(), params: params.clone(), body: body.clone(), module: self.env.module() };
+                self.env.define(name.val.clone(), Value::Function(Rc::new(function)));
+            }
+            Stmt::While(label, condition, body) => return self.run_loop(label, Some(condition), body, span),
+            Stmt::Loop(label, body) => return self.run_loop(label, None, body, span),
+            Stmt::Break(_, label) => return Ok(Flow::Break(label.as_ref().map(|label| label.val.clone()))),
+            Stmt::Continue(_, label) => return Ok(Flow::Continue(label.as_ref().map(|label| label.val.clone()))),
+            Stmt::Import(file, namespace) => {
+                let id = self.import(file)?;
+                self.env.bind_module(namespace.val.clone(), id);
+            }
+            Stmt::FromImport(file, names) => {
+                let id = self.import(file)?;
+                for name in names {
+                    let value = match self.env.member(id, &name.val) {
+                        Some(value) => value.clone(),
+                        None => return Err(DodoRuntimeError::new(name.clone(), format!("'{}' does not define '{}'", file.val, name.val))),
+                    };
+                    self.env.define(name.val.clone(), value);
                 }
-                _ => ()
             }
+            Stmt::Return(_, value) => {
+                let value = match value {
+                    Some(expr) => self.evaluate(expr)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            _ => ()
         }
+        Ok(Flow::Normal)
     }
 }
 
@@ -294,4 +912,61 @@ impl Interpreter {
 //
 //         assert_eq!(val, 44);
 //     }
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+    use super::Interpreter;
+    use crate::core::ast::parser::Parser;
+    use crate::core::dodo::environment::Environment;
+    use crate::core::dodo::error_types::DodoRuntimeError;
+    use crate::core::dodo::value::Value;
+    use crate::core::lexer::lexer::Lexer;
+    use crate::core::token::token::{Token, TokenType};
+
+    fn run(source: &str) -> (Interpreter, Result<(), DodoRuntimeError>) {
+        let mut parser = Parser::new(Lexer::new(source.to_string()).scan_tokens());
+        let program = parser.parse_program();
+        assert!(parser.errors().is_empty(), "{:?}", parser.errors());
+        let mut interpreter = Interpreter::new(Environment::new());
+        let result = interpreter.interpret(&program);
+        (interpreter, result)
+    }
+
+    fn get(interpreter: &Interpreter, name: &str) -> Option<Value> {
+        interpreter.env.get(&Token::new(TokenType::IDENT, name.to_string())).cloned()
+    }
+
+    #[test]
+    fn functions_return_values_in_their_own_frame() {
+        let source = "scalar a\na = 10\nfn add(a, b) {\n    scalar c\n    c = a + b\n    return c\n}\nscalar r\nr = add(1, add(2, 3))\n";
+        let (interpreter, result) = run(source);
+        assert!(result.is_ok());
+        assert_eq!(get(&interpreter, "r"), Some(Value::int(6)));
+        assert_eq!(get(&interpreter, "a"), Some(Value::int(10)));
+        assert_eq!(get(&interpreter, "c"), None);
+    }
+
+    #[test]
+    fn bare_return_stops_the_body() {
+        let source = "scalar x\nfn early() {\n    return\n    x = 1\n}\nx = 2\nprint early()\n";
+        let (interpreter, result) = run(source);
+        assert!(result.is_ok());
+        assert_eq!(get(&interpreter, "x"), Some(Value::int(2)));
+    }
+
+    #[test]
+    fn calls_check_arity_and_callee() {
+        let (_, result) = run("fn add(a, b) {\n    return a + b\n}\nprint add(1)\n");
+        assert_eq!(result.unwrap_err().message, "add expects 2 arguments, got 1");
+
+        let (_, result) = run("scalar n\nn = 1\nprint n(2)\n");
+        assert_eq!(result.unwrap_err().message, "Cannot call a scalar");
+    }
+
+    #[test]
+    fn overflow_promotes_to_a_big_integer_by_default() {
+        let (interpreter, result) = run("scalar x = 170141183460469231731687303715884105727\nx = x + 1\n");
+        assert!(result.is_ok());
+        assert_eq!(get(&interpreter, "x").unwrap().to_string(), "170141183460469231731687303715884105728");
+    }
+}
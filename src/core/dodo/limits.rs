@@ -0,0 +1,137 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Bounds for running untrusted programs; `None` leaves a resource unlimited.
+/// The default imposes no limits beyond the interpreter's recursion guard.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Statements executed plus function calls made.
+    pub max_steps: Option<u64>,
+    /// Bytes of vector and matrix data held by variables and the value being built.
+    pub max_memory: Option<usize>,
+    /// Function calls active at once.
+    pub max_depth: Option<usize>,
+    /// Wall-clock time for one `interpret` call.
+    pub timeout: Option<Duration>,
+    /// Checked before every step; set it from another thread to stop the program.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Refuse builtins that read or write files.
+    pub sandbox: bool,
+}
+
+impl Limits {
+    pub fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Limits;
+    use crate::core::ast::parser::Parser;
+    use crate::core::dodo::environment::Environment;
+    use crate::core::dodo::error_types::{DodoRuntimeError, LimitExceeded};
+    use crate::core::dodo::interpreter::Interpreter;
+    use crate::core::dodo::runner::compile_errors;
+    use crate::core::dodo::value::Scalar;
+    use crate::core::format::formatter::format_source;
+    use crate::core::lexer::lexer::Lexer;
+    use std::io;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn run(source: &str, limits: Limits) -> Result<(), DodoRuntimeError> {
+        let program = Parser::new(Lexer::new(source.to_string()).scan_tokens()).parse_program();
+        let mut interpreter = Interpreter::new(Environment::new());
+        interpreter.set_output(Box::new(io::sink()));
+        interpreter.set_limits(limits);
+        interpreter.interpret(&program)
+    }
+
+    fn limit(source: &str, limits: Limits) -> Option<LimitExceeded> {
+        run(source, limits).err().and_then(|error| error.limit)
+    }
+
+    #[test]
+    fn stops_programs_that_exceed_a_limit() {
+        let recursive = "fn f(n) {\n    return f(n + 1)\n}\nprint f(0)\n";
+        let steps = Limits { max_steps: Some(2), ..Limits::default() };
+        assert_eq!(limit("print 1\nprint 2\nprint 3\n", steps.clone()), Some(LimitExceeded::Steps(2)));
+        assert!(run("print 1\nprint 2\n", steps).is_ok());
+        // Every iteration is a step, so even a loop with an empty body stops
+        assert_eq!(limit("loop {\n}\n", Limits { max_steps: Some(1000), ..Limits::default() }), Some(LimitExceeded::Steps(1000)));
+        assert_eq!(limit(recursive, Limits { max_depth: Some(5), ..Limits::default() }), Some(LimitExceeded::Depth(5)));
+
+        // Room for ten scalars: the two variables hold eight, so only a two-element temporary fits
+        let room = 10 * std::mem::size_of::<Scalar>();
+        let memory = Limits { max_memory: Some(room), ..Limits::default() };
+        assert_eq!(limit("vector v[99999999999]\n", memory.clone()), Some(LimitExceeded::Memory(room)));
+        assert!(run("vector v[4]\nmatrix A[2, 2]\nprint {1, 2} * 2\n", memory.clone()).is_ok());
+        let error = run("vector v[4]\nmatrix A[2, 2]\nprint v * 2\n", memory).unwrap_err();
+        assert_eq!(error.to_string(), format!("[line 3] Limit exceeded: more than {} bytes of vectors and matrices", room));
+
+        let timeout = Limits { timeout: Some(Duration::ZERO), ..Limits::default() };
+        assert_eq!(limit("print 1\n", timeout), Some(LimitExceeded::Timeout(Duration::ZERO)));
+        let cancel = Limits { cancel: Some(Arc::new(AtomicBool::new(true))), ..Limits::default() };
+        assert_eq!(limit("print 1\n", cancel), Some(LimitExceeded::Cancelled));
+    }
+
+    #[test]
+    fn results_are_charged_before_they_are_built() {
+        let room = 10 * std::mem::size_of::<Scalar>();
+        let memory = Limits { max_memory: Some(room), ..Limits::default() };
+        // A wide sparse matrix is cheap to hold, but far too large to make dense
+        let wide = "let S = sparse(1, 2305843009213693952, {0}, {0}, {1})\n";
+        for expr in ["dense(S)", "S + {{1}}", "{{1}, {2}} * S", "cos(S)", "sum(S)", "hstack(S, S)", "vstack(S, S)"] {
+            let source = format!("{}print {}\n", wide, expr);
+            assert_eq!(limit(&source, memory.clone()), Some(LimitExceeded::Memory(room)), "{}", expr);
+        }
+        for expr in ["zeros(4, 1) + zeros(4)", "zeros(4, 1) * zeros(1, 4)", "hstack(zeros(4), zeros(4), zeros(4))", "vstack(zeros(3), zeros(3), zeros(3), zeros(3))"] {
+            let source = format!("print {}\n", expr);
+            assert_eq!(limit(&source, memory.clone()), Some(LimitExceeded::Memory(room)), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn sandbox_and_defaults() {
+        let sandbox = Limits { sandbox: true, ..Limits::default() };
+        let error = run("print loadnpy(\"a.npy\")\n", sandbox.clone()).unwrap_err();
+        assert_eq!((error.message.as_str(), error.limit), ("loadnpy cannot access files in a sandbox", None));
+        assert!(run("print nnz(sparse({{1, 0}, {0, 2}}))\n", sandbox).is_ok());
+
+        // Without limits oversized declarations and runaway recursion still fail cleanly
+        let error = run("vector v[99999999999]\n", Limits::default()).unwrap_err();
+        assert_eq!(error.message, "Cannot allocate vector[99999999999] for 'v'");
+        assert!(run("matrix A[99999999999, 99999999999]\n", Limits::default()).is_err());
+        let shallow = Limits { max_depth: Some(20), ..Limits::default() };
+        assert!(run("fn f(n) {\n    return f(n + 1)\n}\nprint f(0)\n", shallow).unwrap_err().to_string().contains("more than 20 nested calls"));
+    }
+
+    // Parsing right up to the nesting bound takes more than a test thread's stack when
+    // unoptimised, so these run on a thread with the 8 MiB a main thread gets
+    fn with_main_stack<F: FnOnce() + Send + 'static>(f: F) {
+        thread::Builder::new().stack_size(8 << 20).spawn(f).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn deep_nesting_is_a_parse_error() {
+        with_main_stack(|| {
+            let parens = format!("print {}1{}\n", "(".repeat(1000), ")".repeat(1000));
+            let chain = format!("print {}\n", vec!["1"; 10000].join(" + "));
+            let negations = format!("print {}1\n", "-".repeat(10000));
+            let loops = format!("{}break\n{}", "loop {\n".repeat(1000), "}\n".repeat(1000));
+            for source in [parens, chain, negations, loops].iter() {
+                let errors = compile_errors(source, "deep.dodo", &[], false).err().unwrap();
+                assert!(errors[0].ends_with("nested too deeply"), "{}", errors[0]);
+            }
+
+            // Just inside the bound every pass still fits
+            let source = format!("print {}{}{}\n", "(".repeat(101), vec!["-1"; 98].join(" + "), ")".repeat(101));
+            assert!(compile_errors(&source, "deep.dodo", &[], false).is_ok());
+            assert!(run(&format_source(&source).unwrap(), Limits::default()).is_ok());
+        });
+    }
+}
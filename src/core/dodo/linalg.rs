@@ -0,0 +1,271 @@
+use crate::core::dodo::value::{Matrix, Scalar};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Zero};
+use std::mem;
+
+// Rows of an integer or rational matrix scaled to integers, with the product of the scale factors
+fn integer_rows(matrix: &Matrix) -> Option<(Vec<Vec<BigInt>>, BigInt)> {
+    let mut scale = BigInt::one();
+    let mut rows = Vec::with_capacity(matrix.rows);
+    for row in 0..matrix.rows {
+        let values = matrix.row(row).iter().map(Scalar::as_rational).collect::<Option<Vec<BigRational>>>()?;
+        let denominator = values.iter().fold(BigInt::one(), |lcm, val| lcm.lcm(val.denom()));
+        rows.push(values.iter().map(|val| (val * &denominator).to_integer()).collect());
+        scale *= denominator;
+    }
+    Some((rows, scale))
+}
+
+/// Fraction-free (Bareiss) elimination to row echelon form, choosing pivots
+/// among the first `columns` columns. Every division is exact, so entries
+/// stay integers no larger than the minors of the input. Returns the pivot
+/// columns and whether an odd number of rows were swapped.
+fn bareiss(rows: &mut [Vec<BigInt>], columns: usize) -> (Vec<usize>, bool) {
+    let mut pivots = Vec::new();
+    let mut swapped = false;
+    let mut previous = BigInt::one();
+    for col in 0..columns {
+        let row = pivots.len();
+        let pivot = match (row..rows.len()).find(|&candidate| !rows[candidate][col].is_zero()) {
+            Some(pivot) => pivot,
+            None => continue,
+        };
+        if pivot != row {
+            rows.swap(pivot, row);
+            swapped = !swapped;
+        }
+        let (upper, lower) = rows.split_at_mut(row + 1);
+        let pivot_row = &upper[row];
+        for other in lower.iter_mut() {
+            for j in col + 1..other.len() {
+                let val = &pivot_row[col] * &other[j] - &other[col] * &pivot_row[j];
+                other[j] = val / &previous;
+            }
+            other[col] = BigInt::zero();
+        }
+        previous = pivot_row[col].clone();
+        pivots.push(col);
+        if pivots.len() == rows.len() {
+            break;
+        }
+    }
+    (pivots, swapped)
+}
+
+// Gaussian elimination with partial pivoting; entries whose magnitude is below `tolerance` count as zero
+fn eliminate(rows: &mut [Vec<Complex64>], columns: usize, tolerance: f64) -> (Vec<usize>, bool) {
+    let mut pivots = Vec::new();
+    let mut swapped = false;
+    for col in 0..columns {
+        let row = pivots.len();
+        if row == rows.len() {
+            break;
+        }
+        let pivot = (row..rows.len())
+            .max_by(|&a, &b| rows[a][col].norm().total_cmp(&rows[b][col].norm()))
+            .unwrap_or(row);
+        if rows[pivot][col].norm() <= tolerance {
+            continue;
+        }
+        if pivot != row {
+            rows.swap(pivot, row);
+            swapped = !swapped;
+        }
+        let (upper, lower) = rows.split_at_mut(row + 1);
+        let pivot_row = &upper[row];
+        for other in lower.iter_mut() {
+            let factor = other[col] / pivot_row[col];
+            for j in col..other.len() {
+                let val = factor * pivot_row[j];
+                other[j] -= val;
+            }
+        }
+        pivots.push(col);
+    }
+    (pivots, swapped)
+}
+
+fn float_rows(matrix: &Matrix) -> Vec<Vec<Complex64>> {
+    (0..matrix.rows).map(|row| matrix.row(row).iter().map(Scalar::as_complex).collect()).collect()
+}
+
+// Results of the inexact path are complex only when some input was
+fn inexact(val: Complex64, complex: bool) -> Scalar {
+    match complex {
+        true => Scalar::Complex(val),
+        false => Scalar::Float(val.re),
+    }
+}
+
+// Scale for treating float pivots as zero, relative to the largest entry
+fn tolerance(matrix: &Matrix) -> f64 {
+    let largest = matrix.data.iter().map(|val| val.as_complex().norm()).fold(0.0, f64::max);
+    largest * matrix.rows.max(matrix.cols) as f64 * f64::EPSILON
+}
+
+fn square(matrix: &Matrix, function: &str) -> Result<(), String> {
+    match matrix.rows == matrix.cols {
+        true => Ok(()),
+        false => Err(format!("{} expects a square matrix, got {}x{}", function, matrix.rows, matrix.cols)),
+    }
+}
+
+/// Determinant; exact whenever no entry is a float or complex number.
+pub fn det(matrix: &Matrix) -> Result<Scalar, String> {
+    square(matrix, "det")?;
+    let n = matrix.rows;
+    if n == 0 {
+        return Ok(Scalar::Int(1));
+    }
+    if let Some((mut rows, scale)) = integer_rows(matrix) {
+        let (pivots, swapped) = bareiss(&mut rows, n);
+        if pivots.len() < n {
+            return Ok(Scalar::Int(0));
+        }
+        // The last pivot of a Bareiss elimination is the determinant
+        let det = mem::take(&mut rows[n - 1][n - 1]);
+        let det = if swapped { -det } else { det };
+        return Ok(Scalar::rational(BigRational::new(det, scale)));
+    }
+    let complex = matrix.data.iter().any(Scalar::is_complex);
+    let mut rows = float_rows(matrix);
+    let (pivots, swapped) = eliminate(&mut rows, n, 0.0);
+    if pivots.len() < n {
+        return Ok(inexact(Complex64::new(0.0, 0.0), complex));
+    }
+    let det: Complex64 = (0..n).map(|i| rows[i][i]).product();
+    Ok(inexact(if swapped { -det } else { det }, complex))
+}
+
+pub fn rank(matrix: &Matrix) -> usize {
+    match integer_rows(matrix) {
+        Some((mut rows, _)) => bareiss(&mut rows, matrix.cols).0.len(),
+        None => eliminate(&mut float_rows(matrix), matrix.cols, tolerance(matrix)).0.len(),
+    }
+}
+
+/// Solves `a * x = b` for a square, non-singular `a`, one column of `x` per
+/// column of `b`; exact whenever neither side has a float or complex entry.
+pub fn solve(a: &Matrix, b: &Matrix) -> Result<Matrix, String> {
+    solve_for(a, b, "solve")
+}
+
+pub fn identity(n: usize) -> Matrix {
+    Matrix::new(n, n, (0..n * n).map(|k| Scalar::Int((k % (n + 1) == 0) as i128)).collect())
+}
+
+/// Inverse of a square, non-singular matrix; exact like `solve`.
+pub fn inverse(matrix: &Matrix) -> Result<Matrix, String> {
+    solve_for(matrix, &identity(matrix.rows), "inverse")
+}
+
+// `function` names the caller in error messages
+fn solve_for(a: &Matrix, b: &Matrix, function: &str) -> Result<Matrix, String> {
+    square(a, function)?;
+    if a.rows != b.rows {
+        return Err(format!("{} expects a right-hand side with {} rows, got {}", function, a.rows, b.rows));
+    }
+    let (n, k) = (a.rows, b.cols);
+    let mut data = Vec::with_capacity(n * (n + k));
+    for row in 0..n {
+        data.extend_from_slice(a.row(row));
+        data.extend_from_slice(b.row(row));
+    }
+    let augmented = Matrix::new(n, n + k, data);
+    let singular = || format!("{} expects a non-singular matrix", function);
+
+    if let Some((mut rows, _)) = integer_rows(&augmented) {
+        if bareiss(&mut rows, n).0.len() < n {
+            return Err(singular());
+        }
+        let mut x = vec![vec![BigRational::zero(); k]; n];
+        for i in (0..n).rev() {
+            for col in 0..k {
+                let mut sum = BigRational::from_integer(rows[i][n + col].clone());
+                for j in i + 1..n {
+                    sum -= BigRational::from_integer(rows[i][j].clone()) * &x[j][col];
+                }
+                x[i][col] = sum / BigRational::from_integer(rows[i][i].clone());
+            }
+        }
+        let data = x.into_iter().flatten().map(Scalar::rational).collect();
+        return Ok(Matrix::new(n, k, data));
+    }
+
+    let mut rows = float_rows(&augmented);
+    if eliminate(&mut rows, n, tolerance(a)).0.len() < n {
+        return Err(singular());
+    }
+    let complex = augmented.data.iter().any(Scalar::is_complex);
+    let mut x = vec![vec![Complex64::new(0.0, 0.0); k]; n];
+    for i in (0..n).rev() {
+        for col in 0..k {
+            let sum: Complex64 = (i + 1..n).map(|j| rows[i][j] * x[j][col]).sum();
+            x[i][col] = (rows[i][n + col] - sum) / rows[i][i];
+        }
+    }
+    Ok(Matrix::new(n, k, x.into_iter().flatten().map(|val| inexact(val, complex)).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{det, identity, inverse, rank, solve};
+    use crate::core::dodo::value::{Matrix, Scalar};
+    use num_bigint::BigInt;
+    use num_rational::BigRational;
+
+    fn ints(rows: usize, cols: usize, values: &[i128]) -> Matrix {
+        Matrix::new(rows, cols, values.iter().map(|val| Scalar::Int(*val)).collect())
+    }
+
+    fn fraction(numer: i64, denom: i64) -> Scalar {
+        Scalar::rational(BigRational::new(BigInt::from(numer), BigInt::from(denom)))
+    }
+
+    #[test]
+    fn exact_determinants() {
+        assert_eq!(det(&ints(3, 3, &[2, -1, 0, -1, 2, -1, 0, -1, 2])), Ok(Scalar::Int(4)));
+        // Needs a row swap: the first pivot is zero
+        assert_eq!(det(&ints(2, 2, &[0, 1, 1, 0])), Ok(Scalar::Int(-1)));
+        assert_eq!(det(&ints(2, 2, &[1, 2, 2, 4])), Ok(Scalar::Int(0)));
+        assert!(det(&ints(2, 3, &[1, 2, 3, 4, 5, 6])).is_err());
+
+        // The 4x4 Hilbert matrix, whose determinant floats get wrong in the last digits
+        let hilbert = Matrix::new(4, 4, (0..16).map(|k| fraction(1, k / 4 + k % 4 + 1)).collect());
+        assert_eq!(det(&hilbert), Ok(fraction(1, 6_048_000)));
+
+        // Entries past i128 stay exact
+        let huge = i128::MAX;
+        let det_huge = det(&ints(2, 2, &[huge, 1, 1, huge])).unwrap();
+        assert_eq!(det_huge, Scalar::big(BigInt::from(huge) * BigInt::from(huge) - 1));
+        assert_eq!(det(&Matrix::new(2, 2, vec![Scalar::Float(1.5), Scalar::Int(2), Scalar::Int(3), Scalar::Int(4)])), Ok(Scalar::Float(0.0)));
+    }
+
+    #[test]
+    fn rank_and_solve() {
+        assert_eq!(rank(&ints(3, 3, &[1, 2, 3, 2, 4, 6, 1, 0, 1])), 2);
+        assert_eq!(rank(&ints(2, 4, &[0, 0, 1, 2, 0, 0, 2, 4])), 1);
+        assert_eq!(rank(&Matrix::new(2, 2, vec![Scalar::Float(1.0), Scalar::Float(2.0), Scalar::Float(2.0), Scalar::Float(4.0)])), 1);
+
+        let a = ints(3, 3, &[2, -1, 0, -1, 2, -1, 0, -1, 2]);
+        let x = solve(&a, &ints(3, 1, &[1, 0, 0])).unwrap();
+        assert_eq!(x.data, vec![fraction(3, 4), fraction(1, 2), fraction(1, 4)]);
+        assert_eq!(solve(&ints(2, 2, &[1, 2, 2, 4]), &ints(2, 1, &[1, 1])), Err("solve expects a non-singular matrix".to_string()));
+
+        let floats = Matrix::new(2, 2, vec![Scalar::Float(4.0), Scalar::Int(1), Scalar::Int(1), Scalar::Int(3)]);
+        let x = solve(&floats, &ints(2, 1, &[1, 2])).unwrap();
+        assert!((x.data[0].as_f64() - 1.0 / 11.0).abs() < 1e-12 && (x.data[1].as_f64() - 7.0 / 11.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn exact_inverse() {
+        let a = ints(2, 2, &[2, 1, 1, 1]);
+        assert_eq!(inverse(&a), Ok(ints(2, 2, &[1, -1, -1, 2])));
+        assert_eq!(inverse(&ints(1, 1, &[4])), Ok(Matrix::new(1, 1, vec![fraction(1, 4)])));
+        assert_eq!(identity(2), ints(2, 2, &[1, 0, 0, 1]));
+        assert_eq!(inverse(&ints(2, 2, &[1, 2, 2, 4])), Err("inverse expects a non-singular matrix".to_string()));
+    }
+}
@@ -1,4 +1,14 @@
 pub mod environment;
 pub mod repl;
 pub mod interpreter;
-pub mod error_types;
\ No newline at end of file
+pub mod error_types;
+pub mod value;
+pub mod sparse;
+pub mod builtins;
+pub mod runner;
+pub mod modules;
+pub mod stdlib;
+pub mod limits;
+pub mod linalg;
+pub mod stats;
+pub mod random;
@@ -0,0 +1,235 @@
+use crate::core::ast::ast::Program;
+use crate::core::ast::fold::Folder;
+use crate::core::ast::parser::Parser;
+use crate::core::ast::stmt::Stmt;
+use crate::core::check::checker::{Checker, Type};
+use crate::core::dodo::error_types::DodoImportError;
+use crate::core::dodo::stdlib;
+use crate::core::lexer::lexer::Lexer;
+use crate::core::token::token::Token;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Environment variable listing directories to look for imported files in, separated like `PATH`.
+pub const PATH_VAR: &str = "DODO_PATH";
+
+/// A file loaded by an import: parsed, folded and checked, with the type of each global it defines.
+pub struct Module {
+    pub path: PathBuf,
+    pub program: Program,
+    pub exports: HashMap<String, Type>,
+}
+
+/// Finds the files a program imports and loads each one once, before anything runs.
+/// A file name is looked up next to the file importing it, then in each directory of the
+/// search path: those given on the command line followed by those in `DODO_PATH`.
+/// Names starting with `std.` are the standard modules built into the binary.
+pub struct Loader {
+    search_path: Vec<PathBuf>,
+    // Only the standard modules may be imported, as no files may be read
+    sandbox: bool,
+    modules: HashMap<PathBuf, Rc<Module>>,
+    // Files being loaded, outermost first, with their names as written, to report import cycles
+    loading: Vec<(PathBuf, String)>,
+}
+
+impl Loader {
+    pub fn new(search_path: &[PathBuf]) -> Self {
+        let mut search_path = search_path.to_vec();
+        if let Some(paths) = env::var_os(PATH_VAR) {
+            search_path.extend(env::split_paths(&paths).filter(|dir| !dir.as_os_str().is_empty()));
+        }
+        Loader {
+            search_path,
+            sandbox: false,
+            modules: HashMap::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    pub fn set_sandbox(&mut self, sandbox: bool) {
+        self.sandbox = sandbox;
+    }
+
+    fn resolve(&self, name: &str, dir: &Path) -> Option<PathBuf> {
+        std::iter::once(dir)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .and_then(|path| fs::canonicalize(path).ok())
+    }
+
+    /// Loads the files imported by `program`, which was read from `file`, and the files those
+    /// import in turn, recording each in the `imports` of the program importing it.
+    pub fn load_imports(&mut self, program: &mut Program, file: &Path) -> Vec<DodoImportError> {
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let canonical = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        self.imports_of(program, &dir, canonical, file.display().to_string())
+    }
+
+    // Loads the imports of a file, found in `dir`, at `path` and named `name` by whoever imported it
+    fn imports_of(&mut self, program: &mut Program, dir: &Path, path: PathBuf, name: String) -> Vec<DodoImportError> {
+        self.loading.push((path, name));
+        let mut errors = Vec::new();
+        for stmt in program.statements.iter() {
+            let name = match stmt {
+                Stmt::Import(name, _) | Stmt::FromImport(name, _) => name,
+                _ => continue,
+            };
+            if program.imports.contains_key(&name.val) {
+                continue;
+            }
+            match self.load(name, dir) {
+                Ok(module) => {
+                    program.imports.insert(name.val.clone(), module);
+                }
+                Err(mut failed) => errors.append(&mut failed),
+            }
+        }
+        self.loading.pop();
+        errors
+    }
+
+    fn load(&mut self, name: &Token, dir: &Path) -> Result<Rc<Module>, Vec<DodoImportError>> {
+        let failed = |message: String| vec![DodoImportError::new(name.clone(), message)];
+        let builtin = stdlib::source(&name.val);
+        let path = match builtin {
+            Some(_) => PathBuf::from(&name.val),
+            None if name.val.starts_with(stdlib::PREFIX) => {
+                let names: Vec<&str> = stdlib::MODULES.iter().map(|(module, _)| *module).collect();
+                return Err(failed(format!("No standard module '{}'; there are {}", name.val, names.join(", "))));
+            }
+            None if self.sandbox => return Err(failed(format!("Cannot import '{}' in a sandbox, only standard modules", name.val))),
+            None => match self.resolve(&name.val, dir) {
+                Some(path) => path,
+                None => return Err(failed(format!("Cannot find '{}' next to the importing file or in the search path", name.val))),
+            },
+        };
+        if let Some(module) = self.modules.get(&path) {
+            return Ok(module.clone());
+        }
+        if let Some(start) = self.loading.iter().position(|(loading, _)| *loading == path) {
+            let mut cycle: Vec<&str> = self.loading[start..].iter().map(|(_, name)| name.as_str()).collect();
+            cycle.push(&name.val);
+            return Err(failed(format!("Import cycle: {}", cycle.join(" -> "))));
+        }
+        let source = match builtin {
+            Some(source) => source.to_string(),
+            None => fs::read_to_string(&path).map_err(|error| failed(format!("{}: {}", name.val, error)))?,
+        };
+        let in_file = |errors: Vec<String>| errors.iter().map(|error| DodoImportError::new(name.clone(), format!("{}: {}", name.val, error))).collect::<Vec<_>>();
+
+        let mut parser = Parser::new(Lexer::new(source).scan_tokens());
+        let mut program = parser.parse_program();
+        if !parser.errors().is_empty() {
+            return Err(in_file(parser.errors().iter().map(|error| error.to_string()).collect()));
+        }
+        Folder::new().fold(&mut program);
+        let dir = path.parent().map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+        let errors = self.imports_of(&mut program, &dir, path.clone(), name.val.clone());
+        if !errors.is_empty() {
+            return Err(in_file(errors.iter().map(|error| error.to_string()).collect()));
+        }
+        let mut checker = Checker::new();
+        let errors = checker.check(&program);
+        if !errors.is_empty() {
+            return Err(in_file(errors.iter().map(|error| error.to_string()).collect()));
+        }
+        let module = Rc::new(Module {
+            path: path.clone(),
+            program,
+            exports: checker.exports(),
+        });
+        self.modules.insert(path, module.clone());
+        Ok(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Loader;
+    use crate::core::ast::parser::Parser;
+    use crate::core::dodo::environment::Environment;
+    use crate::core::dodo::interpreter::Interpreter;
+    use crate::core::lexer::lexer::Lexer;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Loads and runs `main` from a directory holding `files`, giving what it printed or the errors
+    fn run(dir: &str, files: &[(&str, &str)], main: &str) -> Result<String, Vec<String>> {
+        run_in(dir, files, main, false)
+    }
+
+    fn run_in(dir: &str, files: &[(&str, &str)], main: &str, sandbox: bool) -> Result<String, Vec<String>> {
+        let dir = std::env::temp_dir().join(dir);
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+        let mut program = Parser::new(Lexer::new(main.to_string()).scan_tokens()).parse_program();
+        let mut loader = Loader::new(&[dir.join("lib")]);
+        loader.set_sandbox(sandbox);
+        let errors = loader.load_imports(&mut program, &dir.join("main.dodo"));
+        if !errors.is_empty() {
+            return Err(errors.iter().map(|error| error.to_string()).collect());
+        }
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new(Environment::new());
+        interpreter.set_output(Box::new(Shared(printed.clone())));
+        interpreter.interpret(&program).map_err(|error| vec![error.to_string()])?;
+        let printed = printed.borrow().clone();
+        Ok(String::from_utf8(printed).unwrap())
+    }
+
+    #[test]
+    fn modules_are_loaded_once_into_their_own_globals() {
+        let files = [
+            ("lib/counter.dodo", "scalar calls = 0\nfn bump() {\n    calls = calls + 1\n    return calls\n}\nprint \"loaded\"\n"),
+            ("utils.dodo", "import \"counter.dodo\" as c\nfn twice(v) {\n    c.bump()\n    return v * 2\n}\n"),
+        ];
+        let main = "import \"counter.dodo\" as counter\nfrom \"utils.dodo\" import twice\nscalar calls = 10\nprint twice(4)\nprint counter.bump()\nprint calls\n";
+
+        assert_eq!(run("dodolang_modules_test", &files, main), Ok("loaded\n8\n2\n10\n".to_string()));
+    }
+
+    #[test]
+    fn missing_files_and_cycles() {
+        let files = [("a.dodo", "import \"b.dodo\" as b\n"), ("b.dodo", "from \"a.dodo\" import f\n")];
+        let errors = run("dodolang_cycle_test", &files, "import \"a.dodo\" as a\nimport \"c.dodo\" as c\n").unwrap_err();
+
+        assert_eq!(errors, vec![
+            "[line 1] Import error: a.dodo: [line 1] Import error: b.dodo: [line 1] Import error: Import cycle: a.dodo -> b.dodo -> a.dodo",
+            "[line 2] Import error: Cannot find 'c.dodo' next to the importing file or in the search path",
+        ]);
+    }
+
+    #[test]
+    fn sandbox_only_imports_standard_modules() {
+        let files = [("secret.dodo", "print \"read\"\n")];
+        let errors = run_in("dodolang_sandbox_test", &files, "import \"secret.dodo\" as s\n", true).unwrap_err();
+        assert_eq!(errors, vec!["[line 1] Import error: Cannot import 'secret.dodo' in a sandbox, only standard modules"]);
+
+        let main = "from \"std.geometry\" import norm\nprint norm({3, 4})\n";
+        assert_eq!(run_in("dodolang_sandbox_test", &files, main, true), Ok("5.0\n".to_string()));
+    }
+}
@@ -0,0 +1,81 @@
+/// Seed used until a script calls `seed`, so runs are reproducible by default.
+pub const DEFAULT_SEED: u64 = 0;
+
+/// Deterministic xoshiro256** generator. The same seed gives the same
+/// numbers on every platform.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    /// Expands `seed` into the generator state with SplitMix64, as the
+    /// xoshiro authors recommend.
+    pub fn new(seed: u64) -> Self {
+        let mut mix = seed;
+        let mut next = || {
+            mix = mix.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = mix;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        Rng { state: [next(), next(), next(), next()] }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// Uniform in [0, 1), from the top 53 bits.
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, by the Box-Muller transform.
+    pub fn normal(&mut self) -> f64 {
+        // 1 - uniform lies in (0, 1], so the logarithm is finite
+        let radius = (-2.0 * (1.0 - self.uniform()).ln()).sqrt();
+        radius * (2.0 * std::f64::consts::PI * self.uniform()).cos()
+    }
+
+    /// Uniform in [0, n) for n > 0, without modulo bias.
+    pub fn below(&mut self, n: u64) -> u64 {
+        // Draws above the last whole copy of [0, n) are rejected
+        let zone = u64::MAX - (u64::MAX - n + 1) % n;
+        loop {
+            let draw = self.next_u64();
+            if draw <= zone {
+                return draw % n;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+
+    #[test]
+    fn seeded_sequences_repeat() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        let first: Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..5).map(|_| b.next_u64()).collect::<Vec<u64>>());
+        assert_ne!(first[0], Rng::new(43).next_u64());
+
+        let mut rng = Rng::new(7);
+        assert!((0..1000).map(|_| rng.uniform()).all(|val| (0.0..1.0).contains(&val)));
+        assert!((0..1000).map(|_| rng.below(6)).all(|val| val < 6));
+        let mean = (0..10000).map(|_| rng.normal()).sum::<f64>() / 10000.0;
+        assert!(mean.abs() < 0.05);
+    }
+}
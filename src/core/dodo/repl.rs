@@ -1,52 +1,87 @@
-use crate::core::token::token::Token;
-use crate::core::token::token::TokenType;
-use crate::core::dodo::interpreter;
 use crate::core::lexer::lexer::Lexer;
+use crate::core::ast::fold::Folder;
 use crate::core::ast::parser::Parser;
-use std::io::{self, Write};
+use crate::core::check::checker::Checker;
+use std::io;
+use std::path::{Path, PathBuf};
 use crate::core::dodo::interpreter::Interpreter;
 use crate::core::dodo::environment::Environment;
+use crate::core::dodo::modules::Loader;
+use crate::core::dodo::error_types::throw_error;
+use crate::core::token::token::TokenType;
 
 const PROMPT: &str = ">>> ";
+const CONTINUE_PROMPT: &str = "... ";
+
+// Braces still open in the input, so a function body can span several lines
+fn open_braces(source: &str) -> i64 {
+    Lexer::new(source.to_string()).scan_tokens().iter()
+        .map(|token| match token.token_type {
+            TokenType::LeftBrace => 1,
+            TokenType::RightBrace => -1,
+            _ => 0,
+        })
+        .sum()
+}
 
-pub fn start() {
+// Imports typed at the prompt are looked up in the working directory, then in `search_path`
+pub fn start(search_path: &[PathBuf]) {
     println!("Dodolang!");
 
-    let mut env = Environment::new();
+    let env = Environment::new();
     let mut interpreter = Interpreter::new(env);
+    let mut checker = Checker::new();
+    let mut folder = Folder::new();
+    let mut loader = Loader::new(search_path);
 
     loop {
         print!("{}", PROMPT);
         io::Write::flush(&mut io::stdout()).expect("flush failed!");
 
         let mut buffer = String::new();
-        match io::stdin().read_line(&mut buffer) {
-            Err(error) => {
-                println!("error: {}", error);
-                return;
+        loop {
+            match io::stdin().read_line(&mut buffer) {
+                Err(error) => {
+                    println!("error: {}", error);
+                    return;
+                }
+                Ok(0) => return,
+                Ok(_) => (),
             }
-            Ok(_) => (),
+            if open_braces(&buffer) <= 0 {
+                break;
+            }
+            print!("{}", CONTINUE_PROMPT);
+            io::Write::flush(&mut io::stdout()).expect("flush failed!");
         }
 
-        let mut tokens = Vec::new();
-
-        let mut lexer = Lexer::new(buffer);
-        loop {
-            let lexed_token = lexer.next_token();
-            match lexed_token.token_type {
-                TokenType::EOF =>
-                    {
-                        tokens.push(lexed_token);
-                        break;
-                    }
-                _ => {
-                    tokens.push(lexed_token)
-                }
+        let tokens = Lexer::new(buffer).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let mut program = parser.parse_program();
+        if !parser.errors().is_empty() {
+            for error in parser.errors() {
+                throw_error(error.token.clone(), error.message.clone());
+            }
+            continue;
+        }
+        folder.fold(&mut program);
+        let errors = loader.load_imports(&mut program, Path::new("<stdin>"));
+        if !errors.is_empty() {
+            for error in errors {
+                eprintln!("{}", error);
             }
+            continue;
+        }
+        let errors = checker.check(&program);
+        if !errors.is_empty() {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            continue;
         }
-        let mut parser = Parser::new(tokens.clone());
-        let mut expr = parser.parse();
 
-        let outcome = interpreter.interpret(expr);
+        if let Err(error) = interpreter.interpret(&program) {
+            eprintln!("{}", error);
+        }
     }
 }
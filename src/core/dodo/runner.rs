@@ -0,0 +1,95 @@
+use crate::core::ast::ast::Program;
+use crate::core::ast::fold::Folder;
+use crate::core::ast::parser::Parser;
+use crate::core::check::checker::Checker;
+use crate::core::dodo::environment::Environment;
+use crate::core::dodo::interpreter::Interpreter;
+use crate::core::dodo::modules::Loader;
+use crate::core::dodo::value::Arithmetic;
+use crate::core::lexer::lexer::Lexer;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Exit codes follow sysexits.h
+pub const EX_DATAERR: i32 = 65;
+pub const EX_NOINPUT: i32 = 66;
+pub const EX_SOFTWARE: i32 = 70;
+
+// Parses, folds constants, loads imported files and statically checks a script read from `path`,
+// reporting any errors to stderr; imports are looked up next to the script, then in `search_path`
+pub fn compile(source: &str, path: &str, search_path: &[PathBuf]) -> Option<Program> {
+    match compile_errors(source, path, search_path, false) {
+        Ok(program) => Some(program),
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            None
+        }
+    }
+}
+
+/// Like `compile`, but gives back the errors of the first stage that fails instead of printing them.
+/// In a `sandbox` only the standard modules can be imported, so no file is read.
+pub fn compile_errors(source: &str, path: &str, search_path: &[PathBuf], sandbox: bool) -> Result<Program, Vec<String>> {
+    let tokens = Lexer::new(source.to_string()).scan_tokens();
+    let mut parser = Parser::new(tokens);
+    let mut program = parser.parse_program();
+    if !parser.errors().is_empty() {
+        return Err(parser.errors().iter().map(|error| error.to_string()).collect());
+    }
+    Folder::new().fold(&mut program);
+    let mut loader = Loader::new(search_path);
+    loader.set_sandbox(sandbox);
+    let errors = loader.load_imports(&mut program, Path::new(path));
+    if !errors.is_empty() {
+        return Err(errors.iter().map(|error| error.to_string()).collect());
+    }
+    let errors = Checker::new().check(&program);
+    if !errors.is_empty() {
+        return Err(errors.iter().map(|error| error.to_string()).collect());
+    }
+    Ok(program)
+}
+
+/// Runs a whole script read from `path`, returning the process exit code.
+pub fn run_source(source: &str, path: &str, search_path: &[PathBuf], arithmetic: Arithmetic) -> i32 {
+    let program = match compile(source, path, search_path) {
+        Some(program) => program,
+        None => return EX_DATAERR,
+    };
+
+    let mut interpreter = Interpreter::new(Environment::new());
+    interpreter.set_arithmetic(arithmetic);
+    match interpreter.interpret(&program) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("{}", error);
+            EX_SOFTWARE
+        }
+    }
+}
+
+pub fn run_file(path: &str, search_path: &[PathBuf], arithmetic: Arithmetic) -> i32 {
+    match fs::read_to_string(path) {
+        Ok(source) => run_source(&source, path, search_path, arithmetic),
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            EX_NOINPUT
+        }
+    }
+}
+
+/// `dodolang check <file>`: reports parse and type errors without running the script.
+pub fn check_file(path: &str, search_path: &[PathBuf]) -> i32 {
+    match fs::read_to_string(path) {
+        Ok(source) => match compile(&source, path, search_path) {
+            Some(_) => 0,
+            None => EX_DATAERR,
+        },
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            EX_NOINPUT
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use crate::core::dodo::value::{Arithmetic, Matrix, Scalar};
+use crate::core::token::token::TokenType;
+use std::fmt;
+
+/// Sparse matrix in compressed sparse row (CSR) form. Row `r` owns the entries
+/// `indptr[r]..indptr[r + 1]` of `indices` (column numbers) and `data`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SparseMatrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub indptr: Vec<usize>,
+    pub indices: Vec<usize>,
+    pub data: Vec<Scalar>,
+}
+
+fn add(mode: Arithmetic, left: &Scalar, right: &Scalar) -> Result<Scalar, String> {
+    left.arithmetic_in(mode, TokenType::PLUS, right)
+}
+
+fn mul(mode: Arithmetic, left: &Scalar, right: &Scalar) -> Result<Scalar, String> {
+    left.arithmetic_in(mode, TokenType::ASTERISK, right)
+}
+
+impl SparseMatrix {
+    /// Builds a matrix from coordinate (COO) triplets with 0-based indices.
+    /// Duplicate entries are summed and explicit zeros dropped.
+    pub fn from_triplets(rows: usize, cols: usize, triplets: Vec<(usize, usize, Scalar)>) -> Result<Self, String> {
+        SparseMatrix::from_triplets_in(Arithmetic::Checked, rows, cols, triplets)
+    }
+
+    /// Like `from_triplets`, summing duplicates in the given arithmetic mode.
+    pub fn from_triplets_in(mode: Arithmetic, rows: usize, cols: usize, mut triplets: Vec<(usize, usize, Scalar)>) -> Result<Self, String> {
+        for (row, col, _) in triplets.iter() {
+            if *row >= rows || *col >= cols {
+                return Err(format!("Index ({}, {}) out of bounds for {}x{} sparse matrix", row, col, rows, cols));
+            }
+        }
+        triplets.sort_by_key(|(row, col, _)| (*row, *col));
+
+        let mut merged: Vec<(usize, usize, Scalar)> = Vec::with_capacity(triplets.len());
+        for (row, col, val) in triplets {
+            match merged.last_mut() {
+                Some(last) if last.0 == row && last.1 == col => last.2 = add(mode, &last.2, &val)?,
+                _ => merged.push((row, col, val)),
+            }
+        }
+
+        let mut indptr = Vec::new();
+        rows.checked_add(1)
+            .filter(|len| indptr.try_reserve_exact(*len).is_ok())
+            .ok_or_else(|| format!("Cannot allocate a {}x{} sparse matrix", rows, cols))?;
+        indptr.resize(rows + 1, 0);
+        let mut indices = Vec::with_capacity(merged.len());
+        let mut data = Vec::with_capacity(merged.len());
+        for (row, col, val) in merged.into_iter().filter(|(_, _, val)| !val.is_zero()) {
+            indptr[row + 1] += 1;
+            indices.push(col);
+            data.push(val);
+        }
+        for row in 0..rows {
+            indptr[row + 1] += indptr[row];
+        }
+        Ok(SparseMatrix {
+            rows,
+            cols,
+            indptr,
+            indices,
+            data,
+        })
+    }
+
+    pub fn from_dense(matrix: &Matrix) -> Self {
+        let mut triplets = Vec::new();
+        for row in 0..matrix.rows {
+            for col in 0..matrix.cols {
+                triplets.push((row, col, matrix.get(row, col).clone()));
+            }
+        }
+        SparseMatrix::from_triplets(matrix.rows, matrix.cols, triplets).expect("dense indices are in bounds")
+    }
+
+    /// The matrix with its zeros filled in; fails when it is too large to hold.
+    pub fn to_dense(&self) -> Result<Matrix, String> {
+        let zero = match self.data.iter().any(Scalar::is_float) {
+            true => Scalar::Float(0.0),
+            false => Scalar::Int(0),
+        };
+        let mut dense = Matrix::filled(self.rows, self.cols, zero)?;
+        for (row, col, val) in self.triplets() {
+            dense.data[row * self.cols + col] = val;
+        }
+        Ok(dense)
+    }
+
+    pub fn nnz(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Coordinate (COO) view of the stored entries in row-major order.
+    pub fn triplets(&self) -> Vec<(usize, usize, Scalar)> {
+        let mut triplets = Vec::with_capacity(self.nnz());
+        for row in 0..self.rows {
+            for k in self.indptr[row]..self.indptr[row + 1] {
+                triplets.push((row, self.indices[k], self.data[k].clone()));
+            }
+        }
+        triplets
+    }
+
+    /// Applies `f` to every stored entry, e.g. to scale by a scalar.
+    pub fn map<F>(&self, f: F) -> Result<Self, String> where F: Fn(&Scalar) -> Result<Scalar, String> {
+        let triplets = self.triplets().into_iter()
+            .map(|(row, col, val)| f(&val).map(|val| (row, col, val)))
+            .collect::<Result<Vec<_>, String>>()?;
+        SparseMatrix::from_triplets(self.rows, self.cols, triplets)
+    }
+
+    /// Element-wise sum or difference of two sparse matrices of the same shape.
+    pub fn combine(&self, operator: TokenType, other: &SparseMatrix, mode: Arithmetic) -> Result<Self, String> {
+        if self.rows != other.rows || self.cols != other.cols {
+            return Err(format!("Shape mismatch: {}x{} and {}x{}", self.rows, self.cols, other.rows, other.cols));
+        }
+        let mut triplets = self.triplets();
+        for (row, col, val) in other.triplets() {
+            let val = match operator {
+                TokenType::MINUS => val.negate_in(mode)?,
+                _ => val,
+            };
+            triplets.push((row, col, val));
+        }
+        SparseMatrix::from_triplets_in(mode, self.rows, self.cols, triplets)
+    }
+
+    pub fn mul_vector(&self, vector: &[Scalar], mode: Arithmetic) -> Result<Vec<Scalar>, String> {
+        if self.cols != vector.len() {
+            return Err(format!("Cannot multiply {}x{} sparse matrix by vector of length {}", self.rows, self.cols, vector.len()));
+        }
+        let mut result = Vec::with_capacity(self.rows);
+        for row in 0..self.rows {
+            let mut sum = Scalar::Int(0);
+            for k in self.indptr[row]..self.indptr[row + 1] {
+                sum = add(mode, &sum, &mul(mode, &self.data[k], &vector[self.indices[k]])?)?;
+            }
+            result.push(sum);
+        }
+        Ok(result)
+    }
+
+    pub fn vector_mul(vector: &[Scalar], sparse: &SparseMatrix, mode: Arithmetic) -> Result<Vec<Scalar>, String> {
+        if vector.len() != sparse.rows {
+            return Err(format!("Cannot multiply vector of length {} by {}x{} sparse matrix", vector.len(), sparse.rows, sparse.cols));
+        }
+        let mut result = vec![Scalar::Int(0); sparse.cols];
+        for (row, col, val) in sparse.triplets() {
+            result[col] = add(mode, &result[col], &mul(mode, &vector[row], &val)?)?;
+        }
+        Ok(result)
+    }
+
+    pub fn mul_dense(&self, dense: &Matrix, mode: Arithmetic) -> Result<Matrix, String> {
+        if self.cols != dense.rows {
+            return Err(format!("Cannot multiply {}x{} sparse matrix by {}x{} matrix", self.rows, self.cols, dense.rows, dense.cols));
+        }
+        let mut result = Matrix::filled(self.rows, dense.cols, Scalar::Int(0))?;
+        for (row, k, val) in self.triplets() {
+            for col in 0..dense.cols {
+                let index = row * dense.cols + col;
+                result.data[index] = add(mode, &result.data[index], &mul(mode, &val, dense.get(k, col))?)?;
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn dense_mul(dense: &Matrix, sparse: &SparseMatrix, mode: Arithmetic) -> Result<Matrix, String> {
+        if dense.cols != sparse.rows {
+            return Err(format!("Cannot multiply {}x{} matrix by {}x{} sparse matrix", dense.rows, dense.cols, sparse.rows, sparse.cols));
+        }
+        let mut result = Matrix::filled(dense.rows, sparse.cols, Scalar::Int(0))?;
+        for (k, col, val) in sparse.triplets() {
+            for row in 0..dense.rows {
+                let index = row * sparse.cols + col;
+                result.data[index] = add(mode, &result.data[index], &mul(mode, dense.get(row, k), &val)?)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Sparse product, accumulating each result row over the non-zeros of `self`.
+    pub fn mul_sparse(&self, other: &SparseMatrix, mode: Arithmetic) -> Result<SparseMatrix, String> {
+        if self.cols != other.rows {
+            return Err(format!("Cannot multiply {}x{} sparse matrix by {}x{} sparse matrix", self.rows, self.cols, other.rows, other.cols));
+        }
+        let mut triplets = Vec::new();
+        for row in 0..self.rows {
+            for k in self.indptr[row]..self.indptr[row + 1] {
+                let inner = self.indices[k];
+                for j in other.indptr[inner]..other.indptr[inner + 1] {
+                    triplets.push((row, other.indices[j], mul(mode, &self.data[k], &other.data[j])?));
+                }
+            }
+        }
+        SparseMatrix::from_triplets_in(mode, self.rows, other.cols, triplets)
+    }
+}
+
+impl fmt::Display for SparseMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}x{} sparse, {} stored>", self.rows, self.cols, self.nnz())?;
+        for (row, col, val) in self.triplets() {
+            write!(f, "\n  ({}, {})  {}", row, col, val)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SparseMatrix;
+    use crate::core::dodo::value::{Arithmetic, Matrix, Scalar};
+
+    fn ints(values: &[i128]) -> Vec<Scalar> {
+        values.iter().map(|val| Scalar::Int(*val)).collect()
+    }
+
+    fn example() -> SparseMatrix {
+        // {{1, 0, 2}, {0, 0, 3}}, with (0, 2) given as 1 + 1 and an explicit zero at (1, 0)
+        let triplets = vec![
+            (1, 2, Scalar::Int(3)),
+            (0, 2, Scalar::Int(1)),
+            (0, 0, Scalar::Int(1)),
+            (1, 0, Scalar::Int(0)),
+            (0, 2, Scalar::Int(1)),
+        ];
+        SparseMatrix::from_triplets(2, 3, triplets).unwrap()
+    }
+
+    #[test]
+    fn csr_from_triplets() {
+        let sparse = example();
+
+        assert_eq!(sparse.indptr, vec![0, 2, 3]);
+        assert_eq!(sparse.indices, vec![0, 2, 2]);
+        assert_eq!(sparse.data, ints(&[1, 2, 3]));
+        assert_eq!(sparse.to_dense().unwrap(), Matrix::new(2, 3, ints(&[1, 0, 2, 0, 0, 3])));
+        assert_eq!(SparseMatrix::from_dense(&sparse.to_dense().unwrap()), sparse);
+        assert!(SparseMatrix::from_triplets(2, 2, vec![(2, 0, Scalar::Int(1))]).is_err());
+    }
+
+    #[test]
+    fn products_match_dense() {
+        let sparse = example();
+        let dense = Matrix::new(3, 2, ints(&[1, 2, 3, 4, 5, 6]));
+
+        assert_eq!(sparse.mul_dense(&dense, Arithmetic::Checked).unwrap(), Matrix::new(2, 2, ints(&[11, 14, 15, 18])));
+        assert_eq!(sparse.mul_vector(&ints(&[1, 1, 1]), Arithmetic::Checked).unwrap(), ints(&[3, 3]));
+        assert_eq!(SparseMatrix::vector_mul(&ints(&[1, 1]), &sparse, Arithmetic::Checked).unwrap(), ints(&[1, 0, 5]));
+
+        let right = SparseMatrix::from_dense(&dense);
+        let product = sparse.mul_sparse(&right, Arithmetic::Checked).unwrap();
+        assert_eq!(product.to_dense().unwrap(), Matrix::new(2, 2, ints(&[11, 14, 15, 18])));
+        assert_eq!(SparseMatrix::dense_mul(&dense, &sparse, Arithmetic::Checked).unwrap(), Matrix::new(3, 3, ints(&[1, 0, 8, 3, 0, 18, 5, 0, 28])));
+        assert!(sparse.mul_sparse(&sparse, Arithmetic::Checked).is_err());
+    }
+
+    #[test]
+    fn oversized_matrices_are_errors() {
+        assert!(SparseMatrix::from_triplets(usize::MAX, 1, Vec::new()).is_err());
+        assert!(SparseMatrix::from_triplets(99999999999999, 1, Vec::new()).is_err());
+
+        let wide = SparseMatrix::from_triplets(3, 1 << 62, vec![(0, 0, Scalar::Int(1))]).unwrap();
+        assert!(wide.to_dense().unwrap_err().contains("Cannot allocate"));
+    }
+}
@@ -0,0 +1,223 @@
+use crate::core::dodo::value::{Arithmetic, Matrix, Scalar};
+use crate::core::token::token::TokenType;
+use std::cmp::Ordering;
+
+fn nonempty(items: &[Scalar], function: &str) -> Result<(), String> {
+    match items.is_empty() {
+        true => Err(format!("{} expects at least one element", function)),
+        false => Ok(()),
+    }
+}
+
+fn fold(items: &[Scalar], mode: Arithmetic, operator: TokenType, start: Scalar) -> Result<Scalar, String> {
+    items.iter().try_fold(start, |total, item| total.arithmetic_in(mode, operator, item))
+}
+
+/// Sum; exact for integers and rationals, and 0 when there are no elements.
+pub fn sum(items: &[Scalar], mode: Arithmetic) -> Result<Scalar, String> {
+    fold(items, mode, TokenType::PLUS, Scalar::Int(0))
+}
+
+/// Product; 1 when there are no elements.
+pub fn prod(items: &[Scalar], mode: Arithmetic) -> Result<Scalar, String> {
+    fold(items, mode, TokenType::ASTERISK, Scalar::Int(1))
+}
+
+/// Mean; like `/`, the mean of integers is a float unless it is whole.
+pub fn mean(items: &[Scalar], mode: Arithmetic) -> Result<Scalar, String> {
+    nonempty(items, "mean")?;
+    sum(items, mode)?.arithmetic_in(mode, TokenType::SLASH, &Scalar::Int(items.len() as i128))
+}
+
+// Elements in ascending order; NaN sorts last
+fn sorted(items: &[Scalar], function: &str) -> Result<Vec<Scalar>, String> {
+    if items.iter().any(Scalar::is_complex) {
+        return Err(format!("{} cannot order complex numbers", function));
+    }
+    let mut sorted = items.to_vec();
+    sorted.sort_by(|a, b| a.compare(b).unwrap_or_else(|| a.as_f64().is_nan().cmp(&b.as_f64().is_nan())));
+    Ok(sorted)
+}
+
+/// Median; the mean of the two middle elements when there is an even number.
+pub fn median(items: &[Scalar], mode: Arithmetic) -> Result<Scalar, String> {
+    nonempty(items, "median")?;
+    let sorted = sorted(items, "median")?;
+    if sorted.last().is_some_and(|last| last.as_f64().is_nan()) {
+        return Ok(Scalar::Float(f64::NAN));
+    }
+    let middle = sorted.len() / 2;
+    match sorted.len() % 2 {
+        1 => Ok(sorted[middle].clone()),
+        _ => mean(&sorted[middle - 1..=middle], mode),
+    }
+}
+
+/// Sample variance, normalised by n - 1; 0 for a single element. Complex
+/// elements contribute their squared distance from the mean.
+pub fn var(items: &[Scalar], mode: Arithmetic) -> Result<Scalar, String> {
+    nonempty(items, "var")?;
+    if items.len() == 1 {
+        return Ok(Scalar::Int(0));
+    }
+    let mean = mean(items, mode)?;
+    let mut squares = Vec::with_capacity(items.len());
+    for item in items {
+        let distance = item.arithmetic_in(mode, TokenType::MINUS, &mean)?.abs();
+        squares.push(distance.arithmetic_in(mode, TokenType::ASTERISK, &distance)?);
+    }
+    sum(&squares, mode)?.arithmetic_in(mode, TokenType::SLASH, &Scalar::Int(items.len() as i128 - 1))
+}
+
+/// Sample standard deviation, the square root of `var`.
+pub fn std(items: &[Scalar], mode: Arithmetic) -> Result<Scalar, String> {
+    Ok(Scalar::Float(var(items, mode)?.as_f64().sqrt()))
+}
+
+// Index of the first smallest (`keep` is Less) or largest element; a NaN wins, as in NumPy
+fn extreme(items: &[Scalar], function: &str, keep: Ordering) -> Result<usize, String> {
+    nonempty(items, function)?;
+    if items.iter().any(Scalar::is_complex) {
+        return Err(format!("{} cannot order complex numbers", function));
+    }
+    let mut best = 0;
+    for (index, item) in items.iter().enumerate().skip(1) {
+        match item.compare(&items[best]) {
+            _ if items[best].as_f64().is_nan() => break,
+            Some(ordering) if ordering == keep => best = index,
+            None => best = index,
+            Some(_) => (),
+        }
+    }
+    Ok(best)
+}
+
+pub fn argmin(items: &[Scalar], function: &str) -> Result<usize, String> {
+    extreme(items, function, Ordering::Less)
+}
+
+pub fn argmax(items: &[Scalar], function: &str) -> Result<usize, String> {
+    extreme(items, function, Ordering::Greater)
+}
+
+fn running(items: &[Scalar], mode: Arithmetic, operator: TokenType) -> Result<Vec<Scalar>, String> {
+    let mut totals: Vec<Scalar> = Vec::with_capacity(items.len());
+    for item in items {
+        let total = match totals.last() {
+            Some(total) => total.arithmetic_in(mode, operator, item)?,
+            None => item.clone(),
+        };
+        totals.push(total);
+    }
+    Ok(totals)
+}
+
+/// Running sums, the same length as the input.
+pub fn cumsum(items: &[Scalar], mode: Arithmetic) -> Result<Vec<Scalar>, String> {
+    running(items, mode, TokenType::PLUS)
+}
+
+/// Running products, the same length as the input.
+pub fn cumprod(items: &[Scalar], mode: Arithmetic) -> Result<Vec<Scalar>, String> {
+    running(items, mode, TokenType::ASTERISK)
+}
+
+/// The elements of each column (`axis` 0) or each row (`axis` 1) of a matrix.
+pub fn lanes(matrix: &Matrix, axis: usize) -> Vec<Vec<Scalar>> {
+    match axis {
+        0 => (0..matrix.cols).map(|col| (0..matrix.rows).map(|row| matrix.get(row, col).clone()).collect()).collect(),
+        _ => (0..matrix.rows).map(|row| matrix.row(row).to_vec()).collect(),
+    }
+}
+
+/// Sample covariance of the columns of `data`, whose rows are observations;
+/// normalised by n - 1 like `var`, whose results make up the diagonal.
+pub fn cov(data: &Matrix, mode: Arithmetic) -> Result<Matrix, String> {
+    if data.rows < 2 {
+        return Err(format!("cov expects at least two observations, got {}", data.rows));
+    }
+    let columns = lanes(data, 0);
+    let mut deviations = Vec::with_capacity(columns.len());
+    for column in &columns {
+        let mean = mean(column, mode)?;
+        deviations.push(column.iter().map(|item| item.arithmetic_in(mode, TokenType::MINUS, &mean)).collect::<Result<Vec<Scalar>, String>>()?);
+    }
+    let degrees = Scalar::Int(data.rows as i128 - 1);
+    let mut result = Vec::with_capacity(data.cols * data.cols);
+    for i in 0..data.cols {
+        for j in 0..data.cols {
+            let products = deviations[i].iter().zip(&deviations[j])
+                .map(|(a, b)| a.arithmetic_in(mode, TokenType::ASTERISK, &b.conj()))
+                .collect::<Result<Vec<Scalar>, String>>()?;
+            result.push(sum(&products, mode)?.arithmetic_in(mode, TokenType::SLASH, &degrees)?);
+        }
+    }
+    Ok(Matrix::new(data.cols, data.cols, uniform(result)))
+}
+
+// Gives every element the widest type among them, so an entry that divides exactly
+// is not left an integer beside floats
+fn uniform(items: Vec<Scalar>) -> Vec<Scalar> {
+    if items.iter().any(Scalar::is_complex) {
+        items.iter().map(|item| Scalar::Complex(item.as_complex())).collect()
+    } else if items.iter().any(Scalar::is_float) {
+        items.iter().map(|item| Scalar::Float(item.as_f64())).collect()
+    } else {
+        items
+    }
+}
+
+/// Pearson correlation coefficients of the columns of `data`; NaN where a
+/// column has no variance.
+pub fn corrcoef(data: &Matrix, mode: Arithmetic) -> Result<Matrix, String> {
+    let cov = cov(data, mode).map_err(|error| error.replacen("cov", "corrcoef", 1))?;
+    let n = cov.rows;
+    let data = (0..n * n).map(|k| {
+        let (i, j) = (k / n, k % n);
+        let scale = (cov.get(i, i).as_f64() * cov.get(j, j).as_f64()).sqrt();
+        match cov.get(i, j) {
+            Scalar::Complex(val) => Scalar::Complex(val / scale),
+            other => Scalar::Float(other.as_f64() / scale),
+        }
+    }).collect();
+    Ok(Matrix::new(n, n, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{argmax, corrcoef, cov, cumsum, mean, median, std, var};
+    use crate::core::dodo::value::{Arithmetic, Matrix, Scalar};
+
+    fn ints(values: &[i128]) -> Vec<Scalar> {
+        values.iter().map(|val| Scalar::Int(*val)).collect()
+    }
+
+    #[test]
+    fn summary_statistics() {
+        let mode = Arithmetic::Checked;
+        let data = ints(&[2, 4, 4, 4, 5, 5, 7, 9]);
+        assert_eq!(mean(&data, mode), Ok(Scalar::Int(5)));
+        assert_eq!(median(&data, mode), Ok(Scalar::Float(4.5)));
+        assert_eq!(var(&data, mode), Ok(Scalar::Float(32.0 / 7.0)));
+        assert_eq!(std(&ints(&[1, 3]), mode), Ok(Scalar::Float(2f64.sqrt())));
+        assert_eq!(var(&ints(&[3]), mode), Ok(Scalar::Int(0)));
+        assert_eq!(cumsum(&ints(&[1, 2, 3]), mode), Ok(ints(&[1, 3, 6])));
+        assert_eq!(argmax(&ints(&[1, 7, 7, 2]), "argmax"), Ok(1));
+        assert_eq!(argmax(&[Scalar::Int(1), Scalar::Float(f64::NAN), Scalar::Int(9)], "argmax"), Ok(1));
+        assert!(mean(&[], mode).is_err());
+    }
+
+    #[test]
+    fn covariance_and_correlation() {
+        let mode = Arithmetic::Checked;
+        // The second column is twice the first, the third goes the other way
+        let data = Matrix::new(3, 3, ints(&[1, 2, 3, 2, 4, 2, 3, 6, 1]));
+        assert_eq!(cov(&data, mode), Ok(Matrix::new(3, 3, ints(&[1, 2, -1, 2, 4, -2, -1, -2, 1]))));
+        let corr = corrcoef(&data, mode).unwrap();
+        assert_eq!(corr.data.iter().map(Scalar::as_f64).collect::<Vec<f64>>(), vec![1.0, 1.0, -1.0, 1.0, 1.0, -1.0, -1.0, -1.0, 1.0]);
+        // Entries that divide exactly become floats when others do not
+        let data = Matrix::new(3, 2, ints(&[1, 2, 3, 4, 5, 7]));
+        assert_eq!(cov(&data, mode), Ok(Matrix::new(2, 2, vec![4.0, 5.0, 5.0, 19.0 / 3.0].into_iter().map(Scalar::Float).collect())));
+        assert_eq!(cov(&Matrix::new(1, 2, ints(&[1, 2])), mode), Err("cov expects at least two observations, got 1".to_string()));
+    }
+}
@@ -0,0 +1,66 @@
+/// The standard library: modules written in dodo and compiled into the binary. They are
+/// imported by name, as in `import "std.optim" as optim`, and go through the same
+/// parser, checker and interpreter as any other file.
+pub const MODULES: &[(&str, &str)] = &[
+    ("std.stats", include_str!("std/stats.dodo")),
+    ("std.geometry", include_str!("std/geometry.dodo")),
+    ("std.optim", include_str!("std/optim.dodo")),
+];
+
+// Prefix of the names that refer to the standard library rather than to files
+pub const PREFIX: &str = "std.";
+
+/// Source of the standard module imported as `name`.
+pub fn source(name: &str) -> Option<&'static str> {
+    MODULES.iter().find(|(module, _)| *module == name).map(|(_, source)| *source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MODULES;
+    use crate::core::ast::parser::Parser;
+    use crate::core::dodo::environment::Environment;
+    use crate::core::dodo::interpreter::Interpreter;
+    use crate::core::dodo::modules::Loader;
+    use crate::core::dodo::value::Value;
+    use crate::core::lexer::lexer::Lexer;
+    use std::io;
+    use std::path::Path;
+
+    #[test]
+    fn standard_modules_load_and_run() {
+        let imports: Vec<String> = MODULES.iter().enumerate().map(|(i, (name, _))| format!("import \"{}\" as m{}\n", name, i)).collect();
+        let source = format!("{}fn grad(x) {{\n    return 2 * (x - 3)\n}}\nlet x = m2.gradient_descent(grad, 0.0, 0.25, 40)\nlet d = m1.norm(m1.rotation(1.5) * {{3, 4}})\nlet r = m0.rms({{3, 4}})\n", imports.concat());
+        let mut program = Parser::new(Lexer::new(source).scan_tokens()).parse_program();
+        let errors = Loader::new(&[]).load_imports(&mut program, Path::new("main.dodo"));
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let mut interpreter = Interpreter::new(Environment::new());
+        interpreter.interpret(&program).unwrap();
+        let value = |name: &str| match interpreter.environment().values.get(name) {
+            Some(Value::Scalar(val)) => val.as_f64(),
+            other => panic!("expected a scalar, got {:?}", other),
+        };
+        assert!((value("x") - 3.0).abs() < 1e-9);
+        assert!((value("d") - 5.0).abs() < 1e-9);
+        assert!((value("r") - 12.5f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn optim_refuses_step_counts_that_never_run_out() {
+        let run = |steps: &str| {
+            let source = format!("from \"std.optim\" import gradient_descent, momentum, newton\nfn g(x) {{\n    return x\n}}\nfn one(x) {{\n    return 1\n}}\nprint gradient_descent(g, 1, 0.5, {0})\nprint momentum(g, 1, 0.5, 0.5, {0})\nprint newton(g, one, 1, {0})\n", steps);
+            let mut program = Parser::new(Lexer::new(source).scan_tokens()).parse_program();
+            assert!(Loader::new(&[]).load_imports(&mut program, Path::new("main.dodo")).is_empty());
+            let mut interpreter = Interpreter::new(Environment::new());
+            interpreter.set_output(Box::new(io::sink()));
+            interpreter.interpret(&program).map_err(|error| error.message)
+        };
+
+        assert_eq!(run("3"), Ok(()));
+        assert!(run("-1").unwrap_err().contains("steps must not be negative"));
+        assert!(run("2.5").unwrap_err().contains("steps must be a whole number"));
+        assert!(run("nan").unwrap_err().contains("steps must be a whole number"));
+        assert!(run("inf").unwrap_err().contains("steps must be finite"));
+    }
+}
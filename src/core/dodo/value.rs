@@ -0,0 +1,771 @@
+use crate::core::ast::stmt::Block;
+use crate::core::dodo::sparse::SparseMatrix;
+use crate::core::token::token::{Token, TokenType};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+// Exact powers whose result would need more bits than this fail instead of exhausting memory
+const MAX_POWER_BITS: u64 = 1 << 24;
+
+// Whether `base ^ power` surely needs more than MAX_POWER_BITS; a base of `bits` bits has a
+// magnitude of at least 2^(bits - 1), so its power has at least (bits - 1) * power of them
+fn power_too_large(bits: u64, power: u64) -> bool {
+    bits.saturating_sub(1).saturating_mul(power) > MAX_POWER_BITS
+}
+
+/// What integer operations do when a result does not fit in 128 bits.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Arithmetic {
+    /// Stop with a runtime error.
+    Checked,
+    Wrapping,
+    Saturating,
+    /// Continue with an arbitrary-precision integer.
+    #[default]
+    Promote,
+}
+
+impl FromStr for Arithmetic {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "checked" => Ok(Arithmetic::Checked),
+            "wrapping" => Ok(Arithmetic::Wrapping),
+            "saturating" => Ok(Arithmetic::Saturating),
+            "promote" => Ok(Arithmetic::Promote),
+            other => Err(format!("Unknown arithmetic mode '{}', expected checked, wrapping, saturating or promote", other)),
+        }
+    }
+}
+
+/// The numeric tower, from `Int` through `Big`, `Rational` and `Float` to
+/// `Complex`. `Big` only holds values outside the `i128` range and `Rational`
+/// only non-integers; results that fit a narrower variant go back to it.
+/// Complex results stay complex even when the imaginary part is zero.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Scalar {
+    Int(i128),
+    Big(BigInt),
+    Rational(BigRational),
+    Float(f64),
+    Complex(Complex64),
+}
+
+// Quotient rounded towards negative infinity; `None` only for `i128::MIN // -1`
+fn floor_div(val1: i128, val2: i128) -> Option<i128> {
+    let quotient = val1.checked_div(val2)?;
+    match val1 % val2 != 0 && (val1 < 0) != (val2 < 0) {
+        true => Some(quotient - 1),
+        false => Some(quotient),
+    }
+}
+
+// Remainder with the sign of the divisor, so that `a == (a // b) * b + a % b`
+fn floor_mod(val1: i128, val2: i128) -> i128 {
+    let remainder = val1.checked_rem(val2).unwrap_or(0);
+    match remainder != 0 && (remainder < 0) != (val2 < 0) {
+        true => remainder + val2,
+        false => remainder,
+    }
+}
+
+impl Scalar {
+    pub fn big(val: BigInt) -> Scalar {
+        match val.to_i128() {
+            Some(val) => Scalar::Int(val),
+            None => Scalar::Big(val),
+        }
+    }
+
+    pub fn rational(val: BigRational) -> Scalar {
+        match val.is_integer() {
+            true => Scalar::big(val.to_integer()),
+            false => Scalar::Rational(val),
+        }
+    }
+
+    /// Real value as a float; the real part of a complex number.
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Scalar::Int(val) => *val as f64,
+            Scalar::Big(val) => val.to_f64().unwrap_or(f64::NAN),
+            Scalar::Rational(val) => val.to_f64().unwrap_or(f64::NAN),
+            Scalar::Float(val) => *val,
+            Scalar::Complex(val) => val.re,
+        }
+    }
+
+    pub fn as_complex(&self) -> Complex64 {
+        match self {
+            Scalar::Complex(val) => *val,
+            other => Complex64::new(other.as_f64(), 0.0),
+        }
+    }
+
+    fn as_big(&self) -> BigInt {
+        match self {
+            Scalar::Int(val) => BigInt::from(*val),
+            Scalar::Big(val) => val.clone(),
+            Scalar::Rational(val) => val.to_integer(),
+            other => BigInt::from_f64(other.as_f64()).unwrap_or_default(),
+        }
+    }
+
+    /// Exact value of an integer or rational; `None` for floats and complex numbers.
+    pub fn as_rational(&self) -> Option<BigRational> {
+        match self {
+            Scalar::Rational(val) => Some(val.clone()),
+            Scalar::Float(_) | Scalar::Complex(_) => None,
+            other => Some(BigRational::from_integer(other.as_big())),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Scalar::Int(val) => *val == 0,
+            Scalar::Big(_) | Scalar::Rational(_) => false,
+            Scalar::Float(val) => *val == 0.0,
+            Scalar::Complex(val) => val.re == 0.0 && val.im == 0.0,
+        }
+    }
+
+    pub fn is_complex(&self) -> bool {
+        matches!(self, Scalar::Complex(_))
+    }
+
+    /// Orders two real numbers, exactly unless one is a float; `None` for NaN
+    /// and complex numbers.
+    pub fn compare(&self, other: &Scalar) -> Option<Ordering> {
+        match (self, other) {
+            (Scalar::Int(val1), Scalar::Int(val2)) => return Some(val1.cmp(val2)),
+            (Scalar::Complex(_), _) | (_, Scalar::Complex(_)) => return None,
+            _ => (),
+        }
+        match (self.as_rational(), other.as_rational()) {
+            (Some(val1), Some(val2)) => Some(val1.cmp(&val2)),
+            _ => self.as_f64().partial_cmp(&other.as_f64()),
+        }
+    }
+
+    /// Absolute value, exact for integers and rationals; the modulus of a complex number.
+    pub fn abs(&self) -> Scalar {
+        match self {
+            Scalar::Int(val) => val.checked_abs().map_or_else(|| Scalar::big(BigInt::from(*val).abs()), Scalar::Int),
+            Scalar::Big(val) => Scalar::Big(val.abs()),
+            Scalar::Rational(val) => Scalar::Rational(val.abs()),
+            Scalar::Float(val) => Scalar::Float(val.abs()),
+            Scalar::Complex(val) => Scalar::Float(val.norm()),
+        }
+    }
+
+    /// Complex conjugate; real numbers are their own conjugate.
+    pub fn conj(&self) -> Scalar {
+        match self {
+            Scalar::Complex(val) => Scalar::Complex(val.conj()),
+            other => other.clone(),
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Scalar::Float(_))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Scalar::Int(_) | Scalar::Big(_))
+    }
+
+    /// Exact negation; only `-i128::MIN` needs a big integer.
+    pub fn negate(&self) -> Scalar {
+        match self {
+            Scalar::Int(val) => val.checked_neg().map_or_else(|| Scalar::big(-BigInt::from(*val)), Scalar::Int),
+            Scalar::Big(val) => Scalar::big(-val),
+            Scalar::Rational(val) => Scalar::Rational(-val),
+            Scalar::Float(val) => Scalar::Float(-val),
+            Scalar::Complex(val) => Scalar::Complex(-val),
+        }
+    }
+
+    /// Negation that overflows according to `mode`.
+    pub fn negate_in(&self, mode: Arithmetic) -> Result<Scalar, String> {
+        match self {
+            Scalar::Int(_) => Scalar::Int(0).arithmetic_in(mode, TokenType::MINUS, self),
+            other => Ok(other.negate()),
+        }
+    }
+
+    /// Arithmetic in checked mode, which fails on integer overflow whatever
+    /// mode the interpreter runs in; see `arithmetic_in`.
+    pub fn arithmetic(&self, operator: TokenType, other: &Scalar) -> Result<Scalar, String> {
+        self.arithmetic_in(Arithmetic::Checked, operator, other)
+    }
+
+    /// 1 when `self operator other` holds and 0 otherwise. Numbers of any kind
+    /// compare by value, NaN compares unequal to everything, and complex
+    /// numbers only compare for equality.
+    pub fn comparison(&self, operator: TokenType, other: &Scalar) -> Result<Scalar, String> {
+        let holds = match (operator, self.is_complex() || other.is_complex()) {
+            (TokenType::EqualEqual, true) => self.as_complex() == other.as_complex(),
+            (TokenType::BangEqual, true) => self.as_complex() != other.as_complex(),
+            (_, true) => return Err(format!("Cannot order complex numbers with '{}'", operator)),
+            (_, false) => match (operator, self.compare(other)) {
+                (TokenType::BangEqual, ordering) => ordering != Some(Ordering::Equal),
+                (_, None) => false,
+                (TokenType::EqualEqual, Some(ordering)) => ordering == Ordering::Equal,
+                (TokenType::LESS, Some(ordering)) => ordering == Ordering::Less,
+                (TokenType::LessEqual, Some(ordering)) => ordering != Ordering::Greater,
+                (TokenType::GREATER, Some(ordering)) => ordering == Ordering::Greater,
+                (TokenType::GreaterEqual, Some(ordering)) => ordering != Ordering::Less,
+                _ => return Err(format!("Unsupported operator '{}'", operator)),
+            },
+        };
+        Ok(Scalar::Int(holds as i128))
+    }
+
+    /// `/` on integers is exact when the division is, and a float otherwise;
+    /// rationals stay exact. `//` and `%` round the quotient down and give the
+    /// remainder the divisor's sign.
+    pub fn arithmetic_in(&self, mode: Arithmetic, operator: TokenType, other: &Scalar) -> Result<Scalar, String> {
+        if matches!(operator, TokenType::CARET | TokenType::DotCaret) {
+            return self.power_in(mode, other);
+        }
+        if is_comparison(operator) {
+            return self.comparison(operator, other);
+        }
+        match (self, other) {
+            (Scalar::Int(val1), Scalar::Int(val2)) => {
+                let (val1, val2) = (*val1, *val2);
+                if val2 == 0 && matches!(operator, TokenType::SLASH | TokenType::SlashSlash | TokenType::PERCENT) {
+                    return Err(match operator {
+                        TokenType::PERCENT => "Modulo by zero".to_string(),
+                        _ => "Division by zero".to_string(),
+                    });
+                }
+                let (checked, wrapping, saturating) = match operator {
+                    TokenType::PLUS => (val1.checked_add(val2), val1.wrapping_add(val2), val1.saturating_add(val2)),
+                    TokenType::MINUS => (val1.checked_sub(val2), val1.wrapping_sub(val2), val1.saturating_sub(val2)),
+                    TokenType::ASTERISK => (val1.checked_mul(val2), val1.wrapping_mul(val2), val1.saturating_mul(val2)),
+                    TokenType::SLASH if floor_mod(val1, val2) != 0 => return Ok(Scalar::Float(val1 as f64 / val2 as f64)),
+                    // Only i128::MIN / -1 overflows, and it divides exactly
+                    TokenType::SLASH | TokenType::SlashSlash => (floor_div(val1, val2), val1.wrapping_div(val2), val1.saturating_div(val2)),
+                    TokenType::PERCENT => return Ok(Scalar::Int(floor_mod(val1, val2))),
+                    _ => return Err(format!("Unsupported operator '{}'", operator)),
+                };
+                match (checked, mode) {
+                    (Some(val), _) => Ok(Scalar::Int(val)),
+                    (None, Arithmetic::Checked) => Err(format!("Integer overflow in {} {} {}", val1, operator, val2)),
+                    (None, Arithmetic::Wrapping) => Ok(Scalar::Int(wrapping)),
+                    (None, Arithmetic::Saturating) => Ok(Scalar::Int(saturating)),
+                    (None, Arithmetic::Promote) => Scalar::Big(BigInt::from(val1)).arithmetic_in(mode, operator, other),
+                }
+            }
+            (Scalar::Big(_), Scalar::Int(_)) | (Scalar::Int(_), Scalar::Big(_)) | (Scalar::Big(_), Scalar::Big(_)) => {
+                let (val1, val2) = (self.as_big(), other.as_big());
+                if val2.is_zero() && matches!(operator, TokenType::SLASH | TokenType::SlashSlash | TokenType::PERCENT) {
+                    return Err(match operator {
+                        TokenType::PERCENT => "Modulo by zero".to_string(),
+                        _ => "Division by zero".to_string(),
+                    });
+                }
+                match operator {
+                    TokenType::PLUS => Ok(Scalar::big(val1 + val2)),
+                    TokenType::MINUS => Ok(Scalar::big(val1 - val2)),
+                    TokenType::ASTERISK => Ok(Scalar::big(val1 * val2)),
+                    TokenType::SLASH if !val1.mod_floor(&val2).is_zero() => Ok(Scalar::Float(self.as_f64() / other.as_f64())),
+                    TokenType::SLASH | TokenType::SlashSlash => Ok(Scalar::big(val1.div_floor(&val2))),
+                    TokenType::PERCENT => Ok(Scalar::big(val1.mod_floor(&val2))),
+                    _ => Err(format!("Unsupported operator '{}'", operator)),
+                }
+            }
+            (Scalar::Complex(_), _) | (_, Scalar::Complex(_)) => {
+                let (val1, val2) = (self.as_complex(), other.as_complex());
+                match operator {
+                    TokenType::PLUS => Ok(Scalar::Complex(val1 + val2)),
+                    TokenType::MINUS => Ok(Scalar::Complex(val1 - val2)),
+                    TokenType::ASTERISK => Ok(Scalar::Complex(val1 * val2)),
+                    TokenType::SLASH => Ok(Scalar::Complex(val1 / val2)),
+                    _ => Err(format!("Unsupported operator '{}' for complex numbers", operator)),
+                }
+            }
+            (Scalar::Rational(_), _) | (_, Scalar::Rational(_)) if !self.is_float() && !other.is_float() => {
+                let (val1, val2) = (self.as_rational().unwrap_or_default(), other.as_rational().unwrap_or_default());
+                if val2.is_zero() && matches!(operator, TokenType::SLASH | TokenType::SlashSlash | TokenType::PERCENT) {
+                    return Err(match operator {
+                        TokenType::PERCENT => "Modulo by zero".to_string(),
+                        _ => "Division by zero".to_string(),
+                    });
+                }
+                match operator {
+                    TokenType::PLUS => Ok(Scalar::rational(val1 + val2)),
+                    TokenType::MINUS => Ok(Scalar::rational(val1 - val2)),
+                    TokenType::ASTERISK => Ok(Scalar::rational(val1 * val2)),
+                    TokenType::SLASH => Ok(Scalar::rational(val1 / val2)),
+                    TokenType::SlashSlash => Ok(Scalar::rational((val1 / val2).floor())),
+                    TokenType::PERCENT => {
+                        let quotient = (&val1 / &val2).floor();
+                        Ok(Scalar::rational(val1 - val2 * quotient))
+                    }
+                    _ => Err(format!("Unsupported operator '{}'", operator)),
+                }
+            }
+            _ => {
+                let val1 = self.as_f64();
+                let val2 = other.as_f64();
+                match operator {
+                    TokenType::PLUS => Ok(Scalar::Float(val1 + val2)),
+                    TokenType::MINUS => Ok(Scalar::Float(val1 - val2)),
+                    TokenType::ASTERISK => Ok(Scalar::Float(val1 * val2)),
+                    TokenType::SLASH => Ok(Scalar::Float(val1 / val2)),
+                    TokenType::SlashSlash => Ok(Scalar::Float((val1 / val2).floor())),
+                    TokenType::PERCENT => {
+                        let remainder = val1 % val2;
+                        match remainder != 0.0 && (remainder < 0.0) != (val2 < 0.0) {
+                            true => Ok(Scalar::Float(remainder + val2)),
+                            false => Ok(Scalar::Float(remainder)),
+                        }
+                    }
+                    _ => Err(format!("Unsupported operator '{}'", operator)),
+                }
+            }
+        }
+    }
+}
+
+impl Scalar {
+    /// Integer powers of integers and rationals are exact. Like `/`, a negative
+    /// power of an integer is a float unless the result is whole.
+    pub fn power_in(&self, mode: Arithmetic, exponent: &Scalar) -> Result<Scalar, String> {
+        let too_large = || Err(format!("Exponent {} is too large", exponent));
+        match (self, exponent) {
+            (Scalar::Complex(base), Scalar::Int(power)) if i32::try_from(*power).is_ok() => Ok(Scalar::Complex(base.powi(*power as i32))),
+            (Scalar::Complex(_), _) | (_, Scalar::Complex(_)) => Ok(Scalar::Complex(self.as_complex().powc(exponent.as_complex()))),
+            (Scalar::Int(base), Scalar::Int(power)) if *power >= 0 => {
+                let (base, power) = (*base, *power);
+                match (checked_pow(base, power), mode) {
+                    (Some(val), _) => Ok(Scalar::Int(val)),
+                    (None, Arithmetic::Checked) => Err(format!("Integer overflow in {} ^ {}", base, power)),
+                    (None, Arithmetic::Wrapping) => Ok(Scalar::Int(wrapping_pow(base, power))),
+                    // Only a negative base to an odd power overflows downwards
+                    (None, Arithmetic::Saturating) => match base < 0 && power % 2 == 1 {
+                        true => Ok(Scalar::Int(i128::MIN)),
+                        false => Ok(Scalar::Int(i128::MAX)),
+                    },
+                    (None, Arithmetic::Promote) => Scalar::Big(BigInt::from(base)).power_in(mode, exponent),
+                }
+            }
+            (Scalar::Int(0), Scalar::Int(_)) => Err("Division by zero".to_string()),
+            (Scalar::Int(base), Scalar::Int(power)) => match base {
+                1 => Ok(Scalar::Int(1)),
+                -1 => Ok(Scalar::Int(if power % 2 == 0 { 1 } else { -1 })),
+                _ => Ok(Scalar::Float((*base as f64).powf(*power as f64))),
+            },
+            (Scalar::Big(base), Scalar::Int(power)) if *power >= 0 => match u32::try_from(*power) {
+                Ok(power) if !power_too_large(base.bits(), power as u64) => Ok(Scalar::big(base.pow(power))),
+                _ => too_large(),
+            },
+            (Scalar::Big(_), Scalar::Int(_)) => Ok(Scalar::Float(self.as_f64().powf(exponent.as_f64()))),
+            (Scalar::Rational(base), Scalar::Int(power)) => match i32::try_from(*power) {
+                Ok(power) if !power_too_large(base.numer().bits().max(base.denom().bits()), power.unsigned_abs() as u64) => {
+                    Ok(Scalar::rational(base.pow(power)))
+                }
+                _ => too_large(),
+            },
+            // Of the exact bases, only 0, 1 and -1 have powers this large that fit in memory
+            (_, Scalar::Big(power)) if !self.is_float() => match self {
+                Scalar::Int(0) if power.is_negative() => Err("Division by zero".to_string()),
+                Scalar::Int(0) | Scalar::Int(1) => Ok(self.clone()),
+                Scalar::Int(-1) => Ok(Scalar::Int(if power.is_odd() { -1 } else { 1 })),
+                _ => too_large(),
+            },
+            _ => Ok(Scalar::Float(self.as_f64().powf(exponent.as_f64()))),
+        }
+    }
+}
+
+// Exponentiation by squaring; the base is only squared while more bits of the power remain
+fn checked_pow(mut base: i128, mut power: i128) -> Option<i128> {
+    let mut result: i128 = 1;
+    loop {
+        if power & 1 == 1 {
+            result = result.checked_mul(base)?;
+        }
+        power >>= 1;
+        if power == 0 {
+            return Some(result);
+        }
+        base = base.checked_mul(base)?;
+    }
+}
+
+fn wrapping_pow(mut base: i128, mut power: i128) -> i128 {
+    let mut result: i128 = 1;
+    while power > 0 {
+        if power & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        power >>= 1;
+        base = base.wrapping_mul(base);
+    }
+    result
+}
+
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scalar::Int(val) => write!(f, "{}", val),
+            Scalar::Big(val) => write!(f, "{}", val),
+            Scalar::Rational(val) => write!(f, "{}", val),
+            // Adding zero turns a negative zero real part into a plain 0
+            Scalar::Complex(val) => match val.im.is_sign_negative() {
+                true => write!(f, "{}-{}i", val.re + 0.0, -val.im),
+                false => write!(f, "{}+{}i", val.re + 0.0, val.im),
+            },
+            Scalar::Float(val) => write!(f, "{:?}", val),
+        }
+    }
+}
+
+/// Dense matrix stored in row-major order.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Matrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<Scalar>,
+}
+
+impl Matrix {
+    pub fn new(rows: usize, cols: usize, data: Vec<Scalar>) -> Self {
+        Matrix {
+            rows,
+            cols,
+            data,
+        }
+    }
+
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Matrix::new(rows, cols, vec![Scalar::Int(0); rows * cols])
+    }
+
+    /// Number of elements in a `rows` x `cols` matrix, or an error when it overflows.
+    pub fn size(rows: usize, cols: usize) -> Result<usize, String> {
+        rows.checked_mul(cols).ok_or_else(|| format!("Cannot allocate a {}x{} matrix", rows, cols))
+    }
+
+    /// Empty storage with room for a `rows` x `cols` matrix, failing instead of
+    /// aborting when there is not enough memory for it.
+    pub fn storage(rows: usize, cols: usize) -> Result<Vec<Scalar>, String> {
+        let mut data = Vec::new();
+        data.try_reserve_exact(Matrix::size(rows, cols)?).map_err(|_| format!("Cannot allocate a {}x{} matrix", rows, cols))?;
+        Ok(data)
+    }
+
+    /// A `rows` x `cols` matrix with every element set to `val`, failing like `storage`.
+    pub fn filled(rows: usize, cols: usize, val: Scalar) -> Result<Self, String> {
+        let mut data = Matrix::storage(rows, cols)?;
+        data.resize(rows * cols, val);
+        Ok(Matrix::new(rows, cols, data))
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> &Scalar {
+        &self.data[row * self.cols + col]
+    }
+
+    pub fn row(&self, row: usize) -> &[Scalar] {
+        &self.data[row * self.cols..(row + 1) * self.cols]
+    }
+}
+
+// User-defined function; the body runs in a fresh frame on each call
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Block,
+    // Module whose globals the body sees, as numbered by the environment
+    pub module: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Scalar(Scalar),
+    Vector(Vec<Scalar>),
+    Matrix(Matrix),
+    Sparse(SparseMatrix),
+    Str(String),
+    Function(Rc<Function>),
+    Nil,
+}
+
+impl Value {
+    pub fn int(val: i128) -> Self {
+        Value::Scalar(Scalar::Int(val))
+    }
+
+    pub fn float(val: f64) -> Self {
+        Value::Scalar(Scalar::Float(val))
+    }
+
+    /// Scalars stored by a vector or matrix value; 0 for everything else.
+    pub fn elements(&self) -> usize {
+        match self {
+            Value::Vector(items) => items.len(),
+            Value::Matrix(matrix) => matrix.data.len(),
+            Value::Sparse(sparse) => sparse.nnz(),
+            _ => 0,
+        }
+    }
+
+    /// Like `type_name`, except that dense and sparse matrices are both "matrix".
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Sparse(_) => "matrix",
+            other => other.type_name(),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Scalar(_) => "scalar",
+            Value::Vector(_) => "vector",
+            Value::Matrix(_) => "matrix",
+            Value::Sparse(_) => "sparse",
+            Value::Str(_) => "string",
+            Value::Function(_) => "function",
+            Value::Nil => "nil",
+        }
+    }
+}
+
+/// Whether `operator` is one of `<`, `<=`, `>`, `>=`, `==` and `!=`, which
+/// give 1 or 0 rather than a number.
+pub fn is_comparison(operator: TokenType) -> bool {
+    matches!(operator, TokenType::LESS | TokenType::LessEqual | TokenType::GREATER | TokenType::GreaterEqual | TokenType::EqualEqual | TokenType::BangEqual)
+}
+
+// Rows, columns and elements of a value that takes part in broadcasting
+fn grid(value: &Value) -> Option<(usize, usize, &[Scalar])> {
+    match value {
+        Value::Scalar(val) => Some((1, 1, std::slice::from_ref(val))),
+        Value::Vector(items) => Some((1, items.len(), items)),
+        Value::Matrix(matrix) => Some((matrix.rows, matrix.cols, &matrix.data)),
+        _ => None,
+    }
+}
+
+/// Rows and columns of the result of `broadcast`, so that callers can check
+/// the memory limit before the result is built.
+pub fn broadcast_shape(left: &Value, right: &Value) -> Result<(usize, usize), String> {
+    let ((rows1, cols1, _), (rows2, cols2, _)) = match (grid(left), grid(right)) {
+        (Some(left), Some(right)) => (left, right),
+        _ => return Err(format!("Cannot broadcast {} and {}", left.type_name(), right.type_name())),
+    };
+    let stretch = |a: usize, b: usize| match (a, b) {
+        (1, other) | (other, 1) => Some(other),
+        (a, b) if a == b => Some(a),
+        _ => None,
+    };
+    match (stretch(rows1, rows2), stretch(cols1, cols2)) {
+        (Some(rows), Some(cols)) => Ok((rows, cols)),
+        _ => Err(match (left, right) {
+            (Value::Vector(_), Value::Vector(_)) => format!("Length mismatch: {} and {} elements", cols1, cols2),
+            _ => format!("Shape mismatch: {} and {}", shape(left), shape(right)),
+        }),
+    }
+}
+
+/// Combines two scalars, vectors or dense matrices element by element with
+/// NumPy-style broadcasting: a vector lines up with the columns of a matrix,
+/// and a dimension of length 1 stretches to the length of the other one.
+/// The result is a matrix if either operand is, a vector if either is, and
+/// a scalar otherwise.
+pub fn broadcast<F>(left: &Value, right: &Value, mut f: F) -> Result<Value, String> where F: FnMut(&Scalar, &Scalar) -> Result<Scalar, String> {
+    let (rows, cols) = broadcast_shape(left, right)?;
+    let ((rows1, cols1, data1), (rows2, cols2, data2)) = match (grid(left), grid(right)) {
+        (Some(left), Some(right)) => (left, right),
+        _ => unreachable!("broadcast_shape accepted both operands"),
+    };
+    let mut data = Matrix::storage(rows, cols)?;
+    for row in 0..rows {
+        for col in 0..cols {
+            let index = |rows: usize, cols: usize| (row % rows) * cols + col % cols;
+            data.push(f(&data1[index(rows1, cols1)], &data2[index(rows2, cols2)])?);
+        }
+    }
+    match (left, right) {
+        (Value::Matrix(_), _) | (_, Value::Matrix(_)) => Ok(Value::Matrix(Matrix::new(rows, cols, data))),
+        (Value::Vector(_), _) | (_, Value::Vector(_)) => Ok(Value::Vector(data)),
+        _ => Ok(Value::Scalar(data.remove(0))),
+    }
+}
+
+// Shape as used in mismatch messages: "3" for a vector, "2x3" for a matrix
+fn shape(value: &Value) -> String {
+    match value {
+        Value::Matrix(matrix) => format!("{}x{}", matrix.rows, matrix.cols),
+        other => other.elements().to_string(),
+    }
+}
+
+fn write_row(f: &mut fmt::Formatter<'_>, row: &[Scalar]) -> fmt::Result {
+    write!(f, "{{")?;
+    for (i, item) in row.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    write!(f, "}}")
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Scalar(val) => write!(f, "{}", val),
+            Value::Vector(val) => write_row(f, val),
+            Value::Matrix(matrix) => {
+                write!(f, "{{")?;
+                for row in 0..matrix.rows {
+                    if row > 0 {
+                        write!(f, ",\n ")?;
+                    }
+                    write_row(f, matrix.row(row))?;
+                }
+                write!(f, "}}")
+            }
+            Value::Sparse(sparse) => write!(f, "{}", sparse),
+            Value::Str(val) => write!(f, "{}", val),
+            Value::Function(function) => write!(f, "<fn {}>", function.name.val),
+            Value::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arithmetic, Matrix, Scalar, Value};
+    use crate::core::token::token::TokenType;
+    use num_bigint::BigInt;
+    use num_complex::Complex64;
+    use num_rational::BigRational;
+
+    #[test]
+    fn scalar_promotion() {
+        let int = Scalar::Int(7);
+        let float = Scalar::Float(0.5);
+
+        assert_eq!(int.arithmetic(TokenType::SLASH, &Scalar::Int(2)), Ok(Scalar::Float(3.5)));
+        assert_eq!(Scalar::Int(8).arithmetic(TokenType::SLASH, &Scalar::Int(2)), Ok(Scalar::Int(4)));
+        assert_eq!(int.arithmetic(TokenType::PLUS, &float), Ok(Scalar::Float(7.5)));
+        assert!(int.arithmetic(TokenType::SLASH, &Scalar::Int(0)).is_err());
+    }
+
+    #[test]
+    fn floor_division_and_modulo() {
+        let cases = [(7, 2, 3, 1), (-7, 2, -4, 1), (7, -2, -4, -1), (-7, -2, 3, -1), (6, 3, 2, 0)];
+        for (a, b, quotient, remainder) in cases.iter() {
+            let (a, b) = (Scalar::Int(*a), Scalar::Int(*b));
+            assert_eq!(a.arithmetic(TokenType::SlashSlash, &b), Ok(Scalar::Int(*quotient)));
+            assert_eq!(a.arithmetic(TokenType::PERCENT, &b), Ok(Scalar::Int(*remainder)));
+        }
+        assert_eq!(Scalar::Float(-7.5).arithmetic(TokenType::SlashSlash, &Scalar::Int(2)), Ok(Scalar::Float(-4.0)));
+        assert_eq!(Scalar::Float(-7.5).arithmetic(TokenType::PERCENT, &Scalar::Int(2)), Ok(Scalar::Float(0.5)));
+        assert_eq!(Scalar::Int(1).arithmetic(TokenType::PERCENT, &Scalar::Int(0)), Err("Modulo by zero".to_string()));
+        assert_eq!(Scalar::Int(i128::MIN).arithmetic(TokenType::PERCENT, &Scalar::Int(-1)), Ok(Scalar::Int(0)));
+    }
+
+    #[test]
+    fn overflow_modes() {
+        let (max, one) = (Scalar::Int(i128::MAX), Scalar::Int(1));
+        let add = |mode| max.arithmetic_in(mode, TokenType::PLUS, &one);
+        assert!(add(Arithmetic::Checked).unwrap_err().starts_with("Integer overflow"));
+        assert_eq!(add(Arithmetic::Wrapping), Ok(Scalar::Int(i128::MIN)));
+        assert_eq!(add(Arithmetic::Saturating), Ok(Scalar::Int(i128::MAX)));
+        let big = add(Arithmetic::Promote).unwrap();
+        assert_eq!(big, Scalar::Big(BigInt::from(i128::MAX) + 1));
+        // Results that fit go back to machine integers
+        assert_eq!(big.arithmetic(TokenType::MINUS, &one), Ok(max.clone()));
+        assert_eq!(Scalar::Int(i128::MIN).negate_in(Arithmetic::Saturating), Ok(max));
+        assert_eq!("promote".parse(), Ok(Arithmetic::Promote));
+    }
+
+    #[test]
+    fn powers() {
+        let power = |base: Scalar, exponent: i128, mode| base.power_in(mode, &Scalar::Int(exponent));
+        assert_eq!(power(Scalar::Int(-3), 3, Arithmetic::Checked), Ok(Scalar::Int(-27)));
+        assert_eq!(power(Scalar::Int(-2), 127, Arithmetic::Checked), Ok(Scalar::Int(i128::MIN)));
+        assert_eq!(power(Scalar::Int(2), -2, Arithmetic::Checked), Ok(Scalar::Float(0.25)));
+        assert_eq!(power(Scalar::Int(-1), -3, Arithmetic::Checked), Ok(Scalar::Int(-1)));
+        assert!(power(Scalar::Int(0), -1, Arithmetic::Checked).is_err());
+
+        assert!(power(Scalar::Int(2), 128, Arithmetic::Checked).unwrap_err().starts_with("Integer overflow"));
+        assert_eq!(power(Scalar::Int(2), 128, Arithmetic::Wrapping), Ok(Scalar::Int(0)));
+        assert_eq!(power(Scalar::Int(-3), 81, Arithmetic::Saturating), Ok(Scalar::Int(i128::MIN)));
+        assert_eq!(power(Scalar::Int(2), 128, Arithmetic::Promote), Ok(Scalar::Big(BigInt::from(1) << 128)));
+        // Results too large to hold fail rather than exhausting memory
+        let big = Scalar::Big(BigInt::from(1) << 127);
+        assert_eq!(power(big.clone(), 1 << 20, Arithmetic::Checked), Err("Exponent 1048576 is too large".to_string()));
+        assert_eq!(power(Scalar::Big(-BigInt::from(1)), 1 << 30, Arithmetic::Checked), Ok(Scalar::Int(1)));
+
+        let two_thirds = Scalar::rational(BigRational::new(BigInt::from(2), BigInt::from(3)));
+        assert_eq!(power(two_thirds, -2, Arithmetic::Checked).unwrap().to_string(), "9/4");
+        assert_eq!(Scalar::Int(4).arithmetic(TokenType::DotCaret, &Scalar::Float(0.5)), Ok(Scalar::Float(2.0)));
+        let i = Scalar::Complex(Complex64::new(0.0, 1.0));
+        assert_eq!(i.power_in(Arithmetic::Checked, &Scalar::Int(2)), Ok(Scalar::Complex(Complex64::new(-1.0, 0.0))));
+    }
+
+    #[test]
+    fn broadcasting() {
+        let add = |left: &Value, right: &Value| super::broadcast(left, right, |val1, val2| val1.arithmetic(TokenType::PLUS, val2));
+        let ints = |values: &[i128]| values.iter().map(|val| Scalar::Int(*val)).collect::<Vec<Scalar>>();
+        let column = Value::Matrix(Matrix::new(2, 1, ints(&[10, 20])));
+        let row = Value::Vector(ints(&[1, 2, 3]));
+
+        assert_eq!(add(&column, &row), Ok(Value::Matrix(Matrix::new(2, 3, ints(&[11, 12, 13, 21, 22, 23])))));
+        assert_eq!(add(&row, &Value::Vector(ints(&[1]))), Ok(Value::Vector(ints(&[2, 3, 4]))));
+        assert_eq!(add(&Value::int(1), &Value::int(2)), Ok(Value::int(3)));
+        assert_eq!(add(&row, &Value::Vector(ints(&[1, 2]))), Err("Length mismatch: 3 and 2 elements".to_string()));
+        let square = Value::Matrix(Matrix::new(2, 2, ints(&[1, 2, 3, 4])));
+        assert_eq!(add(&square, &row), Err("Shape mismatch: 2x2 and 3".to_string()));
+    }
+
+    #[test]
+    fn rationals_stay_exact() {
+        let third = Scalar::rational(BigRational::new(BigInt::from(1), BigInt::from(3)));
+        let sum = third.arithmetic(TokenType::PLUS, &Scalar::rational(BigRational::new(BigInt::from(1), BigInt::from(6)))).unwrap();
+        assert_eq!(sum.to_string(), "1/2");
+        // Whole results go back to integers, floats make the result inexact
+        assert_eq!(third.arithmetic(TokenType::ASTERISK, &Scalar::Int(3)), Ok(Scalar::Int(1)));
+        assert!(third.arithmetic(TokenType::PLUS, &Scalar::Float(0.5)).unwrap().is_float());
+        assert_eq!(Scalar::Int(7).arithmetic(TokenType::PERCENT, &third), Ok(Scalar::Int(0)));
+        assert!(third.arithmetic(TokenType::SLASH, &Scalar::Int(0)).is_err());
+    }
+
+    #[test]
+    fn complex_numbers() {
+        let z = Scalar::Complex(Complex64::new(1.0, 2.0));
+        let product = z.arithmetic(TokenType::ASTERISK, &z.conj()).unwrap();
+        assert_eq!(product, Scalar::Complex(Complex64::new(5.0, 0.0)));
+        assert_eq!(Scalar::Int(2).arithmetic(TokenType::MINUS, &z), Ok(Scalar::Complex(Complex64::new(1.0, -2.0))));
+        assert_eq!(Scalar::Complex(Complex64::new(3.0, 4.0)).abs(), Scalar::Float(5.0));
+        assert!(z.arithmetic(TokenType::PERCENT, &Scalar::Int(2)).is_err());
+        assert_eq!(z.to_string(), "1+2i");
+        assert_eq!(z.conj().negate().to_string(), "-1+2i");
+        assert_eq!(Scalar::Complex(Complex64::new(0.0, 2.0)).negate().to_string(), "0-2i");
+    }
+
+    #[test]
+    fn display() {
+        let matrix = Matrix::new(2, 2, vec![Scalar::Int(1), Scalar::Int(2), Scalar::Float(3.0), Scalar::Int(4)]);
+
+        assert_eq!(Value::Vector(vec![Scalar::Int(1), Scalar::Float(2.5)]).to_string(), "{1, 2.5}");
+        assert_eq!(Value::Matrix(matrix).to_string(), "{{1, 2},\n {3.0, 4}}");
+    }
+}
@@ -0,0 +1,391 @@
+use crate::core::ast::ast::Span;
+use crate::core::ast::expr::Expr;
+use crate::core::ast::parser::Parser;
+use crate::core::ast::stmt::{Block, DeclaredType, Stmt};
+use crate::core::dodo::error_types::DodoParseError;
+use crate::core::lexer::lexer::Lexer;
+use crate::core::token::token::{Token, TokenType};
+use std::fs;
+use std::io::{self, Read};
+use std::iter::Peekable;
+use std::path::Path;
+use std::vec::IntoIter;
+
+const INDENT: &str = "    ";
+
+// Column reached after writing `text` starting at `column`
+fn next_column(column: usize, text: &str) -> usize {
+    match text.rfind('\n') {
+        Some(index) => text[index + 1..].chars().count(),
+        None => column + text.chars().count(),
+    }
+}
+
+fn quote(val: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in val.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn labelled(label: &Option<Token>) -> String {
+    match label {
+        Some(label) => format!("{}: ", label.val),
+        None => String::new(),
+    }
+}
+
+// Comment statements at any depth; every other comment sits inside an expression
+fn comment_lines(statements: &[Stmt], spans: &[Span], lines: &mut Vec<usize>) {
+    for (stmt, span) in statements.iter().zip(spans.iter()) {
+        match stmt {
+            Stmt::Comment(_) => lines.push(span.line),
+            stmt => {
+                if let Some(body) = stmt.body() {
+                    comment_lines(&body.statements, &body.spans, lines);
+                }
+            }
+        }
+    }
+}
+
+struct Formatter {
+    out: String,
+    indent: usize,
+    // Comments the parser skipped inside multi-line expressions, by line
+    inner_comments: Peekable<IntoIter<(usize, String)>>,
+    last_line: usize,
+}
+
+impl Formatter {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn statements(&mut self, statements: &[Stmt], spans: &[Span]) {
+        for (index, (stmt, span)) in statements.iter().zip(spans.iter()).enumerate() {
+            if let Stmt::Comment(text) = stmt {
+                let previous = index.checked_sub(1).map(|previous| (&statements[previous], spans[previous]));
+                if let Some((code, code_span)) = previous {
+                    if !matches!(code, Stmt::Comment(_)) && code_span.end_line == span.line {
+                        self.out.pop();
+                        self.out.push(' ');
+                        self.out.push_str(text);
+                        self.out.push('\n');
+                        continue;
+                    }
+                }
+            }
+            // Inner comments of a function or loop body belong to the statements inside it
+            let head_line = match stmt.body() {
+                Some(_) => span.line,
+                None => span.end_line,
+            };
+            let mut first_line = span.line;
+            while let Some((line, text)) = self.inner_comments.next_if(|(line, _)| *line <= head_line) {
+                if self.last_line > 0 && line > self.last_line + 1 {
+                    self.out.push('\n');
+                }
+                self.line(&text);
+                self.last_line = line;
+                first_line = first_line.max(line);
+            }
+            if self.last_line > 0 && first_line > self.last_line + 1 {
+                self.out.push('\n');
+            }
+            self.last_line = span.line;
+            self.statement(stmt);
+            self.last_line = span.end_line;
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        let column = self.indent * INDENT.len();
+        let text = match stmt {
+            Stmt::Definition(name, declared, dims, initial) => {
+                let head = self.declaration(name, *declared, dims);
+                match initial {
+                    Some(value) => {
+                        let prefix = format!("{} = ", head);
+                        format!("{}{}", prefix, self.expr(value, next_column(column, &prefix)))
+                    }
+                    None => head,
+                }
+            }
+            Stmt::Expression(expr) => self.expr(expr, column),
+            Stmt::Print(expr) => format!("print {}", self.expr(expr, column + 6)),
+            Stmt::Comment(text) => text.clone(),
+            Stmt::Return(_, Some(expr)) => format!("return {}", self.expr(expr, column + 7)),
+            Stmt::Return(_, None) => "return".to_string(),
+            Stmt::Function(name, params, body) => {
+                let params: Vec<&str> = params.iter().map(|param| param.val.as_str()).collect();
+                self.block(&format!("fn {}({}) {{", name.val, params.join(", ")), body)
+            }
+            Stmt::While(label, condition, body) => {
+                let head = format!("{}while ", labelled(label));
+                let condition = self.expr(condition, column + head.chars().count());
+                self.block(&format!("{}{} {{", head, condition), body)
+            }
+            Stmt::Loop(label, body) => self.block(&format!("{}loop {{", labelled(label)), body),
+            Stmt::Test(name, body) => self.block(&format!("test {} {{", quote(&name.val)), body),
+            Stmt::Import(file, namespace) => format!("import {} as {}", quote(&file.val), namespace.val),
+            Stmt::FromImport(file, names) => {
+                let names: Vec<&str> = names.iter().map(|name| name.val.as_str()).collect();
+                format!("from {} import {}", quote(&file.val), names.join(", "))
+            }
+            Stmt::Break(keyword, label) | Stmt::Continue(keyword, label) => match label {
+                Some(label) => format!("{} {}", keyword.val, label.val),
+                None => keyword.val.clone(),
+            },
+            other => format!("{}", other),
+        };
+        self.line(&text);
+    }
+
+    // Writes the opening line and the indented body, returning the closing brace
+    fn block(&mut self, head: &str, body: &Block) -> String {
+        self.line(head);
+        self.indent += 1;
+        self.statements(&body.statements, &body.spans);
+        self.indent -= 1;
+        "}".to_string()
+    }
+
+    fn declaration(&self, name: &Token, declared: DeclaredType, dims: &[Expr]) -> String {
+        let dims: Vec<String> = dims.iter().map(|dim| self.expr(dim, 0)).collect();
+        match declared {
+            DeclaredType::Vector | DeclaredType::Matrix => format!("{} {}[{}]", declared.keyword(), name.val, dims.join(", ")),
+            _ => format!("{} {}", declared.keyword(), name.val),
+        }
+    }
+
+    fn list(&self, exprs: &[Expr], column: usize) -> String {
+        let mut text = String::new();
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                text.push_str(", ");
+            }
+            let at = next_column(column, &text);
+            text.push_str(&self.expr(expr, at));
+        }
+        text
+    }
+
+    // Rows go on separate lines with columns right-aligned under each other
+    fn matrix(&self, rows: &[Vec<Expr>], column: usize) -> String {
+        let cells: Vec<Vec<String>> = rows.iter()
+            .map(|row| row.iter().map(|expr| self.expr(expr, 0)).collect())
+            .collect();
+        let mut widths = vec![0; cells.first().map_or(0, Vec::len)];
+        for row in cells.iter() {
+            for (col, cell) in row.iter().enumerate() {
+                widths[col] = widths[col].max(cell.chars().count());
+            }
+        }
+        let rows: Vec<String> = cells.iter()
+            .map(|row| {
+                let padded: Vec<String> = row.iter().enumerate()
+                    .map(|(col, cell)| format!("{:>width$}", cell, width = widths[col]))
+                    .collect();
+                format!("{{{}}}", padded.join(", "))
+            })
+            .collect();
+        format!("{{{}}}", rows.join(&format!(",\n{}", " ".repeat(column + 1))))
+    }
+
+    fn expr(&self, expr: &Expr, column: usize) -> String {
+        match expr {
+            Expr::Literal(val) => val.to_string(),
+            Expr::Float(val) => format!("{:?}", val),
+            Expr::Big(val) => val.clone(),
+            Expr::Imaginary(val) => format!("{}i", val),
+            Expr::Str(val) => quote(val),
+            Expr::Variable(token, _) => token.val.clone(),
+            Expr::Get(namespace, member) => format!("{}.{}", namespace.val, member.val),
+            Expr::Grouping(inner) => format!("({})", self.expr(inner, column + 1)),
+            Expr::Unary(operator, right) => format!("{}{}", operator.val, self.expr(right, column + 1)),
+            Expr::Binary(left, operator, right) => {
+                let left = self.expr(left, column);
+                let prefix = format!("{} {} ", left, operator.val);
+                let right = self.expr(right, next_column(column, &prefix));
+                format!("{}{}", prefix, right)
+            }
+            Expr::Assign(token, value, _) => {
+                let prefix = format!("{} = ", token.val);
+                format!("{}{}", prefix, self.expr(value, column + prefix.chars().count()))
+            }
+            Expr::FunctionCall(callee, args) => {
+                let prefix = format!("{}(", self.expr(callee, column));
+                let args = self.list(args, next_column(column, &prefix));
+                format!("{}{})", prefix, args)
+            }
+            Expr::Vector(_, elements) => format!("{{{}}}", self.list(elements, column + 1)),
+            Expr::Matrix(_, rows) => self.matrix(rows, column),
+            other => format!("{}", other),
+        }
+    }
+}
+
+/// A declaration without its initial value, as the formatter writes it, e.g. "matrix A[n, 2]".
+pub fn declaration(name: &Token, declared: DeclaredType, dims: &[Expr]) -> String {
+    let formatter = Formatter {
+        out: String::new(),
+        indent: 0,
+        inner_comments: Vec::new().into_iter().peekable(),
+        last_line: 0,
+    };
+    formatter.declaration(name, declared, dims)
+}
+
+/// Formats dodo source. Comments are kept in place, blank line runs collapse to
+/// a single blank line and the file ends with exactly one newline.
+pub fn format_source(source: &str) -> Result<String, Vec<DodoParseError>> {
+    let tokens = Lexer::new(source.to_string()).scan_tokens();
+    let comments: Vec<(usize, String)> = tokens.iter()
+        .filter(|token| token.token_type == TokenType::COMMENT)
+        .map(|token| (token.line, token.val.clone()))
+        .collect();
+    let mut parser = Parser::new(tokens);
+    let program = parser.parse_program();
+    if !parser.errors().is_empty() {
+        return Err(parser.errors().to_vec());
+    }
+
+    let mut statement_comments = Vec::new();
+    comment_lines(&program.statements, &program.spans, &mut statement_comments);
+    let inner_comments: Vec<(usize, String)> = comments.into_iter()
+        .filter(|(line, _)| !statement_comments.contains(line))
+        .collect();
+
+    let mut formatter = Formatter {
+        out: String::new(),
+        indent: 0,
+        inner_comments: inner_comments.into_iter().peekable(),
+        last_line: 0,
+    };
+    formatter.statements(&program.statements, &program.spans);
+    Ok(formatter.out)
+}
+
+pub fn collect_files(path: &Path, files: &mut Vec<String>) -> io::Result<()> {
+    if path.is_dir() {
+        let mut entries: Vec<_> = fs::read_dir(path)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.path());
+        for entry in entries {
+            let path = entry.path();
+            if path.is_dir() || path.extension().is_some_and(|ext| ext == "dodo") {
+                collect_files(&path, files)?;
+            }
+        }
+    } else {
+        files.push(path.to_string_lossy().to_string());
+    }
+    Ok(())
+}
+
+/// `dodolang fmt [--check] <file|dir|->...`: rewrites files in place, or with
+/// `--check` only lists the ones that would change. `-` formats stdin to stdout.
+pub fn command(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.is_empty() {
+        eprintln!("Usage: dodolang fmt [--check] <file|dir|->...");
+        return 2;
+    }
+
+    let mut status = 0;
+    for path in paths {
+        if path == "-" {
+            let mut source = String::new();
+            if let Err(error) = io::stdin().read_to_string(&mut source) {
+                eprintln!("error: {}", error);
+                return 1;
+            }
+            match format_source(&source) {
+                Ok(formatted) => print!("{}", formatted),
+                Err(errors) => {
+                    errors.iter().for_each(|error| eprintln!("<stdin>: {}", error));
+                    status = 1;
+                }
+            }
+            continue;
+        }
+        let mut files = Vec::new();
+        if let Err(error) = collect_files(Path::new(path), &mut files) {
+            eprintln!("{}: {}", path, error);
+            status = 1;
+        }
+        for file in files {
+            let source = match fs::read_to_string(&file) {
+                Ok(source) => source,
+                Err(error) => {
+                    eprintln!("{}: {}", file, error);
+                    status = 1;
+                    continue;
+                }
+            };
+            let formatted = match format_source(&source) {
+                Ok(formatted) => formatted,
+                Err(errors) => {
+                    errors.iter().for_each(|error| eprintln!("{}: {}", file, error));
+                    status = 1;
+                    continue;
+                }
+            };
+            if formatted == source {
+                continue;
+            }
+            if check {
+                println!("Would reformat {}", file);
+                status = 1;
+            } else if let Err(error) = fs::write(&file, formatted) {
+                eprintln!("{}: {}", file, error);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_source;
+
+    #[test]
+    fn normalises_spacing_and_keeps_comments() {
+        let source = "# header\n\n\n\nmatrix   A[2,2]\nA={{1,-20},{300,4}} # fill\nprint A*2+loadnpy( \"a.npy\" )\n";
+        let expected = "# header\n\nmatrix A[2, 2]\nA = {{  1, -20},\n     {300,   4}} # fill\nprint A * 2 + loadnpy(\"a.npy\")\n";
+
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn comments_inside_literals_and_errors() {
+        let source = "v = {1\n  # second\n  2}\nprint (1\n";
+
+        assert!(format_source(source).is_err());
+        assert_eq!(format_source("v = {1\n  # second\n  2}").unwrap(), "# second\nv = {1, 2}\n");
+    }
+
+    #[test]
+    fn function_bodies_are_indented() {
+        let source = "fn scale(v,k) {\n\n\nreturn v*k # scaled\n  }\nfn nothing() {\n  # empty\n  return\n}\n";
+        let expected = "fn scale(v, k) {\n\n    return v * k # scaled\n}\nfn nothing() {\n    # empty\n    return\n}\n";
+
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::core::ast::parser::Parser;
+use crate::core::dodo::environment::Environment;
+use crate::core::dodo::interpreter::Interpreter;
+use crate::core::dodo::limits::Limits;
+use crate::core::dodo::random::Rng;
+use crate::core::dodo::runner::compile_errors;
+use crate::core::format::formatter::format_source;
+use crate::core::fuzz::generator::Generator;
+use crate::core::lexer::lexer::Lexer;
+use crate::core::token::token::TokenType;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::panic;
+use std::rc::Rc;
+use std::sync::Once;
+use std::thread;
+
+const DEFAULT_RUNS: u64 = 1000;
+// Top-level statements in a generated program, at most
+const STATEMENTS: usize = 12;
+// Nesting in the long runs of brackets and operators spliced into inputs, at most;
+// twice the parser's bound, so they fall either side of it
+const DEEP: u64 = 400;
+// Each input is checked on a thread with the stack a main thread gets, so what overflows
+// here overflows `dodolang` too. An overflow aborts the process, naming the thread after
+// the command that checks the input again.
+const STACK_SIZE: usize = 8 << 20;
+const WORKER: &str = "dodolang fuzz";
+
+// Characters the lexer has rules for, plus some it does not, weighted towards the former
+const CHARACTERS: &[char] = &[
+    'a', 'e', 'i', 'x', '_', '0', '1', '9', '.', '^', '+', '-', '*', '/', '%', '=', '!', '<', '>', '{', '}', '[', ']',
+    '(', ')', ':', ',', '"', '\\', '#', ' ', ' ', '\t', '\r', '\n', '\n', '\0', '\u{7f}', 'é', 'π', '\u{200b}', '🦤',
+];
+
+// Pieces of source that lex to whole tokens, for building token sequences the parser has not seen
+const FRAGMENTS: &[&str] = &[
+    "scalar", "vector", "matrix", "let", "const", "fn", "return", "while", "loop", "break", "continue", "print",
+    "import", "from", "as", "test", "for", "x", "f", "lu", "1", "2.5", "3i", "1e9", "\"s\"", "\"open", "+", "-", "*",
+    "/", "//", "%", "^", ".^", "=", "==", "!=", "<", "<=", ">", ">=", "!", ".", ",", ":", "(", ")", "[", "]", "{", "}", "\n", "# c\n", "@",
+];
+
+/// A way of building inputs and the property every input must have, besides not panicking.
+pub struct Target {
+    pub name: &'static str,
+    input: fn(&mut Rng) -> String,
+    check: fn(&str) -> Result<(), String>,
+}
+
+pub const TARGETS: &[Target] = &[
+    Target { name: "lexer", input: random_text, check: lex },
+    Target { name: "parser", input: token_soup, check: parse },
+    Target { name: "eval", input: mutated_program, check: evaluate },
+    Target { name: "roundtrip", input: program, check: round_trip },
+];
+
+/// An input that broke a target, with the seed that builds it again.
+pub struct Failure {
+    pub seed: u64,
+    pub input: String,
+    pub message: String,
+}
+
+fn random_text(rng: &mut Rng) -> String {
+    let len = rng.below(80) as usize;
+    (0..len).map(|_| CHARACTERS[rng.below(CHARACTERS.len() as u64) as usize]).collect()
+}
+
+fn token_soup(rng: &mut Rng) -> String {
+    let len = rng.below(40) as usize;
+    let mut fragments = Vec::new();
+    for _ in 0..len {
+        let fragment = FRAGMENTS[rng.below(FRAGMENTS.len() as u64) as usize];
+        // Now and then a fragment repeats many times, making a long run of `(`, `-` or `{`
+        let repeat = if rng.below(20) == 0 { rng.below(DEEP) + 1 } else { 1 };
+        fragments.extend((0..repeat).map(|_| fragment));
+    }
+    fragments.join(" ")
+}
+
+// A number wrapped in many parentheses, negations or powers
+fn deep_nesting(rng: &mut Rng) -> String {
+    let depth = rng.below(DEEP) as usize;
+    match rng.below(3) {
+        0 => format!("{}1{}", "(".repeat(depth), ")".repeat(depth)),
+        1 => format!("{}1", "-".repeat(depth)),
+        _ => vec!["1"; depth + 1].join(" ^ "),
+    }
+}
+
+fn program(rng: &mut Rng) -> String {
+    Generator::new(rng).program(STATEMENTS)
+}
+
+// A generated program with a few lines deleted, repeated or swapped, or text spliced in
+fn mutated_program(rng: &mut Rng) -> String {
+    let mut lines: Vec<String> = program(rng).lines().map(str::to_string).collect();
+    for _ in 0..rng.below(4) {
+        let at = rng.below(lines.len() as u64) as usize;
+        match rng.below(5) {
+            0 if lines.len() > 1 => {
+                lines.remove(at);
+            }
+            1 => lines.insert(at, lines[at].clone()),
+            2 => {
+                let other = rng.below(lines.len() as u64) as usize;
+                lines.swap(at, other);
+            }
+            3 => {
+                let nesting = deep_nesting(rng);
+                lines.insert(at, format!("print {}", nesting));
+            }
+            _ => {
+                let column = rng.below(lines[at].chars().count() as u64 + 1) as usize;
+                let fragment = FRAGMENTS[rng.below(FRAGMENTS.len() as u64) as usize];
+                let line: String = lines[at].chars().take(column).chain(fragment.chars()).chain(lines[at].chars().skip(column)).collect();
+                lines[at] = line;
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+fn lex(source: &str) -> Result<(), String> {
+    let tokens = Lexer::new(source.to_string()).scan_tokens();
+    if tokens.last().map(|token| token.token_type) != Some(TokenType::EOF) {
+        return Err("tokens do not end with EOF".to_string());
+    }
+    match tokens.windows(2).find(|pair| pair[1].line < pair[0].line) {
+        Some(pair) => Err(format!("line numbers go back from {} to {}", pair[0].line, pair[1].line)),
+        None => Ok(()),
+    }
+}
+
+fn parse(source: &str) -> Result<(), String> {
+    let mut tokens = Lexer::new(source.to_string()).scan_tokens();
+    Parser::new(tokens.clone()).parse_program();
+    // The parser must also cope with tokens that stop short of EOF
+    tokens.pop();
+    Parser::new(tokens).parse_program();
+    Ok(())
+}
+
+// Bounds that keep a random program from running for long or holding much memory
+fn limits() -> Limits {
+    Limits {
+        max_steps: Some(20_000),
+        max_memory: Some(1 << 20),
+        max_depth: Some(64),
+        sandbox: true,
+        ..Limits::default()
+    }
+}
+
+#[derive(Clone, Default)]
+struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Compiles and runs a script, giving what it printed and the message of any runtime error
+fn run(source: &str) -> Result<(String, Option<String>), Vec<String>> {
+    let limits = limits();
+    let program = compile_errors(source, "fuzz.dodo", &[], limits.sandbox)?;
+    let captured = Captured::default();
+    let mut interpreter = Interpreter::new(Environment::new());
+    interpreter.set_output(Box::new(captured.clone()));
+    interpreter.set_limits(limits);
+    let error = interpreter.interpret(&program).err().map(|error| error.message);
+    let printed = String::from_utf8_lossy(&captured.0.borrow()).into_owned();
+    Ok((printed, error))
+}
+
+fn evaluate(source: &str) -> Result<(), String> {
+    // Errors are expected from mutated programs; only panics count
+    let _ = run(source);
+    Ok(())
+}
+
+// Generated programs compile, format to text that formats to itself, and behave the same once formatted
+fn round_trip(source: &str) -> Result<(), String> {
+    let before = run(source).map_err(|errors| format!("generated program does not compile:\n{}", errors.join("\n")))?;
+    let formatted = format_source(source).map_err(|_| "generated program does not format".to_string())?;
+    match format_source(&formatted) {
+        Ok(again) if again == formatted => (),
+        Ok(again) => return Err(format!("formatting again changes the program to:\n{}", again)),
+        Err(_) => return Err(format!("formatted program does not parse:\n{}", formatted)),
+    }
+    let after = run(&formatted).map_err(|errors| format!("formatted program does not compile:\n{}\n{}", errors.join("\n"), formatted))?;
+    match before == after {
+        true => Ok(()),
+        false => Err(format!("formatted program behaves differently: {:?} before, {:?} after\n{}", before, after, formatted)),
+    }
+}
+
+// Worker panics come back through `join` and are reported with their seed, so they are not
+// printed as well; other threads keep the hook they had. Installed once, as the hook is
+// shared by the whole process and tests fuzz from several threads at a time.
+fn silence_workers() {
+    static SILENCE: Once = Once::new();
+    SILENCE.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !thread::current().name().is_some_and(|name| name.starts_with(WORKER)) {
+                previous(info);
+            }
+        }));
+    });
+}
+
+// Checks one input on a worker thread named after the command that reruns it
+fn check(target: &Target, seed: u64, input: &str) -> Result<(), String> {
+    let (check, input) = (target.check, input.to_string());
+    let worker = thread::Builder::new()
+        .name(format!("{} --seed {} --runs 1 {}", WORKER, seed, target.name))
+        .stack_size(STACK_SIZE)
+        .spawn(move || check(&input))
+        .map_err(|error| format!("cannot start a worker thread: {}", error))?;
+    match worker.join() {
+        Ok(result) => result,
+        Err(payload) => Err(match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+            (Some(message), _) => format!("panicked: {}", message),
+            (_, Some(message)) => format!("panicked: {}", message),
+            _ => "panicked".to_string(),
+        }),
+    }
+}
+
+/// Runs `runs` inputs through a target, each built from its own seed counting up from
+/// `seed`, and stops at the first that panics or breaks the target's property.
+pub fn fuzz(target: &Target, seed: u64, runs: u64) -> Option<Failure> {
+    silence_workers();
+    for seed in seed..seed.saturating_add(runs) {
+        let input = (target.input)(&mut Rng::new(seed));
+        if let Err(message) = check(target, seed, &input) {
+            return Some(Failure { seed, input, message });
+        }
+    }
+    None
+}
+
+/// `dodolang fuzz [--seed N] [--runs N] [target]...`: feeds random input to the lexer,
+/// the parser and the interpreter, and checks generated programs survive formatting.
+pub fn command(args: &[String]) -> i32 {
+    let usage = || {
+        let names: Vec<&str> = TARGETS.iter().map(|target| target.name).collect();
+        eprintln!("Usage: dodolang fuzz [--seed N] [--runs N] [{}]...", names.join("|"));
+        2
+    };
+    let mut seed = 0;
+    let mut runs = DEFAULT_RUNS;
+    let mut targets = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let number = match arg.as_str() {
+            "--seed" => &mut seed,
+            "--runs" => &mut runs,
+            name => match TARGETS.iter().find(|target| target.name == name) {
+                Some(target) => {
+                    targets.push(target);
+                    continue;
+                }
+                None => return usage(),
+            },
+        };
+        match args.next().and_then(|value| value.parse().ok()) {
+            Some(value) => *number = value,
+            None => return usage(),
+        }
+    }
+    if targets.is_empty() {
+        targets.extend(TARGETS.iter());
+    }
+
+    let mut status = 0;
+    for target in targets {
+        match fuzz(target, seed, runs) {
+            None => println!("fuzz {} ... ok, {} runs from seed {}", target.name, runs, seed),
+            Some(failure) => {
+                println!("fuzz {} ... FAILED at seed {}", target.name, failure.seed);
+                println!("{}", failure.message);
+                println!("---- input ----\n{:?}", failure.input);
+                println!("rerun with: dodolang fuzz --seed {} --runs 1 {}", failure.seed, target.name);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fuzz, Target, TARGETS};
+    use std::thread;
+
+    #[test]
+    fn targets_hold_for_a_few_hundred_seeds() {
+        for target in TARGETS {
+            if let Some(failure) = fuzz(target, 0, 200) {
+                panic!("{} failed at seed {}: {}\n{:?}", target.name, failure.seed, failure.message, failure.input);
+            }
+        }
+    }
+
+    #[test]
+    fn a_panicking_worker_is_a_failure() {
+        let target = Target { name: "panics", input: |_| "input".to_string(), check: |_| panic!("boom") };
+        let failure = fuzz(&target, 7, 3).unwrap();
+        assert_eq!((failure.seed, failure.input.as_str()), (7, "input"));
+        assert_eq!(failure.message, "panicked: boom");
+
+        // Each run gets its own worker's message, however many fuzz at once
+        const ECHO: Target = Target { name: "echo", input: |rng| rng.below(1000).to_string(), check: |input| panic!("input {}", input) };
+        let runs: Vec<_> = (0..8).map(|seed| thread::spawn(move || fuzz(&ECHO, seed, 1).unwrap())).collect();
+        for run in runs {
+            let failure = run.join().unwrap();
+            assert_eq!(failure.message, format!("panicked: input {}", failure.input));
+        }
+    }
+}
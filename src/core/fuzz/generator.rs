@@ -0,0 +1,396 @@
+use crate::core::dodo::random::Rng;
+
+// How deeply expressions and blocks nest
+const MAX_DEPTH: usize = 3;
+// Vectors and matrices have between one and this many elements along each axis
+const MAX_SIZE: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    Scalar,
+    Vector(usize),
+    Matrix(usize, usize),
+}
+
+struct Variable {
+    name: String,
+    shape: Shape,
+    mutable: bool,
+}
+
+/// Writes random programs that parse and type-check: every name is declared before it is
+/// used, shapes agree, loops end and each `break` or `continue` has a loop around it.
+/// What they compute is arbitrary, so running one may still fail, by dividing by zero say.
+pub struct Generator<'a> {
+    rng: &'a mut Rng,
+    out: String,
+    indent: usize,
+    // Globals, then the parameters and locals of the function or test being written
+    globals: Vec<Variable>,
+    locals: Option<Vec<Variable>>,
+    // User functions by name and number of parameters, all taking and returning scalars
+    functions: Vec<(String, usize)>,
+    // Labels of the loops around the statement being written, or none for unlabelled ones
+    loops: Vec<Option<String>>,
+    // Blocks around the statement being written, and expressions around the one being built
+    nesting: usize,
+    depth: usize,
+    names: usize,
+}
+
+impl<'a> Generator<'a> {
+    pub fn new(rng: &'a mut Rng) -> Self {
+        Generator {
+            rng,
+            out: String::new(),
+            indent: 0,
+            globals: Vec::new(),
+            locals: None,
+            functions: Vec::new(),
+            loops: Vec::new(),
+            nesting: 0,
+            depth: 0,
+            names: 0,
+        }
+    }
+
+    /// A program of up to `statements` top-level statements.
+    pub fn program(mut self, statements: usize) -> String {
+        for _ in 0..self.below(statements) + 1 {
+            self.top_level();
+        }
+        self.out
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.rng.below(n as u64) as usize
+    }
+
+    fn chance(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    fn choose<'s>(&mut self, options: &[&'s str]) -> &'s str {
+        options[self.below(options.len())]
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+
+    fn size(&mut self) -> usize {
+        self.rng.below(MAX_SIZE) as usize + 1
+    }
+
+    fn line(&mut self, text: &str) {
+        self.out.push_str(&"    ".repeat(self.indent));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn scope(&mut self) -> &mut Vec<Variable> {
+        match self.locals.as_mut() {
+            Some(locals) => locals,
+            None => &mut self.globals,
+        }
+    }
+
+    fn visible(&self) -> impl Iterator<Item = &Variable> {
+        self.globals.iter().chain(self.locals.iter().flatten())
+    }
+
+    fn variable(&mut self, shape: Shape, mutable: bool) -> Option<String> {
+        let names: Vec<String> = self.visible()
+            .filter(|variable| variable.shape == shape && (variable.mutable || !mutable))
+            .map(|variable| variable.name.clone())
+            .collect();
+        match names.is_empty() {
+            true => None,
+            false => Some(names[self.below(names.len())].clone()),
+        }
+    }
+
+    fn top_level(&mut self) {
+        match self.below(12) {
+            0 => self.function(),
+            1 => self.test(),
+            _ => self.statement(),
+        }
+    }
+
+    fn block(&mut self, head: &str, body: impl FnOnce(&mut Self)) {
+        self.line(&format!("{} {{", head));
+        self.indent += 1;
+        self.nesting += 1;
+        body(self);
+        self.nesting -= 1;
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn body(&mut self) {
+        for _ in 0..self.below(3) + 1 {
+            self.statement();
+        }
+    }
+
+    fn function(&mut self) {
+        let name = self.fresh("f");
+        let params: Vec<String> = (0..self.below(3)).map(|_| self.fresh("p")).collect();
+        let locals = params.iter().map(|param| Variable { name: param.clone(), shape: Shape::Scalar, mutable: true }).collect();
+        self.locals = Some(locals);
+        let outer = std::mem::take(&mut self.loops);
+        self.block(&format!("fn {}({})", name, params.join(", ")), |generator| {
+            generator.body();
+            let value = generator.expr(Shape::Scalar);
+            generator.line(&format!("return {}", value));
+        });
+        self.loops = outer;
+        self.locals = None;
+        // Only callable from here on, so a call never runs before the definition
+        self.functions.push((name, params.len()));
+    }
+
+    fn test(&mut self) {
+        let name = self.fresh("test ");
+        self.locals = Some(Vec::new());
+        self.block(&format!("test \"{}\"", name), |generator| {
+            generator.body();
+            let shape = generator.shape();
+            let value = generator.expr(shape);
+            match generator.below(3) {
+                0 => generator.line(&format!("assert_eq({}, {})", value, value)),
+                1 => generator.line(&format!("assert_close({}, {}, 1e-9)", value, value)),
+                _ => generator.line("assert(1, \"always\")"),
+            }
+        });
+        self.locals = None;
+    }
+
+    fn shape(&mut self) -> Shape {
+        match self.below(4) {
+            0 => Shape::Vector(self.size()),
+            1 => Shape::Matrix(self.size(), self.size()),
+            _ => Shape::Scalar,
+        }
+    }
+
+    fn statement(&mut self) {
+        let in_loop = !self.loops.is_empty();
+        match self.below(10) {
+            // Declarations stay out of loop bodies, which would run them more than once
+            0..=2 if !in_loop => self.declaration(),
+            3 | 4 => {
+                let shape = self.shape();
+                let value = self.expr(shape);
+                self.line(&format!("print {}", value));
+            }
+            5 if self.nesting < MAX_DEPTH => self.while_loop(),
+            6 if self.nesting < MAX_DEPTH => self.labelled_loop(),
+            7 => {
+                let comment = self.choose(&["note", "TODO: {x} // 2", "\"quoted\""]);
+                self.line(&format!("# {}", comment));
+            }
+            _ => self.assignment(),
+        }
+    }
+
+    fn declaration(&mut self) {
+        let shape = self.shape();
+        let name = self.fresh("v");
+        let value = self.expr(shape);
+        let (head, mutable) = match (shape, self.below(4)) {
+            (_, 0) => (format!("let {}", name), false),
+            (Shape::Scalar, _) => (format!("scalar {}", name), true),
+            (Shape::Vector(n), _) => (format!("vector {}[{}]", name, n), true),
+            (Shape::Matrix(rows, columns), _) => (format!("matrix {}[{}, {}]", name, rows, columns), true),
+        };
+        self.line(&format!("{} = {}", head, value));
+        self.scope().push(Variable { name, shape, mutable });
+    }
+
+    fn assignment(&mut self) {
+        let shape = self.shape();
+        match self.variable(shape, true) {
+            Some(name) => {
+                let value = self.expr(shape);
+                self.line(&format!("{} = {}", name, value));
+            }
+            None => {
+                let value = self.call_or(Shape::Scalar);
+                self.line(&value);
+            }
+        }
+    }
+
+    // A while loop counting down a fresh counter, which must be declared outside any loop
+    fn while_loop(&mut self) {
+        let counter = self.fresh("n");
+        let start = self.below(4);
+        if !self.loops.is_empty() {
+            // Inside a loop the counter cannot be declared, so an unlabelled `loop` runs once instead
+            self.loops.push(None);
+            self.block("loop", |generator| {
+                generator.body();
+                generator.line("break");
+            });
+            self.loops.pop();
+            return;
+        }
+        self.line(&format!("scalar {} = {}", counter, start));
+        self.scope().push(Variable { name: counter.clone(), shape: Shape::Scalar, mutable: false });
+        self.loops.push(None);
+        self.block(&format!("while {} > 0", counter), |generator| {
+            generator.line(&format!("{} = {} - 1", counter, counter));
+            generator.body();
+            if generator.chance(3) {
+                let jump = generator.choose(&["continue", "break"]);
+                generator.line(jump);
+            }
+        });
+        self.loops.pop();
+    }
+
+    // A loop that runs once, optionally labelled, whose body may break out of an outer loop
+    fn labelled_loop(&mut self) {
+        let label = match self.chance(2) {
+            true => Some(self.fresh("outer")),
+            false => None,
+        };
+        let head = match &label {
+            Some(label) => format!("{}: loop", label),
+            None => "loop".to_string(),
+        };
+        self.loops.push(label);
+        self.block(&head, |generator| {
+            generator.body();
+            let labels: Vec<String> = generator.loops.iter().flatten().cloned().collect();
+            match labels.is_empty() || generator.chance(2) {
+                true => generator.line("break"),
+                false => {
+                    let label = labels[generator.below(labels.len())].clone();
+                    generator.line(&format!("break {}", label));
+                }
+            }
+        });
+        self.loops.pop();
+    }
+
+    fn call_or(&mut self, shape: Shape) -> String {
+        match self.functions.is_empty() {
+            true => format!("print {}", self.expr(shape)),
+            false => {
+                let at = self.below(self.functions.len());
+                let (name, arity) = self.functions[at].clone();
+                let args: Vec<String> = (0..arity).map(|_| self.expr(Shape::Scalar)).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+        }
+    }
+
+    fn literal(&mut self) -> String {
+        match self.below(8) {
+            0 => format!("{}.{}", self.below(100), self.below(100)),
+            1 => format!("{}i", self.below(10)),
+            2 => format!("{}e{}", self.below(10), self.choose(&["-3", "2", "+1"])),
+            3 if self.chance(4) => "170141183460469231731687303715884105728".to_string(),
+            4 => self.choose(&["pi", "e", "inf", "nan"]).to_string(),
+            _ => self.below(20).to_string(),
+        }
+    }
+
+    fn expr(&mut self, shape: Shape) -> String {
+        self.depth += 1;
+        let leaf = self.depth > MAX_DEPTH || self.chance(3);
+        let expr = match shape {
+            Shape::Scalar => self.scalar(leaf),
+            Shape::Vector(n) => self.vector(n, leaf),
+            Shape::Matrix(rows, columns) => self.matrix(rows, columns, leaf),
+        };
+        self.depth -= 1;
+        // Operands are bracketed so they keep the shape they were built for
+        match leaf || self.depth == 0 {
+            true => expr,
+            false => format!("({})", expr),
+        }
+    }
+
+    fn scalar(&mut self, leaf: bool) -> String {
+        if leaf {
+            return match self.variable(Shape::Scalar, false) {
+                Some(name) if self.chance(2) => name,
+                _ => self.literal(),
+            };
+        }
+        match self.below(8) {
+            0 => format!("{}{}", self.choose(&["-", "!"]), self.expr(Shape::Scalar)),
+            1 => format!("({})", self.expr(Shape::Scalar)),
+            2 => {
+                let function = self.choose(&["abs", "sqrt", "exp", "floor", "sin", "sign"]);
+                format!("{}({})", function, self.expr(Shape::Scalar))
+            }
+            3 => {
+                let shape = Shape::Vector(self.size());
+                let function = self.choose(&["sum", "mean", "max", "prod"]);
+                format!("{}({})", function, self.expr(shape))
+            }
+            4 if !self.functions.is_empty() => self.call_or(Shape::Scalar),
+            _ => {
+                let operator = self.choose(&["+", "-", "*", "/", "//", "%", "^", "<", "<=", ">", ">=", "==", "!="]);
+                format!("{} {} {}", self.expr(Shape::Scalar), operator, self.expr(Shape::Scalar))
+            }
+        }
+    }
+
+    fn elements(&mut self, n: usize) -> String {
+        let elements: Vec<String> = (0..n).map(|_| self.expr(Shape::Scalar)).collect();
+        format!("{{{}}}", elements.join(", "))
+    }
+
+    fn vector(&mut self, n: usize, leaf: bool) -> String {
+        if leaf {
+            return match self.variable(Shape::Vector(n), false) {
+                Some(name) if self.chance(2) => name,
+                _ => self.elements(n),
+            };
+        }
+        let shape = Shape::Vector(n);
+        match self.below(5) {
+            0 => format!("{} .^ {}", self.expr(shape), self.below(3)),
+            1 => format!("{} * {}", self.expr(Shape::Scalar), self.expr(shape)),
+            2 => format!("linspace({}, {}, {})", self.expr(Shape::Scalar), self.expr(Shape::Scalar), n),
+            // `*` between vectors is their dot product, so it is left to scalars
+            _ => {
+                let operator = self.choose(&["+", "-", "/"]);
+                format!("{} {} {}", self.expr(shape), operator, self.expr(shape))
+            }
+        }
+    }
+
+    fn matrix(&mut self, rows: usize, columns: usize, leaf: bool) -> String {
+        if leaf {
+            return match self.variable(Shape::Matrix(rows, columns), false) {
+                Some(name) if self.chance(2) => name,
+                _ => {
+                    let rows: Vec<String> = (0..rows).map(|_| self.elements(columns)).collect();
+                    format!("{{{}}}", rows.join(", "))
+                }
+            };
+        }
+        let shape = Shape::Matrix(rows, columns);
+        match self.below(5) {
+            0 if rows == columns => format!("eye({}) * {}", rows, self.expr(Shape::Scalar)),
+            1 => format!("transpose({})", self.expr(Shape::Matrix(columns, rows))),
+            2 => {
+                let inner = self.size();
+                format!("{} * {}", self.expr(Shape::Matrix(rows, inner)), self.expr(Shape::Matrix(inner, columns)))
+            }
+            3 => format!("zeros({}, {}) - {} .^ 2", rows, columns, self.expr(shape)),
+            _ => {
+                let operator = self.choose(&["+", "-"]);
+                format!("{} {} {}", self.expr(shape), operator, self.expr(shape))
+            }
+        }
+    }
+}
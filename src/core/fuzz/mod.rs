@@ -0,0 +1,2 @@
+pub mod fuzzer;
+pub mod generator;
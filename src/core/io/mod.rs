@@ -0,0 +1,2 @@
+pub mod npy;
+pub mod mtx;
@@ -0,0 +1,258 @@
+use crate::core::dodo::sparse::SparseMatrix;
+use crate::core::dodo::value::{Matrix, Scalar, Value};
+use num_complex::Complex64;
+use std::fmt::Write;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Field {
+    Real,
+    Integer,
+    Complex,
+    Pattern,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Symmetry {
+    General,
+    Symmetric,
+    SkewSymmetric,
+    Hermitian,
+}
+
+// Reads one value; complex entries take two numbers, the real and imaginary parts
+fn parse_entry<'a, I>(tokens: &mut I, field: Field, line: usize) -> Result<Scalar, String> where I: Iterator<Item = &'a str> {
+    let mut next = || tokens.next().ok_or_else(|| format!("Missing value on line {}", line));
+    let token = next()?;
+    let invalid = |token: &str| format!("Invalid value '{}' on line {}", token, line);
+    match field {
+        Field::Integer => token.parse::<i128>().map(Scalar::Int).map_err(|_| invalid(token)),
+        Field::Complex => {
+            let imaginary = next()?;
+            let re = token.parse::<f64>().map_err(|_| invalid(token))?;
+            let im = imaginary.parse::<f64>().map_err(|_| invalid(imaginary))?;
+            Ok(Scalar::Complex(Complex64::new(re, im)))
+        }
+        _ => token.parse::<f64>().map(Scalar::Float).map_err(|_| invalid(token)),
+    }
+}
+
+fn parse_index(token: Option<&str>, limit: usize, line: usize) -> Result<usize, String> {
+    match token.and_then(|token| token.parse::<usize>().ok()) {
+        Some(index) if index >= 1 && index <= limit => Ok(index - 1),
+        _ => Err(format!("Invalid index on line {}", line)),
+    }
+}
+
+/// Parses a Matrix Market file. Coordinate files become sparse matrices,
+/// array files dense matrices.
+pub fn parse(source: &str) -> Result<Value, String> {
+    let mut lines = source.lines().enumerate().map(|(number, line)| (number + 1, line.trim()));
+    let banner = lines.next().map(|(_, line)| line.to_lowercase()).unwrap_or_default();
+    let words: Vec<&str> = banner.split_whitespace().collect();
+    if words.len() != 5 || words[0] != "%%matrixmarket" || words[1] != "matrix" {
+        return Err("Missing '%%MatrixMarket matrix' header".to_string());
+    }
+    let coordinate = match words[2] {
+        "coordinate" => true,
+        "array" => false,
+        format => return Err(format!("Unsupported Matrix Market format '{}'", format)),
+    };
+    let field = match words[3] {
+        "real" | "double" => Field::Real,
+        "integer" => Field::Integer,
+        "complex" => Field::Complex,
+        "pattern" if coordinate => Field::Pattern,
+        field => return Err(format!("Unsupported Matrix Market field '{}'", field)),
+    };
+    let symmetry = match words[4] {
+        "general" => Symmetry::General,
+        "symmetric" => Symmetry::Symmetric,
+        "skew-symmetric" => Symmetry::SkewSymmetric,
+        "hermitian" if field == Field::Complex => Symmetry::Hermitian,
+        symmetry => return Err(format!("Unsupported Matrix Market symmetry '{}'", symmetry)),
+    };
+
+    let mut lines = lines.filter(|(_, line)| !line.is_empty() && !line.starts_with('%'));
+    let (size_line, size) = lines.next().ok_or("Missing Matrix Market size line")?;
+    let size = size.split_whitespace()
+        .map(|dim| dim.parse::<usize>().map_err(|_| format!("Invalid size on line {}", size_line)))
+        .collect::<Result<Vec<usize>, String>>()?;
+    if size.len() != if coordinate { 3 } else { 2 } {
+        return Err(format!("Invalid size on line {}", size_line));
+    }
+    let (rows, cols) = (size[0], size[1]);
+    // Check the declared size up front, as a .npy shape is, before any storage is set aside for it
+    let elements = rows.checked_mul(cols).ok_or_else(|| format!("Matrix size {}x{} on line {} is too large", rows, cols, size_line))?;
+    if coordinate && size[2] > elements {
+        return Err(format!("{} entries declared on line {} do not fit in a {}x{} matrix", size[2], size_line, rows, cols));
+    }
+
+    let mut triplets = Vec::new();
+    if coordinate {
+        for _ in 0..size[2] {
+            let (number, line) = lines.next().ok_or("Fewer entries than declared")?;
+            let mut tokens = line.split_whitespace();
+            let row = parse_index(tokens.next(), rows, number)?;
+            let col = parse_index(tokens.next(), cols, number)?;
+            let val = match field {
+                Field::Pattern => Scalar::Int(1),
+                _ => parse_entry(&mut tokens, field, number)?,
+            };
+            triplets.push((row, col, val));
+        }
+    } else {
+        // Array entries are listed in column-major order; symmetric files only hold the lower triangle.
+        for col in 0..cols {
+            let start = match symmetry {
+                Symmetry::General => 0,
+                Symmetry::Symmetric | Symmetry::Hermitian => col,
+                Symmetry::SkewSymmetric => col + 1,
+            };
+            for row in start..rows {
+                let (number, line) = lines.next().ok_or("Fewer entries than declared")?;
+                triplets.push((row, col, parse_entry(&mut line.split_whitespace(), field, number)?));
+            }
+        }
+    }
+
+    if symmetry != Symmetry::General {
+        let mirrored: Vec<(usize, usize, Scalar)> = triplets.iter()
+            .filter(|(row, col, _)| row != col)
+            .map(|(row, col, val)| match symmetry {
+                Symmetry::SkewSymmetric => (*col, *row, val.negate()),
+                Symmetry::Hermitian => (*col, *row, val.conj()),
+                _ => (*col, *row, val.clone()),
+            })
+            .collect();
+        triplets.extend(mirrored);
+    }
+
+    let sparse = SparseMatrix::from_triplets(rows, cols, triplets)?;
+    match coordinate {
+        true => Ok(Value::Sparse(sparse)),
+        false => Ok(Value::Matrix(sparse.to_dense()?)),
+    }
+}
+
+fn field_name(values: &[Scalar]) -> &'static str {
+    if values.iter().any(Scalar::is_complex) {
+        return "complex";
+    }
+    match values.iter().all(Scalar::is_integer) {
+        true => "integer",
+        false => "real",
+    }
+}
+
+// Rationals are written as their nearest float, since the format has no fractions
+fn entry(val: &Scalar, field: &str) -> String {
+    match (val, field) {
+        (_, "complex") => {
+            let val = val.as_complex();
+            format!("{:?} {:?}", val.re, val.im)
+        }
+        (Scalar::Rational(_), _) => format!("{:?}", val.as_f64()),
+        (other, _) => other.to_string(),
+    }
+}
+
+/// Writes sparse matrices in coordinate format and dense matrices and vectors
+/// (as a single column) in array format.
+pub fn format(value: &Value) -> Result<String, String> {
+    let mut out = String::new();
+    match value {
+        Value::Sparse(sparse) => {
+            let field = field_name(&sparse.data);
+            writeln!(out, "%%MatrixMarket matrix coordinate {} general", field).unwrap();
+            writeln!(out, "{} {} {}", sparse.rows, sparse.cols, sparse.nnz()).unwrap();
+            for (row, col, val) in sparse.triplets() {
+                writeln!(out, "{} {} {}", row + 1, col + 1, entry(&val, field)).unwrap();
+            }
+        }
+        Value::Matrix(matrix) => {
+            let field = field_name(&matrix.data);
+            writeln!(out, "%%MatrixMarket matrix array {} general", field).unwrap();
+            writeln!(out, "{} {}", matrix.rows, matrix.cols).unwrap();
+            for col in 0..matrix.cols {
+                for row in 0..matrix.rows {
+                    writeln!(out, "{}", entry(matrix.get(row, col), field)).unwrap();
+                }
+            }
+        }
+        Value::Vector(vector) => return format(&Value::Matrix(Matrix::new(vector.len(), 1, vector.clone()))),
+        other => return Err(format!("Cannot save a {} in Matrix Market format", other.type_name())),
+    }
+    Ok(out)
+}
+
+pub fn read_mtx(path: &str) -> Result<Value, String> {
+    let source = std::fs::read_to_string(path).map_err(|error| format!("Cannot read '{}': {}", path, error))?;
+    parse(&source)
+}
+
+pub fn write_mtx(path: &str, value: &Value) -> Result<(), String> {
+    std::fs::write(path, format(value)?).map_err(|error| format!("Cannot write '{}': {}", path, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, parse};
+    use crate::core::dodo::value::{Matrix, Scalar, Value};
+    use num_complex::Complex64;
+
+    #[test]
+    fn symmetric_coordinate() {
+        let source = "%%MatrixMarket matrix coordinate real symmetric\n\
+                      % lower triangle only\n\
+                      3 3 3\n\
+                      1 1 4.0\n\
+                      3 1 -1.5\n\
+                      2 2 2\n";
+
+        let dense = match parse(source).unwrap() {
+            Value::Sparse(sparse) => sparse.to_dense().unwrap(),
+            other => panic!("expected sparse, got {}", other),
+        };
+        let expected = vec![4.0, 0.0, -1.5, 0.0, 2.0, 0.0, -1.5, 0.0, 0.0];
+        assert_eq!(dense.data.iter().map(Scalar::as_f64).collect::<Vec<f64>>(), expected);
+    }
+
+    #[test]
+    fn round_trip() {
+        let dense = Value::Matrix(Matrix::new(2, 2, vec![Scalar::Int(1), Scalar::Int(2), Scalar::Int(0), Scalar::Int(4)]));
+        let text = format(&dense).unwrap();
+
+        assert_eq!(text, "%%MatrixMarket matrix array integer general\n2 2\n1\n0\n2\n4\n");
+        assert_eq!(parse(&text).unwrap(), dense);
+        assert!(parse("%%MatrixMarket matrix coordinate real hermitian\n1 1 0\n").is_err());
+        assert!(parse("%%MatrixMarket matrix coordinate integer general\n1 1 1\n2 1 5\n").is_err());
+    }
+
+    #[test]
+    fn complex_entries() {
+        let z = |re, im| Scalar::Complex(Complex64::new(re, im));
+        let vector = Value::Vector(vec![z(1.0, -2.0), Scalar::Int(3)]);
+        let text = format(&vector).unwrap();
+
+        assert_eq!(text, "%%MatrixMarket matrix array complex general\n2 1\n1.0 -2.0\n3.0 0.0\n");
+        assert_eq!(parse(&text).unwrap(), Value::Matrix(Matrix::new(2, 1, vec![z(1.0, -2.0), z(3.0, 0.0)])));
+
+        // Hermitian files store the lower triangle; the upper one is its conjugate
+        let hermitian = parse("%%MatrixMarket matrix coordinate complex hermitian\n2 2 2\n1 1 2 0\n2 1 1 1\n").unwrap();
+        let dense = match hermitian {
+            Value::Sparse(sparse) => sparse.to_dense().unwrap(),
+            other => panic!("expected sparse, got {}", other),
+        };
+        assert_eq!(dense.data, vec![z(2.0, 0.0), z(1.0, -1.0), z(1.0, 1.0), Scalar::Int(0)]);
+    }
+
+    #[test]
+    fn oversized_headers_are_errors() {
+        let error = |source: &str| parse(source).unwrap_err();
+
+        assert!(error("%%MatrixMarket matrix coordinate real general\n99999999999999 1 0\n").contains("Cannot allocate"));
+        assert!(error("%%MatrixMarket matrix array real general\n99999999999999 0\n").contains("Cannot allocate"));
+        assert!(error("%%MatrixMarket matrix array real general\n18446744073709551615 2\n").contains("is too large"));
+        assert!(error("%%MatrixMarket matrix coordinate real general\n2 2 5\n").contains("do not fit"));
+    }
+}
@@ -0,0 +1,385 @@
+use crate::core::dodo::value::{Matrix, Scalar, Value};
+use num_complex::Complex64;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{Read, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Order {
+    C,
+    Fortran,
+}
+
+impl Order {
+    pub fn parse(order: &str) -> Result<Order, String> {
+        match order {
+            "C" | "c" => Ok(Order::C),
+            "F" | "f" => Ok(Order::Fortran),
+            _ => Err(format!("Unknown array order '{}', expected \"C\" or \"F\"", order)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum DType {
+    Int64,
+    Float64,
+    Complex128,
+}
+
+impl DType {
+    fn size(self) -> usize {
+        match self {
+            DType::Complex128 => 16,
+            _ => 8,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Header {
+    dtype: DType,
+    little_endian: bool,
+    order: Order,
+    shape: Vec<usize>,
+}
+
+/// Decodes the contents of a `.npy` file into a scalar, vector or matrix.
+pub fn decode(bytes: &[u8]) -> Result<Value, String> {
+    if bytes.len() < 10 || &bytes[..6] != MAGIC {
+        return Err("Not a NumPy .npy file".to_string());
+    }
+    let (header_len, offset) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 => {
+            if bytes.len() < 12 {
+                return Err("Truncated .npy header".to_string());
+            }
+            (u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize, 12)
+        }
+        version => return Err(format!("Unsupported .npy format version {}", version)),
+    };
+    if bytes.len() < offset + header_len {
+        return Err("Truncated .npy header".to_string());
+    }
+    let header = std::str::from_utf8(&bytes[offset..offset + header_len])
+        .map_err(|_| "Invalid .npy header encoding".to_string())?;
+    let header = parse_header(header)?;
+
+    // The shape comes from the file, so its product may not fit
+    let size = header.dtype.size();
+    let count = header.shape.iter()
+        .try_fold(1usize, |count, &dim| count.checked_mul(dim))
+        .filter(|count| count.checked_mul(size).is_some())
+        .ok_or_else(|| format!("Array shape {:?} is too large", header.shape))?;
+    let data = &bytes[offset + header_len..];
+    if data.len() < count * size {
+        return Err(format!("Expected {} elements in .npy data, found {}", count, data.len() / size));
+    }
+    let float = |raw: &[u8]| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(raw);
+        match header.little_endian {
+            true => f64::from_le_bytes(bytes),
+            false => f64::from_be_bytes(bytes),
+        }
+    };
+    let mut elements = Vec::with_capacity(count);
+    for chunk in data.chunks_exact(size).take(count) {
+        let mut raw = [0u8; 8];
+        raw.copy_from_slice(&chunk[..8]);
+        elements.push(match (header.dtype, header.little_endian) {
+            (DType::Int64, true) => Scalar::Int(i64::from_le_bytes(raw) as i128),
+            (DType::Int64, false) => Scalar::Int(i64::from_be_bytes(raw) as i128),
+            (DType::Float64, _) => Scalar::Float(float(chunk)),
+            (DType::Complex128, _) => Scalar::Complex(Complex64::new(float(&chunk[..8]), float(&chunk[8..]))),
+        });
+    }
+
+    match header.shape.len() {
+        0 => Ok(Value::Scalar(elements.remove(0))),
+        1 => Ok(Value::Vector(elements)),
+        2 => {
+            let (rows, cols) = (header.shape[0], header.shape[1]);
+            if header.order == Order::Fortran {
+                let mut data = Vec::with_capacity(count);
+                for row in 0..rows {
+                    for col in 0..cols {
+                        data.push(elements[col * rows + row].clone());
+                    }
+                }
+                elements = data;
+            }
+            Ok(Value::Matrix(Matrix::new(rows, cols, elements)))
+        }
+        dims => Err(format!("Arrays with {} dimensions are not supported", dims)),
+    }
+}
+
+/// Encodes a scalar, vector or matrix as a version 1.0 `.npy` file. Arrays holding only
+/// integers are written as int64, arrays with a complex element as complex128
+/// and anything else as float64.
+pub fn encode(value: &Value, order: Order) -> Result<Vec<u8>, String> {
+    let (shape, elements): (Vec<usize>, Vec<Scalar>) = match value {
+        Value::Scalar(val) => (vec![], vec![val.clone()]),
+        Value::Vector(val) => (vec![val.len()], val.clone()),
+        Value::Matrix(matrix) => {
+            let elements = match order {
+                Order::C => matrix.data.clone(),
+                Order::Fortran => {
+                    let mut data = Vec::with_capacity(matrix.data.len());
+                    for col in 0..matrix.cols {
+                        for row in 0..matrix.rows {
+                            data.push(matrix.get(row, col).clone());
+                        }
+                    }
+                    data
+                }
+            };
+            (vec![matrix.rows, matrix.cols], elements)
+        }
+        other => return Err(format!("Cannot save a {} as a NumPy array", other.type_name())),
+    };
+    let dtype = match (elements.iter().all(Scalar::is_integer), elements.iter().any(Scalar::is_complex)) {
+        (true, _) => DType::Int64,
+        (false, true) => DType::Complex128,
+        (false, false) => DType::Float64,
+    };
+
+    let mut data = Vec::with_capacity(elements.len() * dtype.size());
+    for item in elements.iter() {
+        match (dtype, item) {
+            (DType::Int64, Scalar::Int(val)) => {
+                let val = i64::try_from(*val).map_err(|_| format!("Integer {} does not fit in int64", val))?;
+                data.extend_from_slice(&val.to_le_bytes());
+            }
+            (DType::Int64, Scalar::Big(val)) => return Err(format!("Integer {} does not fit in int64", val)),
+            (DType::Complex128, _) => {
+                let val = item.as_complex();
+                data.extend_from_slice(&val.re.to_le_bytes());
+                data.extend_from_slice(&val.im.to_le_bytes());
+            }
+            _ => data.extend_from_slice(&item.as_f64().to_le_bytes()),
+        }
+    }
+
+    let shape = match shape.len() {
+        0 => "()".to_string(),
+        1 => format!("({},)", shape[0]),
+        _ => format!("({}, {})", shape[0], shape[1]),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}",
+        match dtype {
+            DType::Int64 => "<i8",
+            DType::Float64 => "<f8",
+            DType::Complex128 => "<c16",
+        },
+        if order == Order::Fortran && value.type_name() == "matrix" { "True" } else { "False" },
+        shape
+    );
+    // Pad so the data starts on a 64 byte boundary, as NumPy does.
+    while !(MAGIC.len() + 4 + header.len() + 1).is_multiple_of(64) {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 4 + header.len() + data.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&[1, 0]);
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    bytes.extend_from_slice(&data);
+    Ok(bytes)
+}
+
+pub fn read_npy(path: &str) -> Result<Value, String> {
+    let bytes = std::fs::read(path).map_err(|error| format!("Cannot read '{}': {}", path, error))?;
+    decode(&bytes)
+}
+
+pub fn write_npy(path: &str, value: &Value, order: Order) -> Result<(), String> {
+    let bytes = encode(value, order)?;
+    std::fs::write(path, bytes).map_err(|error| format!("Cannot write '{}': {}", path, error))
+}
+
+/// Reads the array stored under `name` in a `.npz` archive.
+pub fn read_npz(path: &str, name: &str) -> Result<Value, String> {
+    let file = File::open(path).map_err(|error| format!("Cannot read '{}': {}", path, error))?;
+    let mut archive = ZipArchive::new(file).map_err(|error| format!("Invalid .npz archive '{}': {}", path, error))?;
+    let mut entry = archive
+        .by_name(&format!("{}.npy", name))
+        .map_err(|_| format!("No array named '{}' in '{}'", name, path))?;
+    let mut bytes = Vec::new();
+    entry.read_to_end(&mut bytes).map_err(|error| format!("Cannot read '{}': {}", path, error))?;
+    decode(&bytes)
+}
+
+/// Writes every named array into an uncompressed `.npz` archive, like `numpy.savez`.
+pub fn write_npz(path: &str, arrays: &[(String, Value)]) -> Result<(), String> {
+    let file = File::create(path).map_err(|error| format!("Cannot write '{}': {}", path, error))?;
+    let mut archive = ZipWriter::new(file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for (name, value) in arrays {
+        let bytes = encode(value, Order::C)?;
+        archive
+            .start_file(format!("{}.npy", name), options)
+            .and_then(|_| archive.write_all(&bytes).map_err(Into::into))
+            .map_err(|error| format!("Cannot write '{}': {}", path, error))?;
+    }
+    archive.finish().map_err(|error| format!("Cannot write '{}': {}", path, error))?;
+    Ok(())
+}
+
+// Parses the Python dict literal NumPy writes as the array header,
+// e.g. {'descr': '<f8', 'fortran_order': False, 'shape': (3, 4), }
+fn parse_header(header: &str) -> Result<Header, String> {
+    let invalid = || format!("Invalid .npy header: {}", header.trim());
+    let body = header.trim().strip_prefix('{').and_then(|body| body.strip_suffix('}')).ok_or_else(invalid)?;
+
+    let mut descr = None;
+    let mut order = Order::C;
+    let mut shape = None;
+    let mut rest = body.trim();
+    while !rest.is_empty() {
+        let quote = rest.chars().next().ok_or_else(invalid)?;
+        if quote != '\'' && quote != '"' {
+            return Err(invalid());
+        }
+        let end = rest[1..].find(quote).ok_or_else(invalid)? + 1;
+        let key = &rest[1..end];
+        rest = rest[end + 1..].trim_start().strip_prefix(':').ok_or_else(invalid)?.trim_start();
+
+        let value_end = if rest.starts_with('(') {
+            rest.find(')').ok_or_else(invalid)? + 1
+        } else {
+            rest.find(',').unwrap_or(rest.len())
+        };
+        let value = rest[..value_end].trim();
+        rest = rest[value_end..].trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+
+        match key {
+            "descr" => descr = Some(value.trim_matches(|ch| ch == '\'' || ch == '"').to_string()),
+            "fortran_order" => {
+                order = match value {
+                    "True" => Order::Fortran,
+                    "False" => Order::C,
+                    _ => return Err(invalid()),
+                }
+            }
+            "shape" => {
+                let dims = value.trim_start_matches('(').trim_end_matches(')');
+                let mut parsed = Vec::new();
+                for dim in dims.split(',').map(str::trim).filter(|dim| !dim.is_empty()) {
+                    parsed.push(dim.parse::<usize>().map_err(|_| invalid())?);
+                }
+                shape = Some(parsed);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    let descr = descr.ok_or_else(invalid)?;
+    let (little_endian, dtype) = match descr.as_str() {
+        "<i8" => (true, DType::Int64),
+        ">i8" => (false, DType::Int64),
+        "<f8" => (true, DType::Float64),
+        ">f8" => (false, DType::Float64),
+        "<c16" => (true, DType::Complex128),
+        ">c16" => (false, DType::Complex128),
+        _ => return Err(format!("Unsupported dtype '{}', expected int64, float64 or complex128", descr)),
+    };
+    Ok(Header {
+        dtype,
+        little_endian,
+        order,
+        shape: shape.ok_or_else(invalid)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, read_npz, write_npz, Order};
+    use crate::core::dodo::value::{Matrix, Scalar, Value};
+    use num_complex::Complex64;
+
+    fn int_matrix() -> Value {
+        let data = (1..=6).map(Scalar::Int).collect();
+        Value::Matrix(Matrix::new(2, 3, data))
+    }
+
+    #[test]
+    fn round_trip_both_orders() {
+        for order in [Order::C, Order::Fortran].iter() {
+            let bytes = encode(&int_matrix(), *order).unwrap();
+            assert_eq!(bytes.len() % 64, 48);
+            assert_eq!(decode(&bytes).unwrap(), int_matrix());
+        }
+        let vector = Value::Vector(vec![Scalar::Int(1), Scalar::Float(2.5)]);
+        let promoted = Value::Vector(vec![Scalar::Float(1.0), Scalar::Float(2.5)]);
+        assert_eq!(decode(&encode(&vector, Order::C).unwrap()).unwrap(), promoted);
+
+        let complex = Value::Vector(vec![Scalar::Complex(Complex64::new(1.0, -2.0)), Scalar::Int(3)]);
+        let bytes = encode(&complex, Order::C).unwrap();
+        assert!(String::from_utf8_lossy(&bytes).contains("'descr': '<c16'"));
+        let expected = Value::Vector(vec![Scalar::Complex(Complex64::new(1.0, -2.0)), Scalar::Complex(Complex64::new(3.0, 0.0))]);
+        assert_eq!(decode(&bytes).unwrap(), expected);
+    }
+
+    #[test]
+    fn decodes_numpy_fortran_header() {
+        // np.save of np.array([[1., 2.], [3., 4.]], order='F'), big-endian
+        let header = "{'descr': '>f8', 'fortran_order': True, 'shape': (2, 2), }";
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        for val in [1.0f64, 3.0, 2.0, 4.0].iter() {
+            bytes.extend_from_slice(&val.to_be_bytes());
+        }
+
+        let expected = Matrix::new(2, 2, vec![1.0, 2.0, 3.0, 4.0].into_iter().map(Scalar::Float).collect());
+        assert_eq!(decode(&bytes).unwrap(), Value::Matrix(expected));
+    }
+
+    #[test]
+    fn rejects_unsupported_dtype_and_overflow() {
+        let header = "{'descr': '<i4', 'fortran_order': False, 'shape': (1,), }";
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+
+        assert!(decode(&bytes).unwrap_err().contains("Unsupported dtype"));
+        assert!(encode(&Value::int(i128::MAX), Order::C).is_err());
+    }
+
+    #[test]
+    fn rejects_shape_too_large() {
+        for shape in ["(4294967296, 4294967297)", "(2305843009213693952,)"].iter() {
+            let header = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': {}, }}", shape);
+            let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+            bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(header.as_bytes());
+            bytes.extend_from_slice(&1.0f64.to_le_bytes());
+
+            assert!(decode(&bytes).unwrap_err().contains("is too large"), "{}", shape);
+        }
+    }
+
+    #[test]
+    fn npz_archive() {
+        let path = std::env::temp_dir().join("dodolang_npy_test.npz");
+        let path = path.to_str().unwrap();
+        let arrays = vec![("a".to_string(), int_matrix()), ("b".to_string(), Value::float(0.5))];
+
+        write_npz(path, &arrays).unwrap();
+        assert_eq!(read_npz(path, "a").unwrap(), int_matrix());
+        assert_eq!(read_npz(path, "b").unwrap(), Value::float(0.5));
+        assert!(read_npz(path, "c").is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
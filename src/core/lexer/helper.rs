@@ -3,5 +3,5 @@ pub fn is_letter(ch: char) -> bool {
 }
 
 pub fn is_digit(ch: char) -> bool {
-    ch.is_digit(10)
+    ch.is_ascii_digit()
 }
\ No newline at end of file
@@ -5,28 +5,34 @@ use crate::core::lexer::helper::is_letter;
 use crate::core::lexer::helper::is_digit;
 
 pub struct Lexer {
-    input: String,
     input_chars: Vec<char>,
     position: usize,
     read_position: usize,
     current_char: char,
+    line: usize,
+    line_start: usize,
 }
 
 impl Lexer {
     pub fn new(input: String) -> Lexer {
         let mut lexer = Lexer {
-            input: input.clone(),
             input_chars: input.chars().collect(),
             position: 0,
             read_position: 0,
             current_char: '\0',
+            line: 1,
+            line_start: 0,
         };
         lexer.read_char();
         lexer
     }
 
     fn read_char(&mut self) {
-        if self.read_position >= self.input.len() {
+        if self.current_char == '\n' {
+            self.line += 1;
+            self.line_start = self.read_position;
+        }
+        if self.read_position >= self.input_chars.len() {
             self.current_char = '\0'
         } else {
             self.current_char = self.input_chars[self.read_position]
@@ -35,38 +41,163 @@ impl Lexer {
         self.read_position += 1;
     }
 
-    fn read_identifier(&mut self) -> &str {
+    fn peek_char(&self) -> char {
+        match self.input_chars.get(self.read_position) {
+            Some(ch) => *ch,
+            None => '\0'
+        }
+    }
+
+    fn slice(&self, start: usize) -> String {
+        self.input_chars[start..self.position].iter().collect()
+    }
+
+    fn read_identifier(&mut self) -> String {
         let position = self.position;
-        while is_letter(self.current_char) {
+        while is_letter(self.current_char) || is_digit(self.current_char) {
             self.read_char();
         }
-        &self.input[position..self.position]
+        self.slice(position)
     }
 
-    fn read_number(&mut self) -> &str {
+    fn read_number(&mut self) -> Token {
         let position = self.position;
+        let mut token_type = TokenType::INT;
         while is_digit(self.current_char) {
             self.read_char()
         }
-        &self.input[position..self.position]
+        if self.current_char == '.' && is_digit(self.peek_char()) {
+            token_type = TokenType::FLOAT;
+            self.read_char();
+            while is_digit(self.current_char) {
+                self.read_char()
+            }
+        }
+        if self.current_char == 'e' || self.current_char == 'E' {
+            let next = self.peek_char();
+            let after_sign = self.input_chars.get(self.read_position + 1).copied().unwrap_or('\0');
+            if is_digit(next) || ((next == '+' || next == '-') && is_digit(after_sign)) {
+                token_type = TokenType::FLOAT;
+                self.read_char();
+                if !is_digit(self.current_char) {
+                    self.read_char();
+                }
+                while is_digit(self.current_char) {
+                    self.read_char()
+                }
+            }
+        }
+        // An `i` suffix makes an imaginary literal, unless it starts an identifier
+        if self.current_char == 'i' && !is_letter(self.peek_char()) && !is_digit(self.peek_char()) {
+            let val = self.slice(position);
+            self.read_char();
+            return Token::new(TokenType::IMAGINARY, val);
+        }
+        Token::new(token_type, self.slice(position))
+    }
+
+    fn read_string(&mut self) -> Token {
+        let mut val = String::new();
+        self.read_char();
+        loop {
+            match self.current_char {
+                '"' => break,
+                '\0' | '\n' => return Token::new(TokenType::ILLEGAL, val),
+                '\\' => {
+                    self.read_char();
+                    match self.current_char {
+                        'n' => val.push('\n'),
+                        't' => val.push('\t'),
+                        '"' => val.push('"'),
+                        '\\' => val.push('\\'),
+                        '\0' => return Token::new(TokenType::ILLEGAL, val),
+                        other => {
+                            val.push('\\');
+                            val.push(other);
+                        }
+                    }
+                }
+                other => val.push(other),
+            }
+            self.read_char();
+        }
+        Token::new(TokenType::STRING, val)
+    }
+
+    fn read_comment(&mut self) -> Token {
+        let position = self.position;
+        while self.current_char != '\n' && self.current_char != '\0' {
+            self.read_char()
+        }
+        Token::new(TokenType::COMMENT, self.slice(position).trim_end().to_string())
     }
 
     fn skip_whitespace(&mut self) {
-        while self.current_char == ' ' {
+        while self.current_char == ' ' || self.current_char == '\t' || self.current_char == '\r' {
             self.read_char()
         }
     }
 
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace();
+        let line = self.line;
+        let column = self.position - self.line_start + 1;
+        let token = self.read_token();
+        Token::at(token.token_type, token.val, line, column)
+    }
+
+    /// Lexes the whole input, up to and including the EOF token.
+    pub fn scan_tokens(&mut self) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token();
+            let done = token.token_type == TokenType::EOF;
+            tokens.push(token);
+            if done {
+                return tokens;
+            }
+        }
+    }
+
+    fn read_token(&mut self) -> Token {
         let token = match self.current_char {
 
             // OPERATOR
+            '=' if self.peek_char() == '=' => {
+                self.read_char();
+                Token::new(TokenType::EqualEqual, "==".to_string())
+            }
             '=' => Token::new(TokenType::ASSIGN, self.current_char.to_string()),
+            '!' if self.peek_char() == '=' => {
+                self.read_char();
+                Token::new(TokenType::BangEqual, "!=".to_string())
+            }
+            '!' => Token::new(TokenType::BANG, self.current_char.to_string()),
+            '<' if self.peek_char() == '=' => {
+                self.read_char();
+                Token::new(TokenType::LessEqual, "<=".to_string())
+            }
+            '<' => Token::new(TokenType::LESS, self.current_char.to_string()),
+            '>' if self.peek_char() == '=' => {
+                self.read_char();
+                Token::new(TokenType::GreaterEqual, ">=".to_string())
+            }
+            '>' => Token::new(TokenType::GREATER, self.current_char.to_string()),
             '+' => Token::new(TokenType::PLUS, self.current_char.to_string()),
             '-' => Token::new(TokenType::MINUS, self.current_char.to_string()),
             '*' => Token::new(TokenType::ASTERISK, self.current_char.to_string()),
+            '/' if self.peek_char() == '/' => {
+                self.read_char();
+                Token::new(TokenType::SlashSlash, "//".to_string())
+            }
             '/' => Token::new(TokenType::SLASH, self.current_char.to_string()),
+            '%' => Token::new(TokenType::PERCENT, self.current_char.to_string()),
+            '^' => Token::new(TokenType::CARET, self.current_char.to_string()),
+            '.' if self.peek_char() == '^' => {
+                self.read_char();
+                Token::new(TokenType::DotCaret, ".^".to_string())
+            }
+            '.' => Token::new(TokenType::DOT, self.current_char.to_string()),
 
             // DELIMITER
             '{' => Token::new(TokenType::LeftBrace, self.current_char.to_string()),
@@ -78,11 +209,14 @@ impl Lexer {
             ':' => Token::new(TokenType::COLON, self.current_char.to_string()),
             ',' => Token::new(TokenType::COMMA, self.current_char.to_string()),
 
+            // LITERAL
+            '"' => self.read_string(),
+
             // NEW LINE
             '\n' => Token::new(TokenType::NewLine, self.current_char.to_string()),
 
             // COMMENT
-            '#' => Token::new(TokenType::COMMENT, self.current_char.to_string()),
+            '#' => return self.read_comment(),
 
             // EOF
             '\0' => Token::new(TokenType::EOF, self.current_char.to_string()),
@@ -90,9 +224,9 @@ impl Lexer {
             _ => {
                 if is_letter(self.current_char) {
                     let val = self.read_identifier();
-                    return Token::new(identifier(val), val.to_string());
+                    return Token::new(identifier(&val), val);
                 } else if is_digit(self.current_char) {
-                    return Token::new(TokenType::INT, self.read_number().to_string());
+                    return self.read_number();
                 } else {
                     Token::new(TokenType::ILLEGAL, self.current_char.to_string())
                 }
@@ -139,6 +273,37 @@ mod tests {
     }
 
 
+    #[test]
+    fn division_operators() {
+        let tokens = Lexer::new("a / b // c % d".to_string()).scan_tokens();
+        let types: Vec<TokenType> = tokens.iter().map(|token| token.token_type).collect();
+
+        assert_eq!(types, vec![TokenType::IDENT, TokenType::SLASH, TokenType::IDENT, TokenType::SlashSlash,
+                               TokenType::IDENT, TokenType::PERCENT, TokenType::IDENT, TokenType::EOF]);
+        assert_eq!((tokens[3].val.as_str(), tokens[4].column), ("//", 10));
+    }
+
+    #[test]
+    fn comparison_operators() {
+        let tokens = Lexer::new("a < b <= c > d >= e == f != !g = h".to_string()).scan_tokens();
+        let operators: Vec<&str> = tokens.iter().filter(|token| token.token_type != TokenType::IDENT).map(|token| token.val.as_str()).collect();
+
+        assert_eq!(operators, vec!["<", "<=", ">", ">=", "==", "!=", "!", "=", "\0"]);
+        assert_eq!((tokens[9].token_type, tokens[12].token_type, tokens[13].token_type), (TokenType::EqualEqual, TokenType::BANG, TokenType::IDENT));
+    }
+
+    #[test]
+    fn imaginary_literals() {
+        let tokens = Lexer::new("1 + 3i * 2.5i - 4if".to_string()).scan_tokens();
+        let summary: Vec<(TokenType, &str)> = tokens.iter().map(|token| (token.token_type, token.val.as_str())).collect();
+
+        assert_eq!(summary, vec![
+            (TokenType::INT, "1"), (TokenType::PLUS, "+"), (TokenType::IMAGINARY, "3"), (TokenType::ASTERISK, "*"),
+            (TokenType::IMAGINARY, "2.5"), (TokenType::MINUS, "-"), (TokenType::INT, "4"), (TokenType::IDENT, "if"),
+            (TokenType::EOF, "\0"),
+        ]);
+    }
+
     #[test]
     fn basic_assignment() {
         let input =
@@ -190,5 +355,54 @@ mod tests {
             assert_eq!(lexed_token, *token);
         }
     }
-}
 
+    #[test]
+    fn literals() {
+        let input = "savenpy(\"a\\\"b.npy\", 1.5, 2e3, 7, x1)";
+
+        let expected = [
+            Token::new(TokenType::IDENT, "savenpy".to_string()),
+            Token::new(TokenType::LeftParenthesis, "(".to_string()),
+            Token::new(TokenType::STRING, "a\"b.npy".to_string()),
+            Token::new(TokenType::COMMA, ",".to_string()),
+            Token::new(TokenType::FLOAT, "1.5".to_string()),
+            Token::new(TokenType::COMMA, ",".to_string()),
+            Token::new(TokenType::FLOAT, "2e3".to_string()),
+            Token::new(TokenType::COMMA, ",".to_string()),
+            Token::new(TokenType::INT, "7".to_string()),
+            Token::new(TokenType::COMMA, ",".to_string()),
+            Token::new(TokenType::IDENT, "x1".to_string()),
+            Token::new(TokenType::RightParenthesis, ")".to_string()),
+            Token::new(TokenType::EOF, "\0".to_string()),
+        ];
+
+        let mut lexer = Lexer::new(input.to_string());
+
+        for token in expected.iter() {
+            assert_eq!(lexer.next_token(), *token);
+        }
+    }
+
+    #[test]
+    fn comments_and_positions() {
+        let input = "x = 1 # one\n  # two\nprint x";
+
+        let tokens = Lexer::new(input.to_string()).scan_tokens();
+        let summary: Vec<(TokenType, &str, usize, usize)> = tokens.iter()
+            .map(|token| (token.token_type, token.val.as_str(), token.line, token.column))
+            .collect();
+
+        assert_eq!(summary, vec![
+            (TokenType::IDENT, "x", 1, 1),
+            (TokenType::ASSIGN, "=", 1, 3),
+            (TokenType::INT, "1", 1, 5),
+            (TokenType::COMMENT, "# one", 1, 7),
+            (TokenType::NewLine, "\n", 1, 12),
+            (TokenType::COMMENT, "# two", 2, 3),
+            (TokenType::NewLine, "\n", 2, 8),
+            (TokenType::PRINT, "print", 3, 1),
+            (TokenType::IDENT, "x", 3, 7),
+            (TokenType::EOF, "\0", 3, 8),
+        ]);
+    }
+}
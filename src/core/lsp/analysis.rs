@@ -0,0 +1,254 @@
+use crate::core::ast::ast::Span;
+use crate::core::ast::parser::Parser;
+use crate::core::ast::stmt::Stmt;
+use crate::core::check::checker::Checker;
+use crate::core::dodo::builtins::{self, BUILTINS};
+use crate::core::format::formatter;
+use crate::core::lexer::lexer::Lexer;
+use crate::core::token::token::{Token, TokenType};
+
+const KEYWORDS: &[&str] = &["scalar", "vector", "matrix", "let", "const", "print", "fn", "return", "while", "loop", "break", "continue", "import", "from", "as", "test"];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SymbolKind {
+    Variable,
+    Function,
+    Module,
+    Parameter,
+    Keyword,
+    Builtin,
+}
+
+/// A declared name. `detail` is the declaration as written, e.g. "matrix A[2, 3]".
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub name: Token,
+    pub kind: SymbolKind,
+    pub detail: String,
+    pub span: Span,
+    // Parameters and locals of a function
+    pub children: Vec<Symbol>,
+}
+
+/// Parse or type error at a 1-based position; column 0 when only the line is known.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn at(token: &Token, message: &str) -> Self {
+        let length = match token.token_type {
+            TokenType::EOF => 0,
+            _ => token.val.chars().count(),
+        };
+        Diagnostic {
+            line: token.line,
+            column: token.column,
+            length,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Completion {
+    pub label: String,
+    pub kind: SymbolKind,
+    pub detail: String,
+}
+
+fn declared(statements: &[Stmt], spans: &[Span]) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for (stmt, span) in statements.iter().zip(spans.iter()) {
+        match stmt {
+            Stmt::Definition(name, declared, dims, _) => symbols.push(Symbol {
+                name: name.clone(),
+                kind: SymbolKind::Variable,
+                detail: formatter::declaration(name, *declared, dims),
+                span: *span,
+                children: Vec::new(),
+            }),
+            Stmt::Function(name, params, body) => {
+                let names: Vec<&str> = params.iter().map(|param| param.val.as_str()).collect();
+                let mut children: Vec<Symbol> = params.iter()
+                    .map(|param| Symbol {
+                        name: param.clone(),
+                        kind: SymbolKind::Parameter,
+                        detail: format!("parameter {} of {}", param.val, name.val),
+                        span: *span,
+                        children: Vec::new(),
+                    })
+                    .collect();
+                children.extend(declared(&body.statements, &body.spans));
+                symbols.push(Symbol {
+                    name: name.clone(),
+                    kind: SymbolKind::Function,
+                    detail: format!("fn {}({})", name.val, names.join(", ")),
+                    span: *span,
+                    children,
+                });
+            }
+            Stmt::Import(file, namespace) => symbols.push(Symbol {
+                name: namespace.clone(),
+                kind: SymbolKind::Module,
+                detail: format!("import \"{}\" as {}", file.val, namespace.val),
+                span: *span,
+                children: Vec::new(),
+            }),
+            Stmt::FromImport(file, names) => symbols.extend(names.iter().map(|name| Symbol {
+                name: name.clone(),
+                kind: SymbolKind::Variable,
+                detail: format!("from \"{}\" import {}", file.val, name.val),
+                span: *span,
+                children: Vec::new(),
+            })),
+            // Loop bodies declare into the scope around them
+            Stmt::While(_, _, body) | Stmt::Loop(_, body) => symbols.extend(declared(&body.statements, &body.spans)),
+            _ => (),
+        }
+    }
+    symbols
+}
+
+/// What the language server knows about one document: its tokens, the
+/// declarations found by the parser and the errors from parsing and checking.
+pub struct Analysis {
+    tokens: Vec<Token>,
+    pub symbols: Vec<Symbol>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    pub fn new(source: &str) -> Self {
+        let tokens = Lexer::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens.clone());
+        let program = parser.parse_program();
+        let mut diagnostics: Vec<Diagnostic> = parser.errors().iter()
+            .map(|error| Diagnostic::at(&error.token, &error.message))
+            .collect();
+        // Type errors in a program that does not parse are mostly noise from the missing statements
+        if diagnostics.is_empty() {
+            diagnostics = Checker::new().check(&program).iter()
+                .map(|error| Diagnostic::at(&error.token, &error.message))
+                .collect();
+        }
+        Analysis {
+            symbols: declared(&program.statements, &program.spans),
+            tokens,
+            diagnostics,
+        }
+    }
+
+    /// Identifier under a 1-based position, including the column just past its end.
+    pub fn identifier_at(&self, line: usize, column: usize) -> Option<&Token> {
+        self.tokens.iter().find(|token| {
+            token.token_type == TokenType::IDENT
+                && token.line == line
+                && token.column <= column
+                && column <= token.column + token.val.chars().count()
+        })
+    }
+
+    // Names visible on a line: inside a function its parameters and locals come before the globals
+    fn visible(&self, line: usize) -> Vec<&Symbol> {
+        let mut visible = Vec::new();
+        let function = self.symbols.iter()
+            .find(|symbol| symbol.kind == SymbolKind::Function && symbol.span.line <= line && line <= symbol.span.end_line);
+        if let Some(function) = function {
+            visible.extend(function.children.iter());
+        }
+        visible.extend(self.symbols.iter());
+        visible
+    }
+
+    pub fn definition(&self, line: usize, column: usize) -> Option<&Symbol> {
+        let token = self.identifier_at(line, column)?;
+        self.visible(token.line).into_iter().find(|symbol| symbol.name.val == token.val)
+    }
+
+    pub fn hover(&self, line: usize, column: usize) -> Option<String> {
+        if let Some(symbol) = self.definition(line, column) {
+            return Some(symbol.detail.clone());
+        }
+        let token = self.identifier_at(line, column)?;
+        builtins::lookup(&token.val).map(|builtin| format!("builtin {} ({} arguments)", builtin.name, builtin.arity()))
+    }
+
+    pub fn completions(&self, line: usize) -> Vec<Completion> {
+        let mut completions: Vec<Completion> = Vec::new();
+        for symbol in self.visible(line) {
+            if !completions.iter().any(|completion| completion.label == symbol.name.val) {
+                completions.push(Completion {
+                    label: symbol.name.val.clone(),
+                    kind: symbol.kind,
+                    detail: symbol.detail.clone(),
+                });
+            }
+        }
+        completions.extend(BUILTINS.iter().map(|builtin| Completion {
+            label: builtin.name.to_string(),
+            kind: SymbolKind::Builtin,
+            detail: format!("builtin ({} arguments)", builtin.arity()),
+        }));
+        completions.extend(builtins::CONSTANTS.iter().map(|(name, val)| Completion {
+            label: name.to_string(),
+            kind: SymbolKind::Builtin,
+            detail: format!("constant {}", val),
+        }));
+        completions.extend(KEYWORDS.iter().map(|keyword| Completion {
+            label: keyword.to_string(),
+            kind: SymbolKind::Keyword,
+            detail: "keyword".to_string(),
+        }));
+        completions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Analysis, SymbolKind};
+
+    const SOURCE: &str = "matrix A[2, 3]\nvector v[3]\nfn scale(v, k) {\n    scalar s\n    return v * k\n}\nprint A * scale(v, 2)\n";
+
+    #[test]
+    fn diagnostics_from_parser_and_checker() {
+        let parse = Analysis::new("print (1\nvector y[2]\n");
+        assert_eq!(parse.diagnostics.len(), 1);
+        assert_eq!((parse.diagnostics[0].line, parse.diagnostics[0].column), (1, 9));
+
+        let types = Analysis::new("vector y[3]\nprint y * {1, 2}\n");
+        assert_eq!(types.diagnostics.len(), 1);
+        assert_eq!((types.diagnostics[0].line, types.diagnostics[0].column, types.diagnostics[0].length), (2, 9, 1));
+        assert_eq!(types.diagnostics[0].message, "Shape mismatch for '*': vector[3] and vector[2]");
+    }
+
+    #[test]
+    fn hover_and_scoped_definitions() {
+        let analysis = Analysis::new(SOURCE);
+
+        assert_eq!(analysis.hover(7, 7).as_deref(), Some("matrix A[2, 3]"));
+        assert_eq!(analysis.hover(7, 11).as_deref(), Some("fn scale(v, k)"));
+        // `v` inside the body is the parameter, outside it the global vector
+        assert_eq!(analysis.definition(5, 12).map(|symbol| symbol.kind), Some(SymbolKind::Parameter));
+        let global = analysis.definition(7, 17).unwrap();
+        assert_eq!((global.name.line, global.name.column, global.detail.as_str()), (2, 8, "vector v[3]"));
+        assert_eq!(analysis.hover(1, 1), None);
+        assert!(Analysis::new("savenpy(\"a.npy\", 1)\n").hover(1, 3).unwrap().contains("2 to 3"));
+    }
+
+    #[test]
+    fn symbols_and_completions() {
+        let analysis = Analysis::new(SOURCE);
+        let names: Vec<&str> = analysis.symbols.iter().map(|symbol| symbol.name.val.as_str()).collect();
+        assert_eq!(names, vec!["A", "v", "scale"]);
+        assert_eq!(analysis.symbols[2].children.len(), 3);
+
+        let inside: Vec<String> = analysis.completions(5).into_iter().map(|completion| completion.label).collect();
+        assert!(inside.contains(&"s".to_string()) && inside.contains(&"loadnpy".to_string()) && inside.contains(&"fn".to_string()));
+        assert!(!analysis.completions(7).iter().any(|completion| completion.label == "s"));
+    }
+}
@@ -0,0 +1,346 @@
+use crate::core::lsp::analysis::{Analysis, Diagnostic, Symbol, SymbolKind};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+// LSP enumerations
+const SYNC_FULL: u32 = 1;
+const SEVERITY_ERROR: u32 = 1;
+const SYMBOL_MODULE: u32 = 2;
+const SYMBOL_FUNCTION: u32 = 12;
+const SYMBOL_VARIABLE: u32 = 13;
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_VARIABLE: u32 = 6;
+const COMPLETION_MODULE: u32 = 9;
+const COMPLETION_KEYWORD: u32 = 14;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const PARSE_ERROR: i64 = -32700;
+// Largest message body accepted; the length comes from the client, so it is checked before reading
+const MAX_MESSAGE: usize = 64 << 20;
+
+/// Reads one `Content-Length` framed JSON-RPC message; `None` once the input is closed.
+/// A missing, malformed or oversized length is an error, as the stream cannot be followed after it.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Result<Value, serde_json::Error>>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                let value = value.trim();
+                length = Some(value.parse::<usize>().map_err(|_| invalid(format!("invalid Content-Length '{}'", value)))?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| invalid("missing Content-Length header".to_string()))?;
+    if length > MAX_MESSAGE {
+        return Err(invalid(format!("Content-Length {} is over the {} byte limit", length, MAX_MESSAGE)));
+    }
+    let mut body = Vec::new();
+    if input.take(length as u64).read_to_end(&mut body)? < length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "message body ends before its Content-Length"));
+    }
+    Ok(Some(serde_json::from_slice(&body)))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+// LSP positions are 0-based; tokens count lines and columns from 1
+fn position(line: usize, column: usize) -> Value {
+    json!({ "line": line.saturating_sub(1), "character": column.saturating_sub(1) })
+}
+
+fn diagnostic_range(diagnostic: &Diagnostic) -> Value {
+    match diagnostic.column {
+        0 => json!({ "start": position(diagnostic.line, 1), "end": position(diagnostic.line + 1, 1) }),
+        column => json!({ "start": position(diagnostic.line, column), "end": position(diagnostic.line, column + diagnostic.length) }),
+    }
+}
+
+fn name_range(symbol: &Symbol) -> Value {
+    let token = &symbol.name;
+    json!({ "start": position(token.line, token.column), "end": position(token.line, token.column + token.val.chars().count()) })
+}
+
+fn document_symbol(symbol: &Symbol) -> Value {
+    let kind = match symbol.kind {
+        SymbolKind::Function => SYMBOL_FUNCTION,
+        SymbolKind::Module => SYMBOL_MODULE,
+        _ => SYMBOL_VARIABLE,
+    };
+    let mut value = json!({
+        "name": symbol.name.val,
+        "detail": symbol.detail,
+        "kind": kind,
+        "range": { "start": position(symbol.span.line, 1), "end": position(symbol.span.end_line + 1, 1) },
+        "selectionRange": name_range(symbol),
+    });
+    if !symbol.children.is_empty() {
+        value["children"] = symbol.children.iter().map(document_symbol).collect();
+    }
+    value
+}
+
+/// Language server state: the analysis of every open document, keyed by URI.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Analysis>,
+    shutdown: bool,
+    exit: bool,
+}
+
+impl Server {
+    fn reply(id: Value, result: Value) -> Vec<Value> {
+        vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })]
+    }
+
+    fn error(id: Value, code: i64, message: &str) -> Vec<Value> {
+        vec![json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })]
+    }
+
+    fn publish(&self, uri: &str) -> Vec<Value> {
+        let diagnostics: Vec<Value> = self.documents.get(uri).map_or(Vec::new(), |analysis| {
+            analysis.diagnostics.iter()
+                .map(|diagnostic| json!({
+                    "range": diagnostic_range(diagnostic),
+                    "severity": SEVERITY_ERROR,
+                    "source": "dodolang",
+                    "message": diagnostic.message,
+                }))
+                .collect()
+        });
+        vec![json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })]
+    }
+
+    // Document and 1-based cursor position of a text document position request
+    fn target<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a Analysis, usize, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let line = params["position"]["line"].as_u64()? as usize + 1;
+        let column = params["position"]["character"].as_u64()? as usize + 1;
+        Some((uri, self.documents.get(uri)?, line, column))
+    }
+
+    /// Handles one incoming message and returns the responses and notifications to send.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let id = message.get("id").cloned();
+        let params = &message["params"];
+        let method = message["method"].as_str().unwrap_or_default();
+        // After `shutdown` only `exit` is served; other requests fail and notifications are dropped
+        if self.shutdown && method != "exit" {
+            return match id {
+                Some(id) => Server::error(id, INVALID_REQUEST, "The server is shut down"),
+                None => Vec::new(),
+            };
+        }
+        match method {
+            "initialize" => Server::reply(id.unwrap_or_default(), json!({
+                "capabilities": {
+                    "textDocumentSync": SYNC_FULL,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": { "name": "dodolang", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Server::reply(id.unwrap_or_default(), Value::Null)
+            }
+            "exit" => {
+                self.exit = true;
+                Vec::new()
+            }
+            "textDocument/didOpen" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_string(), Analysis::new(text));
+                self.publish(uri)
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                // Full sync: the last change holds the whole document
+                match params["contentChanges"].as_array().and_then(|changes| changes.last()) {
+                    Some(change) => {
+                        let text = change["text"].as_str().unwrap_or_default();
+                        self.documents.insert(uri.to_string(), Analysis::new(text));
+                        self.publish(uri)
+                    }
+                    None => Vec::new(),
+                }
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                self.documents.remove(uri);
+                self.publish(uri)
+            }
+            "textDocument/hover" => {
+                let hover = self.target(params)
+                    .and_then(|(_, analysis, line, column)| analysis.hover(line, column))
+                    .map_or(Value::Null, |text| json!({ "contents": { "kind": "markdown", "value": format!("```dodo\n{}\n```", text) } }));
+                Server::reply(id.unwrap_or_default(), hover)
+            }
+            "textDocument/definition" => {
+                let location = self.target(params)
+                    .and_then(|(uri, analysis, line, column)| analysis.definition(line, column).map(|symbol| (uri, symbol)))
+                    .map_or(Value::Null, |(uri, symbol)| json!({ "uri": uri, "range": name_range(symbol) }));
+                Server::reply(id.unwrap_or_default(), location)
+            }
+            "textDocument/documentSymbol" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let symbols: Vec<Value> = self.documents.get(uri)
+                    .map_or(Vec::new(), |analysis| analysis.symbols.iter().map(document_symbol).collect());
+                Server::reply(id.unwrap_or_default(), Value::from(symbols))
+            }
+            "textDocument/completion" => {
+                let items: Vec<Value> = self.target(params).map_or(Vec::new(), |(_, analysis, line, _)| {
+                    analysis.completions(line).into_iter()
+                        .map(|completion| {
+                            let kind = match completion.kind {
+                                SymbolKind::Function | SymbolKind::Builtin => COMPLETION_FUNCTION,
+                                SymbolKind::Keyword => COMPLETION_KEYWORD,
+                                SymbolKind::Module => COMPLETION_MODULE,
+                                _ => COMPLETION_VARIABLE,
+                            };
+                            json!({ "label": completion.label, "kind": kind, "detail": completion.detail })
+                        })
+                        .collect()
+                });
+                Server::reply(id.unwrap_or_default(), Value::from(items))
+            }
+            _ => match id {
+                Some(id) => Server::error(id, METHOD_NOT_FOUND, &format!("Unknown method '{}'", method)),
+                None => Vec::new(),
+            },
+        }
+    }
+}
+
+/// Serves requests until the client sends `exit`. Returns 0 after a clean
+/// shutdown and 1 otherwise, as the protocol asks.
+pub fn serve<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<i32> {
+    let mut server = Server::default();
+    while let Some(message) = read_message(&mut input)? {
+        let replies = match message {
+            Ok(message) => server.handle(&message),
+            Err(error) => Server::error(Value::Null, PARSE_ERROR, &error.to_string()),
+        };
+        for reply in replies.iter() {
+            write_message(&mut output, reply)?;
+        }
+        if server.exit {
+            return Ok(if server.shutdown { 0 } else { 1 });
+        }
+    }
+    Ok(1)
+}
+
+/// `dodolang lsp`: runs the language server over stdin and stdout.
+pub fn command() -> i32 {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    match serve(stdin.lock(), stdout.lock()) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("lsp: {}", error);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_message, serve, write_message};
+    use serde_json::{json, Value};
+    use std::io::{Cursor, ErrorKind};
+
+    fn session(messages: &[Value]) -> (i32, Vec<Value>) {
+        let mut input = Vec::new();
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        let code = serve(Cursor::new(input), &mut output).unwrap();
+        let mut replies = Vec::new();
+        let mut output = Cursor::new(output);
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply.unwrap());
+        }
+        (code, replies)
+    }
+
+    #[test]
+    fn full_session() {
+        let uri = "file:///a.dodo";
+        let document = json!({ "uri": uri });
+        let (code, replies) = session(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": { "uri": uri, "text": "vector v[2]\nprint v * {1, 2, 3}\n" } } }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": { "textDocument": document, "position": { "line": 1, "character": 6 } } }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "textDocument/definition", "params": { "textDocument": document, "position": { "line": 1, "character": 6 } } }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "textDocument/documentSymbol", "params": { "textDocument": document } }),
+            json!({ "jsonrpc": "2.0", "id": 5, "method": "nope" }),
+            json!({ "jsonrpc": "2.0", "id": 6, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+
+        assert_eq!(code, 0);
+        assert_eq!(replies.len(), 7);
+        assert_eq!(replies[0]["result"]["capabilities"]["hoverProvider"], json!(true));
+        let diagnostic = &replies[1]["params"]["diagnostics"][0];
+        assert_eq!(diagnostic["range"]["start"], json!({ "line": 1, "character": 8 }));
+        assert_eq!(diagnostic["message"], json!("Shape mismatch for '*': vector[2] and vector[3]"));
+        assert_eq!(replies[2]["result"]["contents"]["value"], json!("```dodo\nvector v[2]\n```"));
+        assert_eq!(replies[3]["result"]["range"]["start"], json!({ "line": 0, "character": 7 }));
+        assert_eq!(replies[4]["result"][0]["name"], json!("v"));
+        assert_eq!(replies[5]["error"]["code"], json!(-32601));
+        assert_eq!(replies[6]["result"], Value::Null);
+    }
+
+    #[test]
+    fn requests_after_shutdown_fail() {
+        let (code, replies) = session(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/documentSymbol", "params": { "textDocument": { "uri": "file:///a.dodo" } } }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": { "uri": "file:///a.dodo", "text": "print x\n" } } }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+
+        assert_eq!(code, 0);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[1]["id"], json!(2));
+        assert_eq!(replies[1]["error"]["code"], json!(-32600));
+    }
+
+    #[test]
+    fn malformed_content_length() {
+        let read = |input: &str| read_message(&mut Cursor::new(input.as_bytes().to_vec()));
+
+        let error = read("Content-Length: 18446744073709551615\r\n\r\n{}").unwrap_err();
+        assert_eq!((error.kind(), error.to_string().as_str()), (ErrorKind::InvalidData, "Content-Length 18446744073709551615 is over the 67108864 byte limit"));
+        assert_eq!(read("Content-Length: 99999999999999999999\r\n\r\n{}").unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read("Content-Length: -1\r\n\r\n{}").unwrap_err().to_string(), "invalid Content-Length '-1'");
+        assert_eq!(read("Content-Type: json\r\n\r\n{}").unwrap_err().to_string(), "missing Content-Length header");
+        assert_eq!(read("Content-Length: 1000\r\n\r\n{}").unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(read("Content-Length: 2\r\n\r\n{}").unwrap().unwrap().unwrap(), json!({}));
+
+        // The server stops rather than guess where the next message starts
+        assert!(serve(Cursor::new(b"Content-Length: x\r\n\r\n".to_vec()), Vec::new()).is_err());
+    }
+}
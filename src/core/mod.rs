@@ -1,4 +1,12 @@
 pub mod lexer;
 pub mod token;
 pub mod ast;
-pub mod dodo;
\ No newline at end of file
+pub mod dodo;
+pub mod io;
+pub mod format;
+pub mod check;
+pub mod lsp;
+pub mod debug;
+pub mod profile;
+pub mod testing;
+pub mod fuzz;
@@ -0,0 +1,228 @@
+use crate::core::ast::ast::Span;
+use crate::core::ast::stmt::Stmt;
+use crate::core::dodo::environment::Environment;
+use crate::core::dodo::error_types::DodoRuntimeError;
+use crate::core::dodo::interpreter::{Hook, Interpreter};
+use crate::core::dodo::runner::{compile, EX_DATAERR, EX_NOINPUT, EX_SOFTWARE};
+use crate::core::dodo::value::Scalar;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Time is inclusive of nested statements and calls; `own` leaves them out.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LineStats {
+    pub hits: usize,
+    pub total: Duration,
+    pub own: Duration,
+    pub allocations: usize,
+    pub elements: usize,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FunctionStats {
+    pub calls: usize,
+    pub total: Duration,
+    pub own: Duration,
+}
+
+// A statement or call still running, with the time spent in what it started so far
+struct Running {
+    start: Instant,
+    children: Duration,
+}
+
+impl Running {
+    fn new() -> Self {
+        Running {
+            start: Instant::now(),
+            children: Duration::ZERO,
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Records where a script spends its time, line by line and per function.
+#[derive(Default)]
+pub struct Profiler {
+    pub lines: BTreeMap<usize, LineStats>,
+    pub functions: BTreeMap<String, FunctionStats>,
+    // Own time in microseconds per call stack, e.g. "<script>:6;scale:4"
+    pub stacks: BTreeMap<String, u128>,
+    statements: Vec<(usize, Running)>,
+    calls: Vec<Running>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /// Per-function and per-line table; `source` supplies the text of each line.
+    pub fn summary(&self, source: &str, total: Duration) -> String {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut out = String::new();
+        writeln!(out, "Total time: {:.3} ms", millis(total)).unwrap();
+
+        if !self.functions.is_empty() {
+            let mut functions: Vec<(&String, &FunctionStats)> = self.functions.iter().collect();
+            functions.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.total));
+            writeln!(out, "\n{:>8} {:>12} {:>12}  function", "calls", "total ms", "own ms").unwrap();
+            for (name, stats) in functions {
+                writeln!(out, "{:>8} {:>12.3} {:>12.3}  {}", stats.calls, millis(stats.total), millis(stats.own), name).unwrap();
+            }
+        }
+
+        writeln!(out, "\n{:>6} {:>8} {:>12} {:>12} {:>8} {:>12}  source", "line", "hits", "total ms", "own ms", "allocs", "bytes").unwrap();
+        for (line, stats) in self.lines.iter() {
+            let text = lines.get(line - 1).map_or("", |text| text.trim());
+            let bytes = stats.elements * mem::size_of::<Scalar>();
+            writeln!(out, "{:>6} {:>8} {:>12.3} {:>12.3} {:>8} {:>12}  {}",
+                     line, stats.hits, millis(stats.total), millis(stats.own), stats.allocations, bytes, text).unwrap();
+        }
+        out
+    }
+
+    /// Collapsed stacks ("frame;frame microseconds" per line) as read by flamegraph tools.
+    pub fn collapsed(&self) -> String {
+        self.stacks.iter().map(|(stack, micros)| format!("{} {}\n", stack, micros)).collect()
+    }
+}
+
+impl Hook for Profiler {
+    fn before_statement(&mut self, _interpreter: &mut Interpreter, _stmt: &Stmt, span: Span) -> Result<(), DodoRuntimeError> {
+        self.lines.entry(span.line).or_default().hits += 1;
+        self.statements.push((span.line, Running::new()));
+        Ok(())
+    }
+
+    fn after_statement(&mut self, interpreter: &Interpreter, span: Span) {
+        let running = match self.statements.pop() {
+            Some((_, running)) => running,
+            None => return,
+        };
+        let elapsed = running.start.elapsed();
+        let own = elapsed.saturating_sub(running.children);
+        let stats = self.lines.entry(span.line).or_default();
+        stats.total += elapsed;
+        stats.own += own;
+        if let Some((_, parent)) = self.statements.last_mut() {
+            parent.children += elapsed;
+        }
+        let stack: Vec<String> = interpreter.call_stack().iter()
+            .map(|frame| format!("{}:{}", frame.name, frame.line))
+            .collect();
+        *self.stacks.entry(stack.join(";")).or_default() += own.as_micros();
+    }
+
+    fn enter_call(&mut self, name: &str) {
+        self.functions.entry(name.to_string()).or_default().calls += 1;
+        self.calls.push(Running::new());
+    }
+
+    fn exit_call(&mut self, name: &str) {
+        let running = match self.calls.pop() {
+            Some(running) => running,
+            None => return,
+        };
+        let elapsed = running.start.elapsed();
+        let stats = self.functions.entry(name.to_string()).or_default();
+        stats.total += elapsed;
+        stats.own += elapsed.saturating_sub(running.children);
+        if let Some(parent) = self.calls.last_mut() {
+            parent.children += elapsed;
+        }
+    }
+
+    fn allocated(&mut self, elements: usize) {
+        if let Some((line, _)) = self.statements.last() {
+            let stats = self.lines.entry(*line).or_default();
+            stats.allocations += 1;
+            stats.elements += elements;
+        }
+    }
+}
+
+/// `dodolang --profile <file>`: runs a script, then prints a profile to stderr
+/// and writes collapsed stacks next to the script with a `.folded` extension.
+pub fn command(path: &str, search_path: &[PathBuf]) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            return EX_NOINPUT;
+        }
+    };
+    let program = match compile(&source, path, search_path) {
+        Some(program) => program,
+        None => return EX_DATAERR,
+    };
+
+    let profiler = Rc::new(RefCell::new(Profiler::new()));
+    let mut interpreter = Interpreter::new(Environment::new());
+    interpreter.set_hook(Box::new(profiler.clone()));
+    let start = Instant::now();
+    let result = interpreter.interpret(&program);
+    let total = start.elapsed();
+
+    let code = match result {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("{}", error);
+            EX_SOFTWARE
+        }
+    };
+    let profiler = profiler.borrow();
+    eprint!("{}", profiler.summary(&source, total));
+    let folded = Path::new(path).with_extension("folded");
+    match fs::write(&folded, profiler.collapsed()) {
+        Ok(()) => eprintln!("\nCollapsed stacks written to {}", folded.display()),
+        Err(error) => eprintln!("{}: {}", folded.display(), error),
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profiler;
+    use crate::core::ast::parser::Parser;
+    use crate::core::dodo::environment::Environment;
+    use crate::core::dodo::interpreter::Interpreter;
+    use crate::core::lexer::lexer::Lexer;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[test]
+    fn counts_lines_calls_and_allocations() {
+        let source = "matrix A[2, 3]\nfn twice(v) {\n    return v * 2\n}\nA = twice(A)\nA = twice(twice(A))\n";
+        let program = Parser::new(Lexer::new(source.to_string()).scan_tokens()).parse_program();
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        let mut interpreter = Interpreter::new(Environment::new());
+        interpreter.set_hook(Box::new(profiler.clone()));
+        interpreter.interpret(&program).unwrap();
+
+        let profiler = profiler.borrow();
+        let hits: Vec<(usize, usize)> = profiler.lines.iter().map(|(line, stats)| (*line, stats.hits)).collect();
+        assert_eq!(hits, vec![(1, 1), (2, 1), (3, 3), (5, 1), (6, 1)]);
+        // The declaration, then one 2x3 temporary per `v * 2`; calls hand back the same value
+        assert_eq!((profiler.lines[&1].allocations, profiler.lines[&1].elements), (1, 6));
+        assert_eq!((profiler.lines[&3].allocations, profiler.lines[&3].elements), (3, 18));
+        assert_eq!(profiler.lines[&6].allocations, 0);
+        assert_eq!(profiler.functions["twice"].calls, 3);
+        assert!(profiler.lines[&6].total >= profiler.lines[&6].own);
+
+        let stacks: Vec<&String> = profiler.stacks.keys().collect();
+        assert_eq!(stacks, vec!["<script>:1", "<script>:2", "<script>:5", "<script>:5;twice:3", "<script>:6", "<script>:6;twice:3"]);
+        assert!(profiler.collapsed().lines().all(|line| line.rsplit(' ').next().unwrap().parse::<u128>().is_ok()));
+        assert!(profiler.summary(source, Duration::from_millis(2)).contains("Total time: 2.000 ms"));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::core::ast::ast::Program;
+use crate::core::ast::stmt::{Block, Stmt};
+use crate::core::dodo::builtins::ASSERTION_FAILED;
+use crate::core::dodo::environment::Environment;
+use crate::core::dodo::interpreter::Interpreter;
+use crate::core::dodo::runner::compile_errors;
+use crate::core::format::formatter::collect_files;
+use crate::core::testing::junit;
+use crate::core::token::token::Token;
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// Name reported for a file that does not compile, in place of its tests
+const COMPILE: &str = "<compile>";
+
+// Collects what a test prints so it can be shown when the test fails
+#[derive(Clone, Default)]
+struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    // An assertion did not hold
+    Failed(String),
+    // Anything else went wrong: a runtime error, or the file did not compile
+    Error(String),
+}
+
+pub struct TestResult {
+    pub name: String,
+    pub outcome: Outcome,
+    pub output: String,
+    pub time: Duration,
+}
+
+/// The results of the tests in one file.
+pub struct Suite {
+    pub path: String,
+    pub tests: Vec<TestResult>,
+}
+
+impl Suite {
+    pub fn failures(&self) -> usize {
+        self.tests.iter().filter(|test| matches!(test.outcome, Outcome::Failed(_))).count()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.tests.iter().filter(|test| matches!(test.outcome, Outcome::Error(_))).count()
+    }
+
+    pub fn time(&self) -> Duration {
+        self.tests.iter().map(|test| test.time).sum()
+    }
+}
+
+// Runs the program in a fresh interpreter and then the one test block, so tests cannot affect each other
+fn run_test(program: &Program, name: &Token, body: &Block) -> TestResult {
+    let captured = Captured::default();
+    let mut interpreter = Interpreter::new(Environment::new());
+    interpreter.set_output(Box::new(captured.clone()));
+    let start = Instant::now();
+    let result = interpreter.interpret(program).and_then(|_| interpreter.run_test(name, body));
+    let time = start.elapsed();
+    let outcome = match result {
+        Ok(()) => Outcome::Passed,
+        Err(error) if error.limit.is_none() && error.message.starts_with(ASSERTION_FAILED) => Outcome::Failed(error.to_string()),
+        Err(error) => Outcome::Error(error.to_string()),
+    };
+    let output = String::from_utf8_lossy(&captured.0.borrow()).into_owned();
+    TestResult { name: name.val.clone(), outcome, output, time }
+}
+
+/// Runs every test block in a script read from `path`. A file that does not compile
+/// gives a single error in place of its tests.
+pub fn run_tests(source: &str, path: &str, search_path: &[PathBuf]) -> Vec<TestResult> {
+    let program = match compile_errors(source, path, search_path, false) {
+        Ok(program) => program,
+        Err(errors) => return vec![TestResult {
+            name: COMPILE.to_string(),
+            outcome: Outcome::Error(errors.join("\n")),
+            output: String::new(),
+            time: Duration::ZERO,
+        }],
+    };
+    program.statements.iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Test(name, body) => Some(run_test(&program, name, body)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `dodolang test [--junit <report.xml>] <file|dir>...`: runs the test blocks of each
+/// script, printing a line per test and a summary, and optionally writing a JUnit report.
+pub fn command(args: &[String], search_path: &[PathBuf]) -> i32 {
+    let usage = || {
+        eprintln!("Usage: dodolang test [--junit <report.xml>] <file|dir>...");
+        2
+    };
+    let mut junit_path = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--junit" => match args.next() {
+                Some(path) => junit_path = Some(path),
+                None => return usage(),
+            },
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        return usage();
+    }
+
+    let mut status = 0;
+    let mut suites = Vec::new();
+    for path in paths {
+        let mut files = Vec::new();
+        if let Err(error) = collect_files(Path::new(path), &mut files) {
+            eprintln!("{}: {}", path, error);
+            status = 1;
+        }
+        for file in files {
+            let tests = match fs::read_to_string(&file) {
+                Ok(source) => run_tests(&source, &file, search_path),
+                Err(error) => {
+                    eprintln!("{}: {}", file, error);
+                    status = 1;
+                    continue;
+                }
+            };
+            for test in tests.iter() {
+                let verdict = match test.outcome {
+                    Outcome::Passed => "ok",
+                    Outcome::Failed(_) => "FAILED",
+                    Outcome::Error(_) => "ERROR",
+                };
+                println!("test {}: {} ... {}", file, test.name, verdict);
+            }
+            if !tests.is_empty() {
+                suites.push(Suite { path: file, tests });
+            }
+        }
+    }
+
+    for suite in suites.iter() {
+        for test in suite.tests.iter() {
+            let message = match &test.outcome {
+                Outcome::Passed => continue,
+                Outcome::Failed(message) | Outcome::Error(message) => message,
+            };
+            println!("\n---- {}: {} ----", suite.path, test.name);
+            print!("{}", test.output);
+            println!("{}", message);
+        }
+    }
+    let total: usize = suites.iter().map(|suite| suite.tests.len()).sum();
+    let failed: usize = suites.iter().map(Suite::failures).sum();
+    let errors: usize = suites.iter().map(Suite::errors).sum();
+    let verdict = if failed + errors == 0 { "ok" } else { "FAILED" };
+    println!("\ntest result: {}. {} passed; {} failed; {} errors", verdict, total - failed - errors, failed, errors);
+    if failed + errors > 0 {
+        status = 1;
+    }
+
+    if let Some(junit_path) = junit_path {
+        if let Err(error) = fs::write(junit_path, junit::report(&suites)) {
+            eprintln!("{}: {}", junit_path, error);
+            status = 1;
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_tests, Outcome};
+
+    #[test]
+    fn tests_run_separately_after_the_program() {
+        let source = "scalar runs = 0\nfn double(x) {\n    return x * 2\n}\nprint \"setup\"\n\ntest \"doubles\" {\n    runs = runs + 1\n    assert_eq(double(2), 4)\n    assert_eq(runs, 1)\n    assert_close({0.1 + 0.2, 1}, {0.3, 1}, 1e-12)\n}\n\ntest \"fails\" {\n    runs = runs + 1\n    assert(runs - 1, \"runs is shared\")\n}\n\ntest \"errors\" {\n    print 1 / 0\n}\n";
+        let results = run_tests(source, "example.dodo", &[]);
+
+        let outcomes: Vec<(&str, &Outcome)> = results.iter().map(|test| (test.name.as_str(), &test.outcome)).collect();
+        assert_eq!(outcomes, vec![
+            ("doubles", &Outcome::Passed),
+            ("fails", &Outcome::Failed("[line 16] Runtime error: Assertion failed: runs is shared".to_string())),
+            ("errors", &Outcome::Error("[line 20] Runtime error: Division by zero".to_string())),
+        ]);
+        assert_eq!(results[0].output, "setup\n");
+    }
+}
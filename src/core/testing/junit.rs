@@ -0,0 +1,108 @@
+use crate::core::testing::harness::{Outcome, Suite};
+use std::fmt::Write;
+use std::time::Duration;
+
+// Escapes text for an XML attribute or element, dropping the control characters XML cannot hold
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn seconds(time: Duration) -> String {
+    format!("{:.6}", time.as_secs_f64())
+}
+
+/// A JUnit XML report with a `testsuite` per file. Failed assertions are `failure`s and
+/// any other error an `error`, each with its first line as the message.
+pub fn report(suites: &[Suite]) -> String {
+    let tests: usize = suites.iter().map(|suite| suite.tests.len()).sum();
+    let failures: usize = suites.iter().map(Suite::failures).sum();
+    let errors: usize = suites.iter().map(Suite::errors).sum();
+    let time: Duration = suites.iter().map(Suite::time).sum();
+
+    let mut out = String::new();
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+    writeln!(out, "<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{}\">", tests, failures, errors, seconds(time)).unwrap();
+    for suite in suites {
+        let path = escape(&suite.path);
+        writeln!(out, "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{}\">",
+            path, suite.tests.len(), suite.failures(), suite.errors(), seconds(suite.time())).unwrap();
+        for test in suite.tests.iter() {
+            write!(out, "    <testcase name=\"{}\" classname=\"{}\" time=\"{}\"", escape(&test.name), path, seconds(test.time)).unwrap();
+            let (tag, message) = match &test.outcome {
+                Outcome::Passed if test.output.is_empty() => {
+                    writeln!(out, "/>").unwrap();
+                    continue;
+                }
+                Outcome::Passed => (None, ""),
+                Outcome::Failed(message) => (Some("failure"), message.as_str()),
+                Outcome::Error(message) => (Some("error"), message.as_str()),
+            };
+            writeln!(out, ">").unwrap();
+            if let Some(tag) = tag {
+                let first = message.lines().next().unwrap_or_default();
+                writeln!(out, "      <{} message=\"{}\">{}</{}>", tag, escape(first), escape(message), tag).unwrap();
+            }
+            if !test.output.is_empty() {
+                writeln!(out, "      <system-out>{}</system-out>", escape(&test.output)).unwrap();
+            }
+            writeln!(out, "    </testcase>").unwrap();
+        }
+        writeln!(out, "  </testsuite>").unwrap();
+    }
+    writeln!(out, "</testsuites>").unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::report;
+    use crate::core::testing::harness::{Outcome, Suite, TestResult};
+    use std::time::Duration;
+
+    fn test(name: &str, outcome: Outcome, output: &str) -> TestResult {
+        TestResult { name: name.to_string(), outcome, output: output.to_string(), time: Duration::from_millis(2) }
+    }
+
+    #[test]
+    fn failures_errors_and_escaping() {
+        let suites = vec![Suite {
+            path: "tests/a&b.dodo".to_string(),
+            tests: vec![
+                test("passes", Outcome::Passed, ""),
+                test("<fails>", Outcome::Failed("[line 3] Runtime error: Assertion failed: 1 != 2".to_string()), "x \"quoted\"\u{1}\n"),
+                test("errors", Outcome::Error("first\nsecond".to_string()), ""),
+            ],
+        }];
+
+        assert_eq!(report(&suites), "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<testsuites tests=\"3\" failures=\"1\" errors=\"1\" time=\"0.006000\">
+  <testsuite name=\"tests/a&amp;b.dodo\" tests=\"3\" failures=\"1\" errors=\"1\" time=\"0.006000\">
+    <testcase name=\"passes\" classname=\"tests/a&amp;b.dodo\" time=\"0.002000\"/>
+    <testcase name=\"&lt;fails&gt;\" classname=\"tests/a&amp;b.dodo\" time=\"0.002000\">
+      <failure message=\"[line 3] Runtime error: Assertion failed: 1 != 2\">[line 3] Runtime error: Assertion failed: 1 != 2</failure>
+      <system-out>x &quot;quoted&quot;
+</system-out>
+    </testcase>
+    <testcase name=\"errors\" classname=\"tests/a&amp;b.dodo\" time=\"0.002000\">
+      <error message=\"first\">first
+second</error>
+    </testcase>
+  </testsuite>
+</testsuites>
+");
+    }
+}
@@ -1,10 +1,15 @@
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
+// Tokens compare by type and text only; line and column are 1-based source
+// positions (0 for tokens synthesised outside the lexer)
 #[allow(non_camel_case_types)]
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub val: String,
+    pub line: usize,
+    pub column: usize,
 }
 
 impl Token {
@@ -12,8 +17,34 @@ impl Token {
         Token {
             token_type,
             val,
+            line: 0,
+            column: 0,
         }
     }
+
+    pub fn at(token_type: TokenType, val: String, line: usize, column: usize) -> Self {
+        Token {
+            token_type,
+            val,
+            line,
+            column,
+        }
+    }
+}
+
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.token_type == other.token_type && self.val == other.val
+    }
+}
+
+impl Eq for Token {}
+
+impl Hash for Token {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.token_type.hash(state);
+        self.val.hash(state);
+    }
 }
 
 #[allow(non_camel_case_types)]
@@ -30,14 +61,21 @@ pub enum TokenType {
     IDENT,
     STRING,
     INT,
+    FLOAT,
+    // Number with an `i` suffix; the value holds the number without it
+    IMAGINARY,
 
     // OPERATOR
 
 
     GREATER,
     // >
+    GreaterEqual,
+    // >=
     LESS,
     // <
+    LessEqual,
+    // <=
     BangEqual,
     // !=
     EqualEqual,
@@ -46,6 +84,14 @@ pub enum TokenType {
     // =
     SLASH,
     // /
+    SlashSlash,
+    // //
+    PERCENT,
+    // %
+    CARET,
+    // ^
+    DotCaret,
+    // .^
     ASTERISK,
     // *
     PLUS,
@@ -71,6 +117,8 @@ pub enum TokenType {
     // :
     COMMA,
     // ,
+    DOT,
+    // .
     BANG,
     // !
 
@@ -82,10 +130,34 @@ pub enum TokenType {
     // Vector variable declaration
     MATRIX,
     // Matrix variable declaration
+    LET,
+    // Immutable binding with the type of its value
+    CONST,
+    // Constant known before the program runs
     FOR,
     // For loop statement
+    WHILE,
+    // Loop while a condition holds
+    LOOP,
+    // Loop until a break
+    BREAK,
+    // Leave a loop
+    CONTINUE,
+    // Start the next iteration of a loop
     PRINT,
     // Print statement
+    FUNCTION,
+    // Function definition
+    RETURN,
+    // Return statement
+    IMPORT,
+    // Import a file as a module
+    FROM,
+    // Import names from a file
+    AS,
+    // Name of an imported module
+    TEST,
+    // Test block run by `dodolang test`
 }
 
 pub fn identifier(identifier: &str) -> TokenType {
@@ -93,10 +165,22 @@ pub fn identifier(identifier: &str) -> TokenType {
         "scalar" => TokenType::SCALAR,
         "vector" => TokenType::VECTOR,
         "matrix" => TokenType::MATRIX,
+        "let" => TokenType::LET,
+        "const" => TokenType::CONST,
 
         "print" => TokenType::PRINT,
 
         "for" => TokenType::FOR,
+        "while" => TokenType::WHILE,
+        "loop" => TokenType::LOOP,
+        "break" => TokenType::BREAK,
+        "continue" => TokenType::CONTINUE,
+        "fn" => TokenType::FUNCTION,
+        "return" => TokenType::RETURN,
+        "import" => TokenType::IMPORT,
+        "from" => TokenType::FROM,
+        "as" => TokenType::AS,
+        "test" => TokenType::TEST,
 
         _ => TokenType::IDENT
     }
@@ -108,6 +192,18 @@ impl fmt::Display for TokenType {
             TokenType::ASTERISK => write!(f, "*"),
             TokenType::PLUS => write!(f, "+"),
             TokenType::MINUS => write!(f, "-"),
+            TokenType::SLASH => write!(f, "/"),
+            TokenType::SlashSlash => write!(f, "//"),
+            TokenType::PERCENT => write!(f, "%"),
+            TokenType::CARET => write!(f, "^"),
+            TokenType::DotCaret => write!(f, ".^"),
+            TokenType::GREATER => write!(f, ">"),
+            TokenType::GreaterEqual => write!(f, ">="),
+            TokenType::LESS => write!(f, "<"),
+            TokenType::LessEqual => write!(f, "<="),
+            TokenType::EqualEqual => write!(f, "=="),
+            TokenType::BangEqual => write!(f, "!="),
+            TokenType::BANG => write!(f, "!"),
             _ => write!(f, "{:?}", self),
         }
     }
@@ -119,6 +215,18 @@ impl fmt::Display for Token {
             TokenType::ASTERISK => write!(f, "*"),
             TokenType::PLUS => write!(f, "+"),
             TokenType::MINUS => write!(f, "-"),
+            TokenType::SLASH => write!(f, "/"),
+            TokenType::SlashSlash => write!(f, "//"),
+            TokenType::PERCENT => write!(f, "%"),
+            TokenType::CARET => write!(f, "^"),
+            TokenType::DotCaret => write!(f, ".^"),
+            TokenType::GREATER => write!(f, ">"),
+            TokenType::GreaterEqual => write!(f, ">="),
+            TokenType::LESS => write!(f, "<"),
+            TokenType::LessEqual => write!(f, "<="),
+            TokenType::EqualEqual => write!(f, "=="),
+            TokenType::BangEqual => write!(f, "!="),
+            TokenType::BANG => write!(f, "!"),
             _ => write!(f, "{:?}", self.token_type),
         }
     }
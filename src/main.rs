@@ -1,13 +1,47 @@
 pub mod core;
-use crate::core::ast::stmt::Stmt;
-use crate::core::ast::expr::Expr;
-use crate::core::token::token::Token;
-use crate::core::token::token::TokenType;
-use crate::core::dodo::interpreter::Interpreter;
 use crate::core::dodo::repl::start;
+use crate::core::dodo::runner::{check_file, run_file};
+use crate::core::dodo::value::Arithmetic;
+use crate::core::debug::{dap, debugger};
+use crate::core::format::formatter;
+use crate::core::fuzz::fuzzer;
+use crate::core::lsp::server;
+use crate::core::profile::profiler;
+use crate::core::testing::harness;
+use std::env;
+use std::path::PathBuf;
+use std::process;
 
 
 fn main() {
-start();
-
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // `--path <dir>` before the command adds a directory to look for imported files in
+    let mut search_path = Vec::new();
+    while args.len() >= 2 && args[0] == "--path" {
+        search_path.push(PathBuf::from(args.remove(1)));
+        args.remove(0);
+    }
+    let code = match args.first().map(String::as_str) {
+        None => {
+            start(&search_path);
+            0
+        }
+        Some("fmt") => formatter::command(&args[1..]),
+        Some("lsp") => server::command(),
+        Some("debug") if args.len() == 2 => debugger::command(&args[1], &search_path),
+        Some("dap") => dap::command(&search_path),
+        Some("--profile") if args.len() == 2 => profiler::command(&args[1], &search_path),
+        Some("check") if args.len() == 2 => check_file(&args[1], &search_path),
+        Some("fuzz") => fuzzer::command(&args[1..]),
+        Some("test") => harness::command(&args[1..], &search_path),
+        Some("--arithmetic") if args.len() == 3 => match args[1].parse() {
+            Ok(arithmetic) => run_file(&args[2], &search_path, arithmetic),
+            Err(message) => {
+                eprintln!("{}", message);
+                2
+            }
+        },
+        Some(path) => run_file(path, &search_path, Arithmetic::default()),
+    };
+    process::exit(code);
 }
@@ -0,0 +1,109 @@
+// Golden tests: runs every `tests/scripts/*.dodo` with the dodolang binary and compares what
+// it prints with the `.expected` file beside it, stdout first and then any stderr after a
+// `--- stderr` line. A line ending in `# expect-error: <text>` must produce an error on that
+// line whose message contains the text, and a script with such lines must fail.
+//
+//     cargo test --test golden                 run every script
+//     cargo test --test golden -- loops        run the scripts whose name contains "loops"
+//     cargo test --test golden -- --bless      rewrite the `.expected` files from the output
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+const SCRIPTS: &str = "tests/scripts";
+const ANNOTATION: &str = "# expect-error:";
+const STDERR: &str = "--- stderr\n";
+
+// Lines annotated with an expected error, with the text the error must contain
+fn expected_errors(source: &str) -> Vec<(usize, String)> {
+    source.lines().enumerate()
+        .filter_map(|(i, line)| line.find(ANNOTATION).map(|at| (i + 1, line[at + ANNOTATION.len()..].trim().to_string())))
+        .collect()
+}
+
+fn check_errors(source: &str, stderr: &str, success: bool) -> Result<(), String> {
+    let expected = expected_errors(source);
+    if expected.is_empty() {
+        return match success {
+            true => Ok(()),
+            false => Err(format!("failed without an expected error:\n{}", stderr)),
+        };
+    }
+    if success {
+        return Err("succeeded, but errors were expected".to_string());
+    }
+    for (line, text) in expected {
+        let prefix = format!("[line {}] ", line);
+        if !stderr.lines().any(|error| error.starts_with(&prefix) && error.contains(&text)) {
+            return Err(format!("no error on line {} containing '{}' in:\n{}", line, text, stderr));
+        }
+    }
+    Ok(())
+}
+
+fn run(script: &Path, bless: bool) -> Result<(), String> {
+    let source = fs::read_to_string(script).map_err(|error| error.to_string())?;
+    // Run from the scripts directory so messages name files as the scripts import them
+    let output = Command::new(env!("CARGO_BIN_EXE_dodolang"))
+        .arg(script.file_name().unwrap())
+        .current_dir(script.parent().unwrap())
+        .env_remove("DODO_PATH")
+        .output()
+        .map_err(|error| format!("cannot run dodolang: {}", error))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    check_errors(&source, &stderr, output.status.success())?;
+
+    let mut actual = stdout.into_owned();
+    if !stderr.is_empty() {
+        actual.push_str(STDERR);
+        actual.push_str(&stderr);
+    }
+    let expected_path = script.with_extension("expected");
+    if bless {
+        return fs::write(&expected_path, actual).map_err(|error| error.to_string());
+    }
+    let expected = fs::read_to_string(&expected_path)
+        .map_err(|_| format!("{} is missing; run with --bless to create it", expected_path.display()))?;
+    if expected != actual {
+        return Err(format!("output differs from {}\n--- expected\n{}--- actual\n{}", expected_path.display(), expected, actual));
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let bless = args.iter().any(|arg| arg == "--bless");
+    let filter = args.iter().find(|arg| !arg.starts_with('-'));
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(SCRIPTS);
+    let mut scripts: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|error| panic!("{}: {}", dir.display(), error))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "dodo"))
+        .filter(|path| filter.is_none_or(|filter| path.to_string_lossy().contains(filter.as_str())))
+        .collect();
+    scripts.sort();
+
+    println!("\nrunning {} golden tests", scripts.len());
+    let mut failures = Vec::new();
+    for script in scripts.iter() {
+        let name = script.file_name().unwrap().to_string_lossy().into_owned();
+        match run(script, bless) {
+            Ok(()) => println!("test {} ... ok", name),
+            Err(message) => {
+                println!("test {} ... FAILED", name);
+                failures.push((name, message));
+            }
+        }
+    }
+    for (name, message) in failures.iter() {
+        println!("\n---- {} ----\n{}", name, message);
+    }
+    println!("\ntest result: {}. {} passed; {} failed\n", if failures.is_empty() { "ok" } else { "FAILED" }, scripts.len() - failures.len(), failures.len());
+    if !failures.is_empty() {
+        process::exit(1);
+    }
+}